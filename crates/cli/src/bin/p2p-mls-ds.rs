@@ -0,0 +1,26 @@
+//! In-memory delivery service for tests and local demos of the mailbox
+//! fallback. Nothing is persisted; restarting the server empties every mailbox.
+
+use docopt::Docopt;
+use p2p_mls_core::ds::{serve, Mailboxes};
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "
+Usage: p2p-mls-ds [--listen=<address>]
+
+Options:
+    --listen=<address>  Address to accept clients on [default: 127.0.0.1:7878].
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+    let args = Docopt::new(USAGE)
+        .and_then(|d| d.parse())
+        .unwrap_or_else(|e| e.exit());
+    let listener = TcpListener::bind(args.get_str("--listen"))?;
+    println!("Delivery service listening on {}", listener.local_addr()?);
+    serve(listener, Arc::new(Mutex::new(Mailboxes::default())))?;
+    Ok(())
+}
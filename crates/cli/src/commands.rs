@@ -0,0 +1,339 @@
+use std::fmt::Display;
+
+use p2p_mls_core::error::NodeError;
+
+/// One `node` command: its docopt patterns, the short names it also goes
+/// by and a line of help. The docopt usage string is built from these.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// Patterns following `node <name>`, one per usage line.
+    pub patterns: &'static [&'static str],
+    pub aliases: &'static [&'static str],
+    pub summary: &'static str,
+}
+
+macro_rules! command {
+    ($name:literal, [$($pattern:literal),*], [$($alias:literal),*], $summary:literal) => {
+        CommandSpec {
+            name: $name,
+            patterns: &[$($pattern),*],
+            aliases: &[$($alias),*],
+            summary: $summary,
+        }
+    };
+}
+
+pub const COMMANDS: &[CommandSpec] = &[
+    command!(
+        "create",
+        ["[<name>] [--manifest=<file>] [--non-repudiation | --max-privacy] [--psk]"],
+        [],
+        "Start a group, \"Test Group\" unless named; --manifest adds everyone listed in one commit"
+    ),
+    command!("groups", [""], [], "List the groups we are in; commands act on the one marked *"),
+    command!(
+        "status",
+        ["[<msg-id>]"],
+        [],
+        "The active group's id, epoch, our leaf index, member count and whether we lead it, or who acknowledged message <msg-id> from `node outbox`"
+    ),
+    command!(
+        "info",
+        [""],
+        [],
+        "The active group's id, epoch, our leaf index, member count, ciphersuite and pending proposals, for debugging"
+    ),
+    command!("use", ["<name>"], [], "Switch the group commands act on"),
+    command!(
+        "join",
+        ["[<room>]", "--from=<peer>"],
+        ["j", "/join"],
+        "Ask to be admitted: by any leader, the leader of a room from `node rooms`, or one peer directly"
+    ),
+    command!("rooms", [""], [], "Groups announced nearby, with join policy, size and leader fingerprint"),
+    command!("share-group", [""], [], "Publish the group's public state so newcomers can join by external commit"),
+    command!("join-external", ["[<group>]"], [], "Add ourselves to a shared group with an external commit"),
+    command!("leave", [""], [], "Ask to be removed from the group and forget it"),
+    command!("requests", [""], ["pending"], "Join requests waiting for a decision"),
+    command!(
+        "accept",
+        ["<request>... [--guest=<secs>]"],
+        [],
+        "Add the joiners of waiting requests, several in one commit; --guest removes them after <secs>"
+    ),
+    command!("approve", ["<peer>"], [], "Add the joiner whose request came from <peer>, by peer id or its end"),
+    command!("decline", ["<request>..."], [], "Drop waiting join requests"),
+    command!(
+        "join-window",
+        ["<ms>"],
+        [],
+        "Add join requests arriving within <ms> of each other to the active group in one commit, 0 for one each"
+    ),
+    command!("remove", ["<peer>"], [], "Remove a member by peer id and rotate the group keys"),
+    command!(
+        "promote",
+        ["<peer> [--add-only | --remove-only]"],
+        [],
+        "Let a member add and remove others too; --add-only or --remove-only grants one right"
+    ),
+    command!("demote", ["<peer>"], [], "Take a member's admin rights back"),
+    command!("guests", [""], [], "Members let in with --guest and how long each may stay"),
+    command!("extend", ["<peer> <secs>"], [], "Let a guest stay <secs> longer, as leader or admin"),
+    command!(
+        "propose",
+        ["add <file>", "remove <peer>", "update"],
+        [],
+        "Propose a change without committing it"
+    ),
+    command!("proposals", [""], [], "Proposals waiting for the next commit, ours and members'"),
+    command!("commit", [""], [], "Apply every pending proposal in one commit"),
+    command!("update", [""], [], "Replace our leaf keys with fresh ones"),
+    command!("psk", [""], [], "Make the active group require the pre-shared key in P2P_MLS_GROUP_PSK"),
+    command!(
+        "send",
+        ["<message>", "<name> <message>"],
+        ["s", "/msg"],
+        "Send a message to the active group, or to the group named"
+    ),
+    command!("broadcast", ["<message>"], [], "Send a message to every group we are in"),
+    command!("outbox", [""], [], "Our recent messages and how each went out"),
+    command!("sendfile", ["<path>"], [], "Send a file to the active group in encrypted chunks"),
+    command!(
+        "stream",
+        ["<message>"],
+        [],
+        "Send real-time data to connected members over the experimental --media channel"
+    ),
+    command!(
+        "journal",
+        ["[--at=<seq>]"],
+        [],
+        "Every change to our groups, or the groups replayed as they were after change <seq>"
+    ),
+    command!("inspect", ["<message>"], [], "Show ciphertext size, padding, epoch and generation without sending"),
+    command!(
+        "provision",
+        ["--count=<n> --out=<dir>"],
+        [],
+        "Write identities, key packages and a manifest for devices"
+    ),
+    command!("typing", [""], [], "Tell the group we are typing; the TUI runs it as we type"),
+    command!("telemetry", ["<sensor> <value>"], [], "Send a compact binary sensor reading"),
+    command!("audit", ["[<log>]"], [], "Print verified (message, signer) receipts, ours or from a log file"),
+    command!(
+        "retention",
+        ["[<count> [--max-age=<hours>]] [--absence=<disclosure>]"],
+        [],
+        "Send members we add the group's last <count> texts, 0 to stop; --absence none, summary or full sets what members added back get of their absence"
+    ),
+    command!(
+        "features",
+        ["[<feature> (on | off)]"],
+        [],
+        "Turn a feature on or off for the whole group, as leader or admin; without one list them"
+    ),
+    command!("history", ["[<count>]"], [], "The last <count> texts kept with --history, 20 unless given"),
+    command!("fingerprint", [""], [], "Print our credential fingerprint for out-of-band verification"),
+    command!("verify", ["<identity> <fingerprint>..."], [], "Mark a member verified after comparing fingerprints"),
+    command!("admission", [""], [], "Admitted and rejected join requests by reason"),
+    command!("peers", [""], [], "Connected peers with the transport of each connection, and those we blocked"),
+    command!(
+        "block",
+        ["<peer> [--remove]"],
+        [],
+        "Drop all traffic from a peer and close its connections; --remove also removes it from the active group"
+    ),
+    command!("unblock", ["<peer>"], [], "Take traffic from a blocked peer again"),
+    command!("members", [""], [], "Everyone who can read our messages"),
+    command!(
+        "about-group",
+        [""],
+        [],
+        "How many members run each client version and platform, for the leader and admins"
+    ),
+    command!("backup", [""], [], "Upload changed state now"),
+    command!("prove", ["<identity> [--out=<file>]"], [], "Signed proof that a member is in the group at this epoch"),
+    command!(
+        "recover",
+        ["[--report | --rejoin]"],
+        [],
+        "Split-brain recovery: probe members, show the branches, or rejoin the canonical one"
+    ),
+    command!("introduce", [""], [], "Send the group the addresses we reach members at"),
+    command!("archive", ["<group> [--out=<file>]"], [], "Freeze a group into an encrypted archive and leave it"),
+    command!("open-archive", ["<file>"], [], "Show an archive's members and signed history"),
+    command!("migrate", ["<file>..."], [], "Rewrite stored files in the current schema"),
+    command!("queue", [""], [], "List commands waiting for the node"),
+    command!("cancel", ["<id>"], [], "Drop a queued command before it starts"),
+    command!("reload", [""], [], "Read the --config file again and apply it, as SIGHUP does"),
+    command!(
+        "install",
+        ["--systemd [--data-dir=<dir>] [--unit=<file>]"],
+        [],
+        "Write a hardened systemd unit running this binary, creating the data directory (ours unless given) with a default config"
+    ),
+    command!("help", ["[<command>]"], ["?"], "List the commands, or show how to use one"),
+];
+
+impl CommandSpec {
+    fn usages(&self) -> impl Iterator<Item = String> + '_ {
+        self.patterns.iter().map(move |pattern| {
+            format!("node {} {}", self.name, pattern)
+                .trim_end()
+                .to_string()
+        })
+    }
+}
+
+impl Display for CommandSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for usage in self.usages() {
+            writeln!(f, "{}", usage)?;
+        }
+        if !self.aliases.is_empty() {
+            writeln!(f, "Also: {}", self.aliases.join(", "))?;
+        }
+        write!(f, "{}", self.summary)
+    }
+}
+
+/// The docopt usage string for every command.
+pub fn usage() -> String {
+    let mut lines = COMMANDS.iter().flat_map(CommandSpec::usages);
+    let mut usage = format!("Usage: {}\n", lines.next().unwrap_or_default());
+    for line in lines {
+        usage.push_str(&format!("       {}\n", line));
+    }
+    usage
+}
+
+/// The command called `name` or one of its aliases.
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name == name || command.aliases.contains(&name))
+}
+
+/// Rewrites an alias, typed with or without `node`, to the command it
+/// stands for: `s hi` and `node s hi` become `node send hi`.
+pub fn expand_aliases(line: &str) -> String {
+    let words: Vec<&str> = line.split(' ').collect();
+    let (first, rest) = match words[..] {
+        ["node", first, ..] => (first, &words[2..]),
+        [first, ..] => (first, &words[1..]),
+        [] => return line.to_string(),
+    };
+    match COMMANDS
+        .iter()
+        .find(|command| command.aliases.contains(&first))
+    {
+        Some(command) => [&["node", command.name], rest].concat().join(" "),
+        None => line.to_string(),
+    }
+}
+
+/// `node help` on its own lists every command; with a command, its usage.
+pub fn help(command: &str) -> Result<String, NodeError> {
+    if command.is_empty() {
+        let width = COMMANDS.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        let lines: Vec<String> = COMMANDS
+            .iter()
+            .map(|c| format!("  {:width$}  {}", c.name, c.summary, width = width))
+            .collect();
+        return Ok(format!(
+            "Commands, typed after `node`:\n{}\n`node help <command>` shows how to use one.",
+            lines.join("\n")
+        ));
+    }
+    find(command)
+        .map(ToString::to_string)
+        .ok_or_else(|| unknown(command))
+}
+
+/// What to tell the user about a line docopt refused: the usage of the
+/// command they typed, or the command they probably meant.
+pub fn explain_error(line: &str) -> String {
+    let name = match line.split(' ').collect::<Vec<_>>()[..] {
+        ["node", name, ..] => name,
+        [""] | ["node"] => return "Type `node help` for the list of commands.".to_string(),
+        _ => return "Commands start with `node`; `node help` lists them.".to_string(),
+    };
+    match find(name) {
+        Some(command) => format!(
+            "Usage:\n{}\n`node help {}` for more.",
+            command.usages().collect::<Vec<_>>().join("\n"),
+            command.name
+        ),
+        None => unknown(name).to_string(),
+    }
+}
+
+fn unknown(name: &str) -> NodeError {
+    match suggest(name) {
+        Some(command) => NodeError::Other(format!(
+            "Unknown command {}, did you mean `node {}`?",
+            name, command
+        )),
+        None => NodeError::Other(format!(
+            "Unknown command {}, `node help` lists the commands",
+            name
+        )),
+    }
+}
+
+// The command closest to `name` by edit distance, if it is close enough
+// to be a typo.
+fn suggest(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|command| (edit_distance(name, command.name), command.name))
+        .filter(|(distance, command)| *distance <= 2 && *distance < command.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, command)| command)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(a != *b))
+                .min(row[j] + 1)
+                .min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use docopt::Docopt;
+
+    use super::*;
+
+    #[test]
+    fn aliases_expand_and_typos_get_suggestions() {
+        assert_eq!(expand_aliases("s hello"), "node send hello");
+        assert_eq!(expand_aliases("node j 2"), "node join 2");
+        assert_eq!(expand_aliases("/msg work hi"), "node send work hi");
+        assert_eq!(expand_aliases("node send s"), "node send s");
+
+        let args = Docopt::new(usage())
+            .and_then(|d| d.argv(expand_aliases("j --from=abc").split(' ')).parse())
+            .unwrap();
+        assert!(args.get_bool("join"));
+        assert_eq!(args.get_str("--from"), "abc");
+
+        assert_eq!(suggest("jion"), Some("join"));
+        assert_eq!(suggest("membres"), Some("members"));
+        assert_eq!(suggest("xyzzy"), None);
+        assert!(explain_error("node sned hi").contains("`node send`"));
+        assert!(explain_error("node use").contains("node use <name>"));
+        assert!(help("j").unwrap().starts_with("node join [<room>]"));
+    }
+}
@@ -0,0 +1,190 @@
+//! Settings read from the `--config` file, or `~/.p2p-mls/config.toml`
+//! when there is one and no other is named, and read again on `node
+//! reload` or SIGHUP so they change without a restart.
+//!
+//! The file is TOML, and every key is optional; one that is given takes
+//! the place of the matching flag:
+//!
+//! ```toml
+//! log_level = "debug"      # off, error, warn, info, debug or trace; RUST_LOG, when set, wins
+//! admit_rate = 3           # --admit-rate
+//! admit_global_rate = 30   # --admit-global-rate
+//! bootstrap = ["/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW..."]  # besides --bootstrap
+//! listen = ["/ip4/0.0.0.0/tcp/4001"]  # --listen
+//! identity = "/etc/p2p-mls/identity.json"  # unless --identity is given
+//! default_group = "ops"    # what `node create` calls a group without a name
+//! ciphersuite = "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519"  # of groups we start
+//!
+//! [notifications]
+//! bell = true              # ring the terminal bell for each text message received
+//! ```
+//!
+//! Listen addresses, identity, default group and ciphersuite are read at
+//! startup only. A reload applies the rest to the running node: groups, connections and rate
+//! limit counts stay as they are, see `AdmissionControl::reconfigure`.
+//! Bootstrap peers added by a reload are dialed, and handed to the DHT
+//! when it runs.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use log::LevelFilter;
+use openmls::prelude::Ciphersuite;
+use serde::Deserialize;
+
+use p2p_mls_core::{
+    admission::{AdmissionConfig, RateLimit},
+    crypto::parse_ciphersuite,
+    error::NodeError,
+    identity::PeerId,
+};
+use p2p_mls_net::{parse_bootstrap, Multiaddr};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    log_level: Option<String>,
+    admit_rate: Option<u32>,
+    admit_global_rate: Option<u32>,
+    bootstrap: Vec<String>,
+    listen: Vec<String>,
+    identity: Option<PathBuf>,
+    default_group: Option<String>,
+    ciphersuite: Option<String>,
+    pub notifications: Notifications,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    pub bell: bool,
+}
+
+/// `~/.p2p-mls/config.toml`, if it exists.
+pub fn default_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(".p2p-mls").join("config.toml")).filter(|path| path.exists())
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, NodeError> {
+        let contents = std::fs::read_to_string(path)?;
+        Config::parse(&contents)
+            .map_err(|e| NodeError::Parse(format!("Invalid config {}: {}", path.display(), e)))
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, NodeError> {
+        let config: Config =
+            toml::from_str(contents).map_err(|e| NodeError::Parse(e.to_string()))?;
+        if let Some(level) = &config.log_level {
+            LevelFilter::from_str(level)
+                .map_err(|_| NodeError::Parse(format!("Unknown log level {}", level)))?;
+        }
+        config.bootstrap()?;
+        config.listen()?;
+        config.ciphersuite()?;
+        Ok(config)
+    }
+
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        self.log_level
+            .as_deref()
+            .map(|level| LevelFilter::from_str(level).expect("checked on parse"))
+    }
+
+    /// `flags`, the admission settings from the command line, with the
+    /// rates this file sets.
+    pub fn admission(&self, flags: AdmissionConfig) -> AdmissionConfig {
+        AdmissionConfig {
+            per_peer: self
+                .admit_rate
+                .map_or(flags.per_peer, RateLimit::per_minute),
+            global: self
+                .admit_global_rate
+                .map_or(flags.global, RateLimit::per_minute),
+            ..flags
+        }
+    }
+
+    /// Bootstrap peers, see `p2p_mls_net::parse_bootstrap`.
+    pub fn bootstrap(&self) -> Result<Vec<(PeerId, Multiaddr)>, NodeError> {
+        self.bootstrap
+            .iter()
+            .map(|address| parse_bootstrap(address))
+            .collect()
+    }
+
+    /// Addresses to listen on instead of `--listen`'s, if any.
+    pub fn listen(&self) -> Result<Vec<Multiaddr>, NodeError> {
+        self.listen
+            .iter()
+            .map(|address| {
+                address.parse().map_err(|e| {
+                    NodeError::Parse(format!("Invalid listen address {}: {}", address, e))
+                })
+            })
+            .collect()
+    }
+
+    pub fn identity(&self) -> Option<&Path> {
+        self.identity.as_deref()
+    }
+
+    pub fn default_group(&self) -> Option<&str> {
+        self.default_group.as_deref()
+    }
+
+    pub fn ciphersuite(&self) -> Result<Option<Ciphersuite>, NodeError> {
+        self.ciphersuite
+            .as_deref()
+            .map(parse_ciphersuite)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_files_override_flags_they_name() {
+        let config = Config::parse(
+            r#"
+            log_level = "debug"
+            admit_rate = 10
+            bootstrap = ["/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"]
+            listen = ["/ip4/0.0.0.0/tcp/4001", "/ip4/0.0.0.0/tcp/4002/ws"]
+            identity = "identity.json"
+            default_group = "ops"
+            ciphersuite = "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519"
+
+            [notifications]
+            bell = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.log_level(), Some(LevelFilter::Debug));
+        assert!(config.notifications.bell);
+        assert_eq!(config.bootstrap().unwrap().len(), 1);
+        assert_eq!(config.listen().unwrap().len(), 2);
+        assert_eq!(config.identity(), Some(Path::new("identity.json")));
+        assert_eq!(config.default_group(), Some("ops"));
+        assert_eq!(
+            config.ciphersuite().unwrap(),
+            Some(Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519)
+        );
+        let admission = config.admission(AdmissionConfig::default());
+        assert_eq!(admission.per_peer, RateLimit::per_minute(10));
+        assert_eq!(admission.global, AdmissionConfig::default().global);
+
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert!(Config::parse("log_level = \"loud\"").is_err());
+        assert!(Config::parse("admit_rate = -1").is_err());
+        assert!(Config::parse("listen_on = []").is_err());
+        assert!(Config::parse("listen = [\"0.0.0.0:4001\"]").is_err());
+        assert!(
+            Config::parse("ciphersuite = \"MLS_256_DHKEMX448_AES256GCM_SHA512_Ed448\"").is_err()
+        );
+        assert!(Config::parse("bootstrap = [\"/ip4/127.0.0.1/tcp/4001\"]").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Gateway that republishes decrypted group telemetry to a local MQTT broker.
+//!
+//! Only the handful of MQTT 3.1.1 packets the gateway needs are implemented
+//! (CONNECT, PUBLISH and SUBSCRIBE at QoS 0, and the keep-alive pings), which
+//! keeps the feature free of an async MQTT stack. Instead the gateway runs
+//! on threads of its own, so a slow or vanished broker never holds up the
+//! node: readings queue for the publishing thread, and each connection
+//! reconnects by itself when the broker goes away. Readings go to
+//! `<prefix>/<schema>/<version>` as JSON objects; anything published to
+//! `<prefix>/outbound` is sent back into the group when the reverse
+//! direction is enabled.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{Map, Number, Value};
+
+use p2p_mls_core::telemetry::{FieldValue, Telemetry};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+// The broker drops a client silent for one and a half of these, so we ping
+// after half of one passes without a packet.
+const KEEP_ALIVE_SECS: u16 = 60;
+const PING_INTERVAL: Duration = Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// Readings queued while the broker is away, beyond which new ones are dropped.
+const QUEUED_READINGS: usize = 1024;
+
+pub struct MqttClient {
+    stream: TcpStream,
+}
+
+impl MqttClient {
+    pub fn connect(address: &str, client_id: &str) -> io::Result<MqttClient> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(PING_INTERVAL))?;
+        let mut client = MqttClient { stream };
+
+        let mut body = Vec::new();
+        write_string(&mut body, "MQTT");
+        body.push(4); // protocol level 3.1.1
+        body.push(0x02); // clean session
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        write_string(&mut body, client_id);
+        client.write_packet(CONNECT, &body)?;
+
+        match client.read_packet()? {
+            Some((header, body)) if header & 0xF0 == CONNACK && body.get(1) == Some(&0) => {
+                Ok(client)
+            }
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "MQTT broker refused connection",
+            )),
+            None => Err(unanswered()),
+        }
+    }
+
+    /// Connects again, waiting longer after each failed attempt, until the
+    /// broker answers.
+    pub fn reconnect(address: &str, client_id: &str) -> MqttClient {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            thread::sleep(delay);
+            match MqttClient::connect(address, client_id) {
+                Ok(client) => {
+                    log::info!("Reconnected to MQTT broker at {}", address);
+                    return client;
+                }
+                Err(e) => log::debug!("Could not reconnect to MQTT broker: {}", e),
+            }
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+
+    /// Sends a PINGREQ and waits for its PINGRESP, failing if the broker
+    /// stays silent for a ping interval.
+    pub fn ping(&mut self) -> io::Result<()> {
+        self.write_packet(PINGREQ, &[])?;
+        loop {
+            match self.read_packet()? {
+                Some((header, _)) if header & 0xF0 == PINGRESP => return Ok(()),
+                Some(_) => continue,
+                None => return Err(unanswered()),
+            }
+        }
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let mut body = Vec::new();
+        write_string(&mut body, topic);
+        body.extend_from_slice(payload);
+        self.write_packet(PUBLISH, &body)
+    }
+
+    pub fn subscribe(&mut self, topic: &str) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u16.to_be_bytes()); // packet identifier
+        write_string(&mut body, topic);
+        body.push(0); // QoS 0
+        self.write_packet(SUBSCRIBE, &body)
+    }
+
+    /// Blocks until the broker delivers a PUBLISH, skipping any other packets
+    /// and pinging the broker while the connection is idle.
+    pub fn next_publish(&mut self) -> io::Result<(String, Vec<u8>)> {
+        let mut pinged = false;
+        loop {
+            let (header, body) = match self.read_packet()? {
+                Some(packet) => packet,
+                None if pinged => return Err(unanswered()),
+                None => {
+                    self.write_packet(PINGREQ, &[])?;
+                    pinged = true;
+                    continue;
+                }
+            };
+            pinged = false;
+            if header & 0xF0 != PUBLISH || body.len() < 2 {
+                continue;
+            }
+            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            let mut offset = 2 + topic_len;
+            if body.len() < offset {
+                continue;
+            }
+            let topic = String::from_utf8_lossy(&body[2..offset]).to_string();
+            if header & 0x06 != 0 {
+                offset += 2; // QoS > 0 carries a packet identifier
+            }
+            return Ok((topic, body.get(offset..).unwrap_or_default().to_vec()));
+        }
+    }
+
+    fn write_packet(&mut self, header: u8, body: &[u8]) -> io::Result<()> {
+        let mut packet = vec![header];
+        packet.extend_from_slice(&encode_remaining_length(body.len()));
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)
+    }
+
+    /// `None` when a ping interval passes before the next packet starts; a
+    /// timeout inside a packet is an error like any other.
+    fn read_packet(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let mut header = [0u8; 1];
+        match self.stream.read_exact(&mut header) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None)
+            }
+            result => result?,
+        }
+        let mut length = 0usize;
+        let mut multiplier = 1usize;
+        loop {
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte)?;
+            length += (byte[0] & 0x7F) as usize * multiplier;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            multiplier *= 128;
+            if multiplier > 128 * 128 * 128 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Malformed MQTT remaining length",
+                ));
+            }
+        }
+        let mut body = vec![0u8; length];
+        self.stream.read_exact(&mut body)?;
+        Ok(Some((header[0], body)))
+    }
+}
+
+fn unanswered() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "MQTT broker stopped answering")
+}
+
+fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn encode_remaining_length(mut length: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        encoded.push(byte);
+        if length == 0 {
+            return encoded;
+        }
+    }
+}
+
+/// The sending half of the gateway. Publishing happens on a thread of its
+/// own, so [`Gateway::forward`] never blocks on the broker.
+pub struct Gateway {
+    readings: SyncSender<(String, Vec<u8>)>,
+    address: String,
+    client_id: String,
+    prefix: String,
+}
+
+impl Gateway {
+    /// Connects to the broker, so a wrong address fails here, and starts the
+    /// publishing thread.
+    pub fn start(address: &str, client_id: &str, prefix: &str) -> io::Result<Gateway> {
+        let client = MqttClient::connect(address, client_id)?;
+        let (readings, queued) = mpsc::sync_channel(QUEUED_READINGS);
+        let (broker, id) = (address.to_string(), client_id.to_string());
+        thread::Builder::new()
+            .name("mqtt publisher".to_string())
+            .spawn(move || publish_readings(client, &broker, &id, queued))?;
+        Ok(Gateway {
+            readings,
+            address: address.to_string(),
+            client_id: client_id.to_string(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub fn outbound_topic(&self) -> String {
+        format!("{}/outbound", self.prefix)
+    }
+
+    /// Subscribes a second connection to the outbound topic and hands each
+    /// payload to `deliver` on a thread of its own, until `deliver` returns
+    /// false.
+    pub fn reverse(
+        &self,
+        mut deliver: impl FnMut(Vec<u8>) -> bool + Send + 'static,
+    ) -> io::Result<()> {
+        // The broker disconnects the older of two sessions with one id.
+        let client_id = format!("{}-outbound", self.client_id);
+        let topic = self.outbound_topic();
+        let mut client = MqttClient::connect(&self.address, &client_id)?;
+        client.subscribe(&topic)?;
+        let address = self.address.clone();
+        thread::Builder::new()
+            .name("mqtt subscriber".to_string())
+            .spawn(move || loop {
+                match client.next_publish() {
+                    Ok((_, payload)) => {
+                        if !deliver(payload) {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Lost MQTT subscription to {}: {}", topic, e);
+                        client = MqttClient::reconnect(&address, &client_id);
+                        if let Err(e) = client.subscribe(&topic) {
+                            log::warn!("Could not subscribe to {}: {}", topic, e);
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Queues a reading for the publishing thread, failing only when the
+    /// queue is full because the broker has been away too long.
+    pub fn forward(&self, sender: &str, telemetry: &Telemetry) -> io::Result<()> {
+        let topic = format!("{}/{}/{}", self.prefix, telemetry.schema, telemetry.version);
+        let payload = telemetry_json(sender, telemetry).to_string().into_bytes();
+        match self.readings.try_send((topic, payload)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "MQTT broker unreachable, dropping the reading",
+            )),
+            Err(TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+// Runs until the `Gateway` is dropped, pinging while no readings come.
+fn publish_readings(
+    mut client: MqttClient,
+    address: &str,
+    client_id: &str,
+    queued: mpsc::Receiver<(String, Vec<u8>)>,
+) {
+    loop {
+        let result = match queued.recv_timeout(PING_INTERVAL) {
+            Ok((topic, payload)) => client.publish(&topic, &payload),
+            Err(RecvTimeoutError::Timeout) => client.ping(),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if let Err(e) = result {
+            log::warn!("Lost MQTT broker at {}: {}", address, e);
+            client = MqttClient::reconnect(address, client_id);
+        }
+    }
+}
+
+pub fn telemetry_json(sender: &str, telemetry: &Telemetry) -> Value {
+    let mut object = Map::new();
+    object.insert("sender".to_string(), Value::from(sender));
+    for (name, value) in &telemetry.fields {
+        let value = match value {
+            FieldValue::Unsigned(v) => Value::from(*v),
+            FieldValue::Signed(v) => Value::from(*v),
+            FieldValue::Float(v) => Number::from_f64(*v).map_or(Value::Null, Value::Number),
+        };
+        object.insert(name.clone(), value);
+    }
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length_encoding() {
+        assert_eq!(encode_remaining_length(0), vec![0x00]);
+        assert_eq!(encode_remaining_length(127), vec![0x7F]);
+        assert_eq!(encode_remaining_length(128), vec![0x80, 0x01]);
+        assert_eq!(encode_remaining_length(16_383), vec![0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn pings_wait_for_the_answer() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut connect = [0u8; 2];
+            stream.read_exact(&mut connect).unwrap();
+            let mut rest = vec![0u8; connect[1] as usize];
+            stream.read_exact(&mut rest).unwrap();
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+            let mut ping = [0u8; 2];
+            stream.read_exact(&mut ping).unwrap();
+            assert_eq!(ping, [PINGREQ, 0]);
+            stream.write_all(&[PINGRESP, 0]).unwrap();
+        });
+        let mut client = MqttClient::connect(&address, "test").unwrap();
+        client.ping().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn telemetry_as_json() {
+        let telemetry = Telemetry {
+            schema: 1,
+            version: 1,
+            fields: vec![
+                ("sensor".to_string(), FieldValue::Unsigned(2)),
+                ("value".to_string(), FieldValue::Float(1.5)),
+            ],
+        };
+        assert_eq!(
+            telemetry_json("peer", &telemetry).to_string(),
+            r#"{"sender":"peer","sensor":2,"value":1.5}"#
+        );
+    }
+}
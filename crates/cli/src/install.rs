@@ -0,0 +1,288 @@
+//! Where a node keeps its files, and installing it as a service.
+//!
+//! With `--data-dir` every file the node reads or writes defaults to a
+//! name inside one directory, so a service needs only that directory
+//! writable:
+//!
+//! - `config.toml`, the `--config` file, used when it exists;
+//! - `identity.json`, the `--identity` file, used when it exists, for
+//!   example one written by `node provision`;
+//! - `keys.json`, the `--key-store`;
+//! - `audit.jsonl`, the `--audit-log`;
+//! - `downloads/`, the `--downloads` directory;
+//! - `node.lock`, locked while a node runs on the directory, holding its PID.
+//!
+//! Flags that are given still win. A second node on the same directory
+//! would corrupt the key store and group state, so it refuses to start
+//! while the lock is held; `--force-unlock` takes the directory over from
+//! a holder that is no longer running, say on a filesystem that kept a
+//! dead process's lock, and refuses while it might still be. `node install --systemd` creates the
+//! directory, owner-only, with the embedded default config, and writes a
+//! hardened systemd unit running the node on it.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use p2p_mls_core::error::NodeError;
+
+/// The `config.toml` written by `node install`, every setting commented out.
+pub const DEFAULT_CONFIG: &str = include_str!("../default-config.toml");
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/p2p-mls";
+pub const DEFAULT_UNIT: &str = "/etc/systemd/system/p2p-mls.service";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    root: PathBuf,
+}
+
+impl DataDir {
+    pub fn new(root: impl Into<PathBuf>) -> DataDir {
+        DataDir { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn config(&self) -> PathBuf {
+        self.root.join("config.toml")
+    }
+
+    pub fn identity(&self) -> PathBuf {
+        self.root.join("identity.json")
+    }
+
+    pub fn key_store(&self) -> PathBuf {
+        self.root.join("keys.json")
+    }
+
+    pub fn audit_log(&self) -> PathBuf {
+        self.root.join("audit.jsonl")
+    }
+
+    pub fn history(&self) -> PathBuf {
+        self.root.join("history.jsonl")
+    }
+
+    pub fn downloads(&self) -> PathBuf {
+        self.root.join("downloads")
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        self.root.join("node.lock")
+    }
+
+    /// Creates the directory readable by us alone, and a default config in
+    /// it unless it has one.
+    pub fn create(&self) -> Result<(), NodeError> {
+        fs::create_dir_all(&self.root)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.root, fs::Permissions::from_mode(0o700))?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(self.config()) {
+            Ok(mut file) => Ok(file.write_all(DEFAULT_CONFIG.as_bytes())?),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Locks the directory for this process until the lock is dropped.
+    /// With `force` a lock whose holder, by the PID in it, is no longer
+    /// running is broken: the lock file is replaced, so the stale lock is
+    /// left on a file nobody opens. A lock held by a running process, or
+    /// one whose holder cannot be told, is never broken.
+    pub fn lock(&self, force: bool) -> Result<DataDirLock, NodeError> {
+        let path = self.lock_file();
+        let mut file = open_lock_file(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path).unwrap_or_default();
+                let pid = match pid.trim() {
+                    "" => "unknown",
+                    pid => pid,
+                };
+                if !force {
+                    return Err(NodeError::Other(format!(
+                        "{} is in use: already running, PID {}; pass --force-unlock if it is not",
+                        self.root.display(),
+                        pid
+                    )));
+                }
+                if !has_exited(pid) {
+                    return Err(NodeError::Other(format!(
+                        "{} is in use: PID {} may still be running, stop it before forcing the lock",
+                        self.root.display(),
+                        pid
+                    )));
+                }
+                fs::remove_file(&path)?;
+                file = open_lock_file(&path)?;
+                file.try_lock().map_err(|e| match e {
+                    TryLockError::WouldBlock => NodeError::Other(format!(
+                        "{} was locked again while breaking a stale lock",
+                        self.root.display()
+                    )),
+                    TryLockError::Error(e) => e.into(),
+                })?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        Ok(DataDirLock { _file: file })
+    }
+}
+
+fn open_lock_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+// Whether the process `pid` names is known to be gone; only Linux, by its
+// `/proc`, can tell.
+fn has_exited(pid: &str) -> bool {
+    let proc = Path::new("/proc");
+    pid.parse::<u32>().is_ok() && proc.join("self").exists() && !proc.join(pid).exists()
+}
+
+/// Held while a node runs on a [`DataDir`]; dropping it, or exiting,
+/// unlocks the directory.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+/// A systemd unit running `exe` on `data_dir` as `user`, without a
+/// terminal, with the rest of the system read-only to it.
+pub fn systemd_unit(exe: &Path, data_dir: &DataDir, user: Option<&str>) -> String {
+    let root = data_dir.root().display();
+    let mut unit = format!(
+        "[Unit]
+Description=p2p-mls node
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+ExecStart={} --data-dir={} --daemon --prompt=none
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+",
+        exe.display(),
+        root
+    );
+    if let Some(user) = user {
+        unit.push_str(&format!("User={}\n", user));
+    }
+    unit.push_str(&format!(
+        "UMask=0077
+ReadWritePaths={}
+ProtectSystem=strict
+ProtectHome=read-only
+PrivateTmp=yes
+PrivateDevices=yes
+NoNewPrivileges=yes
+CapabilityBoundingSet=
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectControlGroups=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6 AF_NETLINK
+RestrictNamespaces=yes
+RestrictRealtime=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+
+[Install]
+WantedBy=multi-user.target
+",
+        root
+    ));
+    unit
+}
+
+/// Prepares `data_dir` and writes the unit for this binary to `unit`.
+pub fn install_systemd(data_dir: &DataDir, unit: &Path) -> Result<(), NodeError> {
+    data_dir.create()?;
+    let exe = std::env::current_exe()?;
+    // Root keeps the default; anyone else runs the service as themselves.
+    let user = std::env::var("USER").ok().filter(|user| user != "root");
+    fs::write(unit, systemd_unit(&exe, data_dir, user.as_deref()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn installs_run_the_node_on_its_data_dir() {
+        assert_eq!(Config::parse(DEFAULT_CONFIG).unwrap(), Config::default());
+
+        let root = std::env::temp_dir().join(format!("p2p-mls-install-{}", std::process::id()));
+        let data_dir = DataDir::new(&root);
+        data_dir.create().unwrap();
+        fs::write(data_dir.config(), "log_level = \"debug\"").unwrap();
+        // An existing config is kept.
+        data_dir.create().unwrap();
+        assert_eq!(
+            fs::read_to_string(data_dir.config()).unwrap(),
+            "log_level = \"debug\""
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&root).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        let unit = systemd_unit(Path::new("/usr/bin/mls"), &data_dir, Some("alice"));
+        assert!(unit.contains(&format!(
+            "ExecStart=/usr/bin/mls --data-dir={} --daemon",
+            root.display()
+        )));
+        assert!(unit.contains(&format!("ReadWritePaths={}\n", root.display())));
+        assert!(unit.contains("User=alice\n"));
+        assert!(!systemd_unit(Path::new("/usr/bin/mls"), &data_dir, None).contains("User="));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn one_node_runs_on_a_data_dir() {
+        let root = std::env::temp_dir().join(format!("p2p-mls-lock-{}", std::process::id()));
+        let data_dir = DataDir::new(&root);
+        data_dir.create().unwrap();
+
+        let lock = data_dir.lock(false).unwrap();
+        let pid = std::process::id().to_string();
+        assert_eq!(fs::read_to_string(data_dir.lock_file()).unwrap(), pid);
+        let refused = data_dir.lock(false).unwrap_err().to_string();
+        assert!(refused.contains(&format!("already running, PID {}", pid)));
+
+        let refused = data_dir.lock(true).unwrap_err().to_string();
+        assert!(refused.contains(&format!("PID {} may still be running", pid)));
+
+        // A lock left behind by a process that is gone can be broken.
+        fs::write(data_dir.lock_file(), u32::MAX.to_string()).unwrap();
+        if cfg!(target_os = "linux") {
+            let forced = data_dir.lock(true).unwrap();
+            assert!(data_dir.lock(false).is_err());
+            drop(forced);
+        }
+        drop(lock);
+        data_dir.lock(false).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
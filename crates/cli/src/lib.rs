@@ -0,0 +1,779 @@
+//! The `mls` terminal client: the node commands read from stdin, the
+//! `--config` file and the prompt. The node itself is `p2p-mls-core`, the
+//! swarm `p2p-mls-net`.
+
+use docopt::{ArgvMap, Docopt};
+use openmls::prelude::KeyPackage;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+mod commands;
+pub mod config;
+#[cfg(feature = "mqtt")]
+pub mod gateway;
+pub mod install;
+pub mod output;
+pub mod prompt;
+mod queue;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+pub use commands::{CommandSpec, COMMANDS};
+pub use queue::{CommandQueue, QueuedCommand};
+
+use p2p_mls_core::{
+    ack::{self, MessageId},
+    admins::Rights,
+    audit::AuditEntry,
+    backfill::{Disclosure, Retention},
+    backup::DEFAULT_ITERATIONS,
+    capabilities::Capability,
+    crypto::{credential_identity, hex_encode},
+    error::NodeError,
+    features::Feature,
+    guests::Remaining,
+    health::SendOutcome,
+    identity::PeerId,
+    manifest::Manifest,
+    node::Node,
+    policy::GroupPolicy,
+    protocol::WireMessage,
+    provision::provision,
+    psk::GroupPsk,
+    qos::Qos,
+    schema::migrate_file,
+    telemetry::TelemetryFrame,
+};
+
+type Message = Vec<u8>;
+
+// Texts `node history` prints unless given a count.
+const DEFAULT_HISTORY: usize = 20;
+
+/// Commands about the command queue, answered without waiting for the node.
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueueControl {
+    List,
+    Cancel(u64),
+    /// Read the `--config` file again, see `config`.
+    Reload,
+    /// Write a systemd unit, see `install`. Without a `data_dir` the node's
+    /// own is used.
+    Install {
+        data_dir: Option<PathBuf>,
+        unit: PathBuf,
+    },
+}
+
+pub fn queue_control(line: &str) -> Result<Option<QueueControl>, NodeError> {
+    let args = match parse_args(line) {
+        Ok(args) => args,
+        Err(_) => return Ok(None),
+    };
+    if args.get_bool("queue") {
+        Ok(Some(QueueControl::List))
+    } else if args.get_bool("cancel") {
+        let id = args
+            .get_str("<id>")
+            .trim_start_matches('#')
+            .parse()
+            .map_err(|_| NodeError::Other("<id> must be a number from `node queue`".to_string()))?;
+        Ok(Some(QueueControl::Cancel(id)))
+    } else if args.get_bool("reload") {
+        Ok(Some(QueueControl::Reload))
+    } else if args.get_bool("install") {
+        Ok(Some(QueueControl::Install {
+            data_dir: Some(args.get_str("--data-dir"))
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            unit: match args.get_str("--unit") {
+                "" => install::DEFAULT_UNIT.into(),
+                unit => unit.into(),
+            },
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+// Command line helper for Node actions
+pub fn parse_stdin(node: &mut Node, line: String) -> Result<Message, NodeError> {
+    let args_res = parse_args(&line);
+    let mut msg = Vec::new();
+    match args_res {
+        Ok(args) => {
+            let user_message = args.get_str("<message>");
+            let group = args.get_str("<name>");
+            if args.get_bool("help") {
+                say!("{}", commands::help(args.get_str("<command>"))?);
+            } else if args.get_bool("create") {
+                let name = match group {
+                    "" => node.default_group_name().to_string(),
+                    name => name.to_string(),
+                };
+                // Derived first, so a missing passphrase leaves no group behind.
+                let psk = match args.get_bool("--psk") {
+                    true => Some(group_psk(node)?),
+                    false => None,
+                };
+                say!("Creating group {}.", name);
+                node.create_group(
+                    &name,
+                    GroupPolicy {
+                        non_repudiation: args.get_bool("--non-repudiation"),
+                        max_privacy: args.get_bool("--max-privacy"),
+                        ..GroupPolicy::default()
+                    },
+                )?;
+                if let Some(psk) = psk {
+                    let id = node.inject_psk(psk)?;
+                    say!("The group requires pre-shared key {}.", hex_encode(&id));
+                }
+                let manifest_path = args.get_str("--manifest");
+                if !manifest_path.is_empty() {
+                    msg = bootstrap_from_manifest(node, Path::new(manifest_path))?;
+                }
+            } else if args.get_bool("groups") {
+                let groups = node.groups();
+                if groups.is_empty() {
+                    say!("not in any group");
+                }
+                for group in groups {
+                    say!("{}", group);
+                }
+            } else if args.get_bool("status") && !args.get_str("<msg-id>").is_empty() {
+                let id: MessageId = args.get_str("<msg-id>").parse()?;
+                match node.delivery(id) {
+                    Some(delivery) => say!("{}", delivery),
+                    None => say!("No message {} being followed", id),
+                }
+            } else if args.get_bool("status") {
+                match node.group_info() {
+                    Some(info) => say!("{}", info),
+                    None => say!("not in a group"),
+                }
+                for (epoch, messages) in node.held_back() {
+                    say!(
+                        "{} messages for epoch {} wait for the commit that starts it",
+                        messages,
+                        epoch
+                    );
+                }
+            } else if args.get_bool("info") {
+                let info = node.group_info().ok_or(NodeError::NoGroup)?;
+                say!("group {} ({})", info.name, hex_encode(&info.group_id));
+                say!("epoch {}", info.epoch);
+                say!(
+                    "we are leaf {} of {} members{}",
+                    info.own_leaf_index,
+                    info.members,
+                    if info.is_leader { ", the leader" } else { "" }
+                );
+                say!("ciphersuite {}", info.ciphersuite);
+                say!("{} proposals pending", info.pending_proposals);
+            } else if args.get_bool("use") {
+                node.use_group(group)?;
+                say!("Commands now act on group {}.", group);
+            } else if args.get_bool("join") {
+                let room = args.get_str("<room>");
+                let from = args.get_str("--from");
+                msg = if !from.is_empty() {
+                    let peer = find_peer(node, from)?;
+                    say!("Asking {} alone to admit us.", node.display_name(&peer));
+                    node.request_join_from(&peer)?;
+                    Vec::new()
+                } else if room.is_empty() {
+                    say!("Joining group.");
+                    WireMessage::from(node.create_join_request()?).encode()?
+                } else {
+                    let index = room.trim_start_matches('#').parse().map_err(|_| {
+                        NodeError::Other("<room> must be a number from `node rooms`".to_string())
+                    })?;
+                    say!("Joining room #{}.", index);
+                    WireMessage::from(node.create_room_join_request(index)?).encode()?
+                };
+            } else if args.get_bool("share-group") {
+                msg = WireMessage::from(node.share_group()?).encode()?;
+                say!("Shared the group; anyone who hears it can join until the next epoch.");
+            } else if args.get_bool("join-external") {
+                let group = Some(args.get_str("<group>")).filter(|group| !group.is_empty());
+                let (group, commit) = node.join_shared_group(group)?;
+                msg = WireMessage::from(commit).encode()?;
+                say!("Joined {} by external commit.", group);
+            } else if args.get_bool("leave") {
+                msg = WireMessage::from(node.leave_group()?).encode()?;
+                say!("Left the group.");
+            } else if args.get_bool("propose") {
+                let proposal = if args.get_bool("add") {
+                    let path = args.get_str("<file>");
+                    let bytes = std::fs::read(path)?;
+                    let key_package = KeyPackage::try_from(bytes.as_slice()).map_err(|e| {
+                        NodeError::Parse(format!("Invalid key package {}: {:?}", path, e))
+                    })?;
+                    node.propose_add(key_package)?
+                } else if args.get_bool("remove") {
+                    node.propose_remove(args.get_str("<peer>"))?
+                } else {
+                    node.propose_update()?
+                };
+                msg = WireMessage::from(proposal).encode()?;
+                say!("Proposed; `node commit` applies every pending proposal at once.");
+            } else if args.get_bool("proposals") {
+                let proposals = node.pending_proposals()?;
+                if proposals.is_empty() {
+                    say!("no pending proposals");
+                }
+                for proposal in proposals {
+                    say!("{}", proposal);
+                }
+            } else if args.get_bool("commit") {
+                msg = WireMessage::from(node.commit_pending_proposals()?).encode()?;
+                say!("Committed the pending proposals.");
+            } else if args.get_bool("requests") {
+                let requests: Vec<_> = node.pending_joins().cloned().collect();
+                if requests.is_empty() {
+                    say!("no join requests waiting");
+                }
+                for pending in requests {
+                    say!(
+                        "#{} {} ({}) for {}",
+                        pending.id,
+                        credential_identity(pending.request.key_package.credential()),
+                        node.display_name(&pending.peer),
+                        node.group_name(&pending.group_id)
+                    );
+                }
+            } else if args.get_bool("accept") {
+                let ids = request_ids(&args.get_vec("<request>"))?;
+                let guest = match args.get_str("--guest") {
+                    "" => None,
+                    secs => Some(Duration::from_secs(secs.parse().map_err(|_| {
+                        NodeError::Other("--guest must be a number of seconds".to_string())
+                    })?)),
+                };
+                match guest {
+                    Some(access) => node.accept_guests(&ids, access)?,
+                    None => node.accept_joins(&ids)?,
+                }
+                let guest = guest
+                    .map(|access| format!(" as guests for {}", Remaining(access)))
+                    .unwrap_or_default();
+                match ids[..] {
+                    [id] => say!("Admitted join request #{}{}.", id, guest),
+                    _ => say!(
+                        "Admitted {} join requests in one commit{}.",
+                        ids.len(),
+                        guest
+                    ),
+                }
+            } else if args.get_bool("approve") {
+                let (peer, id) = pending_join_from(node, args.get_str("<peer>"))?;
+                node.accept_join(id)?;
+                say!("Admitted {}.", node.display_name(&peer));
+            } else if args.get_bool("decline") {
+                for id in request_ids(&args.get_vec("<request>"))? {
+                    node.decline_join(id)?;
+                    say!("Declined join request #{}.", id);
+                }
+            } else if args.get_bool("join-window") {
+                let ms = args.get_str("<ms>").parse().map_err(|_| {
+                    NodeError::Other("<ms> must be a number of milliseconds".to_string())
+                })?;
+                node.set_group_join_window(Duration::from_millis(ms))?;
+                match ms {
+                    0 => say!("Adding joiners as their requests arrive."),
+                    ms => say!(
+                        "Adding joiners whose requests arrive within {}ms in one commit.",
+                        ms
+                    ),
+                }
+            } else if args.get_bool("remove") {
+                let peer = args.get_str("<peer>");
+                msg = WireMessage::from(node.remove_member(peer)?).encode()?;
+                say!("Removed {} from the group.", peer);
+            } else if args.get_bool("promote") {
+                let peer = args.get_str("<peer>");
+                let rights = if args.get_bool("--add-only") {
+                    Rights::ADD
+                } else if args.get_bool("--remove-only") {
+                    Rights::REMOVE
+                } else {
+                    Rights::ALL
+                };
+                msg = WireMessage::from(node.set_rights(peer, rights)?).encode()?;
+                say!("{} is now an admin who {}.", peer, rights);
+            } else if args.get_bool("demote") {
+                let peer = args.get_str("<peer>");
+                msg = WireMessage::from(node.set_rights(peer, Rights::default())?).encode()?;
+                say!("{} is no longer an admin.", peer);
+            } else if args.get_bool("guests") {
+                let guests = node.guests()?;
+                if guests.is_empty() {
+                    say!("no guests");
+                }
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                for guest in guests {
+                    let remaining = Duration::from_secs(guest.expires.saturating_sub(now));
+                    say!("{} for {}", guest.identity, Remaining(remaining));
+                }
+            } else if args.get_bool("extend") {
+                let peer = args.get_str("<peer>");
+                let secs = args.get_str("<secs>").parse().map_err(|_| {
+                    NodeError::Other("<secs> must be a number of seconds".to_string())
+                })?;
+                let (_, msg_out) = node.extend_guest(peer, Duration::from_secs(secs))?;
+                msg = WireMessage::from(msg_out).encode()?;
+                say!(
+                    "{} may stay {} longer.",
+                    peer,
+                    Remaining(Duration::from_secs(secs))
+                );
+            } else if args.get_bool("update") {
+                msg = WireMessage::from(node.self_update()?).encode()?;
+                say!("Replaced our leaf keys.");
+            } else if args.get_bool("psk") {
+                let id = node.inject_psk(group_psk(node)?)?;
+                say!(
+                    "The group now requires pre-shared key {}; members without it can no longer read or send.",
+                    hex_encode(&id)
+                );
+            } else if args.get_bool("rooms") {
+                say!("{}", node.rooms());
+            } else if args.get_bool("provision") {
+                let count = args
+                    .get_str("--count")
+                    .parse()
+                    .map_err(|_| NodeError::Other("--count must be a number".to_string()))?;
+                let manifest = provision(count, Path::new(args.get_str("--out")))?;
+                say!(
+                    "Provisioned {} identities, manifest at {}",
+                    count,
+                    manifest.display()
+                );
+            } else if args.get_bool("audit") {
+                let file = args.get_str("<log>");
+                let (entries, shut) = match file {
+                    "" => (node.audit_log().entries().to_vec(), 0),
+                    _ => node.read_history(&std::fs::read(file)?)?,
+                };
+                for entry in entries {
+                    match entry {
+                        AuditEntry::SignedMessage {
+                            epoch,
+                            signer,
+                            message,
+                            ..
+                        } => say!(
+                            "epoch {} signed by {}: {}",
+                            epoch,
+                            signer,
+                            String::from_utf8_lossy(ack::text_of(&message))
+                        ),
+                    }
+                }
+                if shut > 0 {
+                    say!("{} sealed entries of groups we are not in", shut);
+                }
+            } else if args.get_bool("retention") {
+                let count = args.get_str("<count>");
+                if !count.is_empty() {
+                    let count = count
+                        .parse()
+                        .map_err(|_| NodeError::Other("<count> must be a number".to_string()))?;
+                    let max_age = match args.get_str("--max-age") {
+                        "" => None,
+                        hours => Some(Duration::from_secs(
+                            hours.parse::<u64>().map_err(|_| {
+                                NodeError::Other("--max-age must be a number of hours".to_string())
+                            })? * 3600,
+                        )),
+                    };
+                    node.set_retention(Retention::new(count, max_age)?)?;
+                }
+                let absence = args.get_str("--absence");
+                if !absence.is_empty() {
+                    node.set_disclosure(absence.parse()?)?;
+                }
+                say!("The group keeps {}.", node.retention()?);
+                let disclosed = match node.disclosure()? {
+                    Disclosure::None => "nothing said while they were away",
+                    Disclosure::Summary => "how many texts they missed and from whom",
+                    Disclosure::Full => "every text kept, like any joiner",
+                };
+                say!("Members we add back after a removal get {}.", disclosed);
+            } else if args.get_bool("features") {
+                let feature = args.get_str("<feature>");
+                if !feature.is_empty() {
+                    let feature: Feature = feature.parse()?;
+                    msg = WireMessage::from(node.set_feature(feature, args.get_bool("on"))?)
+                        .encode()?;
+                }
+                say!("The group has {}.", node.features()?);
+            } else if args.get_bool("history") {
+                let count = match args.get_str("<count>") {
+                    "" => DEFAULT_HISTORY,
+                    count => count
+                        .parse()
+                        .map_err(|_| NodeError::Other("<count> must be a number".to_string()))?,
+                };
+                for message in node.recent_messages(count)? {
+                    say!(
+                        "[{}] {}@{} (epoch {}): {}",
+                        message.at,
+                        message.sender,
+                        message.group,
+                        message.epoch,
+                        message.text
+                    );
+                }
+            } else if args.get_bool("fingerprint") {
+                say!("{}", node.fingerprint());
+            } else if args.get_bool("verify") {
+                let identity = args.get_str("<identity>");
+                node.verify_member(identity, &args.get_vec("<fingerprint>").join(""))?;
+                say!("Verified {}", identity);
+            } else if args.get_bool("backup") {
+                let uploaded = node.run_backup()?;
+                say!("Backed up {} changed sections", uploaded);
+            } else if args.get_bool("peers") {
+                say!("{}", node.peers());
+            } else if args.get_bool("block") {
+                let peer = find_peer(node, args.get_str("<peer>"))?;
+                match node.peers_mut().block(peer) {
+                    true => say!(
+                        "Blocked {}; its traffic is dropped.",
+                        node.display_name(&peer)
+                    ),
+                    false => say!("{} is blocked already.", node.display_name(&peer)),
+                }
+                if args.get_bool("--remove") {
+                    msg = WireMessage::from(node.remove_member(&peer.to_string())?).encode()?;
+                    say!("Removed {} from the group.", node.display_name(&peer));
+                }
+            } else if args.get_bool("unblock") {
+                let peer = find_blocked(node, args.get_str("<peer>"))?;
+                node.peers_mut().unblock(&peer);
+                say!("Unblocked {}.", node.display_name(&peer));
+            } else if args.get_bool("about-group") {
+                say!("{}", node.client_stats()?);
+            } else if args.get_bool("members") {
+                for member in node.list_members()? {
+                    say!("{}", member);
+                }
+            } else if args.get_bool("outbox") {
+                for sent in node.outbox().messages() {
+                    let group = node.group_name(&sent.group_id);
+                    match node.deliveries().for_send(&sent.group_id, sent.seq) {
+                        Some(delivery) => {
+                            say!("{} #{}: {}; id {}", group, sent.seq, sent, delivery)
+                        }
+                        None => say!("{} #{}: {}", group, sent.seq, sent),
+                    }
+                }
+                say!(
+                    "{} echoes of our own frames dropped",
+                    node.outbox().echoes()
+                );
+            } else if args.get_bool("journal") {
+                let at = args.get_str("--at");
+                if at.is_empty() {
+                    for entry in node.journal().entries() {
+                        say!("{}", entry);
+                    }
+                } else {
+                    let seq = at.trim_start_matches('#').parse().map_err(|_| {
+                        NodeError::Other("--at must be a number from `node journal`".to_string())
+                    })?;
+                    say!("{}", node.journal().replay(seq));
+                }
+            } else if args.get_bool("admission") {
+                say!("{}", node.admission_metrics());
+            } else if args.get_bool("prove") {
+                let proof = node
+                    .membership_proof(args.get_str("<identity>"))?
+                    .encode()?;
+                let out = args.get_str("--out");
+                if out.is_empty() {
+                    say!("{}", proof);
+                } else {
+                    std::fs::write(out, proof)?;
+                    say!("Wrote membership proof to {}", out);
+                }
+            } else if args.get_bool("introduce") {
+                msg = WireMessage::from(node.create_address_book_message()?).encode()?;
+                say!("Shared our address book with the group.");
+            } else if args.get_bool("archive") {
+                let group = args.get_str("<group>");
+                let out = match args.get_str("--out") {
+                    "" => format!("{}.archive", group),
+                    out => out.to_string(),
+                };
+                let sealed = node.seal_group(group, &archive_passphrase()?, DEFAULT_ITERATIONS)?;
+                write_archive(&out, &sealed)?;
+                node.forget_group(group)?;
+                say!(
+                    "Archived group {} to {}, it is no longer active here.",
+                    group,
+                    out
+                );
+            } else if args.get_bool("open-archive") {
+                let bytes = std::fs::read(args.get_str("<file>"))?;
+                say!("{}", node.open_archive(&bytes, &archive_passphrase()?)?);
+            } else if args.get_bool("migrate") {
+                for file in args.get_vec("<file>") {
+                    match migrate_file(Path::new(file)) {
+                        Ok(migrated) => say!("{}: {}", file, migrated),
+                        Err(e) => say!("{}: {}", file, e),
+                    }
+                }
+            } else if args.get_bool("recover") {
+                msg = recover(node, args.get_bool("--report"), args.get_bool("--rejoin"))?;
+            } else if args.get_bool("typing") {
+                // Says nothing, and is dropped rather than held, see `typing`.
+                if let Some(msg_out) = node.create_typing_message(Instant::now())? {
+                    msg = WireMessage::from(msg_out).encode_as(Qos::Realtime)?;
+                }
+            } else if args.get_bool("telemetry") {
+                let sensor = args
+                    .get_str("<sensor>")
+                    .parse()
+                    .map_err(|_| NodeError::Other("<sensor> must be a number".to_string()))?;
+                let value = args
+                    .get_str("<value>")
+                    .parse()
+                    .map_err(|_| NodeError::Other("<value> must be a number".to_string()))?;
+                // A reading goes stale, so it skips ahead of chat, see `qos`.
+                msg = WireMessage::from(
+                    node.create_telemetry_message(&TelemetryFrame::scalar_reading(sensor, value))?,
+                )
+                .encode_as(Qos::Realtime)?;
+            } else if args.get_bool("sendfile") {
+                let path = Path::new(args.get_str("<path>"));
+                let (offer, chunks) = node.send_file(path)?;
+                msg = WireMessage::from(offer).encode()?;
+                let group_id = node
+                    .group_info()
+                    .map(|info| info.group_id)
+                    .unwrap_or_default();
+                if !node.group_supports(&group_id, Capability::FileTransfer) {
+                    say!("Not every member said it can receive files");
+                }
+                say!("Sending {} in {} chunks", path.display(), chunks);
+            } else if args.get_bool("stream") {
+                match node.stream(user_message.as_bytes())? {
+                    0 => say!("No members connected to stream to"),
+                    1 => say!("Streamed to 1 member"),
+                    peers => say!("Streamed to {} members", peers),
+                }
+            } else if args.get_bool("inspect") {
+                let inspection = node.inspect_message(user_message)?;
+                say!(
+                    "plaintext {} bytes -> ciphertext {} bytes ({} bytes padding, block size {}), epoch {}, generation {}",
+                    inspection.plaintext_size,
+                    inspection.ciphertext_size,
+                    inspection.padding_applied,
+                    inspection.padding_block,
+                    inspection.epoch,
+                    inspection.generation
+                );
+            } else if args.get_bool("broadcast") {
+                for (group, outcome) in node.broadcast(user_message)? {
+                    match outcome {
+                        Ok(SendOutcome::Sent { health, .. } | SendOutcome::Queued { health })
+                            if !health.is_healthy() =>
+                        {
+                            say!("{}: sending anyway: {}", group, health)
+                        }
+                        Ok(SendOutcome::Sent { .. } | SendOutcome::Queued { .. }) => {
+                            say!("{}: sending", group)
+                        }
+                        Ok(SendOutcome::Pending { health, .. }) if !health.is_healthy() => {
+                            say!("{}: encrypting, sending anyway: {}", group, health)
+                        }
+                        Ok(SendOutcome::Pending { .. }) => say!("{}: encrypting", group),
+                        Ok(SendOutcome::Held(health)) => say!(
+                            "{}: holding the message until the group is healthy: {}",
+                            group,
+                            health
+                        ),
+                        Err(e) => say!("{}: {}", group, e),
+                    }
+                }
+            } else if !user_message.is_empty() {
+                let group = Some(group).filter(|group| !group.is_empty());
+                match node.send_text(group, user_message)? {
+                    SendOutcome::Sent { message, health } => {
+                        if !health.is_healthy() {
+                            say!("Sending anyway: {}", health);
+                        }
+                        // Shown once the network event loop sends it, see `outbox`.
+                        msg = WireMessage::from(message).encode()?;
+                    }
+                    // The encryption worker hands it on, see `run_encryptions`.
+                    SendOutcome::Pending { health, .. } | SendOutcome::Queued { health }
+                        if !health.is_healthy() =>
+                    {
+                        say!("Sending anyway: {}", health)
+                    }
+                    SendOutcome::Pending { .. } | SendOutcome::Queued { .. } => {}
+                    SendOutcome::Held(health) => {
+                        say!("Holding the message until the group is healthy: {}", health)
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            say!("{}", commands::explain_error(&line));
+        }
+    }
+    Ok(msg)
+}
+
+// Parses a typed line against the usage built from `COMMANDS`, after
+// expanding aliases.
+fn parse_args(line: &str) -> Result<ArgvMap, docopt::Error> {
+    let line = commands::expand_aliases(line);
+    Docopt::new(commands::usage()).and_then(|d| d.argv(line.split(' ')).parse())
+}
+
+// A connected peer by full PeerId or the end of it, as names show it.
+// Resolves the end of a peer id against the peers we are connected to,
+// have discovered, or share the active group with.
+fn find_peer(node: &Node, peer: &str) -> Result<PeerId, NodeError> {
+    if let Ok(peer) = peer.parse() {
+        return Ok(peer);
+    }
+    let members = node.list_members().unwrap_or_default();
+    let known = node
+        .peers()
+        .peer_ids()
+        .chain(node.peers().discovered_peers())
+        .copied()
+        .chain(members.iter().filter_map(|member| member.peer_id));
+    match_peer(known, peer, || {
+        format!(
+            "No known peer {}, see `node peers` and `node members`, or give the full peer id",
+            peer
+        )
+    })
+}
+
+// The latest waiting join request of the peer `peer` names, matched like
+// in `find_peer` among the peers with requests waiting.
+fn pending_join_from(node: &Node, peer: &str) -> Result<(PeerId, u64), NodeError> {
+    let latest: BTreeMap<PeerId, u64> = node
+        .pending_joins()
+        .map(|pending| (pending.peer, pending.id))
+        .collect();
+    let found = match_peer(latest.keys().copied(), peer, || {
+        format!("No join request from {}, see `node pending`", peer)
+    })?;
+    Ok((found, latest[&found]))
+}
+
+// Like `find_peer`, among the peers we blocked, who are not connected.
+fn find_blocked(node: &Node, peer: &str) -> Result<PeerId, NodeError> {
+    match_peer(node.peers().blocked().copied(), peer, || {
+        format!("No blocked peer {}, see `node peers`", peer)
+    })
+}
+
+// The one peer among `candidates` whose id ends with `peer`, or the error
+// `missing` makes when there is none.
+fn match_peer(
+    candidates: impl Iterator<Item = PeerId>,
+    peer: &str,
+    missing: impl FnOnce() -> String,
+) -> Result<PeerId, NodeError> {
+    let matching: BTreeSet<PeerId> = candidates
+        .filter(|id| id.to_string().ends_with(peer))
+        .collect();
+    match matching.iter().collect::<Vec<_>>()[..] {
+        [peer] => Ok(*peer),
+        [] => Err(NodeError::Other(missing())),
+        _ => Err(NodeError::Other(format!(
+            "{} matches several peers, give more of it",
+            peer
+        ))),
+    }
+}
+
+fn request_ids(requests: &[&str]) -> Result<Vec<u64>, NodeError> {
+    requests
+        .iter()
+        .map(|request| {
+            request.trim_start_matches('#').parse().map_err(|_| {
+                NodeError::Other("<request> must be a number from `node requests`".to_string())
+            })
+        })
+        .collect()
+}
+
+fn group_psk(node: &Node) -> Result<GroupPsk, NodeError> {
+    let passphrase = std::env::var("P2P_MLS_GROUP_PSK").map_err(|_| {
+        NodeError::Other("Set P2P_MLS_GROUP_PSK to the passphrase shared with members".to_string())
+    })?;
+    node.psk_from_passphrase(&passphrase, DEFAULT_ITERATIONS)
+}
+
+// Writes next to `out` and renames into place, so the group is only dropped
+// once its archive is whole on disk.
+fn write_archive(out: &str, sealed: &[u8]) -> std::io::Result<()> {
+    let partial = format!("{}.partial", out);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial)?;
+    file.write_all(sealed)?;
+    file.sync_all()?;
+    std::fs::rename(&partial, out)
+}
+
+fn archive_passphrase() -> Result<String, NodeError> {
+    std::env::var("P2P_MLS_ARCHIVE_PASSPHRASE").map_err(|_| {
+        NodeError::Other("Set P2P_MLS_ARCHIVE_PASSPHRASE to archive or open archives".to_string())
+    })
+}
+
+// Walks through split-brain recovery: probe the group, read the report once
+// members have answered, and rejoin if we ended up off the canonical branch.
+fn recover(node: &mut Node, report: bool, rejoin: bool) -> Result<Message, NodeError> {
+    if rejoin {
+        say!("Leaving our branch and asking the leader to re-admit us.");
+        return WireMessage::from(node.rejoin()?).encode();
+    }
+    if report {
+        say!("{}", node.recovery_report()?);
+        return Ok(Vec::new());
+    }
+    let probe = node.start_recovery()?;
+    say!("Asked members for their state, run `node recover --report` once they answered.");
+    WireMessage::from(probe).encode()
+}
+
+// Adds every manifest member in one commit. The Welcome is written to disk when the
+// manifest names an output directory, otherwise it is handed back for publishing.
+fn bootstrap_from_manifest(node: &mut Node, path: &Path) -> Result<Message, NodeError> {
+    let manifest = Manifest::load(path)?;
+    let key_packages = manifest.key_packages()?;
+    if key_packages.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (_, invite) = node.add_members_to_group(&key_packages)?;
+    let written = manifest.write_welcomes(&key_packages, &invite)?;
+    if written.is_empty() {
+        say!("Added {} members, publishing welcome.", key_packages.len());
+        return WireMessage::from(invite).encode();
+    }
+    for path in written {
+        say!("Wrote welcome to {}", path.display());
+    }
+    Ok(Vec::new())
+}
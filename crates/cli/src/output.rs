@@ -0,0 +1,95 @@
+//! Where the client's output goes: printed to the terminal, or into the
+//! TUI while it runs, see `tui`.
+//!
+//! Command replies and network events are [`Output::Line`]s. What
+//! `Node::handle_incoming` reports comes as messages and notices of the
+//! group it happened in, so the TUI can show each in its group's tab.
+
+use std::fmt::Display;
+use std::sync::mpsc::Sender;
+use std::sync::OnceLock;
+
+use colored::Colorize;
+
+static REDIRECT: OnceLock<Sender<Output>> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Line(String),
+    /// A message `sender` sent, or we did as "me". `group` is given when we
+    /// are in several, as it is printed then.
+    Message {
+        group: Option<String>,
+        sender: String,
+        text: String,
+    },
+    /// Something that happened in `group`, such as a member joining.
+    Notice {
+        group: String,
+        text: String,
+    },
+    /// Our groups and the active group's members, for the TUI's tabs and
+    /// sidebar; only sent while redirected.
+    Roster {
+        groups: Vec<String>,
+        active: Option<String>,
+        members: Vec<String>,
+    },
+    /// The prompt, see `prompt`; only sent while redirected.
+    Prompt(String),
+    /// `sender` is typing in `group`, see `typing`; only sent while
+    /// redirected, and never kept.
+    Typing {
+        group: String,
+        sender: String,
+    },
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::Line(text) | Output::Notice { text, .. } => write!(f, "{}", text),
+            Output::Message {
+                group: None,
+                sender,
+                text,
+            } => write!(f, "{}:{}", sender.red(), text.blue()),
+            Output::Message {
+                group: Some(group),
+                sender,
+                text,
+            } => write!(
+                f,
+                "{}:{}",
+                format!("{}@{}", sender, group).red(),
+                text.blue()
+            ),
+            Output::Roster { .. } | Output::Prompt(_) | Output::Typing { .. } => Ok(()),
+        }
+    }
+}
+
+/// Sends all further output to `sink` instead of the terminal.
+pub fn redirect(sink: Sender<Output>) {
+    REDIRECT.set(sink).ok();
+}
+
+pub fn is_redirected() -> bool {
+    REDIRECT.get().is_some()
+}
+
+pub fn show(output: Output) {
+    match REDIRECT.get() {
+        // Output after the TUI quit has nowhere to go.
+        Some(sink) => sink.send(output).unwrap_or_default(),
+        None => println!("{}", output),
+    }
+}
+
+/// `println!`, through [`show`].
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::output::show($crate::output::Output::Line(format!($($arg)*)))
+    };
+}
@@ -0,0 +1,116 @@
+//! The interactive prompt, rendered from the node's security state.
+//!
+//! The prompt is redrawn after every command so that a channel sliding into
+//! a risky state (unverified members, failing decryption, a forked epoch) is
+//! visible where the user is typing. Formatters are pluggable through
+//! [`PromptFormatter`].
+
+use colored::Colorize;
+
+pub use p2p_mls_core::security::SecurityState;
+
+pub trait PromptFormatter: Send {
+    fn format(&self, state: &SecurityState) -> String;
+}
+
+fn describe(state: &SecurityState) -> String {
+    let epoch = match state.epoch {
+        Some(epoch) => epoch,
+        None => return "[no group]".to_string(),
+    };
+    let mut prompt = format!(
+        "[epoch {} | {}/{} verified",
+        epoch, state.verified_members, state.members
+    );
+    if state.policy.non_repudiation {
+        prompt.push_str(" | signed");
+    }
+    if state.policy.max_privacy {
+        prompt.push_str(" | private");
+    }
+    if state.desynced {
+        prompt.push_str(" | DESYNCED");
+    } else if state.degraded {
+        prompt.push_str(" | DEGRADED");
+    }
+    prompt.push(']');
+    prompt
+}
+
+/// Green when every member is verified and traffic is healthy, yellow with
+/// unverified members, red when degraded or desynced.
+#[derive(Debug, Default)]
+pub struct ColoredPrompt;
+
+impl PromptFormatter for ColoredPrompt {
+    fn format(&self, state: &SecurityState) -> String {
+        let prompt = format!("{} >", describe(state));
+        if state.epoch.is_none() {
+            prompt.normal().to_string()
+        } else if state.degraded || state.desynced {
+            prompt.red().bold().to_string()
+        } else if state.verified_members < state.members {
+            prompt.yellow().to_string()
+        } else {
+            prompt.green().to_string()
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PlainPrompt;
+
+impl PromptFormatter for PlainPrompt {
+    fn format(&self, state: &SecurityState) -> String {
+        format!("{} >", describe(state))
+    }
+}
+
+/// Prints nothing, for scripted use.
+#[derive(Debug, Default)]
+pub struct NoPrompt;
+
+impl PromptFormatter for NoPrompt {
+    fn format(&self, _state: &SecurityState) -> String {
+        String::new()
+    }
+}
+
+pub fn formatter(style: &str) -> Option<Box<dyn PromptFormatter>> {
+    match style {
+        "color" => Some(Box::new(ColoredPrompt)),
+        "plain" => Some(Box::new(PlainPrompt)),
+        "none" => Some(Box::new(NoPrompt)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p2p_mls_core::node::Node;
+
+    #[test]
+    fn prompt_tracks_verification() {
+        let mut alice = Node::default();
+        let bob = Node::default();
+        assert_eq!(PlainPrompt.format(&alice.security_state()), "[no group] >");
+
+        alice.join_new_group();
+        alice.add_member_to_group(bob.get_key_package()).unwrap();
+        assert_eq!(
+            PlainPrompt.format(&alice.security_state()),
+            "[epoch 1 | 1/2 verified] >"
+        );
+
+        let identity =
+            p2p_mls_core::crypto::credential_identity(bob.get_key_package().credential());
+        assert!(alice.verify_member(&identity, "0000").is_err());
+        alice
+            .verify_member(&identity, &bob.fingerprint().to_string())
+            .unwrap();
+        let state = alice.security_state();
+        assert_eq!(state.verified_percent(), 100);
+        assert_eq!(PlainPrompt.format(&state), "[epoch 1 | 2/2 verified] >");
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+use p2p_mls_core::error::NodeError;
+
+/// A typed command waiting for the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedCommand {
+    pub id: u64,
+    pub line: String,
+    /// Whether we told the user it was queued, so they hear when it starts.
+    pub announced: bool,
+}
+
+/// Commands typed while the node is busy, run one at a time in order.
+#[derive(Debug, Default)]
+pub struct CommandQueue {
+    next_id: u64,
+    pending: VecDeque<QueuedCommand>,
+    running: Option<QueuedCommand>,
+}
+
+impl CommandQueue {
+    /// Queues `line` and returns it, marked announced when it has to wait
+    /// behind other commands or a `node_busy` node.
+    pub fn push(&mut self, line: String, node_busy: bool) -> QueuedCommand {
+        self.next_id += 1;
+        let command = QueuedCommand {
+            id: self.next_id,
+            line,
+            announced: node_busy || self.ahead() > 0,
+        };
+        self.pending.push_back(command.clone());
+        command
+    }
+
+    /// How many commands run before one pushed now.
+    pub fn ahead(&self) -> usize {
+        self.pending.len() + usize::from(self.running.is_some())
+    }
+
+    /// Takes the next command to run.
+    pub fn start_next(&mut self) -> Option<QueuedCommand> {
+        self.running = self.pending.pop_front();
+        self.running.clone()
+    }
+
+    pub fn finish(&mut self) {
+        self.running = None;
+    }
+
+    /// Drops command `id` if it has not started.
+    pub fn cancel(&mut self, id: u64) -> Result<QueuedCommand, NodeError> {
+        if self
+            .running
+            .as_ref()
+            .is_some_and(|command| command.id == id)
+        {
+            return Err(NodeError::Other(format!("#{} is already processing", id)));
+        }
+        let index = self
+            .pending
+            .iter()
+            .position(|command| command.id == id)
+            .ok_or_else(|| NodeError::Other(format!("#{} is not queued", id)))?;
+        Ok(self.pending.remove(index).expect("index is in range"))
+    }
+}
+
+impl Display for CommandQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let running = self.running.iter().map(|c| (c, "processing"));
+        let pending = self.pending.iter().map(|c| (c, "queued"));
+        let lines: Vec<String> = running
+            .chain(pending)
+            .map(|(command, state)| format!("#{} {}: {}", command.id, state, command.line))
+            .collect();
+        if lines.is_empty() {
+            return write!(f, "no commands queued");
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_commands_can_be_cancelled_until_they_start() {
+        let mut queue = CommandQueue::default();
+        let first = queue.push("node create".to_string(), false);
+        assert!(!first.announced);
+        let second = queue.push("node send hi".to_string(), false);
+        assert!(second.announced);
+        let third = queue.push("node send bye".to_string(), false);
+
+        assert_eq!(queue.start_next().unwrap().id, first.id);
+        assert!(queue.cancel(first.id).is_err());
+        assert_eq!(queue.cancel(third.id).unwrap().line, "node send bye");
+        assert!(queue.cancel(third.id).is_err());
+        assert_eq!(queue.ahead(), 2);
+        queue.finish();
+
+        assert_eq!(queue.start_next().unwrap().id, second.id);
+        queue.finish();
+        assert!(queue.start_next().is_none());
+        assert_eq!(queue.to_string(), "no commands queued");
+    }
+}
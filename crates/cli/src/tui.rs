@@ -0,0 +1,403 @@
+//! The `--tui` terminal interface: a tab per group over a scrollback pane,
+//! the active group's members in a sidebar and an input box.
+//!
+//! It runs on its own thread and owns the terminal. Everything the client
+//! would print arrives as [`Output`], see `output`, and what is typed goes
+//! back as lines, the same commands as on stdin. Switching tabs runs
+//! `node use` for the tab's group, so commands act on the group shown.
+//! Typing runs `node typing` now and then, and members typing in the group
+//! shown are named above the input box until they stop.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use async_std::channel::Sender;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, Paragraph, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+
+use p2p_mls_core::typing::{TYPING_INTERVAL, TYPING_TIMEOUT};
+
+use crate::output::{self, Output};
+
+/// Entries kept for scrolling back, across all tabs.
+const SCROLLBACK: usize = 2000;
+
+/// How long to wait for a key before drawing output that arrived.
+const TICK: Duration = Duration::from_millis(50);
+
+/// Takes over the terminal and sends every further [`Output`] there. Lines
+/// typed are sent to `lines`, which closes when the user quits with Esc or
+/// Ctrl-C.
+pub fn spawn(lines: Sender<String>) -> io::Result<JoinHandle<()>> {
+    let (sink, outputs) = mpsc::channel();
+    let terminal = ratatui::try_init()?;
+    // Colors are drawn by the TUI, not as escape codes in the text.
+    colored::control::set_override(false);
+    output::redirect(sink);
+    Ok(std::thread::spawn(move || {
+        let result = run(terminal, outputs, lines);
+        ratatui::restore();
+        if let Err(e) = result {
+            eprintln!("TUI failed: {}", e);
+        }
+    }))
+}
+
+/// Gives the terminal back, for when the client exits before the TUI does.
+pub fn restore() {
+    ratatui::restore();
+}
+
+fn run(
+    mut terminal: DefaultTerminal,
+    outputs: Receiver<Output>,
+    lines: Sender<String>,
+) -> io::Result<()> {
+    let mut app = App::default();
+    loop {
+        loop {
+            match outputs.try_recv() {
+                Ok(output) => app.push(output),
+                Err(TryRecvError::Empty) => break,
+                // The client is gone.
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        terminal.draw(|frame| app.draw(frame))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        let line = match key.code {
+            KeyCode::Esc => return Ok(()),
+            KeyCode::Char('c' | 'd') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(())
+            }
+            KeyCode::Enter => Some(std::mem::take(&mut app.input)),
+            KeyCode::Tab => app.select(1),
+            KeyCode::BackTab => app.select(-1),
+            KeyCode::PageUp => {
+                app.scroll += 10;
+                None
+            }
+            KeyCode::PageDown => {
+                app.scroll = app.scroll.saturating_sub(10);
+                None
+            }
+            KeyCode::Backspace => {
+                app.input.pop();
+                None
+            }
+            KeyCode::Char(c) => {
+                app.input.push(c);
+                app.typed(Instant::now())
+            }
+            _ => None,
+        };
+        if let Some(line) = line.filter(|line| !line.trim().is_empty()) {
+            if lines.send_blocking(line).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// An entry of the scrollback and the group whose tab shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    group: Option<String>,
+    output: Output,
+}
+
+#[derive(Debug, Default)]
+struct App {
+    entries: Vec<Entry>,
+    groups: Vec<String>,
+    active: Option<String>,
+    members: Vec<String>,
+    prompt: String,
+    /// 0 is the tab showing everything, then one per group.
+    tab: usize,
+    /// Lines scrolled up from the newest.
+    scroll: usize,
+    input: String,
+    /// Who is typing in which group, and until when.
+    typing: Vec<(String, String, Instant)>,
+    /// When we last ran `node typing`.
+    typing_sent: Option<Instant>,
+}
+
+impl App {
+    fn push(&mut self, output: Output) {
+        let group = match &output {
+            Output::Roster {
+                groups,
+                active,
+                members,
+            } => {
+                let shown = self.shown_group().map(str::to_string);
+                self.groups = groups.clone();
+                self.active = active.clone();
+                self.members = members.clone();
+                // Stay on the group we were looking at if it is still there.
+                self.tab = shown
+                    .and_then(|shown| self.groups.iter().position(|group| *group == shown))
+                    .map_or(0, |index| index + 1);
+                return;
+            }
+            Output::Prompt(prompt) => {
+                self.prompt = prompt.clone();
+                return;
+            }
+            Output::Typing { group, sender } => {
+                self.typing
+                    .retain(|(typing_in, typist, _)| (typing_in, typist) != (group, sender));
+                self.typing.push((
+                    group.clone(),
+                    sender.clone(),
+                    Instant::now() + TYPING_TIMEOUT,
+                ));
+                return;
+            }
+            Output::Message {
+                group: Some(group), ..
+            }
+            | Output::Notice { group, .. } => Some(group.clone()),
+            // Only printed without a group when we are in one.
+            Output::Message { group: None, .. } => self.active.clone(),
+            // Replies to commands, shown where they were typed.
+            Output::Line(_) => self.shown_group().map(str::to_string),
+        };
+        // Whoever sent a message is done typing it.
+        if let (Some(group), Output::Message { sender, .. }) = (&group, &output) {
+            self.typing
+                .retain(|(typing_in, typist, _)| (typing_in, typist) != (group, sender));
+        }
+        self.entries.push(Entry { group, output });
+        if self.entries.len() > SCROLLBACK {
+            self.entries.drain(..self.entries.len() - SCROLLBACK);
+        }
+    }
+
+    fn shown_group(&self) -> Option<&str> {
+        match self.tab {
+            0 => None,
+            tab => self.groups.get(tab - 1).map(String::as_str),
+        }
+    }
+
+    /// The `node typing` to run after a key typed at `now`, at most once per
+    /// `TYPING_INTERVAL`; the node holds back more itself.
+    fn typed(&mut self, now: Instant) -> Option<String> {
+        if self
+            .typing_sent
+            .is_some_and(|sent| now.saturating_duration_since(sent) < TYPING_INTERVAL)
+        {
+            return None;
+        }
+        self.typing_sent = Some(now);
+        Some("node typing".to_string())
+    }
+
+    /// Who is typing in the group shown, or in any group on the first tab,
+    /// at `now`.
+    fn typing_line(&self, now: Instant) -> Option<String> {
+        let shown = self.shown_group();
+        let typists: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|(group, _, until)| {
+                *until > now && (shown.is_none() || shown == Some(group.as_str()))
+            })
+            .map(|(_, typist, _)| typist.as_str())
+            .collect();
+        match typists.as_slice() {
+            [] => None,
+            [typist] => Some(format!("{} is typing…", typist)),
+            typists => Some(format!("{} are typing…", typists.join(", "))),
+        }
+    }
+
+    /// Moves `by` tabs along, returning the `node use` that makes the new
+    /// tab's group active.
+    fn select(&mut self, by: isize) -> Option<String> {
+        let tabs = self.groups.len() + 1;
+        self.tab = (self.tab as isize + by).rem_euclid(tabs as isize) as usize;
+        self.scroll = 0;
+        self.shown_group()
+            .filter(|group| self.active.as_deref() != Some(*group))
+            .map(|group| format!("node use {}", group))
+    }
+
+    /// The lines of the shown tab's entries, oldest first.
+    fn lines(&self) -> Vec<Line<'_>> {
+        let shown = self.shown_group();
+        self.entries
+            .iter()
+            .filter(|entry| shown.is_none() || entry.group.as_deref() == shown)
+            .flat_map(|entry| match &entry.output {
+                Output::Message {
+                    group,
+                    sender,
+                    text,
+                } => {
+                    let sender = match (group, shown) {
+                        (Some(group), None) => format!("{}@{}", sender, group),
+                        _ => sender.clone(),
+                    };
+                    vec![Line::from(vec![
+                        Span::styled(sender, Style::default().fg(Color::Red)),
+                        Span::raw(":"),
+                        Span::styled(text.as_str(), Style::default().fg(Color::Blue)),
+                    ])]
+                }
+                Output::Line(text) | Output::Notice { text, .. } => {
+                    text.lines().map(Line::raw).collect()
+                }
+                Output::Roster { .. } | Output::Prompt(_) | Output::Typing { .. } => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tabs, body, input] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let [scrollback, sidebar] =
+            Layout::horizontal([Constraint::Min(20), Constraint::Length(28)]).areas(body);
+
+        let titles = std::iter::once("all".to_string()).chain(self.groups.iter().map(|group| {
+            match self.active.as_deref() == Some(group) {
+                true => format!("{}*", group),
+                false => group.clone(),
+            }
+        }));
+        frame.render_widget(
+            Tabs::new(titles)
+                .select(self.tab)
+                .block(Block::default().borders(Borders::ALL).title("groups (Tab)"))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            tabs,
+        );
+
+        let lines = self.lines();
+        let height = scrollback.height.saturating_sub(2) as usize;
+        let end = lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(height);
+        frame.render_widget(
+            Paragraph::new(lines[start..end].to_vec())
+                .block(Block::default().borders(Borders::ALL)),
+            scrollback,
+        );
+
+        let title = match &self.active {
+            Some(group) => format!("members of {}", group),
+            None => "no group".to_string(),
+        };
+        frame.render_widget(
+            List::new(self.members.iter().map(String::as_str))
+                .block(Block::default().borders(Borders::ALL).title(title)),
+            sidebar,
+        );
+
+        let title = match self.typing_line(Instant::now()) {
+            Some(typing) if self.prompt.is_empty() => typing,
+            Some(typing) => format!("{} · {}", self.prompt, typing),
+            None => self.prompt.clone(),
+        };
+        frame.render_widget(
+            Paragraph::new(self.input.as_str())
+                .block(Block::default().borders(Borders::ALL).title(title)),
+            input,
+        );
+        frame.set_cursor_position((input.x + 1 + self.input.chars().count() as u16, input.y + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roster(groups: &[&str], active: &str) -> Output {
+        Output::Roster {
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            active: Some(active.to_string()),
+            members: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn entries_go_to_their_group_tab() {
+        let mut app = App::default();
+        app.push(roster(&["home", "work"], "home"));
+        app.push(Output::Message {
+            group: Some("work".to_string()),
+            sender: "bob".to_string(),
+            text: "hi".to_string(),
+        });
+        app.push(Output::Notice {
+            group: "home".to_string(),
+            text: "carol was removed from home".to_string(),
+        });
+        assert_eq!(app.lines().len(), 2);
+
+        assert_eq!(app.select(2), Some("node use work".to_string()));
+        app.push(Output::Line("Commands now act on group work.".to_string()));
+        app.push(roster(&["home", "work"], "work"));
+        assert_eq!(app.shown_group(), Some("work"));
+        assert_eq!(app.lines().len(), 2);
+        assert_eq!(app.lines()[0].to_string(), "bob:hi");
+
+        // The group we looked at stays shown when another one appears.
+        app.push(roster(&["abc", "home", "work"], "work"));
+        assert_eq!(app.shown_group(), Some("work"));
+        assert_eq!(app.select(1), None);
+        assert_eq!(app.lines().len(), 3);
+    }
+
+    #[test]
+    fn typists_are_shown_until_they_stop_or_send() {
+        let mut app = App::default();
+        app.push(roster(&["home", "work"], "home"));
+        let now = Instant::now();
+        assert_eq!(app.typed(now), Some("node typing".to_string()));
+        assert_eq!(app.typed(now + Duration::from_secs(1)), None);
+        assert!(app.typed(now + TYPING_INTERVAL).is_some());
+
+        for (group, sender) in [("home", "bob"), ("work", "carol")] {
+            app.push(Output::Typing {
+                group: group.to_string(),
+                sender: sender.to_string(),
+            });
+        }
+        assert_eq!(
+            app.typing_line(Instant::now()),
+            Some("bob, carol are typing…".to_string())
+        );
+        app.select(1);
+        assert_eq!(
+            app.typing_line(Instant::now()),
+            Some("bob is typing…".to_string())
+        );
+        assert_eq!(app.typing_line(Instant::now() + TYPING_TIMEOUT), None);
+        app.push(Output::Message {
+            group: Some("home".to_string()),
+            sender: "bob".to_string(),
+            text: "hi".to_string(),
+        });
+        assert_eq!(app.typing_line(Instant::now()), None);
+        assert!(app.lines().len() == 1);
+    }
+}
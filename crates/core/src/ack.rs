@@ -0,0 +1,402 @@
+//! Delivery acknowledgments for text messages.
+//!
+//! Publishing a frame tells us how many peers floodsub handed it to, not
+//! whether any member could read it. Text we send therefore goes out as
+//! `0xF6 | id: 8 bytes | text`, and every member that decrypts it answers
+//! in the group with an encrypted `0xF5 | id`. The sender counts who
+//! answered in its [`Deliveries`] and, after the ack timeout, sends the
+//! text again with the same id to whoever did not, up to
+//! [`MAX_ATTEMPTS`] times. Receivers ack every copy but show only the
+//! first, see [`Deliveries::first_receipt`]. Like the other markers, both
+//! bytes can never start UTF-8 text.
+//!
+//! Members that opted in to read receipts also answer `0xF0 | id` once
+//! the application has shown the text, see `Node::mark_read`, and the
+//! sender notes who read it next to who acked it. Groups under the maximum
+//! privacy policy send and count none.
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use openmls::prelude::OpenMlsCryptoProvider;
+use openmls_traits::random::OpenMlsRand;
+
+use crate::{
+    crypto::{hex_decode, hex_encode},
+    error::NodeError,
+};
+
+const TRACKED: u8 = 0xF6;
+const ACK: u8 = 0xF5;
+const READ: u8 = 0xF0;
+const ID_LEN: usize = 8;
+
+/// Messages followed, oldest forgotten first.
+pub const DELIVERIES_LEN: usize = 256;
+/// Ids of messages we received, to show a resent one only once.
+const SEEN_LEN: usize = 1024;
+/// How often a message is sent in all, the first time included.
+pub const MAX_ATTEMPTS: u32 = 4;
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId([u8; ID_LEN]);
+
+impl MessageId {
+    pub fn generate(backend: &impl OpenMlsCryptoProvider) -> Result<MessageId, NodeError> {
+        let bytes = backend
+            .rand()
+            .random_array()
+            .map_err(|e| NodeError::Crypto(format!("Could not generate message id: {:?}", e)))?;
+        Ok(MessageId(bytes))
+    }
+}
+
+impl Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex_encode(&self.0))
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<MessageId, NodeError> {
+        hex_decode(s)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(MessageId)
+            .ok_or_else(|| NodeError::Parse(format!("{} is not a message id", s)))
+    }
+}
+
+/// `text` as sent to be acknowledged.
+pub fn tracked(id: MessageId, text: &str) -> Vec<u8> {
+    let mut bytes = vec![TRACKED];
+    bytes.extend_from_slice(&id.0);
+    bytes.extend_from_slice(text.as_bytes());
+    bytes
+}
+
+pub fn is_tracked(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&TRACKED)
+}
+
+/// Splits a tracked message into its id and text.
+pub fn untrack(bytes: &[u8]) -> Result<(MessageId, &[u8]), NodeError> {
+    match bytes {
+        [TRACKED, rest @ ..] if rest.len() >= ID_LEN => {
+            let (id, text) = rest.split_at(ID_LEN);
+            Ok((MessageId(id.try_into().expect("eight bytes")), text))
+        }
+        _ => Err(NodeError::Parse("Malformed tracked message".to_string())),
+    }
+}
+
+/// The text in `bytes`, without its id if it was tracked; for signed
+/// payloads kept as evidence, see `audit`.
+pub fn text_of(bytes: &[u8]) -> &[u8] {
+    untrack(bytes).map_or(bytes, |(_, text)| text)
+}
+
+pub fn ack(id: MessageId) -> Vec<u8> {
+    let mut bytes = vec![ACK];
+    bytes.extend_from_slice(&id.0);
+    bytes
+}
+
+pub fn is_ack(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&ACK)
+}
+
+pub fn decode_ack(bytes: &[u8]) -> Result<MessageId, NodeError> {
+    match bytes {
+        [ACK, id @ ..] if id.len() == ID_LEN => Ok(MessageId(id.try_into().expect("eight bytes"))),
+        _ => Err(NodeError::Parse("Malformed acknowledgment".to_string())),
+    }
+}
+
+pub fn read(id: MessageId) -> Vec<u8> {
+    let mut bytes = vec![READ];
+    bytes.extend_from_slice(&id.0);
+    bytes
+}
+
+pub fn is_read(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&READ)
+}
+
+pub fn decode_read(bytes: &[u8]) -> Result<MessageId, NodeError> {
+    match bytes {
+        [READ, id @ ..] if id.len() == ID_LEN => Ok(MessageId(id.try_into().expect("eight bytes"))),
+        _ => Err(NodeError::Parse("Malformed read receipt".to_string())),
+    }
+}
+
+/// One text message we sent and who has acknowledged it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub id: MessageId,
+    pub group_id: Vec<u8>,
+    /// The message's outbox sequence number in its group.
+    pub seq: u64,
+    pub text: String,
+    /// The other members when it was first sent.
+    pub recipients: BTreeSet<String>,
+    pub acked: BTreeSet<String>,
+    /// Members whose application showed it, see `Node::mark_read`.
+    pub read: BTreeSet<String>,
+    pub attempts: u32,
+    pub sent_at: Instant,
+}
+
+impl Delivery {
+    /// Recipients that have not acknowledged it yet.
+    pub fn waiting(&self) -> impl Iterator<Item = &String> {
+        self.recipients.difference(&self.acked)
+    }
+
+    pub fn is_delivered(&self) -> bool {
+        self.waiting().next().is_none()
+    }
+}
+
+impl Display for Delivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: acknowledged by {} of {}",
+            self.id,
+            self.acked.intersection(&self.recipients).count(),
+            self.recipients.len()
+        )?;
+        let waiting: Vec<&str> = self.waiting().map(String::as_str).collect();
+        if !waiting.is_empty() {
+            write!(f, ", waiting for {}", waiting.join(", "))?;
+        }
+        if !self.read.is_empty() {
+            write!(f, ", read by {}", self.read.len())?;
+        }
+        match self.attempts {
+            1 => write!(f, ", sent once"),
+            attempts => write!(f, ", sent {} times", attempts),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Deliveries {
+    sent: VecDeque<Delivery>,
+    seen: HashSet<MessageId>,
+    seen_order: VecDeque<MessageId>,
+    timeout: Duration,
+}
+
+impl Default for Deliveries {
+    fn default() -> Deliveries {
+        Deliveries {
+            sent: VecDeque::new(),
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            timeout: DEFAULT_ACK_TIMEOUT,
+        }
+    }
+}
+
+impl Deliveries {
+    /// How long to wait for acks before sending again; zero never resends.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn track(&mut self, delivery: Delivery) {
+        if self.sent.len() >= DELIVERIES_LEN {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(delivery);
+    }
+
+    /// Counts `identity`'s ack of `id`; acks of messages we forgot, or did
+    /// not send, are ignored. Returns whether it was news.
+    pub fn acked(&mut self, id: MessageId, identity: String) -> bool {
+        match self.sent.iter_mut().find(|delivery| delivery.id == id) {
+            Some(delivery) => delivery.acked.insert(identity),
+            None => false,
+        }
+    }
+
+    /// Notes that `identity` read `id`, as `acked` does; reading implies
+    /// receiving, so it counts as an ack too.
+    pub fn read(&mut self, id: MessageId, identity: String) -> Option<&Delivery> {
+        let delivery = self.sent.iter_mut().find(|delivery| delivery.id == id)?;
+        delivery.acked.insert(identity.clone());
+        delivery.read.insert(identity).then_some(&*delivery)
+    }
+
+    /// Whether `id` is a message we have not received before.
+    pub fn first_receipt(&mut self, id: MessageId) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        if self.seen_order.len() >= SEEN_LEN {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen_order.push_back(id);
+        true
+    }
+
+    /// Ids of the messages to send again at `now`, counting the attempt.
+    pub fn due(&mut self, now: Instant) -> Vec<MessageId> {
+        if self.timeout.is_zero() {
+            return Vec::new();
+        }
+        let timeout = self.timeout;
+        self.sent
+            .iter_mut()
+            .filter(|delivery| {
+                !delivery.is_delivered()
+                    && delivery.attempts < MAX_ATTEMPTS
+                    && now.saturating_duration_since(delivery.sent_at) >= timeout
+            })
+            .map(|delivery| {
+                delivery.attempts += 1;
+                delivery.sent_at = now;
+                delivery.id
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: MessageId) -> Option<&Delivery> {
+        self.sent.iter().find(|delivery| delivery.id == id)
+    }
+
+    /// The message `seq` of `group_id` in the outbox, while followed.
+    pub fn for_send(&self, group_id: &[u8], seq: u64) -> Option<&Delivery> {
+        self.sent
+            .iter()
+            .find(|delivery| delivery.seq == seq && delivery.group_id == group_id)
+    }
+
+    /// Messages followed, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Delivery> {
+        self.sent.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::credential_identity;
+    use crate::events::NodeEvent;
+    use crate::node::{ApplicationPayload, Node};
+    use crate::policy::GroupPolicy;
+    use crate::protocol::WireMessage;
+
+    fn only_frame(node: &mut Node) -> openmls::prelude::MlsMessageOut {
+        match &node.take_outgoing()[..] {
+            [WireMessage::MlsMessage(msg_out)] => msg_out.clone(),
+            frames => panic!("expected one message, got {:?}", frames),
+        }
+    }
+
+    #[test]
+    fn messages_are_acknowledged_and_resent_until_they_are() {
+        let (mut alice, mut bob, mut carol) = (Node::default(), Node::default(), Node::default());
+        alice.join_new_group();
+        let (_, invite) = alice
+            .add_members_to_group(&[bob.get_key_package(), carol.get_key_package()])
+            .unwrap();
+        bob.join_existing_group(invite.clone()).unwrap();
+        carol.join_existing_group(invite).unwrap();
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+
+        // Carol misses the first copy.
+        let msg_out = alice.create_message("hello").unwrap();
+        let id = alice.deliveries().iter().next().unwrap().id;
+        assert_eq!(id.to_string().parse::<MessageId>().unwrap(), id);
+        assert_eq!(
+            bob.parse_application_message(msg_out).unwrap(),
+            Some(ApplicationPayload::Text("hello".to_string()))
+        );
+        assert_eq!(
+            alice
+                .parse_application_message(only_frame(&mut bob))
+                .unwrap(),
+            None
+        );
+        let delivery = alice.delivery(id).unwrap();
+        assert_eq!(
+            delivery.to_string(),
+            format!(
+                "{}: acknowledged by 1 of 2, waiting for {}, sent once",
+                id, carol_identity
+            )
+        );
+
+        let start = delivery.sent_at;
+        assert_eq!(alice.retransmit_unacked(start), 0);
+        assert_eq!(alice.retransmit_unacked(start + DEFAULT_ACK_TIMEOUT), 1);
+        let resent = only_frame(&mut alice);
+        assert_eq!(
+            carol.parse_application_message(resent.clone()).unwrap(),
+            Some(ApplicationPayload::Text("hello".to_string()))
+        );
+        // Bob already has it, and only acknowledges it again.
+        assert_eq!(bob.parse_application_message(resent).unwrap(), None);
+        for member in [&mut bob, &mut carol] {
+            let ack = only_frame(member);
+            alice.parse_application_message(ack).unwrap();
+        }
+        let delivery = alice.delivery(id).unwrap();
+        assert!(delivery.is_delivered());
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(alice.retransmit_unacked(start + DEFAULT_ACK_TIMEOUT * 4), 0);
+    }
+
+    #[test]
+    fn readers_are_reported_unless_the_policy_forbids_receipts() {
+        for policy in [GroupPolicy::default(), GroupPolicy::max_privacy()] {
+            let (mut alice, mut bob) = (Node::default(), Node::default());
+            alice.join_new_group_with_policy(policy).unwrap();
+            let (_, invite) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+            bob.join_existing_group(invite).unwrap();
+            bob.set_read_receipts(true);
+            let bob_peer = bob.get_network_keypair().public().to_peer_id();
+            let bob_identity = credential_identity(bob.get_key_package().credential());
+
+            let msg_out = alice.create_message("read me").unwrap();
+            let id = alice.deliveries().iter().next().unwrap().id;
+            bob.parse_application_message(msg_out).unwrap();
+            alice
+                .parse_application_message(only_frame(&mut bob))
+                .unwrap();
+            if policy.max_privacy {
+                assert_eq!(bob.mark_read(), 0);
+                continue;
+            }
+            // Nothing more until the text has been shown.
+            assert!(bob.take_outgoing().is_empty());
+            assert_eq!(bob.mark_read(), 1);
+            assert_eq!(bob.mark_read(), 0);
+            let receipt = only_frame(&mut bob);
+            let frame = WireMessage::from(receipt).encode().unwrap();
+            match &alice.handle_incoming(&bob_peer, &frame)[..] {
+                [NodeEvent::ReadBy {
+                    id: read, reader, ..
+                }] => {
+                    assert_eq!(*read, id);
+                    assert_eq!(reader, &bob_identity);
+                }
+                events => panic!("{:?}", events),
+            }
+            assert!(alice
+                .delivery(id)
+                .unwrap()
+                .to_string()
+                .ends_with("acknowledged by 1 of 1, read by 1, sent once"));
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Members the leader lets add and remove others.
+//!
+//! The leader is the group's creator, the member at leaf 0. It can grant
+//! other members the right to add members, to remove them, or both, with
+//! `node promote` and `node demote`, and sends the whole roster to the group
+//! as an application message `0xF7 | count: u8 | (key<u8> | rights: u8)*`,
+//! keyed by signature key. The roster is sent again after every add, so
+//! newcomers learn it. Members take rosters only from the leader, and
+//! refuse commits adding or removing members from anyone without the
+//! right; members removing themselves and external joins need none.
+//!
+//! Only the leader answers join requests that name no leader, so admins
+//! do not race it; an admin with the add right answers requests that name
+//! it, such as from its room announcement.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xF7;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Rights(u8);
+
+impl Rights {
+    pub const ADD: Rights = Rights(1);
+    pub const REMOVE: Rights = Rights(1 << 1);
+    pub const ALL: Rights = Rights(1 | 1 << 1);
+
+    pub fn with(self, rights: Rights) -> Rights {
+        Rights(self.0 | rights.0)
+    }
+
+    pub fn contains(self, rights: Rights) -> bool {
+        self.0 & rights.0 == rights.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Display for Rights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = [(Rights::ADD, "add"), (Rights::REMOVE, "remove")]
+            .iter()
+            .filter(|(right, _)| self.contains(*right))
+            .map(|(_, name)| *name)
+            .collect();
+        match names.as_slice() {
+            [] => write!(f, "no rights"),
+            names => write!(f, "may {}", names.join(" and ")),
+        }
+    }
+}
+
+/// The rights granted in a group, by signature key.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdminRoster {
+    grants: HashMap<Vec<u8>, Rights>,
+}
+
+impl AdminRoster {
+    pub fn is_roster(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn rights(&self, signature_key: &[u8]) -> Rights {
+        self.grants.get(signature_key).copied().unwrap_or_default()
+    }
+
+    /// Sets the rights of `signature_key`; no rights drop it from the roster.
+    pub fn set(&mut self, signature_key: &[u8], rights: Rights) {
+        if rights.is_empty() {
+            self.grants.remove(signature_key);
+        } else {
+            self.grants.insert(signature_key.to_vec(), rights);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    pub fn grants(&self) -> impl Iterator<Item = (&[u8], Rights)> {
+        self.grants
+            .iter()
+            .map(|(key, rights)| (key.as_slice(), *rights))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut grants: Vec<_> = self.grants.iter().collect();
+        grants.sort();
+        let mut bytes = vec![MARKER, grants.len() as u8];
+        for (key, rights) in grants {
+            bytes.push(key.len() as u8);
+            bytes.extend_from_slice(key);
+            bytes.push(rights.0);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<AdminRoster, NodeError> {
+        let malformed = || NodeError::Parse("Malformed admin roster".to_string());
+        let (count, mut rest) = match bytes {
+            [MARKER, count, rest @ ..] => (*count, rest),
+            _ => return Err(malformed()),
+        };
+        let mut roster = AdminRoster::default();
+        for _ in 0..count {
+            let (key, rights, tail) = match rest {
+                [len, tail @ ..] if tail.len() > *len as usize => {
+                    let (key, tail) = tail.split_at(*len as usize);
+                    (key, tail[0], &tail[1..])
+                }
+                _ => return Err(malformed()),
+            };
+            roster.set(key, Rights(rights & Rights::ALL.0));
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+        Ok(roster)
+    }
+}
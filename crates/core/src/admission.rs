@@ -0,0 +1,774 @@
+//! Admission control for key packages arriving at the group leader.
+//!
+//! Every accepted key package costs the leader an add, a commit and a
+//! Welcome, so join requests pass through per-peer and global token buckets
+//! first. A group can additionally require a proof of work over the key
+//! package, or a tag derived from a pre-shared key handed out with the
+//! invitation.
+//!
+//! A key package is public and can be replayed by anyone who saw it, so a
+//! request must also be fresh: it carries a random nonce and a timestamp
+//! signed by the libp2p key whose peer id is the key package's credential
+//! identity. Leaders refuse requests more than [`MAX_JOIN_AGE`] away from
+//! their clock, and each nonce only once.
+//!
+//! Key packages must also be valid for [`AdmissionConfig::lifetime_tolerance`]
+//! either side of now, see `lifetime`.
+//!
+//! Requests are `0xFD | pow nonce: u64 | tag<u8> | key package | leader<u8>`,
+//! with an empty leader unless the request picks a room from `node rooms`,
+//! followed by `nonce<u8> | timestamp: u64 | public key<u16> | signature<u8>`.
+//!
+//! Requests that pass these checks wait in [`PendingJoins`] until the
+//! application accepts or declines them, unless [`JoinApproval::Auto`] adds
+//! them right away.
+//!
+//! On the joiner's side, [`WelcomePolicy`] decides which Welcomes we accept
+//! while waiting for one.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use openmls::prelude::{
+    HashType, KeyPackage, OpenMlsCrypto, OpenMlsCryptoProvider, OpenMlsRand, TlsDeserializeTrait,
+};
+
+use crate::{
+    codec,
+    crypto::fingerprint::ct_eq,
+    error::NodeError,
+    identity::{Keypair, PeerId, PublicKey},
+    lifetime::{Lifetime, LifetimeError},
+};
+
+const MARKER: u8 = 0xFD;
+const POW_LABEL: &[u8] = b"p2p-mls join pow";
+const FRESHNESS_LABEL: &[u8] = b"p2p-mls join freshness";
+const NONCE_LEN: usize = 16;
+/// How far a join request's timestamp may be from the leader's clock,
+/// either way.
+pub const MAX_JOIN_AGE: Duration = Duration::from_secs(300);
+// Idle buckets are dropped once this many peers are tracked.
+pub(crate) const MAX_TRACKED_PEERS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn per_minute(burst: u32) -> RateLimit {
+        RateLimit {
+            burst,
+            per: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdmissionConfig {
+    pub per_peer: RateLimit,
+    pub global: RateLimit,
+    /// Leading zero bits required of `SHA-256(label | key package | nonce)`.
+    pub proof_of_work: Option<u8>,
+    pub psk: Option<Vec<u8>>,
+    /// How long before and after now a key package must be valid.
+    pub lifetime_tolerance: Duration,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> AdmissionConfig {
+        AdmissionConfig {
+            per_peer: RateLimit::per_minute(3),
+            global: RateLimit::per_minute(30),
+            proof_of_work: None,
+            psk: None,
+            lifetime_tolerance: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Which Welcomes a node waiting to join accepts. Anyone who can publish
+/// on the topic can send one, so with `Leader` we only join a group led by
+/// the member our join request named, such as a room's announced leader.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WelcomePolicy {
+    #[default]
+    Any,
+    Leader,
+}
+
+impl WelcomePolicy {
+    pub fn parse(policy: &str) -> Result<WelcomePolicy, NodeError> {
+        match policy {
+            "any" => Ok(WelcomePolicy::Any),
+            "leader" => Ok(WelcomePolicy::Leader),
+            _ => Err(NodeError::Other(
+                "--welcome-from must be any or leader".to_string(),
+            )),
+        }
+    }
+}
+
+/// Whether join requests that pass admission control are added right away
+/// or wait for `Node::accept_join` or `Node::decline_join`, unless a join
+/// policy decides them, see `hooks`. Waiting is the default, in the CLI
+/// too, so a person or a policy approves every joiner; the CLI only opts
+/// into `Auto` when started with `--approve-joins=auto`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JoinApproval {
+    #[default]
+    Manual,
+    Auto,
+}
+
+impl JoinApproval {
+    pub fn parse(approval: &str) -> Result<JoinApproval, NodeError> {
+        match approval {
+            "manual" => Ok(JoinApproval::Manual),
+            "auto" => Ok(JoinApproval::Auto),
+            _ => Err(NodeError::Other(
+                "--approve-joins must be auto or manual".to_string(),
+            )),
+        }
+    }
+}
+
+/// A join request that passed admission control, waiting for a decision.
+#[derive(Debug, Clone)]
+pub struct PendingJoin {
+    pub id: u64,
+    pub peer: PeerId,
+    /// The group the request was for when it arrived.
+    pub group_id: Vec<u8>,
+    pub request: JoinRequest,
+}
+
+#[derive(Debug, Default)]
+pub struct PendingJoins {
+    next_id: u64,
+    requests: Vec<PendingJoin>,
+}
+
+impl PendingJoins {
+    /// Queues a request under a fresh id, replacing an earlier one `peer`
+    /// made for the same group.
+    pub fn push(&mut self, peer: PeerId, group_id: Vec<u8>, request: JoinRequest) -> u64 {
+        self.requests
+            .retain(|pending| pending.peer != peer || pending.group_id != group_id);
+        self.next_id += 1;
+        self.requests.push(PendingJoin {
+            id: self.next_id,
+            peer,
+            group_id,
+            request,
+        });
+        self.next_id
+    }
+
+    pub fn get(&self, id: u64) -> Result<&PendingJoin, NodeError> {
+        self.requests
+            .iter()
+            .find(|pending| pending.id == id)
+            .ok_or_else(|| NodeError::Other(format!("No join request #{} is waiting", id)))
+    }
+
+    pub fn take(&mut self, id: u64) -> Result<PendingJoin, NodeError> {
+        let index = self
+            .requests
+            .iter()
+            .position(|pending| pending.id == id)
+            .ok_or_else(|| NodeError::Other(format!("No join request #{} is waiting", id)))?;
+        Ok(self.requests.remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PendingJoin> {
+        self.requests.iter()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AdmissionMetrics {
+    pub admitted: u64,
+    pub peer_rate_limited: u64,
+    pub global_rate_limited: u64,
+    pub invalid_proof_of_work: u64,
+    pub invalid_psk: u64,
+    /// Unsigned, stale or replayed requests.
+    pub not_fresh: u64,
+    /// Key packages expired, or not yet valid, within the tolerance.
+    pub lifetime: u64,
+}
+
+impl AdmissionMetrics {
+    pub fn rejected(&self) -> u64 {
+        self.peer_rate_limited
+            + self.global_rate_limited
+            + self.invalid_proof_of_work
+            + self.invalid_psk
+            + self.not_fresh
+            + self.lifetime
+    }
+}
+
+impl Display for AdmissionMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "admitted {}, rejected {} (peer rate {}, global rate {}, proof of work {}, psk {}, freshness {}, lifetime {})",
+            self.admitted,
+            self.rejected(),
+            self.peer_rate_limited,
+            self.global_rate_limited,
+            self.invalid_proof_of_work,
+            self.invalid_psk,
+            self.not_fresh,
+            self.lifetime
+        )
+    }
+}
+
+/// Signed proof that a join request was made just now by the owner of its
+/// key package.
+#[derive(Debug, Clone)]
+pub struct Freshness {
+    pub nonce: Vec<u8>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub public_key: PublicKey,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinRequest {
+    pub key_package: KeyPackage,
+    pub nonce: u64,
+    pub psk_tag: Vec<u8>,
+    /// Identity of the leader whose room we want to join; without one any
+    /// leader that hears the request may admit us.
+    pub leader: Option<String>,
+    pub freshness: Option<Freshness>,
+}
+
+impl JoinRequest {
+    /// Builds a request satisfying `config`, solving the proof of work if one
+    /// is required.
+    pub fn new(
+        key_package: KeyPackage,
+        config: &AdmissionConfig,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<JoinRequest, NodeError> {
+        let key_package_bytes = codec::to_tls(&key_package, "key package")?;
+        let nonce = match config.proof_of_work {
+            Some(difficulty) => (0..u64::MAX)
+                .find(|&nonce| {
+                    pow_hash(backend, &key_package_bytes, nonce)
+                        .is_ok_and(|hash| leading_zero_bits(&hash) >= difficulty as u32)
+                })
+                .ok_or_else(|| NodeError::Other("Could not solve proof of work".to_string()))?,
+            None => 0,
+        };
+        let psk_tag = match &config.psk {
+            Some(psk) => psk_tag(backend, psk, &key_package_bytes)?,
+            None => Vec::new(),
+        };
+        Ok(JoinRequest {
+            key_package,
+            nonce,
+            psk_tag,
+            leader: None,
+            freshness: None,
+        })
+    }
+
+    /// Signs a fresh nonce and `now` with `keypair`, which must be the key
+    /// the credential identity is the peer id of. Sign last: the signature
+    /// covers the rest of the request.
+    pub fn sign(
+        mut self,
+        keypair: &Keypair,
+        now: SystemTime,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<JoinRequest, NodeError> {
+        let nonce = backend
+            .rand()
+            .random_vec(NONCE_LEN)
+            .map_err(|e| NodeError::Other(format!("Could not generate nonce: {:?}", e)))?;
+        let timestamp = unix_seconds(now);
+        let signature = keypair.sign(&self.signed_content(&nonce, timestamp)?);
+        self.freshness = Some(Freshness {
+            nonce,
+            timestamp,
+            public_key: keypair.public(),
+            signature,
+        });
+        Ok(self)
+    }
+
+    fn signed_content(&self, nonce: &[u8], timestamp: u64) -> Result<Vec<u8>, NodeError> {
+        let mut content = FRESHNESS_LABEL.to_vec();
+        content.extend(self.encode_unsigned()?);
+        content.extend_from_slice(nonce);
+        content.extend_from_slice(&timestamp.to_be_bytes());
+        Ok(content)
+    }
+
+    pub fn for_leader(mut self, leader: &str) -> JoinRequest {
+        self.leader = Some(leader.to_string());
+        self
+    }
+
+    /// Whether the leader with `identity` should handle this request.
+    pub fn is_for(&self, identity: &str) -> bool {
+        self.leader
+            .as_deref()
+            .is_none_or(|leader| leader == identity)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        if self.nonce == 0
+            && self.psk_tag.is_empty()
+            && self.leader.is_none()
+            && self.freshness.is_none()
+        {
+            return codec::to_tls(&self.key_package, "key package");
+        }
+        let mut bytes = self.encode_unsigned()?;
+        if let Some(freshness) = &self.freshness {
+            let public_key = freshness.public_key.to_protobuf_encoding();
+            bytes.push(freshness.nonce.len() as u8);
+            bytes.extend_from_slice(&freshness.nonce);
+            bytes.extend_from_slice(&freshness.timestamp.to_be_bytes());
+            bytes.extend_from_slice(&(public_key.len() as u16).to_be_bytes());
+            bytes.extend(public_key);
+            bytes.push(freshness.signature.len() as u8);
+            bytes.extend_from_slice(&freshness.signature);
+        }
+        Ok(bytes)
+    }
+
+    // Everything but the freshness proof, which signs it.
+    fn encode_unsigned(&self) -> Result<Vec<u8>, NodeError> {
+        let key_package = codec::to_tls(&self.key_package, "key package")?;
+        let mut bytes = vec![MARKER];
+        bytes.extend_from_slice(&self.nonce.to_be_bytes());
+        bytes.push(self.psk_tag.len() as u8);
+        bytes.extend_from_slice(&self.psk_tag);
+        bytes.extend(key_package);
+        let leader = self.leader.as_deref().unwrap_or_default();
+        bytes.push(leader.len() as u8);
+        bytes.extend_from_slice(leader.as_bytes());
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<JoinRequest, NodeError> {
+        let malformed = || NodeError::Parse("Malformed join request".to_string());
+        if bytes.first() != Some(&MARKER) {
+            let key_package = KeyPackage::try_from(bytes).map_err(|_| malformed())?;
+            return Ok(JoinRequest {
+                key_package,
+                nonce: 0,
+                psk_tag: Vec::new(),
+                leader: None,
+                freshness: None,
+            });
+        }
+        let nonce = u64::from_be_bytes(bytes.get(1..9).ok_or_else(malformed)?.try_into().unwrap());
+        let tag_len = *bytes.get(9).ok_or_else(malformed)? as usize;
+        let psk_tag = bytes.get(10..10 + tag_len).ok_or_else(malformed)?.to_vec();
+        let mut rest = &bytes[10 + tag_len..];
+        let key_package = KeyPackage::tls_deserialize(&mut rest).map_err(|_| malformed())?;
+        let mut take = |len: usize| -> Result<&[u8], NodeError> {
+            let field = rest.get(..len).ok_or_else(malformed)?;
+            rest = &rest[len..];
+            Ok(field)
+        };
+        let leader_len = take(1)?[0] as usize;
+        let leader = String::from_utf8(take(leader_len)?.to_vec()).map_err(|_| malformed())?;
+        let leader = Some(leader).filter(|leader| !leader.is_empty());
+        let freshness = match take(1) {
+            Ok(&[nonce_len]) => {
+                let nonce = take(nonce_len as usize)?.to_vec();
+                let timestamp = u64::from_be_bytes(take(8)?.try_into().unwrap());
+                let key_len = u16::from_be_bytes(take(2)?.try_into().unwrap());
+                let public_key = PublicKey::from_protobuf_encoding(take(key_len as usize)?)
+                    .map_err(|_| malformed())?;
+                let signature_len = take(1)?[0] as usize;
+                let signature = take(signature_len)?.to_vec();
+                Some(Freshness {
+                    nonce,
+                    timestamp,
+                    public_key,
+                    signature,
+                })
+            }
+            _ => None,
+        };
+        Ok(JoinRequest {
+            key_package,
+            nonce,
+            psk_tag,
+            leader,
+            freshness,
+        })
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs()
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    pub(crate) tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    pub(crate) fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let rate = limit.burst as f64 / limit.per.as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    pub(crate) fn try_take(&mut self, limit: RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct AdmissionControl {
+    config: AdmissionConfig,
+    peers: HashMap<PeerId, TokenBucket>,
+    global: TokenBucket,
+    metrics: AdmissionMetrics,
+    // Nonces of admitted requests by timestamp, until they would be stale.
+    seen_nonces: HashMap<Vec<u8>, u64>,
+}
+
+impl Default for AdmissionControl {
+    fn default() -> AdmissionControl {
+        AdmissionControl::new(AdmissionConfig::default())
+    }
+}
+
+impl AdmissionControl {
+    pub fn new(config: AdmissionConfig) -> AdmissionControl {
+        let global = TokenBucket::new(config.global, Instant::now());
+        AdmissionControl {
+            config,
+            peers: HashMap::new(),
+            global,
+            metrics: AdmissionMetrics::default(),
+            seen_nonces: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &AdmissionConfig {
+        &self.config
+    }
+
+    /// Applies `config` from now on, keeping the counts, rate limit buckets
+    /// and nonces seen so far.
+    pub fn reconfigure(&mut self, config: AdmissionConfig) {
+        self.config = config;
+    }
+
+    pub fn metrics(&self) -> &AdmissionMetrics {
+        &self.metrics
+    }
+
+    /// Decides whether the leader should spend an add and commit on `request`.
+    /// Rate limits are checked first since they cost nothing to evaluate.
+    pub fn admit(
+        &mut self,
+        peer: &PeerId,
+        request: &JoinRequest,
+        backend: &impl OpenMlsCryptoProvider,
+        now: Instant,
+    ) -> Result<(), NodeError> {
+        if self.peers.len() >= MAX_TRACKED_PEERS {
+            let limit = self.config.per_peer;
+            self.peers.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst as f64
+            });
+        }
+        let per_peer = self.config.per_peer;
+        if !self
+            .peers
+            .entry(*peer)
+            .or_insert_with(|| TokenBucket::new(per_peer, now))
+            .try_take(per_peer, now)
+        {
+            self.metrics.peer_rate_limited += 1;
+            return Err(NodeError::Other(format!(
+                "Too many join requests from {}",
+                peer
+            )));
+        }
+        if !self.global.try_take(self.config.global, now) {
+            self.metrics.global_rate_limited += 1;
+            return Err(NodeError::Other(
+                "Too many join requests, try again later".to_string(),
+            ));
+        }
+
+        let key_package_bytes = codec::to_tls(&request.key_package, "key package")?;
+        if let Some(difficulty) = self.config.proof_of_work {
+            let hash = pow_hash(backend, &key_package_bytes, request.nonce)?;
+            if leading_zero_bits(&hash) < difficulty as u32 {
+                self.metrics.invalid_proof_of_work += 1;
+                return Err(NodeError::Other("Insufficient proof of work".to_string()));
+            }
+        }
+        if let Some(psk) = &self.config.psk {
+            let expected = psk_tag(backend, psk, &key_package_bytes)?;
+            if !ct_eq(&expected, &request.psk_tag) {
+                self.metrics.invalid_psk += 1;
+                return Err(NodeError::Other(
+                    "Join request not authorized by group PSK".to_string(),
+                ));
+            }
+        }
+        self.metrics.admitted += 1;
+        Ok(())
+    }
+
+    /// Checks that `lifetime` covers the tolerance either side of `now`.
+    pub fn check_lifetime(
+        &mut self,
+        lifetime: Option<Lifetime>,
+        now: SystemTime,
+    ) -> Result<(), LifetimeError> {
+        // OpenMLS gives every key package a lifetime; one without is not ours to judge.
+        let result = lifetime.map_or(Ok(()), |lifetime| {
+            lifetime.check(now, self.config.lifetime_tolerance)
+        });
+        if result.is_err() {
+            self.metrics.lifetime += 1;
+        }
+        result
+    }
+
+    /// Checks that `request` was signed by the owner of its key package
+    /// within [`MAX_JOIN_AGE`] of `now`, and consumes its nonce.
+    pub fn check_freshness(
+        &mut self,
+        request: &JoinRequest,
+        now: SystemTime,
+    ) -> Result<(), NodeError> {
+        let result = self.verify_freshness(request, unix_seconds(now));
+        if result.is_err() {
+            self.metrics.not_fresh += 1;
+        }
+        result
+    }
+
+    fn verify_freshness(&mut self, request: &JoinRequest, now: u64) -> Result<(), NodeError> {
+        let freshness = request
+            .freshness
+            .as_ref()
+            .ok_or_else(|| NodeError::Other("Join request is not signed".to_string()))?;
+        let max_age = MAX_JOIN_AGE.as_secs();
+        if freshness.timestamp.abs_diff(now) > max_age {
+            return Err(NodeError::Other("Stale join request".to_string()));
+        }
+        self.seen_nonces
+            .retain(|_, timestamp| timestamp.saturating_add(max_age) >= now);
+        if self.seen_nonces.contains_key(&freshness.nonce) {
+            return Err(NodeError::Other("Replayed join request".to_string()));
+        }
+        let signer = PeerId::from(freshness.public_key.clone());
+        if !ct_eq(
+            &signer.to_bytes(),
+            request.key_package.credential().identity(),
+        ) {
+            return Err(NodeError::Other(
+                "Join request not signed by the key package's owner".to_string(),
+            ));
+        }
+        let content = request.signed_content(&freshness.nonce, freshness.timestamp)?;
+        if !freshness.public_key.verify(&content, &freshness.signature) {
+            return Err(NodeError::Other(
+                "Invalid join request signature".to_string(),
+            ));
+        }
+        self.seen_nonces
+            .insert(freshness.nonce.clone(), freshness.timestamp);
+        Ok(())
+    }
+}
+
+fn pow_hash(
+    backend: &impl OpenMlsCryptoProvider,
+    key_package: &[u8],
+    nonce: u64,
+) -> Result<Vec<u8>, NodeError> {
+    let mut input = Vec::with_capacity(POW_LABEL.len() + key_package.len() + 8);
+    input.extend_from_slice(POW_LABEL);
+    input.extend_from_slice(key_package);
+    input.extend_from_slice(&nonce.to_be_bytes());
+    backend
+        .crypto()
+        .hash(HashType::Sha2_256, &input)
+        .map_err(|e| NodeError::Other(format!("Could not hash join request: {:?}", e)))
+}
+
+// HKDF-Extract is HMAC keyed with the salt, so this is HMAC-SHA256(psk, key package).
+fn psk_tag(
+    backend: &impl OpenMlsCryptoProvider,
+    psk: &[u8],
+    key_package: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    backend
+        .crypto()
+        .hkdf_extract(HashType::Sha2_256, psk, key_package)
+        .map(|tag| tag.as_slice().to_vec())
+        .map_err(|e| NodeError::Other(format!("Could not authenticate join request: {:?}", e)))
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+
+    #[test]
+    fn admission_limits_and_proofs() {
+        let backend = OpenMlsRustCrypto::default();
+        let now = Instant::now();
+        let peer = PeerId::random();
+        let request = JoinRequest::decode(
+            &JoinRequest::new(
+                Node::default().get_key_package(),
+                &AdmissionConfig::default(),
+                &backend,
+            )
+            .unwrap()
+            .encode()
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mut control = AdmissionControl::new(AdmissionConfig {
+            per_peer: RateLimit::per_minute(2),
+            global: RateLimit::per_minute(3),
+            ..AdmissionConfig::default()
+        });
+        assert!(control.admit(&peer, &request, &backend, now).is_ok());
+        assert!(control.admit(&peer, &request, &backend, now).is_ok());
+        assert!(control.admit(&peer, &request, &backend, now).is_err());
+        let other = PeerId::random();
+        assert!(control.admit(&other, &request, &backend, now).is_ok());
+        assert!(control.admit(&other, &request, &backend, now).is_err());
+        assert_eq!(control.metrics().peer_rate_limited, 1);
+        assert_eq!(control.metrics().global_rate_limited, 1);
+        let later = now + Duration::from_secs(60);
+        assert!(control.admit(&peer, &request, &backend, later).is_ok());
+        control.reconfigure(AdmissionConfig {
+            global: RateLimit::per_minute(10),
+            ..AdmissionConfig::default()
+        });
+        assert_eq!(control.config().global, RateLimit::per_minute(10));
+        assert_eq!(control.metrics().global_rate_limited, 1);
+
+        let config = AdmissionConfig {
+            proof_of_work: Some(8),
+            psk: Some(b"invitation secret".to_vec()),
+            ..AdmissionConfig::default()
+        };
+        let mut control = AdmissionControl::new(config.clone());
+        assert!(control.admit(&peer, &request, &backend, now).is_err());
+        assert_eq!(control.metrics().invalid_proof_of_work, 1);
+
+        let proven = JoinRequest::decode(
+            &JoinRequest::new(request.key_package.clone(), &config, &backend)
+                .unwrap()
+                .encode()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(control.admit(&peer, &proven, &backend, now).is_ok());
+
+        let wrong_psk = AdmissionConfig {
+            psk: Some(b"guess".to_vec()),
+            ..config
+        };
+        let forged = JoinRequest::new(request.key_package, &wrong_psk, &backend).unwrap();
+        assert!(control.admit(&other, &forged, &backend, now).is_err());
+        assert_eq!(control.metrics().invalid_psk, 1);
+        assert_eq!(control.metrics().admitted, 1);
+    }
+
+    #[test]
+    fn join_requests_must_be_fresh() {
+        let backend = OpenMlsRustCrypto::default();
+        let mut node = Node::default();
+        let now = SystemTime::now();
+        let mut control = AdmissionControl::default();
+        let request =
+            JoinRequest::decode(&node.create_join_request().unwrap().encode().unwrap()).unwrap();
+        assert!(request.leader.is_none());
+        assert!(control.check_freshness(&request, now).is_ok());
+        // Captured and sent again.
+        assert!(control.check_freshness(&request, now).is_err());
+
+        let unsigned = JoinRequest::new(
+            node.get_key_package(),
+            &AdmissionConfig::default(),
+            &backend,
+        )
+        .unwrap();
+        assert!(control.check_freshness(&unsigned, now).is_err());
+        let sign = |request: JoinRequest, keypair: &Keypair, at: SystemTime| {
+            JoinRequest::decode(
+                &request
+                    .sign(keypair, at, &backend)
+                    .unwrap()
+                    .encode()
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        let old = now - MAX_JOIN_AGE - Duration::from_secs(1);
+        let stale = sign(unsigned.clone(), &node.get_network_keypair(), old);
+        assert!(control.check_freshness(&stale, now).is_err());
+        let impostor = sign(unsigned.clone(), &Keypair::generate_ed25519(), now);
+        assert!(control.check_freshness(&impostor, now).is_err());
+        let mut redirected = sign(unsigned, &node.get_network_keypair(), now);
+        redirected.leader = Some("someone else".to_string());
+        assert!(control.check_freshness(&redirected, now).is_err());
+        assert_eq!(control.metrics().not_fresh, 5);
+        redirected.leader = None;
+        assert!(control.check_freshness(&redirected, now).is_ok());
+    }
+}
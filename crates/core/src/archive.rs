@@ -0,0 +1,194 @@
+//! Cold storage for groups we are done with.
+//!
+//! `node archive` freezes the group: its final state, epoch history and the
+//! signed messages recorded for it are written to a [`GroupArchive`], and once
+//! that is stored the node drops the group along with the key package it
+//! joined with. The
+//! archive is deflated and then sealed like a backup section, with
+//! ChaCha20-Poly1305 under a passphrase-derived key:
+//! `MAGIC | iterations: u32 | salt | nonce | ciphertext`. Opening an archive
+//! is read-only; the state can be inspected but never rejoins the network.
+
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use openmls::{group::MlsGroup, prelude::OpenMlsCryptoProvider, prelude::OpenMlsRand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ack,
+    audit::AuditEntry,
+    backup::{derive_key, open, seal, SALT_LEN},
+    crypto::{credential_identity, hex_encode},
+    error::NodeError,
+    policy::GroupPolicy,
+    schema::{self, Artifact},
+};
+
+pub(crate) const MAGIC: &[u8; 8] = b"P2PMLSA1";
+const OBJECT_NAME: &str = "archive";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupArchive {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    /// Seconds since the UNIX epoch.
+    pub archived_at: u64,
+    pub was_leader: bool,
+    pub policy: Vec<u8>,
+    /// The group as saved by `MlsGroup::save`.
+    pub state: Vec<u8>,
+    pub epoch_digests: Vec<(u64, Vec<u8>)>,
+    pub history: Vec<AuditEntry>,
+}
+
+impl GroupArchive {
+    pub fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+            .as_secs()
+    }
+
+    /// Identities of the members at the time the group was archived.
+    pub fn members(&self) -> Result<Vec<String>, NodeError> {
+        let group = MlsGroup::load(self.state.as_slice())?;
+        Ok(group
+            .members()
+            .iter()
+            .map(|member| credential_identity(member.credential()))
+            .collect())
+    }
+
+    pub fn seal(
+        &self,
+        passphrase: &str,
+        iterations: u32,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<Vec<u8>, NodeError> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&schema::encode(Artifact::Archive, self)?)?;
+        let compressed = encoder.finish()?;
+        let salt = backend
+            .rand()
+            .random_vec(SALT_LEN)
+            .map_err(|e| NodeError::Other(format!("Could not generate salt: {:?}", e)))?;
+        let key = derive_key(backend, passphrase, &salt, iterations)?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&iterations.to_be_bytes());
+        bytes.extend_from_slice(&salt);
+        bytes.extend(seal(backend, &key, OBJECT_NAME, &compressed)?);
+        Ok(bytes)
+    }
+
+    pub fn open(
+        bytes: &[u8],
+        passphrase: &str,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<GroupArchive, NodeError> {
+        let header_len = MAGIC.len() + 4 + SALT_LEN;
+        if bytes.len() < header_len || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(NodeError::Parse("Not a p2p-mls archive".to_string()));
+        }
+        let iterations = u32::from_be_bytes(bytes[8..12].try_into().expect("4 bytes"));
+        let key = derive_key(backend, passphrase, &bytes[12..header_len], iterations)?;
+        let compressed = open(backend, &key, OBJECT_NAME, &bytes[header_len..])?;
+        let mut json = Vec::new();
+        DeflateDecoder::new(compressed.as_slice()).read_to_end(&mut json)?;
+        schema::decode(Artifact::Archive, &json)
+    }
+}
+
+/// Whether `name`, as typed by the user, refers to the group with
+/// `group_id`: either its hex form or the id itself when it is text.
+pub fn names_group(group_id: &[u8], name: &str) -> bool {
+    hex_encode(group_id) == name.to_lowercase() || group_id == name.as_bytes()
+}
+
+impl Display for GroupArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let policy = GroupPolicy::decode(&self.policy).unwrap_or_default();
+        writeln!(
+            f,
+            "group {} archived at {} in epoch {}",
+            hex_encode(&self.group_id),
+            self.archived_at,
+            self.epoch
+        )?;
+        writeln!(
+            f,
+            "non-repudiation {}, max privacy {}, we {} the leader",
+            policy.non_repudiation,
+            policy.max_privacy,
+            if self.was_leader { "were" } else { "were not" }
+        )?;
+        if let Ok(members) = self.members() {
+            writeln!(f, "members: {}", members.join(", "))?;
+        }
+        write!(f, "{} signed messages", self.history.len())?;
+        for entry in &self.history {
+            match entry {
+                AuditEntry::SignedMessage {
+                    epoch,
+                    signer,
+                    message,
+                    ..
+                } => write!(
+                    f,
+                    "\nepoch {} signed by {}: {}",
+                    epoch,
+                    signer,
+                    String::from_utf8_lossy(ack::text_of(message))
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls::prelude::{MlsMessageOut, TlsSerializeTrait};
+
+    #[test]
+    fn archived_group_reopens_read_only() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice
+            .join_new_group_with_policy(GroupPolicy {
+                non_repudiation: true,
+                max_privacy: false,
+                ..GroupPolicy::default()
+            })
+            .unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let msg = alice.create_message("for the record").unwrap();
+        let msg = MlsMessageOut::try_from_bytes(&msg.tls_serialize_detached().unwrap()).unwrap();
+        bob.parse_message(msg).unwrap();
+
+        assert!(bob.seal_group("elsewhere", "secret", 1).is_err());
+        let sealed = bob.seal_group("Test Group", "secret", 1).unwrap();
+        assert!(bob.in_group());
+        bob.forget_group("Test Group").unwrap();
+        assert!(!bob.in_group());
+        assert!(bob.create_message("after archiving").is_err());
+
+        assert!(bob.open_archive(&sealed, "wrong").is_err());
+        let archive = bob.open_archive(&sealed, "secret").unwrap();
+        assert_eq!(archive.epoch, 1);
+        assert_eq!(archive.members().unwrap().len(), 2);
+        assert_eq!(archive.history.len(), 1);
+        assert!(archive.to_string().contains("for the record"));
+
+        // A fresh key package lets Bob join other groups.
+        let mut carol = Node::default();
+        carol.join_new_group();
+        let (_, welcome) = carol.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+//! Append-only audit log of security relevant records.
+//!
+//! Entries are kept in memory and, when a path is configured, appended to it
+//! as JSON lines after a schema header, see `schema`.
+//!
+//! With a history passphrase the appended lines are sealed per group, as
+//! `{"Sealed": {"group_id": ..., "sealed": ...}}`, under ChaCha20-Poly1305
+//! with a key mixing the passphrase into a secret exported from the group
+//! when we joined it. That secret is only kept with the group's state, so
+//! the file of one group's history does not open another's, and once we
+//! are out of a group its lines no longer open at all.
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use openmls::prelude::{HashType, OpenMlsCrypto, OpenMlsCryptoProvider};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    backup::{open, seal},
+    codec,
+    crypto::hex_encode,
+    error::NodeError,
+    schema,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditEntry {
+    /// An application message whose explicit author signature verified.
+    SignedMessage {
+        group_id: Vec<u8>,
+        epoch: u64,
+        signer: String,
+        message: Vec<u8>,
+        signature: Vec<u8>,
+    },
+}
+
+impl AuditEntry {
+    pub fn group_id(&self) -> &[u8] {
+        match self {
+            AuditEntry::SignedMessage { group_id, .. } => group_id,
+        }
+    }
+}
+
+// A line of the file that is not an entry in the clear.
+#[derive(Serialize, Deserialize)]
+enum SealedLine {
+    Sealed { group_id: Vec<u8>, sealed: Vec<u8> },
+}
+
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+    path: Option<PathBuf>,
+    sealing_key: Option<Vec<u8>>, // derived from the history passphrase
+}
+
+impl AuditLog {
+    pub fn with_path(path: PathBuf) -> AuditLog {
+        AuditLog {
+            entries: Vec::new(),
+            path: Some(path),
+            sealing_key: None,
+        }
+    }
+
+    /// Starts from entries recovered elsewhere, e.g. from a backup. They are
+    /// not written to `path` again.
+    pub fn from_entries(entries: Vec<AuditEntry>, path: Option<PathBuf>) -> AuditLog {
+        AuditLog {
+            entries,
+            path,
+            sealing_key: None,
+        }
+    }
+
+    /// Seals the lines written from now on, see the module docs.
+    pub fn seal_with(&mut self, sealing_key: Vec<u8>) {
+        self.sealing_key = Some(sealing_key);
+    }
+
+    /// Records `entry`; `group_secret` is the history secret of its group.
+    pub fn record(
+        &mut self,
+        entry: AuditEntry,
+        group_secret: &[u8],
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(), NodeError> {
+        if let Some(path) = &self.path {
+            let json = codec::to_json(&entry, "audit entry")?;
+            let mut line = match &self.sealing_key {
+                Some(sealing_key) => {
+                    let key = group_key(backend, sealing_key, group_secret)?;
+                    let sealed = SealedLine::Sealed {
+                        group_id: entry.group_id().to_vec(),
+                        sealed: seal(backend, &key, &hex_encode(entry.group_id()), &json)?,
+                    };
+                    codec::to_json(&sealed, "sealed audit entry")?
+                }
+                None => json,
+            };
+            line.push(b'\n');
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            if file.metadata()?.len() == 0 {
+                file.write_all(&schema::history_header())?;
+            }
+            file.write_all(&line)?;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Reads an audit log file, opening the sealed lines of the groups in
+    /// `group_secrets`. Returns the entries read and how many sealed lines
+    /// stayed shut.
+    pub fn read_file(
+        &self,
+        bytes: &[u8],
+        group_secrets: &HashMap<Vec<u8>, Vec<u8>>,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(Vec<AuditEntry>, usize), NodeError> {
+        let mut entries = Vec::new();
+        let mut shut = 0;
+        for line in schema::decode_history::<Value>(bytes)? {
+            let (group_id, sealed) = match serde_json::from_value(line.clone()) {
+                Ok(SealedLine::Sealed { group_id, sealed }) => (group_id, sealed),
+                Err(_) => {
+                    entries.push(
+                        serde_json::from_value(line)
+                            .map_err(|e| NodeError::Other(e.to_string()))?,
+                    );
+                    continue;
+                }
+            };
+            let opened = match (&self.sealing_key, group_secrets.get(&group_id)) {
+                (Some(sealing_key), Some(secret)) => group_key(backend, sealing_key, secret)
+                    .and_then(|key| open(backend, &key, &hex_encode(&group_id), &sealed))
+                    .ok()
+                    .and_then(|json| serde_json::from_slice(&json).ok()),
+                _ => None,
+            };
+            match opened {
+                Some(entry) => entries.push(entry),
+                None => shut += 1,
+            }
+        }
+        Ok((entries, shut))
+    }
+}
+
+// HKDF-Extract with the group's secret as salt.
+fn group_key(
+    backend: &impl OpenMlsCryptoProvider,
+    sealing_key: &[u8],
+    group_secret: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    if group_secret.is_empty() {
+        return Err(NodeError::Other("Group has no history secret".to_string()));
+    }
+    backend
+        .crypto()
+        .hkdf_extract(HashType::Sha2_256, group_secret, sealing_key)
+        .map_err(|e| NodeError::Other(format!("Could not derive history key: {:?}", e)))
+}
@@ -0,0 +1,360 @@
+//! Catching new members up on what was said before they joined.
+//!
+//! MLS gives joiners nothing from before their Welcome. Groups keep no
+//! history by default; with a retention policy set, see `Node::set_retention`,
+//! a member keeps the group's latest texts in memory, up to a count and an
+//! age, and after adding members sends them in an application message of
+//! the new epoch, `0xF1 | JSON`, naming the joiners. Other members drop it.
+//!
+//! Members removed and added again are joiners too, but what was said while
+//! they were away is up to the group's [`Disclosure`] on the sender's side,
+//! see `Node::set_disclosure`: by default they get only the kept texts from
+//! before their removal, with `summary` also how many texts they missed and
+//! from whom, with `full` everything kept.
+//!
+//! MLS authenticates the member sharing the backlog, but who said what in
+//! it is only as that member saw it. Groups under the maximum privacy
+//! policy keep nothing.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{codec, error::NodeError};
+
+const MARKER: u8 = 0xF1;
+
+/// The most texts a group keeps for joiners, so a backlog fits a message.
+pub const MAX_RETAINED: usize = 200;
+
+/// How much of a group's history joiners are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// How many of the latest texts; none when 0.
+    pub messages: usize,
+    /// Older texts are left out.
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    pub fn new(messages: usize, max_age: Option<Duration>) -> Result<Retention, NodeError> {
+        if messages > MAX_RETAINED {
+            return Err(NodeError::Other(format!(
+                "Groups keep at most {} messages for joiners",
+                MAX_RETAINED
+            )));
+        }
+        Ok(Retention { messages, max_age })
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.messages == 0
+    }
+}
+
+impl Display for Retention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max_age {
+            _ if self.is_off() => write!(f, "no history for joiners"),
+            Some(age) if age.as_secs() % 3600 == 0 => write!(
+                f,
+                "the last {} messages of the last {}h for joiners",
+                self.messages,
+                age.as_secs() / 3600
+            ),
+            Some(age) => write!(
+                f,
+                "the last {} messages of the last {}s for joiners",
+                self.messages,
+                age.as_secs()
+            ),
+            None => write!(f, "the last {} messages for joiners", self.messages),
+        }
+    }
+}
+
+/// What members added back after a removal are sent of their absence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Disclosure {
+    /// Nothing said since the removal.
+    #[default]
+    None,
+    /// How many texts they missed and who sent them, without the texts.
+    Summary,
+    /// Every text kept, as for anyone joining.
+    Full,
+}
+
+impl FromStr for Disclosure {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<Disclosure, NodeError> {
+        match s {
+            "none" => Ok(Disclosure::None),
+            "summary" => Ok(Disclosure::Summary),
+            "full" => Ok(Disclosure::Full),
+            _ => Err(NodeError::Other(format!(
+                "Unknown disclosure {}, expected none, summary or full",
+                s
+            ))),
+        }
+    }
+}
+
+impl Display for Disclosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Disclosure::None => "none",
+            Disclosure::Summary => "summary",
+            Disclosure::Full => "full",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The texts a member added back missed while away, under
+/// [`Disclosure::Summary`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Absence {
+    pub messages: usize,
+    /// Credential identities of their authors.
+    pub senders: Vec<String>,
+}
+
+impl Display for Absence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} messages from {} while we were away",
+            self.messages,
+            self.senders.join(", ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedMessage {
+    /// The credential identity of the author.
+    pub sender: String,
+    /// Seconds since the UNIX epoch, when the sharer saw it.
+    pub at: u64,
+    pub text: String,
+}
+
+impl Display for SharedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {}", self.at, self.sender, self.text)
+    }
+}
+
+/// A group's latest texts under its retention policy, and how many had
+/// been kept when each member that left did.
+#[derive(Debug, Default)]
+pub struct Recent {
+    retention: Retention,
+    disclosure: Disclosure,
+    // Each text with the count of texts pushed before it.
+    messages: VecDeque<(u64, SharedMessage)>,
+    pushed: u64,
+    departed: HashMap<String, u64>,
+}
+
+impl Recent {
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    pub fn disclosure(&self) -> Disclosure {
+        self.disclosure
+    }
+
+    pub fn set_disclosure(&mut self, disclosure: Disclosure) {
+        self.disclosure = disclosure;
+    }
+
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.trim();
+    }
+
+    pub fn push(&mut self, message: SharedMessage) {
+        if self.retention.is_off() {
+            return;
+        }
+        self.messages.push_back((self.pushed, message));
+        self.pushed += 1;
+        self.trim();
+    }
+
+    /// Records that the member `identity` was removed, after every text
+    /// pushed so far.
+    pub fn departed(&mut self, identity: String) {
+        self.departed.insert(identity, self.pushed);
+    }
+
+    /// The texts to send joiners at `now`, seconds since the UNIX epoch.
+    pub fn kept(&self, now: u64) -> Vec<SharedMessage> {
+        self.counted(now)
+            .map(|(_, message)| message.clone())
+            .collect()
+    }
+
+    /// The texts to send the joiner `identity` at `now`, and a summary of
+    /// the rest when it was removed before; forgets its removal.
+    pub fn share_with(
+        &mut self,
+        identity: &str,
+        now: u64,
+    ) -> (Vec<SharedMessage>, Option<Absence>) {
+        let departed = match self.departed.remove(identity) {
+            Some(departed) if self.disclosure != Disclosure::Full => departed,
+            _ => return (self.kept(now), None),
+        };
+        let (before, since): (Vec<_>, Vec<_>) = self
+            .counted(now)
+            .partition(|(pushed, _)| *pushed < departed);
+        let absence = match self.disclosure {
+            Disclosure::Summary if !since.is_empty() => Some(Absence {
+                messages: since.len(),
+                senders: since
+                    .iter()
+                    .map(|(_, message)| message.sender.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+            }),
+            _ => None,
+        };
+        let before = before.into_iter().map(|(_, message)| message.clone());
+        (before.collect(), absence)
+    }
+
+    // The texts kept at `now`, with their counts.
+    fn counted(&self, now: u64) -> impl Iterator<Item = &(u64, SharedMessage)> {
+        let oldest = match self.retention.max_age {
+            Some(age) => now.saturating_sub(age.as_secs()),
+            None => 0,
+        };
+        self.messages
+            .iter()
+            .filter(move |(_, message)| message.at >= oldest)
+    }
+
+    fn trim(&mut self) {
+        let excess = self.messages.len().saturating_sub(self.retention.messages);
+        self.messages.drain(..excess);
+    }
+}
+
+/// Texts sent to the members just added.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backlog {
+    /// Credential identities of the joiners.
+    pub recipients: Vec<String>,
+    pub messages: Vec<SharedMessage>,
+    /// For members added back under [`Disclosure::Summary`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub absence: Option<Absence>,
+}
+
+impl Backlog {
+    pub fn is_backlog(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_json(self, "backlog")?);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Backlog, NodeError> {
+        if !Backlog::is_backlog(bytes) {
+            return Err(NodeError::Parse("Not a backlog".to_string()));
+        }
+        serde_json::from_slice(&bytes[1..])
+            .map_err(|e| NodeError::Parse(format!("Invalid backlog: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(at: u64, text: &str) -> SharedMessage {
+        SharedMessage {
+            sender: "alice".to_string(),
+            at,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn recent_keeps_what_the_retention_allows() {
+        let mut recent = Recent::default();
+        recent.push(message(100, "before"));
+        assert!(recent.kept(100).is_empty());
+
+        recent.set_retention(Retention::new(2, Some(Duration::from_secs(60))).unwrap());
+        for (at, text) in [(100, "one"), (150, "two"), (200, "three")] {
+            recent.push(message(at, text));
+        }
+        let texts = |kept: Vec<SharedMessage>| -> Vec<String> {
+            kept.into_iter().map(|message| message.text).collect()
+        };
+        assert_eq!(texts(recent.kept(200)), ["two", "three"]);
+        assert_eq!(texts(recent.kept(240)), ["three"]);
+        recent.set_retention(Retention::new(1, None).unwrap());
+        assert_eq!(texts(recent.kept(1000)), ["three"]);
+        assert!(Retention::new(MAX_RETAINED + 1, None).is_err());
+
+        let backlog = Backlog {
+            recipients: vec!["bob".to_string()],
+            messages: recent.kept(1000),
+            absence: None,
+        };
+        assert_eq!(
+            Backlog::decode(&backlog.encode().unwrap()).unwrap(),
+            backlog
+        );
+    }
+
+    #[test]
+    fn members_added_back_hear_of_their_absence_as_disclosed() {
+        let mut recent = Recent::default();
+        recent.set_retention(Retention::new(10, None).unwrap());
+        recent.push(message(100, "before"));
+        recent.departed("bob".to_string());
+        recent.push(message(100, "away"));
+        let texts = |kept: &[SharedMessage]| -> Vec<String> {
+            kept.iter().map(|message| message.text.clone()).collect()
+        };
+
+        let (kept, absence) = recent.share_with("bob", 100);
+        assert_eq!((texts(&kept), absence), (vec!["before".to_string()], None));
+        // Back in the group, so the next time is as a joiner.
+        assert_eq!(recent.share_with("bob", 100).0.len(), 2);
+
+        recent.set_disclosure("summary".parse().unwrap());
+        recent.departed("bob".to_string());
+        recent.push(message(100, "away again"));
+        let (kept, absence) = recent.share_with("bob", 100);
+        assert_eq!(texts(&kept), ["before", "away"]);
+        assert_eq!(
+            absence,
+            Some(Absence {
+                messages: 1,
+                senders: vec!["alice".to_string()],
+            })
+        );
+
+        recent.set_disclosure(Disclosure::Full);
+        recent.departed("bob".to_string());
+        recent.push(message(100, "all of it"));
+        assert_eq!(recent.share_with("bob", 100), (recent.kept(100), None));
+        assert!("some".parse::<Disclosure>().is_err());
+    }
+}
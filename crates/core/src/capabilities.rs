@@ -0,0 +1,230 @@
+//! Application features each member understands, so senders can leave out
+//! messages some members could not read.
+//!
+//! Text and telemetry are understood by everyone. Anything else is a
+//! [`Capability`], and members advertise the set their build supports as
+//! an application message `0xF9 | bits: u32`. A newcomer advertises once it
+//! has heard from the group, as only then it knows the group's policy, see
+//! `psk`; one joining by external commit advertises right away. Members
+//! reply with theirs, once per epoch, when they hear from a member they
+//! know nothing about. Bits we do not know are kept, so newer builds can add
+//! features without breaking older ones.
+//!
+//! Members that opt in, see `Node::set_client_info`, follow the bits with
+//! coarse [`ClientInfo`], `(length: u8 | UTF-8)` for their version and then
+//! their platform, so admins can see with [`ClientStats`] who would be left
+//! behind before turning on a new feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xF9;
+const MAX_CLIENT_FIELD_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Reactions,
+    FileTransfer,
+    CrdtKv,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 3] = [
+        Capability::Reactions,
+        Capability::FileTransfer,
+        Capability::CrdtKv,
+    ];
+
+    const fn bit(self) -> u32 {
+        match self {
+            Capability::Reactions => 1,
+            Capability::FileTransfer => 1 << 1,
+            Capability::CrdtKv => 1 << 2,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Reactions => "reactions",
+            Capability::FileTransfer => "file-transfer",
+            Capability::CrdtKv => "crdt-kv",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+/// What this build understands beyond text and telemetry.
+pub const SUPPORTED: Capabilities = Capabilities(Capability::FileTransfer.bit());
+
+impl Capabilities {
+    pub fn with(self, capability: Capability) -> Capabilities {
+        Capabilities(self.0 | capability.bit())
+    }
+
+    pub fn contains(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    pub fn is_capabilities(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn encode(self) -> Vec<u8> {
+        let mut bytes = vec![MARKER];
+        bytes.extend_from_slice(&self.0.to_be_bytes());
+        bytes
+    }
+
+    /// The advertisement, followed by `client` if we share it.
+    pub fn encode_with(self, client: Option<&ClientInfo>) -> Vec<u8> {
+        let mut bytes = self.encode();
+        if let Some(client) = client {
+            for field in [&client.version, &client.platform] {
+                bytes.push(field.len() as u8);
+                bytes.extend_from_slice(field.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Reads an advertisement; anything after the bits is left for newer
+    /// builds.
+    pub fn decode(bytes: &[u8]) -> Result<Capabilities, NodeError> {
+        match bytes {
+            [MARKER, bits @ ..] if bits.len() >= 4 => Ok(Capabilities(u32::from_be_bytes(
+                bits[..4].try_into().expect("four bytes"),
+            ))),
+            _ => Err(NodeError::Parse("Malformed capabilities".to_string())),
+        }
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = Capability::ALL
+            .iter()
+            .filter(|capability| self.contains(**capability))
+            .map(|capability| capability.name())
+            .collect();
+        match names.as_slice() {
+            [] => write!(f, "text and telemetry only"),
+            names => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// What a member runs, as coarse as is useful for planning upgrades.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub version: String,
+    /// The operating system, e.g. "linux".
+    pub platform: String,
+}
+
+impl ClientInfo {
+    pub fn new(version: &str, platform: &str) -> Result<ClientInfo, NodeError> {
+        for field in [version, platform] {
+            if field.is_empty()
+                || field.len() > MAX_CLIENT_FIELD_LEN
+                || field.chars().any(|c| c.is_control())
+            {
+                return Err(NodeError::Other(format!(
+                    "Invalid client info {:?}, at most {} printable bytes",
+                    field, MAX_CLIENT_FIELD_LEN
+                )));
+            }
+        }
+        Ok(ClientInfo {
+            version: version.to_string(),
+            platform: platform.to_string(),
+        })
+    }
+
+    /// `version` of a client running on this platform.
+    pub fn this_platform(version: &str) -> Result<ClientInfo, NodeError> {
+        ClientInfo::new(version, std::env::consts::OS)
+    }
+
+    /// The client info following the bits of an advertisement, if any.
+    pub fn from_advert(bytes: &[u8]) -> Option<ClientInfo> {
+        let mut rest = bytes.get(1 + 4..)?;
+        let mut fields = Vec::new();
+        for _ in 0..2 {
+            let (len, tail) = rest.split_first()?;
+            let field = std::str::from_utf8(tail.get(..*len as usize)?).ok()?;
+            fields.push(field);
+            rest = &tail[*len as usize..];
+        }
+        ClientInfo::new(fields[0], fields[1]).ok()
+    }
+}
+
+/// How many members of a group run each version and platform.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pub members: usize,
+    pub versions: BTreeMap<String, usize>,
+    pub platforms: BTreeMap<String, usize>,
+    /// Members that did not share what they run.
+    pub undisclosed: usize,
+}
+
+impl ClientStats {
+    pub fn count(&mut self, client: Option<&ClientInfo>) {
+        self.members += 1;
+        match client {
+            Some(client) => {
+                *self.versions.entry(client.version.clone()).or_default() += 1;
+                *self.platforms.entry(client.platform.clone()).or_default() += 1;
+            }
+            None => self.undisclosed += 1,
+        }
+    }
+}
+
+impl Display for ClientStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let counts = |counts: &BTreeMap<String, usize>| {
+            counts
+                .iter()
+                .map(|(name, count)| format!("{} x{}", name, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        writeln!(f, "{} members", self.members)?;
+        writeln!(f, "versions: {}", counts(&self.versions))?;
+        writeln!(f, "platforms: {}", counts(&self.platforms))?;
+        write!(f, "not shared: {}", self.undisclosed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_info_follows_the_bits() {
+        let capabilities = SUPPORTED.with(Capability::Reactions);
+        let client = ClientInfo::new("0.1.0", "linux").unwrap();
+        let advert = capabilities.encode_with(Some(&client));
+        assert_eq!(Capabilities::decode(&advert).unwrap(), capabilities);
+        assert_eq!(ClientInfo::from_advert(&advert), Some(client.clone()));
+        assert_eq!(ClientInfo::from_advert(&capabilities.encode()), None);
+        assert_eq!(ClientInfo::from_advert(&advert[..advert.len() - 1]), None);
+        assert!(ClientInfo::new("0.1.0\n", "linux").is_err());
+
+        let mut stats = ClientStats::default();
+        stats.count(Some(&client));
+        stats.count(Some(&client));
+        stats.count(None);
+        assert_eq!(stats.versions["0.1.0"], 2);
+        assert_eq!(
+            stats.to_string(),
+            "3 members\nversions: 0.1.0 x2\nplatforms: linux x2\nnot shared: 1"
+        );
+    }
+}
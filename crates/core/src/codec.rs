@@ -0,0 +1,57 @@
+//! Serialization of what we send and store.
+//!
+//! TLS-encoding an MLS structure or writing one of our payloads as JSON can
+//! fail, if rarely, e.g. on a vector longer than its length prefix allows.
+//! Everything goes through here so such a failure comes back as an error
+//! naming what could not be serialized, for the caller to report, instead
+//! of aborting the node.
+
+use openmls::prelude::{TlsDeserializeTrait, TlsSerializeTrait};
+use serde::Serialize;
+
+use crate::error::NodeError;
+
+/// TLS-encodes `value`; `what` names it in the error.
+pub fn to_tls(value: &impl TlsSerializeTrait, what: &str) -> Result<Vec<u8>, NodeError> {
+    value
+        .tls_serialize_detached()
+        .map_err(|e| NodeError::serialize(what, e))
+}
+
+/// Reads a TLS-encoded `T` off the front of `bytes`, leaving the rest.
+pub fn from_tls<T: TlsDeserializeTrait>(bytes: &mut &[u8], what: &str) -> Result<T, NodeError> {
+    T::tls_deserialize(bytes).map_err(|_| NodeError::Parse(format!("Malformed {}", what)))
+}
+
+pub fn to_json(value: &impl Serialize, what: &str) -> Result<Vec<u8>, NodeError> {
+    serde_json::to_vec(value).map_err(|e| NodeError::serialize(what, e))
+}
+
+pub fn to_json_string(value: &impl Serialize, what: &str) -> Result<String, NodeError> {
+    serde_json::to_string(value).map_err(|e| NodeError::serialize(what, e))
+}
+
+pub fn to_json_pretty(value: &impl Serialize, what: &str) -> Result<String, NodeError> {
+    serde_json::to_string_pretty(value).map_err(|e| NodeError::serialize(what, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls::prelude::KeyPackage;
+    use std::collections::HashMap;
+
+    #[test]
+    fn failures_are_errors_naming_what_failed() {
+        let key_package = Node::default().get_key_package();
+        let bytes = to_tls(&key_package, "key package").unwrap();
+        let decoded: KeyPackage = from_tls(&mut bytes.as_slice(), "key package").unwrap();
+        assert_eq!(decoded, key_package);
+
+        // JSON objects only take string keys.
+        let unkeyable = HashMap::from([((1, 2), 3)]);
+        let error = to_json(&unkeyable, "roster").unwrap_err().to_string();
+        assert!(error.starts_with("Could not serialize roster"), "{}", error);
+    }
+}
@@ -0,0 +1,283 @@
+//! Behavioural conformance suite for clients that interoperate with p2p-mls.
+//!
+//! A client under test is wrapped in [`ConformanceClient`], which only deals
+//! in wire bytes so bindings (WASM, mobile) can be driven through the same
+//! interface as [`Node`]. [`Simulation`] is an in-memory network that
+//! broadcasts frames between clients, and [`run`] plays every check against
+//! fresh clients from a factory, so mixed groups can be tested by handing out
+//! different implementations.
+//!
+//! Enabled with the `conformance` feature.
+
+use std::fmt::Display;
+
+use openmls::prelude::{
+    KeyPackage, MlsMessageOut, TlsDeserializeTrait, TlsSerializeTrait, Welcome,
+};
+
+use crate::{codec, error::NodeError, node::Node};
+
+pub trait ConformanceClient {
+    fn create_group(&mut self) -> Result<(), NodeError>;
+    /// A TLS-encoded key package.
+    fn key_package(&self) -> Vec<u8>;
+    /// Adds the member and returns the commit and Welcome frames.
+    fn add_member(&mut self, key_package: &[u8]) -> Result<(Vec<u8>, Vec<u8>), NodeError>;
+    fn join(&mut self, welcome: &[u8]) -> Result<(), NodeError>;
+    fn send(&mut self, text: &str) -> Result<Vec<u8>, NodeError>;
+    /// Processes any group frame, returning the text of application messages.
+    fn receive(&mut self, frame: &[u8]) -> Result<Option<String>, NodeError>;
+    fn epoch(&self) -> Option<u64>;
+}
+
+impl ConformanceClient for Node {
+    fn create_group(&mut self) -> Result<(), NodeError> {
+        self.join_new_group();
+        Ok(())
+    }
+
+    fn key_package(&self) -> Vec<u8> {
+        self.get_key_package()
+            .tls_serialize_detached()
+            .expect("key package should serialize")
+    }
+
+    fn add_member(&mut self, key_package: &[u8]) -> Result<(Vec<u8>, Vec<u8>), NodeError> {
+        let key_package = KeyPackage::tls_deserialize(&mut &*key_package)
+            .map_err(|e| NodeError::Parse(format!("Invalid key package: {:?}", e)))?;
+        let (commit, invite) = self.add_member_to_group(key_package)?;
+        Ok((
+            codec::to_tls(&commit, "commit")?,
+            codec::to_tls(&invite.welcome, "Welcome")?,
+        ))
+    }
+
+    fn join(&mut self, welcome: &[u8]) -> Result<(), NodeError> {
+        let welcome = Welcome::tls_deserialize(&mut &*welcome)
+            .map_err(|e| NodeError::Parse(format!("Invalid welcome: {:?}", e)))?;
+        self.join_existing_group(welcome.into()).map(|_| ())
+    }
+
+    fn send(&mut self, text: &str) -> Result<Vec<u8>, NodeError> {
+        codec::to_tls(&self.create_message(text)?, "message")
+    }
+
+    fn receive(&mut self, frame: &[u8]) -> Result<Option<String>, NodeError> {
+        let msg_out = MlsMessageOut::try_from_bytes(frame)
+            .map_err(|e| NodeError::Parse(format!("Invalid frame: {:?}", e)))?;
+        self.parse_message(msg_out)
+    }
+
+    fn epoch(&self) -> Option<u64> {
+        self.security_state().epoch
+    }
+}
+
+/// Clients on a shared in-memory topic. Client 0 creates the group.
+pub struct Simulation<C> {
+    pub clients: Vec<C>,
+}
+
+impl<C: ConformanceClient> Simulation<C> {
+    /// Builds a group of `size` clients from `new_client`, adding members one
+    /// commit at a time as the leader would.
+    pub fn group(size: usize, new_client: &mut dyn FnMut() -> C) -> Result<Simulation<C>, String> {
+        let mut simulation = Simulation {
+            clients: vec![new_client()],
+        };
+        simulation.clients[0]
+            .create_group()
+            .map_err(|e| format!("create group: {}", e))?;
+        for _ in 1..size {
+            simulation.add(new_client())?;
+        }
+        Ok(simulation)
+    }
+
+    /// Adds `client` through the leader and delivers the commit to everyone else.
+    pub fn add(&mut self, mut client: C) -> Result<(), String> {
+        let (commit, welcome) = self.clients[0]
+            .add_member(&client.key_package())
+            .map_err(|e| format!("add member: {}", e))?;
+        self.broadcast(0, &commit)?;
+        client.join(&welcome).map_err(|e| format!("join: {}", e))?;
+        self.clients.push(client);
+        Ok(())
+    }
+
+    /// Delivers `frame` from client `from` to every other client and returns
+    /// what each of them decrypted, in client order.
+    pub fn broadcast(&mut self, from: usize, frame: &[u8]) -> Result<Vec<Option<String>>, String> {
+        let mut received = Vec::new();
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            if i != from {
+                received.push(
+                    client
+                        .receive(frame)
+                        .map_err(|e| format!("client {} receive: {}", i, e))?,
+                );
+            }
+        }
+        Ok(received)
+    }
+
+    pub fn send(&mut self, from: usize, text: &str) -> Result<Vec<Option<String>>, String> {
+        let frame = self.clients[from]
+            .send(text)
+            .map_err(|e| format!("client {} send: {}", from, e))?;
+        self.broadcast(from, &frame)
+    }
+}
+
+fn expect(condition: bool, failure: &str) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(failure.to_string())
+    }
+}
+
+fn everyone_reads_everyone<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let mut simulation = Simulation::group(3, new_client)?;
+    for from in 0..3 {
+        let text = format!("hello from {}", from);
+        let received = simulation.send(from, &text)?;
+        expect(
+            received.iter().all(|r| r.as_deref() == Some(text.as_str())),
+            "a member did not decrypt an application message",
+        )?;
+    }
+    Ok(())
+}
+
+fn commits_advance_every_epoch<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let simulation = Simulation::group(3, new_client)?;
+    let epochs: Vec<Option<u64>> = simulation.clients.iter().map(C::epoch).collect();
+    expect(
+        epochs.iter().all(|epoch| *epoch == Some(2)),
+        &format!("expected every member at epoch 2, got {:?}", epochs),
+    )
+}
+
+fn late_joiner_cannot_read_history<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let mut simulation = Simulation::group(2, new_client)?;
+    let old = simulation.clients[0]
+        .send("before you joined")
+        .map_err(|e| e.to_string())?;
+    simulation.add(new_client())?;
+    expect(
+        !matches!(simulation.clients[2].receive(&old), Ok(Some(_))),
+        "a new member decrypted a message from before it joined",
+    )
+}
+
+fn replays_are_rejected<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let mut simulation = Simulation::group(2, new_client)?;
+    let frame = simulation.clients[0]
+        .send("once")
+        .map_err(|e| e.to_string())?;
+    simulation.broadcast(0, &frame)?;
+    expect(
+        !matches!(simulation.clients[1].receive(&frame), Ok(Some(_))),
+        "a replayed message was accepted",
+    )
+}
+
+fn tampering_is_rejected<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let mut simulation = Simulation::group(2, new_client)?;
+    let mut frame = simulation.clients[0]
+        .send("untouched")
+        .map_err(|e| e.to_string())?;
+    let last = frame.len() - 1;
+    frame[last] ^= 0x01;
+    expect(
+        !matches!(simulation.clients[1].receive(&frame), Ok(Some(_))),
+        "a tampered message was accepted",
+    )
+}
+
+fn welcome_is_only_for_its_recipient<C: ConformanceClient>(
+    new_client: &mut dyn FnMut() -> C,
+) -> Result<(), String> {
+    let mut leader = new_client();
+    leader.create_group().map_err(|e| e.to_string())?;
+    let invited = new_client();
+    let mut outsider = new_client();
+    let (_, welcome) = leader
+        .add_member(&invited.key_package())
+        .map_err(|e| e.to_string())?;
+    expect(
+        outsider.join(&welcome).is_err(),
+        "a Welcome was accepted by a client it was not addressed to",
+    )
+}
+
+type Check<C> = fn(&mut dyn FnMut() -> C) -> Result<(), String>;
+
+#[derive(Debug)]
+pub struct ConformanceReport {
+    pub results: Vec<(&'static str, Result<(), String>)>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "ok   {}", name)?,
+                Err(failure) => writeln!(f, "FAIL {}: {}", name, failure)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every check, each against fresh clients from `new_client`.
+pub fn run<C: ConformanceClient>(mut new_client: impl FnMut() -> C) -> ConformanceReport {
+    let checks: [(&'static str, Check<C>); 6] = [
+        ("everyone_reads_everyone", everyone_reads_everyone),
+        ("commits_advance_every_epoch", commits_advance_every_epoch),
+        (
+            "late_joiner_cannot_read_history",
+            late_joiner_cannot_read_history,
+        ),
+        ("replays_are_rejected", replays_are_rejected),
+        ("tampering_is_rejected", tampering_is_rejected),
+        (
+            "welcome_is_only_for_its_recipient",
+            welcome_is_only_for_its_recipient,
+        ),
+    ];
+    ConformanceReport {
+        results: checks
+            .iter()
+            .map(|(name, check)| (*name, check(&mut new_client)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_passes_conformance_suite() {
+        let report = run(Node::default);
+        assert!(report.passed(), "{}", report);
+    }
+}
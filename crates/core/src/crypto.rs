@@ -0,0 +1,487 @@
+#[cfg(feature = "test-utils")]
+pub mod faults;
+pub mod fingerprint;
+pub mod key_store;
+
+use lazy_static;
+
+use openmls::framing::WireFormat;
+use openmls::prelude::*;
+use openmls::{
+    credentials::{CredentialBundle, CredentialType},
+    prelude::SignatureScheme,
+};
+
+use crate::{error::NodeError, identity::PeerId, psk::GroupPsk};
+
+const PSK_LABEL: &str = "p2p-mls psk";
+
+/// The ciphersuite key packages, and so groups we start, use unless the
+/// node is configured with another, see [`parse_ciphersuite`].
+pub const CIPHERSUITE: Ciphersuite =
+    Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+
+/// The ciphersuites our Ed25519 credentials can be used with.
+pub const SUPPORTED_CIPHERSUITES: [Ciphersuite; 2] = [
+    Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519,
+    Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+];
+
+/// One of the [`SUPPORTED_CIPHERSUITES`] by its name, as `node info` shows it.
+pub fn parse_ciphersuite(name: &str) -> Result<Ciphersuite, NodeError> {
+    SUPPORTED_CIPHERSUITES
+        .into_iter()
+        .find(|ciphersuite| ciphersuite.to_string() == name)
+        .ok_or_else(|| {
+            NodeError::Parse(format!(
+                "Unsupported ciphersuite {}, use one of {}",
+                name,
+                SUPPORTED_CIPHERSUITES.map(|c| c.to_string()).join(", ")
+            ))
+        })
+}
+
+lazy_static! {
+static ref MLS_GROUP_CONFIG: MlsGroupConfig = MlsGroupConfig::builder()
+    // We send ciphertext, but external commits can only be plaintext.
+    .wire_format_policy(MIXED_CIPHERTEXT_WIRE_FORMAT_POLICY)
+    .padding_size(100)
+    .sender_ratchet_configuration(SenderRatchetConfiguration::new(
+        10,   // out_of_order_tolerance
+        2000, // maximum_forward_distance
+    ))
+    .use_ratchet_tree_extension(true)
+    .build();
+}
+
+/// The sender ratchet's window: how many generations a message may fall
+/// behind the newest one read, and how far ahead of it.
+pub fn sender_ratchet_window() -> (u32, u32) {
+    let ratchet = MLS_GROUP_CONFIG.sender_ratchet_configuration();
+    (
+        ratchet.out_of_order_tolerance(),
+        ratchet.maximum_forward_distance(),
+    )
+}
+
+pub fn generate_credential_bundle_from_identity(
+    identity: Vec<u8>,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<Credential, NodeError> {
+    generate_credential_bundle(
+        identity,
+        CredentialType::Basic,
+        SignatureScheme::ED25519,
+        backend,
+    )
+}
+
+// OpenMLS 0.4 never fills in `UnverifiedMessage::aad`, so read the authenticated
+// data of an encrypted message straight off the wire. The layout is
+// wire_format | group_id<u8> | epoch | content_type | authenticated_data<u32> | ...
+// and is only trustworthy once the message has decrypted successfully.
+pub fn ciphertext_authenticated_data(msg: &MlsMessageOut) -> Option<Vec<u8>> {
+    if msg.wire_format() != WireFormat::MlsCiphertext {
+        return None;
+    }
+    let bytes = msg.tls_serialize_detached().ok()?;
+    let group_id_len = *bytes.get(1)? as usize;
+    let aad_len_offset = 2 + group_id_len + 8 + 1;
+    let aad_len = u32::from_be_bytes(
+        bytes
+            .get(aad_len_offset..aad_len_offset + 4)?
+            .try_into()
+            .ok()?,
+    );
+    let aad_offset = aad_len_offset + 4;
+    bytes
+        .get(aad_offset..aad_offset + aad_len as usize)
+        .map(<[u8]>::to_vec)
+}
+
+// Credential identities are PeerId bytes; fall back to hex for anything else.
+pub fn credential_identity(credential: &Credential) -> String {
+    let identity = credential.identity();
+    match PeerId::from_bytes(identity) {
+        Ok(peer_id) => peer_id.to_string(),
+        Err(_) => hex_encode(identity),
+    }
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding, as HTTP basic auth and PEM use it.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes().take_while(|c| *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// A helper to create and store credentials.
+fn generate_credential_bundle(
+    identity: Vec<u8>,
+    credential_type: CredentialType,
+    signature_algorithm: SignatureScheme,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<Credential, NodeError> {
+    let credential_bundle =
+        CredentialBundle::new(identity, credential_type, signature_algorithm, backend)
+            .map_err(|e| NodeError::Crypto(format!("Could not create credential: {:?}", e)))?;
+    store_credential_bundle(&credential_bundle, backend)?;
+    Ok(credential_bundle.into_parts().0)
+}
+
+// Store the credential bundle into the key store so OpenMLS has access
+// to it.
+pub fn store_credential_bundle(
+    credential_bundle: &CredentialBundle,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<(), NodeError> {
+    let credential_id = credential_bundle
+        .credential()
+        .signature_key()
+        .tls_serialize_detached()
+        .map_err(|e| NodeError::Crypto(format!("Could not serialize signature key: {:?}", e)))?;
+    backend
+        .key_store()
+        .store(&credential_id, credential_bundle)
+        .map_err(|e| NodeError::Crypto(format!("Could not store credential: {:?}", e)))
+}
+
+// Reads a credential bundle back out of the key store.
+pub fn read_credential_bundle(
+    credential: &Credential,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Option<CredentialBundle> {
+    let credential_id = credential.signature_key().tls_serialize_detached().ok()?;
+    backend.key_store().read(&credential_id)
+}
+pub fn generate_mls_group_from_welcome(
+    backend: &impl OpenMlsCryptoProvider,
+    welcome: Welcome,
+) -> Result<MlsGroup, WelcomeError> {
+    MlsGroup::new_from_welcome(
+        backend,
+        &MLS_GROUP_CONFIG,
+        welcome,
+        None, // We use the ratchet tree extension, so we don't provide a ratchet tree here
+    )
+}
+
+// Joins a group from its public state by external commit. The commit stays
+// pending until the caller merges it.
+pub fn generate_mls_group_from_external_commit(
+    backend: &impl OpenMlsCryptoProvider,
+    tree: &[Option<Node>],
+    public_group_state: VerifiablePublicGroupState,
+    credential_bundle: &CredentialBundle,
+) -> Result<(MlsGroup, MlsMessageOut), ExternalCommitError> {
+    // OpenMLS frames the commit as plaintext but labels it with the outgoing
+    // wire format, so it only decodes when that is plaintext too.
+    let config = MlsGroupConfig::builder()
+        .wire_format_policy(MIXED_PLAINTEXT_WIRE_FORMAT_POLICY)
+        .padding_size(MLS_GROUP_CONFIG.padding_size())
+        .sender_ratchet_configuration(MLS_GROUP_CONFIG.sender_ratchet_configuration().clone())
+        .use_ratchet_tree_extension(true)
+        .build();
+    let (mut mls_group, commit) = MlsGroup::join_by_external_commit(
+        backend,
+        Some(tree),
+        public_group_state,
+        &config,
+        &[],
+        credential_bundle,
+    )?;
+    mls_group.set_configuration(&MLS_GROUP_CONFIG);
+    Ok((mls_group, commit))
+}
+
+// Makes an independent copy of a group by round-tripping it through its
+// serialized form, so callers can experiment without touching the original.
+pub fn clone_mls_group(group: &mut MlsGroup) -> Result<MlsGroup, std::io::Error> {
+    let mut serialized = Vec::new();
+    group.save(&mut serialized)?;
+    MlsGroup::load(serialized.as_slice())
+}
+
+// Copies a group configuration, changing only the padding block size.
+pub fn with_padding_size(config: &MlsGroupConfig, padding_size: usize) -> MlsGroupConfig {
+    MlsGroupConfig::builder()
+        .wire_format_policy(config.wire_format_policy())
+        .padding_size(padding_size)
+        .max_past_epochs(config.max_past_epochs())
+        .number_of_resumtion_secrets(config.number_of_resumption_secrets())
+        .use_ratchet_tree_extension(config.use_ratchet_tree_extension())
+        .sender_ratchet_configuration(config.sender_ratchet_configuration().clone())
+        .build()
+}
+
+// Mixes a pre-shared key into the current epoch, see `psk`. The exported
+// secret changes with the epoch, so each epoch seals under its own key.
+pub fn psk_epoch_key(
+    backend: &impl OpenMlsCryptoProvider,
+    mls_group: &MlsGroup,
+    psk: &GroupPsk,
+) -> Option<Vec<u8>> {
+    let secret = mls_group
+        .export_secret(backend, PSK_LABEL, psk.id().as_slice(), 32)
+        .ok()?;
+    backend
+        .crypto()
+        .hkdf_extract(HashType::Sha2_256, psk.secret(), &secret)
+        .ok()
+}
+
+pub const GROUP_ID_LEN: usize = 32;
+
+// Group ids are random, so groups started under the same name by different
+// nodes never collide. Names travel next to the Welcome, see
+// `protocol::Invite`.
+pub fn generate_group_id(backend: &impl OpenMlsCryptoProvider) -> Result<Vec<u8>, NodeError> {
+    backend
+        .rand()
+        .random_vec(GROUP_ID_LEN)
+        .map_err(|e| NodeError::Crypto(format!("Could not generate group id: {:?}", e)))
+}
+
+pub fn generate_mls_group(
+    backend: &impl OpenMlsCryptoProvider,
+    key_package: KeyPackage,
+    group_id: &[u8],
+) -> Result<MlsGroup, NodeError> {
+    let group_id = GroupId::from_slice(group_id);
+    let key_package_ref = key_package
+        .hash_ref(backend.crypto())
+        .map_err(|e| NodeError::Crypto(format!("Could not hash key package: {:?}", e)))?;
+    MlsGroup::new(
+        backend,
+        &MLS_GROUP_CONFIG,
+        group_id,
+        key_package_ref.as_slice(),
+    )
+    .map_err(|e| NodeError::Crypto(format!("Could not create group: {:?}", e)))
+}
+
+// A helper to create key package bundles.
+pub fn generate_key_package_bundle(
+    ciphersuite: Ciphersuite,
+    credential: &Credential,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<KeyPackage, NodeError> {
+    generate_key_package_bundle_with(ciphersuite, credential, vec![], backend)
+}
+
+// The same, with `extensions` such as a certificate chain, see `pki`.
+pub fn generate_key_package_bundle_with(
+    ciphersuite: Ciphersuite,
+    credential: &Credential,
+    extensions: Vec<Extension>,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<KeyPackage, NodeError> {
+    // Fetch the credential bundle from the key store
+    let credential_bundle = read_credential_bundle(credential, backend)
+        .ok_or_else(|| NodeError::Crypto("Missing own credential bundle".to_string()))?;
+
+    // Create the key package bundle
+    let key_package_bundle =
+        KeyPackageBundle::new(&[ciphersuite], &credential_bundle, backend, extensions)
+            .map_err(|e| NodeError::Crypto(format!("Could not create key package: {:?}", e)))?;
+
+    store_key_package_bundle(&key_package_bundle, backend)?;
+    Ok(key_package_bundle.into_parts().0)
+}
+
+// Store the key package bundle in the key store, keyed by its hash reference,
+// which is where OpenMLS looks for it when processing a Welcome.
+pub fn store_key_package_bundle(
+    key_package_bundle: &KeyPackageBundle,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Result<(), NodeError> {
+    let key_package_id = key_package_bundle
+        .key_package()
+        .hash_ref(backend.crypto())
+        .map_err(|e| NodeError::Crypto(format!("Could not hash key package: {:?}", e)))?;
+    backend
+        .key_store()
+        .store(key_package_id.value(), key_package_bundle)
+        .map_err(|e| NodeError::Crypto(format!("Could not store key package: {:?}", e)))
+}
+
+// Reads the private key package bundle for one of our own key packages;
+// `None` too when the key package cannot be hashed to look it up.
+pub fn read_key_package_bundle(
+    key_package: &KeyPackage,
+    backend: &impl OpenMlsCryptoProvider,
+) -> Option<KeyPackageBundle> {
+    let key_package_id = key_package.hash_ref(backend.crypto()).ok()?;
+    backend.key_store().read(key_package_id.value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+
+    #[test]
+    fn ciphersuites_parse_by_name() {
+        for ciphersuite in SUPPORTED_CIPHERSUITES {
+            assert_eq!(
+                parse_ciphersuite(&ciphersuite.to_string()).unwrap(),
+                ciphersuite
+            );
+        }
+        assert!(parse_ciphersuite("MLS_128_DHKEMP256_AES128GCM_SHA256_P256").is_err());
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        assert_eq!(base64_encode(b"alice:pw"), "YWxpY2U6cHc=");
+        for bytes in [&b""[..], b"h", b"he", b"hel", b"hello"] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).unwrap(), bytes);
+        }
+        assert!(base64_decode("aGV*bG8=").is_none());
+    }
+
+    #[test]
+    fn smoke_test() -> Result<(), ()> {
+        let backend = &OpenMlsRustCrypto::default();
+
+        let bob_credential =
+            generate_credential_bundle_from_identity("Bob1".into(), backend).unwrap();
+        let alice_credential =
+            generate_credential_bundle_from_identity("Alice1".into(), backend).unwrap();
+
+        let bob_key_package =
+            generate_key_package_bundle(CIPHERSUITE, &bob_credential, backend).unwrap();
+        let alice_key_package =
+            generate_key_package_bundle(CIPHERSUITE, &alice_credential, backend).unwrap();
+
+        let group_id = GroupId::from_slice(b"Test Group");
+
+        // Here is the group
+        let mut alice_group = MlsGroup::new(
+            backend,
+            &MLS_GROUP_CONFIG,
+            group_id,
+            alice_key_package
+                .hash_ref(backend.crypto())
+                .expect("Could not hash KeyPackage.")
+                .as_slice(),
+        )
+        .expect("An unexpected error occurred.");
+
+        let (_, welcome) = alice_group
+            .add_members(backend, &[bob_key_package])
+            .expect("Could not add members.");
+
+        // Join a group from a welcome message
+        alice_group
+            .merge_pending_commit()
+            .expect("error merging pending commit");
+        // Now Maxim can join the group.
+
+        let mut bob_group = MlsGroup::new_from_welcome(
+            backend,
+            &MLS_GROUP_CONFIG,
+            welcome,
+            // The public tree is need and transferred out of band.
+            // It is also possible to use the [`RatchetTreeExtension`]
+            //Some(alice_group.export_ratchet_tree()),
+            None,
+        )
+        .expect("Error joining group from Welcome");
+
+        // try sending some messages and then updating commit package
+        let message_alice = b"Hi, I'm Alice!";
+        let mls_message_out = alice_group
+            .create_message(backend, message_alice)
+            .expect("Error creating application message.");
+
+        let unverified_message = bob_group
+            .parse_message(mls_message_out.into(), backend)
+            .expect("Could not parse message.");
+
+        let processed_message = bob_group
+            .process_unverified_message(
+                unverified_message,
+                None, // No external signature key
+                backend,
+            )
+            .expect("Could not process unverified message.");
+
+        if let ProcessedMessage::ApplicationMessage(application_message) = processed_message {
+            // Check the message
+            assert_eq!(application_message.into_bytes(), b"Hi, I'm Alice!");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn helpers_report_backend_failures() {
+        use crate::crypto::{faults::Fault, key_store::Backend};
+
+        let backend = Backend::default();
+        let faults = backend.faults().clone();
+        faults.inject(Fault::KeyStoreWrite);
+        assert!(generate_credential_bundle_from_identity("Bob".into(), &backend).is_err());
+        faults.clear(Fault::KeyStoreWrite);
+        let credential = generate_credential_bundle_from_identity("Bob".into(), &backend).unwrap();
+
+        faults.inject(Fault::Sign);
+        assert!(generate_key_package_bundle(CIPHERSUITE, &credential, &backend).is_err());
+        faults.clear(Fault::Sign);
+        let key_package = generate_key_package_bundle(CIPHERSUITE, &credential, &backend).unwrap();
+
+        faults.inject(Fault::Hash);
+        assert!(read_key_package_bundle(&key_package, &backend).is_none());
+        assert!(generate_mls_group(&backend, key_package.clone(), b"group").is_err());
+        faults.clear(Fault::Hash);
+        assert!(read_key_package_bundle(&key_package, &backend).is_some());
+        assert!(generate_mls_group(&backend, key_package, b"group").is_ok());
+    }
+}
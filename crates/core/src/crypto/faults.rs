@@ -0,0 +1,232 @@
+//! A crypto backend that fails on demand, for testing error paths.
+//!
+//! Built with the `test-utils` feature, [`Backend`](super::key_store::Backend)
+//! runs on [`FaultyCrypto`] and its key store checks the same [`Faults`]:
+//! after `Faults::inject`, every operation of that kind fails until
+//! `Faults::clear`, so tests can drive `crypto` helpers and `Node` down
+//! branches real RustCrypto never takes.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::{
+    crypto::OpenMlsCrypto,
+    random::OpenMlsRand,
+    types::{
+        AeadType, Ciphersuite, CryptoError, ExporterSecret, HashType, HpkeCiphertext, HpkeConfig,
+        HpkeKeyPair, KemOutput, SignatureScheme,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Storing a value in the key store.
+    KeyStoreWrite,
+    /// Hashing, and the HKDF key package references are derived with.
+    Hash,
+    Sign,
+}
+
+impl Fault {
+    fn bit(self) -> u8 {
+        match self {
+            Fault::KeyStoreWrite => 1,
+            Fault::Hash => 1 << 1,
+            Fault::Sign => 1 << 2,
+        }
+    }
+}
+
+/// The faults injected into one backend, shared by its crypto provider and
+/// key store.
+#[derive(Debug, Clone, Default)]
+pub struct Faults(Arc<AtomicU8>);
+
+impl Faults {
+    pub fn inject(&self, fault: Fault) {
+        self.0.fetch_or(fault.bit(), Ordering::SeqCst);
+    }
+
+    pub fn clear(&self, fault: Fault) {
+        self.0.fetch_and(!fault.bit(), Ordering::SeqCst);
+    }
+
+    pub fn is_set(&self, fault: Fault) -> bool {
+        self.0.load(Ordering::SeqCst) & fault.bit() != 0
+    }
+
+    fn check(&self, fault: Fault) -> Result<(), CryptoError> {
+        match self.is_set(fault) {
+            true => Err(CryptoError::CryptoLibraryError),
+            false => Ok(()),
+        }
+    }
+}
+
+/// RustCrypto, except for the operations its [`Faults`] say fail.
+#[derive(Debug)]
+pub struct FaultyCrypto {
+    inner: RustCrypto,
+    faults: Faults,
+}
+
+impl FaultyCrypto {
+    pub fn new(faults: Faults) -> FaultyCrypto {
+        FaultyCrypto {
+            inner: RustCrypto::default(),
+            faults,
+        }
+    }
+}
+
+impl OpenMlsCrypto for FaultyCrypto {
+    fn supports(&self, ciphersuite: Ciphersuite) -> Result<(), CryptoError> {
+        self.inner.supports(ciphersuite)
+    }
+
+    fn supported_ciphersuites(&self) -> Vec<Ciphersuite> {
+        self.inner.supported_ciphersuites()
+    }
+
+    fn hkdf_extract(
+        &self,
+        hash_type: HashType,
+        salt: &[u8],
+        ikm: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.faults.check(Fault::Hash)?;
+        self.inner.hkdf_extract(hash_type, salt, ikm)
+    }
+
+    fn hkdf_expand(
+        &self,
+        hash_type: HashType,
+        prk: &[u8],
+        info: &[u8],
+        okm_len: usize,
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.faults.check(Fault::Hash)?;
+        self.inner.hkdf_expand(hash_type, prk, info, okm_len)
+    }
+
+    fn hash(&self, hash_type: HashType, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.faults.check(Fault::Hash)?;
+        self.inner.hash(hash_type, data)
+    }
+
+    fn aead_encrypt(
+        &self,
+        alg: AeadType,
+        key: &[u8],
+        data: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.inner.aead_encrypt(alg, key, data, nonce, aad)
+    }
+
+    fn aead_decrypt(
+        &self,
+        alg: AeadType,
+        key: &[u8],
+        ct_tag: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.inner.aead_decrypt(alg, key, ct_tag, nonce, aad)
+    }
+
+    fn signature_key_gen(&self, alg: SignatureScheme) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        self.inner.signature_key_gen(alg)
+    }
+
+    fn verify_signature(
+        &self,
+        alg: SignatureScheme,
+        data: &[u8],
+        pk: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError> {
+        self.inner.verify_signature(alg, data, pk, signature)
+    }
+
+    fn sign(&self, alg: SignatureScheme, data: &[u8], key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.faults.check(Fault::Sign)?;
+        self.inner.sign(alg, data, key)
+    }
+
+    fn hpke_seal(
+        &self,
+        config: HpkeConfig,
+        pk_r: &[u8],
+        info: &[u8],
+        aad: &[u8],
+        ptxt: &[u8],
+    ) -> HpkeCiphertext {
+        self.inner.hpke_seal(config, pk_r, info, aad, ptxt)
+    }
+
+    fn hpke_open(
+        &self,
+        config: HpkeConfig,
+        input: &HpkeCiphertext,
+        sk_r: &[u8],
+        info: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.inner.hpke_open(config, input, sk_r, info, aad)
+    }
+
+    fn hpke_setup_sender_and_export(
+        &self,
+        config: HpkeConfig,
+        pk_r: &[u8],
+        info: &[u8],
+        exporter_context: &[u8],
+        exporter_length: usize,
+    ) -> Result<(KemOutput, ExporterSecret), CryptoError> {
+        self.inner.hpke_setup_sender_and_export(
+            config,
+            pk_r,
+            info,
+            exporter_context,
+            exporter_length,
+        )
+    }
+
+    fn hpke_setup_receiver_and_export(
+        &self,
+        config: HpkeConfig,
+        enc: &[u8],
+        sk_r: &[u8],
+        info: &[u8],
+        exporter_context: &[u8],
+        exporter_length: usize,
+    ) -> Result<ExporterSecret, CryptoError> {
+        self.inner.hpke_setup_receiver_and_export(
+            config,
+            enc,
+            sk_r,
+            info,
+            exporter_context,
+            exporter_length,
+        )
+    }
+
+    fn derive_hpke_keypair(&self, config: HpkeConfig, ikm: &[u8]) -> HpkeKeyPair {
+        self.inner.derive_hpke_keypair(config, ikm)
+    }
+}
+
+impl OpenMlsRand for FaultyCrypto {
+    type Error = <RustCrypto as OpenMlsRand>::Error;
+
+    fn random_array<const N: usize>(&self) -> Result<[u8; N], Self::Error> {
+        self.inner.random_array()
+    }
+
+    fn random_vec(&self, len: usize) -> Result<Vec<u8>, Self::Error> {
+        self.inner.random_vec(len)
+    }
+}
@@ -0,0 +1,122 @@
+//! Fingerprints and comparisons for member identities.
+//!
+//! All matching of credentials, PeerIds and keys goes through here so the
+//! comparisons are constant time and do not leak, through timing, how much
+//! of a pinned or allow-listed value an attacker has guessed.
+
+use std::fmt::Display;
+
+use openmls::prelude::{Credential, HashType, OpenMlsCrypto, OpenMlsCryptoProvider};
+
+use super::hex_encode;
+use crate::identity::PeerId;
+
+/// Compares two byte strings without short-circuiting on the first difference.
+/// Only the lengths, which are public, are compared eagerly.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // Keep the optimizer from turning the fold back into an early exit.
+    std::hint::black_box(diff) == 0
+}
+
+/// Whether two credentials carry the same identity and signature key.
+pub fn same_credential(a: &Credential, b: &Credential) -> bool {
+    // Evaluate both so the result does not reveal which part differed.
+    let identity = ct_eq(a.identity(), b.identity());
+    let signature_key = ct_eq(a.signature_key().as_slice(), b.signature_key().as_slice());
+    identity & signature_key
+}
+
+pub fn same_signature_key(a: &Credential, b: &Credential) -> bool {
+    ct_eq(a.signature_key().as_slice(), b.signature_key().as_slice())
+}
+
+/// Whether `credential` belongs to `peer_id`, i.e. its identity is the PeerId bytes.
+pub fn credential_matches_peer(credential: &Credential, peer_id: &PeerId) -> bool {
+    ct_eq(credential.identity(), &peer_id.to_bytes())
+}
+
+/// Whether `credential` has the printed `identity`, as produced by
+/// `crypto::credential_identity`.
+pub fn credential_has_identity(credential: &Credential, identity: &str) -> bool {
+    ct_eq(
+        super::credential_identity(credential).as_bytes(),
+        identity.as_bytes(),
+    )
+}
+
+/// SHA-256 over a credential's signature key, for out-of-band verification.
+#[derive(Debug, Clone, Copy, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of_credential(
+        credential: &Credential,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Fingerprint {
+        let hash = backend
+            .crypto()
+            .hash(HashType::Sha2_256, credential.signature_key().as_slice())
+            .expect("SHA-256 is always supported");
+        Fingerprint(hash.try_into().expect("SHA-256 digests are 32 bytes"))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Fingerprint {
+    fn eq(&self, other: &Fingerprint) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+// Printed as groups of four hex digits, which is easier to read aloud.
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = hex_encode(&self.0);
+        let groups: Vec<&str> = (0..hex.len()).step_by(4).map(|i| &hex[i..i + 4]).collect();
+        write!(f, "{}", groups.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+
+    #[test]
+    fn fingerprints_compare_credentials() {
+        assert!(ct_eq(b"abc", b"abc"));
+        assert!(!ct_eq(b"abc", b"abd"));
+        assert!(!ct_eq(b"abc", b"ab"));
+
+        let backend = OpenMlsRustCrypto::default();
+        let alice = Node::default();
+        let bob = Node::default();
+        let alice_credential = alice.get_key_package().credential().clone();
+        let bob_credential = bob.get_key_package().credential().clone();
+        assert!(same_credential(&alice_credential, &alice_credential));
+        assert!(!same_credential(&alice_credential, &bob_credential));
+        assert!(credential_matches_peer(
+            &alice_credential,
+            &alice.get_network_keypair().public().to_peer_id()
+        ));
+
+        let fingerprint = Fingerprint::of_credential(&alice_credential, &backend);
+        assert_eq!(
+            fingerprint,
+            Fingerprint::of_credential(&alice_credential, &backend)
+        );
+        assert_ne!(
+            fingerprint,
+            Fingerprint::of_credential(&bob_credential, &backend)
+        );
+        assert_eq!(fingerprint.to_string().len(), 64 + 15);
+    }
+}
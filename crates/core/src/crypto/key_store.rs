@@ -0,0 +1,414 @@
+//! An OpenMLS key store that survives restarts.
+//!
+//! `OpenMlsRustCrypto` keeps credential and key package bundles in memory
+//! only. [`FileKeyStore`] keeps them in memory too, and once given a path
+//! rewrites the whole store to it, as a versioned [`Artifact::KeyStore`],
+//! after every change. The file is written next to its final place and
+//! renamed over it, so a crash leaves the old or the new store, never half
+//! of one. It holds private keys and is only readable by its owner.
+//!
+//! A node whose identity is sealed under a passphrase, see `provision`,
+//! seals the store under it too, since the store holds the same signature
+//! key: `MAGIC | iterations: u32 | salt | nonce | ciphertext`, with
+//! ChaCha20-Poly1305 under a passphrase-derived key, like a backup section.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use openmls_rust_crypto::OpenMlsRustCrypto;
+#[cfg(not(feature = "test-utils"))]
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::{
+    key_store::{FromKeyStoreValue, OpenMlsKeyStore, ToKeyStoreValue},
+    random::OpenMlsRand,
+    OpenMlsCryptoProvider,
+};
+
+#[cfg(feature = "test-utils")]
+use super::faults::{Fault, Faults, FaultyCrypto};
+use crate::{
+    backup::{derive_key, open, seal, SALT_LEN},
+    error::NodeError,
+    schema::{self, Artifact},
+};
+
+pub(crate) const MAGIC: &[u8; 8] = b"P2PMLSK1";
+const OBJECT_NAME: &str = "key store";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStoreError {
+    Serialization,
+    Write(String),
+}
+
+impl From<KeyStoreError> for String {
+    fn from(error: KeyStoreError) -> String {
+        match error {
+            KeyStoreError::Serialization => "Could not serialize key store value".to_string(),
+            KeyStoreError::Write(e) => format!("Could not write key store: {}", e),
+        }
+    }
+}
+
+// The key the store is sealed under, with what derived it.
+struct Sealing {
+    key: Vec<u8>,
+    salt: Vec<u8>,
+    iterations: u32,
+}
+
+#[derive(Default)]
+pub struct FileKeyStore {
+    values: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+    path: Option<PathBuf>,
+    sealing: Option<Sealing>,
+    #[cfg(feature = "test-utils")]
+    faults: Faults,
+}
+
+// Without the values, which are private keys.
+impl std::fmt::Debug for FileKeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileKeyStore")
+            .field("values", &self.len())
+            .field("path", &self.path)
+            .field("sealed", &self.sealing.is_some())
+            .finish()
+    }
+}
+
+impl FileKeyStore {
+    /// Keeps the store at `path` from now on. Values already in the file are
+    /// loaded, except where we hold a value under the same key, and the
+    /// merged store is written back.
+    pub fn persist_to(&mut self, path: &Path) -> Result<(), NodeError> {
+        self.persist(path, None)
+    }
+
+    /// Like [`FileKeyStore::persist_to`], but keeps the file sealed under
+    /// `passphrase`. A store written in the clear is sealed from now on.
+    pub fn persist_sealed_to(
+        &mut self,
+        path: &Path,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<(), NodeError> {
+        self.persist(path, Some((passphrase, iterations)))
+    }
+
+    fn persist(&mut self, path: &Path, passphrase: Option<(&str, u32)>) -> Result<(), NodeError> {
+        let backend = OpenMlsRustCrypto::default();
+        let mut contents = if path.exists() {
+            Some(fs::read(path)?)
+        } else {
+            None
+        };
+        if let Some(bytes) = contents.as_ref().filter(|bytes| bytes.starts_with(MAGIC)) {
+            let header_len = MAGIC.len() + 4 + SALT_LEN;
+            let (passphrase, _) = passphrase.ok_or_else(|| {
+                NodeError::Crypto(format!(
+                    "{} is sealed, it needs the identity passphrase",
+                    path.display()
+                ))
+            })?;
+            if bytes.len() < header_len {
+                return Err(NodeError::Parse(format!(
+                    "{} is not a sealed key store",
+                    path.display()
+                )));
+            }
+            let iterations = u32::from_be_bytes(bytes[8..12].try_into().expect("4 bytes"));
+            let salt = bytes[12..header_len].to_vec();
+            let key = derive_key(&backend, passphrase, &salt, iterations)?;
+            let plain = open(&backend, &key, OBJECT_NAME, &bytes[header_len..])?;
+            self.sealing = Some(Sealing {
+                key,
+                salt,
+                iterations,
+            });
+            contents = Some(plain);
+        } else if let Some((passphrase, iterations)) = passphrase {
+            let salt = backend
+                .rand()
+                .random_vec(SALT_LEN)
+                .map_err(|e| NodeError::Crypto(format!("Could not generate salt: {:?}", e)))?;
+            self.sealing = Some(Sealing {
+                key: derive_key(&backend, passphrase, &salt, iterations)?,
+                salt,
+                iterations,
+            });
+        }
+        if let Some(contents) = contents {
+            let stored: Vec<(Vec<u8>, Vec<u8>)> = schema::decode(Artifact::KeyStore, &contents)?;
+            let values = self.values.get_mut().expect("key store lock");
+            for (key, value) in stored {
+                values.entry(key).or_insert(value);
+            }
+        }
+        self.path = Some(path.to_path_buf());
+        self.write().map_err(|e| NodeError::Crypto(e.into()))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.read().expect("key store lock").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn write(&self) -> Result<(), KeyStoreError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let values: Vec<(Vec<u8>, Vec<u8>)> = self
+            .values
+            .read()
+            .expect("key store lock")
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let mut bytes = schema::encode(Artifact::KeyStore, &values)
+            .map_err(|_| KeyStoreError::Serialization)?;
+        if let Some(sealing) = &self.sealing {
+            let mut sealed = MAGIC.to_vec();
+            sealed.extend_from_slice(&sealing.iterations.to_be_bytes());
+            sealed.extend_from_slice(&sealing.salt);
+            sealed.extend(
+                seal(
+                    &OpenMlsRustCrypto::default(),
+                    &sealing.key,
+                    OBJECT_NAME,
+                    &bytes,
+                )
+                .map_err(|e| KeyStoreError::Write(e.to_string()))?,
+            );
+            bytes = sealed;
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let write = || -> std::io::Result<()> {
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(&partial)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            fs::rename(&partial, path)
+        };
+        write().map_err(|e| KeyStoreError::Write(e.to_string()))
+    }
+}
+
+impl OpenMlsKeyStore for FileKeyStore {
+    type Error = KeyStoreError;
+
+    fn store<V: ToKeyStoreValue>(&self, k: &[u8], v: &V) -> Result<(), Self::Error> {
+        #[cfg(feature = "test-utils")]
+        if self.faults.is_set(Fault::KeyStoreWrite) {
+            return Err(KeyStoreError::Write("injected fault".to_string()));
+        }
+        let value = v
+            .to_key_store_value()
+            .map_err(|_| KeyStoreError::Serialization)?;
+        self.values
+            .write()
+            .expect("key store lock")
+            .insert(k.to_vec(), value);
+        self.write()
+    }
+
+    fn read<V: FromKeyStoreValue>(&self, k: &[u8]) -> Option<V> {
+        let values = self.values.read().expect("key store lock");
+        values
+            .get(k)
+            .and_then(|value| V::from_key_store_value(value).ok())
+    }
+
+    fn delete(&self, k: &[u8]) -> Result<(), Self::Error> {
+        let removed = self.values.write().expect("key store lock").remove(k);
+        match removed {
+            Some(_) => self.write(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(feature = "test-utils"))]
+type Crypto = RustCrypto;
+#[cfg(feature = "test-utils")]
+type Crypto = FaultyCrypto;
+
+/// The crypto provider a node runs on: RustCrypto with a [`FileKeyStore`].
+/// With `test-utils`, operations fail on demand, see `faults`.
+#[derive(Debug)]
+pub struct Backend {
+    crypto: Crypto,
+    key_store: FileKeyStore,
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        let key_store = FileKeyStore::default();
+        #[cfg(feature = "test-utils")]
+        let crypto = FaultyCrypto::new(key_store.faults.clone());
+        #[cfg(not(feature = "test-utils"))]
+        let crypto = RustCrypto::default();
+        Backend { crypto, key_store }
+    }
+}
+
+impl Backend {
+    pub fn key_store_mut(&mut self) -> &mut FileKeyStore {
+        &mut self.key_store
+    }
+
+    #[cfg(feature = "test-utils")]
+    pub fn faults(&self) -> &Faults {
+        &self.key_store.faults
+    }
+}
+
+impl OpenMlsCryptoProvider for Backend {
+    type CryptoProvider = Crypto;
+    type RandProvider = Crypto;
+    type KeyStoreProvider = FileKeyStore;
+
+    fn crypto(&self) -> &Self::CryptoProvider {
+        &self.crypto
+    }
+
+    fn rand(&self) -> &Self::RandProvider {
+        &self.crypto
+    }
+
+    fn key_store(&self) -> &Self::KeyStoreProvider {
+        &self.key_store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{
+        generate_credential_bundle_from_identity, generate_key_package_bundle,
+        read_credential_bundle, read_key_package_bundle, CIPHERSUITE,
+    };
+
+    #[test]
+    fn bundles_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-key-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keys.json");
+
+        let mut backend = Backend::default();
+        backend.key_store_mut().persist_to(&path).unwrap();
+        let credential =
+            generate_credential_bundle_from_identity(b"alice".to_vec(), &backend).unwrap();
+        let key_package = generate_key_package_bundle(CIPHERSUITE, &credential, &backend).unwrap();
+
+        let mut restarted = Backend::default();
+        restarted.key_store_mut().persist_to(&path).unwrap();
+        assert_eq!(restarted.key_store().len(), 2);
+        assert!(read_credential_bundle(&credential, &restarted).is_some());
+        assert!(read_key_package_bundle(&key_package, &restarted).is_some());
+
+        let key_package_ref = key_package.hash_ref(restarted.crypto()).unwrap();
+        restarted
+            .key_store()
+            .delete(key_package_ref.value())
+            .unwrap();
+        let mut again = Backend::default();
+        again.key_store_mut().persist_to(&path).unwrap();
+        assert_eq!(again.key_store().len(), 1);
+        assert!(Backend::default().key_store().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Whether `file` holds `secret`, as raw bytes or as the decimal lists
+    // JSON renders bytes as, nested once for the values of the store.
+    fn leaks(file: &[u8], secret: &[u8]) -> bool {
+        let decimal = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let rendered = decimal(secret);
+        [
+            secret.to_vec(),
+            rendered.clone().into_bytes(),
+            decimal(rendered.as_bytes()).into_bytes(),
+        ]
+        .iter()
+        .any(|needle| {
+            file.windows(needle.len())
+                .any(|window| window == needle.as_slice())
+        })
+    }
+
+    #[test]
+    fn sealed_store_keeps_the_signature_key_out_of_the_file() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-sealed-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("keys.json");
+
+        let mut backend = Backend::default();
+        let credential =
+            generate_credential_bundle_from_identity(b"alice".to_vec(), &backend).unwrap();
+        let bundle: serde_json::Value = serde_json::from_slice(
+            backend
+                .key_store()
+                .values
+                .read()
+                .unwrap()
+                .values()
+                .next()
+                .unwrap(),
+        )
+        .unwrap();
+        let private_key: Vec<u8> = bundle["signature_private_key"]["value"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b.as_u64().unwrap() as u8)
+            .take(32)
+            .collect();
+
+        // In the clear, the private key is there for anyone to read.
+        backend.key_store_mut().persist_to(&path).unwrap();
+        assert!(leaks(&fs::read(&path).unwrap(), &private_key));
+
+        backend
+            .key_store_mut()
+            .persist_sealed_to(&path, "secret", 1)
+            .unwrap();
+        let sealed = fs::read(&path).unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert!(!leaks(&sealed, &private_key));
+
+        let mut restarted = Backend::default();
+        assert!(restarted.key_store_mut().persist_to(&path).is_err());
+        assert!(restarted
+            .key_store_mut()
+            .persist_sealed_to(&path, "wrong", 1)
+            .is_err());
+        restarted
+            .key_store_mut()
+            .persist_sealed_to(&path, "secret", 1)
+            .unwrap();
+        assert!(read_credential_bundle(&credential, &restarted).is_some());
+        assert!(!leaks(&fs::read(&path).unwrap(), &private_key));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//! What we know about a group message that would not decrypt or verify.
+//!
+//! Rather than a bare "Could not parse message", a failure reports a
+//! [`DecryptFailure`]: the wire format, content type and epoch the frame
+//! claims, our own epoch, the sender once its sender data decrypted, and
+//! whether the sender's generation fell outside our ratchet's window. MLS
+//! encrypts the generation along with the sender, so its exact value is
+//! not known on failure, see [`Generation`]. [`DecryptFailure::remedy`]
+//! reads the numbers as what to do about it.
+
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+use openmls::framing::{errors::MessageDecryptionError, WireFormat};
+use openmls::prelude::{MlsMessageOut, ParseMessageError, UnverifiedMessageError, ValidationError};
+
+use crate::{crypto::sender_ratchet_window, error::NodeError};
+
+/// How the sender's generation compares with what our ratchet accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generation {
+    /// Nothing says the generation was the problem.
+    Unknown,
+    /// We hold no key for it: it is more than `tolerance` generations
+    /// behind the newest we read, more than `distance` ahead of it, or one
+    /// we read before and deleted the key of. OpenMLS reports all three as
+    /// one error.
+    OutOfWindow { tolerance: u32, distance: u32 },
+}
+
+impl Generation {
+    fn of(error: &ParseMessageError) -> Generation {
+        match error {
+            ParseMessageError::ValidationError(ValidationError::UnableToDecrypt(
+                MessageDecryptionError::GenerationOutOfBound,
+            )) => {
+                let (tolerance, distance) = sender_ratchet_window();
+                Generation::OutOfWindow {
+                    tolerance,
+                    distance,
+                }
+            }
+            _ => Generation::Unknown,
+        }
+    }
+}
+
+impl Display for Generation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Generation::Unknown => write!(f, "generation unknown"),
+            Generation::OutOfWindow {
+                tolerance,
+                distance,
+            } => write!(
+                f,
+                "generation already read, more than {} behind or more than {} ahead",
+                tolerance, distance
+            ),
+        }
+    }
+}
+
+/// What a message carries, as far as its framing says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Content {
+    Application,
+    Proposal,
+    Commit,
+}
+
+impl Content {
+    // OpenMLS does not export its content type, so it is told apart by name.
+    fn of(msg_out: &MlsMessageOut) -> Content {
+        match format!("{:?}", msg_out.content_type()).as_str() {
+            "Proposal" => Content::Proposal,
+            "Commit" => Content::Commit,
+            _ => Content::Application,
+        }
+    }
+}
+
+/// What to do about a message that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Remedy {
+    /// It is from an epoch we left, a copy of one we read, or too far out
+    /// of order to read: nothing.
+    Ignore,
+    /// It is from an epoch we have not reached, so commits are missing:
+    /// wait for them, or find them with `node recover`.
+    CatchUp,
+    /// It is from our epoch and still unreadable, so our view of the group
+    /// and the sender's diverged: `node recover`.
+    Recover,
+}
+
+impl Display for Remedy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Remedy::Ignore => write!(f, "nothing to do"),
+            Remedy::CatchUp => write!(f, "commits are missing, see `node recover --report`"),
+            Remedy::Recover => write!(f, "our state diverged, see `node recover`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecryptFailure {
+    pub group: String,
+    /// The sender's identity, once the sender data decrypted.
+    pub sender: Option<String>,
+    pub wire_format: WireFormat,
+    pub content: Content,
+    /// The epoch the frame claims.
+    pub epoch: u64,
+    /// Our epoch of the group when it arrived.
+    pub local_epoch: u64,
+    pub generation: Generation,
+    /// What OpenMLS said.
+    pub reason: String,
+}
+
+impl DecryptFailure {
+    /// What `msg_out` says about itself, before it is parsed.
+    pub fn of(group: &str, msg_out: &MlsMessageOut, local_epoch: u64) -> DecryptFailure {
+        DecryptFailure {
+            group: group.to_string(),
+            sender: None,
+            wire_format: msg_out.wire_format(),
+            content: Content::of(msg_out),
+            epoch: msg_out.epoch().as_u64(),
+            local_epoch,
+            generation: Generation::Unknown,
+            reason: String::new(),
+        }
+    }
+
+    /// The message failed to parse, which includes decryption.
+    pub fn parse_failed(mut self, error: &ParseMessageError) -> NodeError {
+        self.generation = Generation::of(error);
+        self.reason = error.to_string();
+        NodeError::Decrypt(Box::new(self))
+    }
+
+    /// The message decrypted, from `sender`, but failed to verify.
+    pub fn verify_failed(mut self, sender: String, error: &UnverifiedMessageError) -> NodeError {
+        self.sender = Some(sender);
+        self.reason = error.to_string();
+        NodeError::Decrypt(Box::new(self))
+    }
+
+    pub fn remedy(&self) -> Remedy {
+        match self.epoch.cmp(&self.local_epoch) {
+            Ordering::Less => Remedy::Ignore,
+            Ordering::Greater => Remedy::CatchUp,
+            Ordering::Equal => match self.generation {
+                Generation::OutOfWindow { .. } => Remedy::Ignore,
+                Generation::Unknown => Remedy::Recover,
+            },
+        }
+    }
+}
+
+impl Display for DecryptFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let wire_format = match self.wire_format {
+            WireFormat::MlsCiphertext => "ciphertext",
+            WireFormat::MlsPlaintext => "plaintext",
+        };
+        let content = match self.content {
+            Content::Application => "application message",
+            Content::Proposal => "proposal",
+            Content::Commit => "commit",
+        };
+        write!(
+            f,
+            "Could not read {} {} in {} from {}: {} (epoch {}, ours {}, {})",
+            wire_format,
+            content,
+            self.group,
+            self.sender.as_deref().unwrap_or("an unknown sender"),
+            self.reason,
+            self.epoch,
+            self.local_epoch,
+            self.generation
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::NodeEvent;
+    use crate::node::Node;
+    use crate::protocol::WireMessage;
+
+    #[test]
+    fn failures_say_which_epoch_and_generation_were_off() {
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+
+        // A copy of a message already read needs a key we deleted.
+        let frame = WireMessage::from(alice.create_message("once").unwrap())
+            .encode()
+            .unwrap();
+        bob.handle_incoming(&alice_peer, &frame);
+        let failure = match bob.handle_incoming(&alice_peer, &frame).remove(0) {
+            NodeEvent::DecryptFailed { failure, .. } => failure,
+            event => panic!("{:?}", event),
+        };
+        assert!(matches!(
+            failure.generation,
+            Generation::OutOfWindow { tolerance: 10, .. }
+        ));
+        assert_eq!((failure.epoch, failure.local_epoch), (1, 1));
+        assert_eq!(failure.content, Content::Application);
+        assert_eq!(failure.remedy(), Remedy::Ignore);
+        assert!(failure
+            .to_string()
+            .ends_with("(epoch 1, ours 1, generation already read, more than 10 behind or more than 2000 ahead)"));
+
+        // One from an epoch bob has since left.
+        let old = alice.create_message("late").unwrap();
+        let commit = alice.self_update().unwrap();
+        bob.parse_application_message(commit).unwrap();
+        match bob.parse_application_message(old) {
+            Err(NodeError::Decrypt(failure)) => {
+                assert_eq!((failure.epoch, failure.local_epoch), (1, 2));
+                assert_eq!(failure.remedy(), Remedy::Ignore);
+            }
+            result => panic!("{:?}", result),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! A minimal delivery service: mailboxes that hold frames for peers who
+//! cannot be reached over floodsub.
+//!
+//! The protocol is one JSON [`DsRequest`] per line over TCP, each answered
+//! with one JSON [`DsResponse`] line. Frames get increasing sequence numbers
+//! per mailbox, so a client fetches everything after the last one it saw.
+//! [`Mailboxes`] keeps them in memory, which is all the `p2p-mls-ds` test
+//! server needs.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{codec, error::NodeError};
+
+/// Frames kept per mailbox before the oldest are dropped.
+pub const MAILBOX_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DsRequest {
+    Deposit {
+        mailbox: String,
+        sender: String,
+        frame: Vec<u8>,
+    },
+    Fetch {
+        mailbox: String,
+        after: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredFrame {
+    pub seq: u64,
+    pub sender: String,
+    pub frame: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DsResponse {
+    Deposited { seq: u64 },
+    Frames { frames: Vec<StoredFrame> },
+    Error { reason: String },
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    next_seq: u64,
+    frames: VecDeque<StoredFrame>,
+}
+
+#[derive(Debug, Default)]
+pub struct Mailboxes {
+    mailboxes: HashMap<String, Mailbox>,
+}
+
+impl Mailboxes {
+    pub fn handle(&mut self, request: DsRequest) -> DsResponse {
+        match request {
+            DsRequest::Deposit {
+                mailbox,
+                sender,
+                frame,
+            } => {
+                let mailbox = self.mailboxes.entry(mailbox).or_default();
+                mailbox.next_seq += 1;
+                let seq = mailbox.next_seq;
+                mailbox.frames.push_back(StoredFrame { seq, sender, frame });
+                if mailbox.frames.len() > MAILBOX_CAPACITY {
+                    mailbox.frames.pop_front();
+                }
+                DsResponse::Deposited { seq }
+            }
+            DsRequest::Fetch { mailbox, after } => DsResponse::Frames {
+                frames: self
+                    .mailboxes
+                    .get(&mailbox)
+                    .map(|mailbox| {
+                        mailbox
+                            .frames
+                            .iter()
+                            .filter(|frame| frame.seq > after)
+                            .cloned()
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Serves `mailboxes` on `listener`, one thread per connection, until the
+/// listener fails.
+pub fn serve(listener: TcpListener, mailboxes: Arc<Mutex<Mailboxes>>) -> Result<(), NodeError> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let mailboxes = Arc::clone(&mailboxes);
+        std::thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &mailboxes) {
+                log::debug!("delivery service connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, mailboxes: &Mutex<Mailboxes>) -> Result<(), NodeError> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str(&line?) {
+            Ok(request) => mailboxes.lock().expect("mailboxes lock").handle(request),
+            Err(e) => DsResponse::Error {
+                reason: format!("Invalid request: {}", e),
+            },
+        };
+        let mut bytes = codec::to_json(&response, "mailbox response")?;
+        bytes.push(b'\n');
+        writer.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+/// Talks to a delivery service, with a fresh connection per request.
+#[derive(Debug, Clone)]
+pub struct DsClient {
+    address: String,
+}
+
+impl DsClient {
+    pub fn new(address: &str) -> DsClient {
+        DsClient {
+            address: address.to_string(),
+        }
+    }
+
+    pub fn deposit(&self, mailbox: &str, sender: &str, frame: &[u8]) -> Result<u64, NodeError> {
+        match self.request(&DsRequest::Deposit {
+            mailbox: mailbox.to_string(),
+            sender: sender.to_string(),
+            frame: frame.to_vec(),
+        })? {
+            DsResponse::Deposited { seq } => Ok(seq),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Frames in `mailbox` with a sequence number above `after`, oldest first.
+    pub fn fetch(&self, mailbox: &str, after: u64) -> Result<Vec<StoredFrame>, NodeError> {
+        match self.request(&DsRequest::Fetch {
+            mailbox: mailbox.to_string(),
+            after,
+        })? {
+            DsResponse::Frames { frames } => Ok(frames),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn request(&self, request: &DsRequest) -> Result<DsResponse, NodeError> {
+        let mut stream = TcpStream::connect(&self.address)?;
+        let mut bytes = codec::to_json(request, "mailbox request")?;
+        bytes.push(b'\n');
+        stream.write_all(&bytes)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        serde_json::from_str(&line)
+            .map_err(|e| NodeError::Network(format!("Invalid delivery service response: {}", e)))
+    }
+}
+
+fn unexpected(response: DsResponse) -> NodeError {
+    match response {
+        DsResponse::Error { reason } => NodeError::Network(format!("Delivery service: {}", reason)),
+        other => NodeError::Network(format!("Unexpected delivery service response {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls::prelude::{MlsMessageOut, TlsSerializeTrait};
+
+    #[test]
+    fn mailbox_delivers_group_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || serve(listener, Arc::default()));
+        let client = DsClient::new(&address);
+
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        for text in ["first", "second"] {
+            let msg = alice.create_message(text).unwrap();
+            client
+                .deposit("chat", "alice", &msg.tls_serialize_detached().unwrap())
+                .unwrap();
+        }
+        let frames = client.fetch("chat", 0).unwrap();
+        assert_eq!(frames.len(), 2);
+        for (frame, text) in frames.iter().zip(["first", "second"]) {
+            let msg = MlsMessageOut::try_from_bytes(&frame.frame).unwrap();
+            assert_eq!(bob.parse_message(msg).unwrap(), Some(text.to_string()));
+        }
+        assert!(client.fetch("chat", frames[1].seq).unwrap().is_empty());
+        assert!(client.fetch("elsewhere", 0).unwrap().is_empty());
+    }
+}
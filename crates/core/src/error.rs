@@ -0,0 +1,83 @@
+use openmls::prelude::{ParseMessageError, WelcomeError};
+use thiserror::Error;
+
+use crate::diagnostics::DecryptFailure;
+
+/// What went wrong, by kind, so callers can match on the cases they handle
+/// and show the rest. Every variant displays as the message the node has
+/// always printed for it.
+#[derive(Debug, Error)]
+pub enum NodeError {
+    /// A command aimed at the active group while we are in none.
+    #[error("Not in a group")]
+    NoGroup,
+    /// Something only the group leader does, such as "admits members".
+    #[error("Only a group leader {0}")]
+    NotLeader(&'static str),
+    /// A proposal, commit or control message from a member without the
+    /// rights it takes, see `admins`.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// Joining from a Welcome failed.
+    #[error("{0}")]
+    Welcome(#[from] WelcomeError),
+    /// An MLS message that does not parse.
+    #[error("{0}")]
+    MlsParse(#[from] ParseMessageError),
+    /// A group message that would not decrypt or verify, and why.
+    #[error("{0}")]
+    Decrypt(Box<DecryptFailure>),
+    /// A frame or stored value that does not decode.
+    #[error("{0}")]
+    Parse(String),
+    /// `what` could not be serialized, see `codec`.
+    #[error("Could not serialize {what}: {detail}")]
+    Serialization { what: String, detail: String },
+    /// The crypto backend or key store failed, see `crypto`.
+    #[error("{0}")]
+    Crypto(String),
+    /// Listening, dialing or reaching a remote service failed.
+    #[error("{0}")]
+    Network(String),
+    /// A task on the other end of a channel is gone.
+    #[error("Channel closed")]
+    ChannelClosed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Everything without a variant of its own yet.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl NodeError {
+    pub fn serialize(what: &str, error: impl std::fmt::Debug) -> NodeError {
+        NodeError::Serialization {
+            what: what.to_string(),
+            detail: format!("{:?}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_keep_their_messages() {
+        assert_eq!(NodeError::NoGroup.to_string(), "Not in a group");
+        assert_eq!(
+            NodeError::NotLeader("admits members").to_string(),
+            "Only a group leader admits members"
+        );
+        assert_eq!(
+            NodeError::NotLeader("and admins it allows remove members").to_string(),
+            "Only a group leader and admins it allows remove members"
+        );
+        assert_eq!(
+            NodeError::serialize("roster", "bad").to_string(),
+            "Could not serialize roster: \"bad\""
+        );
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert_eq!(NodeError::from(io).to_string(), "no such file");
+    }
+}
@@ -0,0 +1,84 @@
+//! What handling a frame from the network did, for the application to show.
+//!
+//! `Node::handle_incoming` takes a frame as it arrived, does whatever the
+//! protocol asks of us, and reports what happened as [`NodeEvent`]s. Frames
+//! it has to publish in turn, such as a Welcome for a member it admitted,
+//! wait in the node until the application takes them with
+//! `Node::take_outgoing`. The CLI prints the events; other applications can
+//! match on them instead.
+
+use crate::{
+    ack::MessageId, diagnostics::DecryptFailure, error::NodeError, identity::PeerId,
+    lifetime::LifetimeError, node::ApplicationPayload,
+};
+
+#[derive(Debug)]
+pub enum NodeEvent {
+    /// A payload decrypted in `group`; `peer` is `None` for messages held
+    /// back until their Welcome or commit arrived. `peer` only passed the
+    /// message on; `author` is the credential identity of the member MLS
+    /// says sent it, `None` for senders that are not members.
+    MessageReceived {
+        peer: Option<PeerId>,
+        author: Option<String>,
+        group: String,
+        payload: ApplicationPayload,
+    },
+    /// `peer` passed on a message of `group` that `author` sent, and comes
+    /// just before that message. Members publish their own messages, so
+    /// this is a relay or someone replaying others' traffic.
+    SenderMismatch {
+        peer: PeerId,
+        author: String,
+        group: String,
+    },
+    /// `reader` showed our text message `id`, number `seq` in the outbox
+    /// of `group`, see `Node::mark_read`.
+    ReadBy {
+        group: String,
+        id: MessageId,
+        seq: u64,
+        reader: String,
+    },
+    /// The member `identity` is typing in `group`; show it until
+    /// `typing::TYPING_TIMEOUT` passes without another.
+    Typing { group: String, identity: String },
+    /// The owner of the key package `peer` sent passed admission control and
+    /// waits for `Node::accept_join` or `Node::decline_join` with `id`.
+    JoinRequested {
+        peer: PeerId,
+        group: String,
+        id: u64,
+    },
+    /// We added the owner of the key package `peer` sent to `group`.
+    MemberJoined { peer: PeerId, group: String },
+    /// A commit removed a member other than us from `group`.
+    MemberRemoved { group: String, identity: String },
+    /// We joined `group` from a Welcome `peer` sent.
+    WelcomeReceived { peer: PeerId, group: String },
+    /// A commit moved `group` on to `epoch`.
+    CommitApplied { group: String, epoch: u64 },
+    /// A leader refused our key package; we made a fresh one.
+    KeyPackageRefused { peer: PeerId, reason: LifetimeError },
+    /// `peer` shared a group we can join by external commit.
+    GroupShared {
+        peer: PeerId,
+        group: String,
+        group_id: String,
+    },
+    /// `peer` answered our recovery probe.
+    RecoveryReceived { peer: PeerId },
+    /// A group message from `peer` would not decrypt or verify, see
+    /// `diagnostics` for what to do about it.
+    DecryptFailed {
+        peer: PeerId,
+        failure: DecryptFailure,
+    },
+    /// A frame from `peer` could not be handled; `context` says what we
+    /// were doing, such as "Could not join group".
+    Error {
+        peer: PeerId,
+        context: &'static str,
+        error: NodeError,
+    },
+}
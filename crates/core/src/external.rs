@@ -0,0 +1,101 @@
+//! Joining a group without its leader, by external commit.
+//!
+//! Any member can publish the group's public state, signed by them, and its
+//! ratchet tree with `node share-group`. A node that heard it joins with
+//! `node join-external`: it builds a commit adding itself and merges it
+//! right away, and members add it as they process the commit. The leader
+//! does not need to be online, but none of its admission checks run, so
+//! anyone who hears the public state can join until the next epoch; share
+//! it only for groups meant to be open.
+//!
+//! The joiner only knows the topic of the epoch its commit starts, so the
+//! commit goes out on the rendezvous topic, where every member listens.
+//!
+//! A shared group is `group_id<u8> | public group state<u32> | tree<u32> |
+//! name`, the state and each node of the tree in their TLS serialization.
+//! Groups shared before names travelled end after the tree.
+
+use openmls::prelude::{Node as TreeNode, TlsDeserializeTrait, VerifiablePublicGroupState};
+
+use crate::{
+    codec,
+    error::NodeError,
+    node::{check_group_name, group_name},
+};
+
+/// Shared groups remembered at once; further ones are ignored.
+pub const MAX_SHARED_GROUPS: usize = 32;
+
+/// What a newcomer needs to join a group by external commit.
+#[derive(Debug, Clone)]
+pub struct SharedGroup {
+    pub group_id: Vec<u8>,
+    pub public_group_state: VerifiablePublicGroupState,
+    pub tree: Vec<Option<TreeNode>>,
+    pub name: Option<String>,
+}
+
+impl SharedGroup {
+    /// The name it was shared under, else its id.
+    pub fn name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| group_name(&self.group_id))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let state = codec::to_tls(&self.public_group_state, "public group state")?;
+        let mut tree = Vec::new();
+        for node in &self.tree {
+            tree.extend(codec::to_tls(node, "ratchet tree node")?);
+        }
+        let mut bytes = vec![self.group_id.len() as u8];
+        bytes.extend_from_slice(&self.group_id);
+        for field in [state, tree] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend(field);
+        }
+        if let Some(name) = &self.name {
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SharedGroup, NodeError> {
+        let malformed = || NodeError::Parse("Malformed shared group".to_string());
+        let (group_id, rest) = match bytes {
+            [len, rest @ ..] if rest.len() >= *len as usize => rest.split_at(*len as usize),
+            _ => return Err(malformed()),
+        };
+        let (mut state, rest) = length_prefixed(rest).ok_or_else(malformed)?;
+        let (mut tree_bytes, rest) = length_prefixed(rest).ok_or_else(malformed)?;
+        let name = match rest {
+            [] => None,
+            name => {
+                let name = std::str::from_utf8(name).map_err(|_| malformed())?;
+                check_group_name(name)?;
+                Some(name.to_string())
+            }
+        };
+        let public_group_state =
+            VerifiablePublicGroupState::tls_deserialize(&mut state).map_err(|_| malformed())?;
+        let mut tree = Vec::new();
+        while !tree_bytes.is_empty() {
+            tree.push(
+                Option::<TreeNode>::tls_deserialize(&mut tree_bytes).map_err(|_| malformed())?,
+            );
+        }
+        Ok(SharedGroup {
+            group_id: group_id.to_vec(),
+            public_group_state,
+            tree,
+            name,
+        })
+    }
+}
+
+fn length_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?) as usize;
+    let rest = &bytes[4..];
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
@@ -0,0 +1,167 @@
+//! Optional behaviours a group turns on or off for all its members.
+//!
+//! Unlike capabilities, which say what each member's client can do, the
+//! feature set says what the group wants: admins, see `admins`, switch a
+//! feature with `node features` and send the whole set to the group as an
+//! application message `0xEF | flags: u8`. Members take it only from a
+//! member with some right, and every member's node then holds back the
+//! behaviour itself, sending and accepting nothing for a feature that is
+//! off. Whoever adds members sends the set again when it differs from the
+//! default, so newcomers learn it. Every feature is on by default.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xEF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Reactions to messages, for clients that support them.
+    Reactions,
+    /// Recent texts sent to joiners, see `backfill`.
+    HistorySync,
+    /// Presence and typing indicators.
+    Presence,
+    /// Read receipts, see `ack`; never under the maximum privacy policy.
+    ReadReceipts,
+}
+
+pub const FEATURES: [Feature; 4] = [
+    Feature::Reactions,
+    Feature::HistorySync,
+    Feature::Presence,
+    Feature::ReadReceipts,
+];
+
+impl Feature {
+    fn bit(self) -> u8 {
+        match self {
+            Feature::Reactions => 1,
+            Feature::HistorySync => 1 << 1,
+            Feature::Presence => 1 << 2,
+            Feature::ReadReceipts => 1 << 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Reactions => "reactions",
+            Feature::HistorySync => "history-sync",
+            Feature::Presence => "presence",
+            Feature::ReadReceipts => "read-receipts",
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Feature {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<Feature, NodeError> {
+        FEATURES
+            .into_iter()
+            .find(|feature| feature.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = FEATURES.iter().map(|feature| feature.name()).collect();
+                NodeError::Parse(format!(
+                    "{} is not a feature, try one of {}",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+/// The features a group has on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureFlags(u8);
+
+impl Default for FeatureFlags {
+    fn default() -> FeatureFlags {
+        FeatureFlags(
+            FEATURES
+                .iter()
+                .fold(0, |flags, feature| flags | feature.bit()),
+        )
+    }
+}
+
+impl FeatureFlags {
+    pub fn is_flags(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn enabled(self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub fn set(&mut self, feature: Feature, enabled: bool) {
+        if enabled {
+            self.0 |= feature.bit();
+        } else {
+            self.0 &= !feature.bit();
+        }
+    }
+
+    pub fn encode(self) -> Vec<u8> {
+        vec![MARKER, self.0]
+    }
+
+    /// The flags alone, as `metadata` carries them.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Bits of features this client does not know are dropped.
+    pub fn from_bits(bits: u8) -> FeatureFlags {
+        FeatureFlags(bits & FeatureFlags::default().0)
+    }
+
+    /// See [`FeatureFlags::from_bits`].
+    pub fn decode(bytes: &[u8]) -> Result<FeatureFlags, NodeError> {
+        match bytes {
+            [MARKER, flags] => Ok(FeatureFlags::from_bits(*flags)),
+            _ => Err(NodeError::Parse("Malformed feature flags".to_string())),
+        }
+    }
+}
+
+impl Display for FeatureFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let features: Vec<String> = FEATURES
+            .iter()
+            .map(|feature| match self.enabled(*feature) {
+                true => format!("{} on", feature),
+                false => format!("{} off", feature),
+            })
+            .collect();
+        write!(f, "{}", features.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_round_trip_and_name_their_features() {
+        let mut flags = FeatureFlags::default();
+        assert!(FEATURES.iter().all(|feature| flags.enabled(*feature)));
+        flags.set("history-sync".parse().unwrap(), false);
+        assert!(!flags.enabled(Feature::HistorySync));
+        assert_eq!(FeatureFlags::decode(&flags.encode()).unwrap(), flags);
+        assert_eq!(
+            flags.to_string(),
+            "reactions on, history-sync off, presence on, read-receipts on"
+        );
+        assert!("typing".parse::<Feature>().is_err());
+        assert!(FeatureFlags::decode(&[MARKER]).is_err());
+    }
+}
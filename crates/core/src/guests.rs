@@ -0,0 +1,199 @@
+//! Members let in for a while.
+//!
+//! Join requests accepted with `node accept <request> --guest=<secs>` add
+//! their joiners as guests, and the group is told when each guest's access
+//! ends in an application message `0xED | expires: u64 | identity`, in
+//! seconds since the UNIX epoch. Members take these only from a member who
+//! may remove others, see `admins`, and keep them with the group in
+//! backups, so a leader restarted from one still removes its guests on
+//! time, see `Node::expire_guests`. [`GUEST_WARNING`] before the end the
+//! leader sends the guest's entry again, which the guest's client shows as
+//! a warning. Admins who may remove members extend access with
+//! `node extend`, sending the new end the same way.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xED;
+
+/// How long before their access ends guests are warned.
+pub const GUEST_WARNING: Duration = Duration::from_secs(300);
+
+/// When a guest's access ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuestEntry {
+    /// The credential identity of the guest.
+    pub identity: String,
+    /// Seconds since the UNIX epoch.
+    pub expires: u64,
+}
+
+impl GuestEntry {
+    pub fn is_entry(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(self.expires.to_be_bytes());
+        bytes.extend(self.identity.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<GuestEntry, NodeError> {
+        let malformed = || NodeError::Parse("Malformed guest entry".to_string());
+        match bytes {
+            [MARKER, rest @ ..] if rest.len() > 8 => {
+                let (expires, identity) = rest.split_at(8);
+                Ok(GuestEntry {
+                    identity: String::from_utf8(identity.to_vec()).map_err(|_| malformed())?,
+                    expires: u64::from_be_bytes(expires.try_into().expect("8 bytes")),
+                })
+            }
+            _ => Err(malformed()),
+        }
+    }
+}
+
+/// What is left of a guest's access, in the largest whole unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Remaining(pub Duration);
+
+impl Display for Remaining {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.as_secs() {
+            secs if secs >= 7200 => write!(f, "{} hours", secs / 3600),
+            secs if secs >= 120 => write!(f, "{} minutes", secs / 60),
+            secs => write!(f, "{} seconds", secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Guest {
+    expires: u64,
+    // Whether the leader warned the guest since the end last changed.
+    warned: bool,
+}
+
+/// A group's guests, by credential identity.
+#[derive(Debug, Default)]
+pub struct Guests {
+    guests: BTreeMap<String, Guest>,
+}
+
+impl Guests {
+    pub fn from_entries(entries: Vec<GuestEntry>) -> Guests {
+        let mut guests = Guests::default();
+        for entry in entries {
+            guests.set(entry);
+        }
+        guests
+    }
+
+    pub fn entries(&self) -> Vec<GuestEntry> {
+        self.guests
+            .iter()
+            .map(|(identity, guest)| GuestEntry {
+                identity: identity.clone(),
+                expires: guest.expires,
+            })
+            .collect()
+    }
+
+    pub fn get(&self, identity: &str) -> Option<u64> {
+        self.guests.get(identity).map(|guest| guest.expires)
+    }
+
+    /// Records when `entry`'s guest has to go, to be warned again.
+    pub fn set(&mut self, entry: GuestEntry) {
+        self.guests.insert(
+            entry.identity,
+            Guest {
+                expires: entry.expires,
+                warned: false,
+            },
+        );
+    }
+
+    /// Moves a guest's end `by` later, counting from `now` if it passed.
+    pub fn extend(&mut self, identity: &str, by: Duration, now: u64) -> Option<GuestEntry> {
+        let expires = self.get(identity)?.max(now) + by.as_secs();
+        let entry = GuestEntry {
+            identity: identity.to_string(),
+            expires,
+        };
+        self.set(entry.clone());
+        Some(entry)
+    }
+
+    pub fn forget(&mut self, identity: &str) {
+        self.guests.remove(identity);
+    }
+
+    /// The guests to warn at `now`, counting them as warned.
+    pub fn to_warn(&mut self, now: u64) -> Vec<GuestEntry> {
+        let warning = GUEST_WARNING.as_secs();
+        self.guests
+            .iter_mut()
+            .filter(|(_, guest)| !guest.warned && guest.expires > now)
+            .filter(|(_, guest)| guest.expires - now <= warning)
+            .map(|(identity, guest)| {
+                guest.warned = true;
+                GuestEntry {
+                    identity: identity.clone(),
+                    expires: guest.expires,
+                }
+            })
+            .collect()
+    }
+
+    /// The guests whose access ended by `now`.
+    pub fn expired(&self, now: u64) -> Vec<String> {
+        self.guests
+            .iter()
+            .filter(|(_, guest)| guest.expires <= now)
+            .map(|(identity, _)| identity.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guests_are_warned_once_then_expire_unless_extended() {
+        let mut guests = Guests::default();
+        let entry = GuestEntry {
+            identity: "bob".to_string(),
+            expires: 1000,
+        };
+        assert_eq!(GuestEntry::decode(&entry.encode()).unwrap(), entry);
+        assert!(GuestEntry::decode(&[MARKER, 0, 0]).is_err());
+        guests.set(entry.clone());
+
+        assert!(guests.to_warn(600).is_empty());
+        assert_eq!(guests.to_warn(700), [entry]);
+        assert!(guests.to_warn(800).is_empty());
+        assert!(guests.expired(999).is_empty());
+
+        let extended = guests.extend("bob", Duration::from_secs(600), 900).unwrap();
+        assert_eq!(extended.expires, 1600);
+        assert!(guests.to_warn(900).is_empty());
+        assert_eq!(guests.to_warn(1300).len(), 1);
+        assert_eq!(guests.expired(1600), ["bob"]);
+        assert!(guests
+            .extend("carol", Duration::from_secs(60), 900)
+            .is_none());
+
+        let restored = Guests::from_entries(guests.entries());
+        assert_eq!(restored.get("bob"), Some(1600));
+        assert_eq!(Remaining(Duration::from_secs(299)).to_string(), "4 minutes");
+    }
+}
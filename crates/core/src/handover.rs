@@ -0,0 +1,108 @@
+//! Leaders going offline on purpose.
+//!
+//! A leader that exits tells each group it leads who takes over, so members
+//! know whom to ask for invites, and hands over the join requests still
+//! waiting for it, so joiners are not left without an answer. It first
+//! grants the successor every right, see `admins`, then sends an
+//! application message `0xF3 | JSON`, signed with its credential key over
+//! the group id like address books, see `introduction`.
+//!
+//! Members take the notice only from the leader. The successor queues the
+//! handed-over requests as if they had arrived at it and, until the leader
+//! speaks in the group again, answers join requests naming no leader.
+
+use openmls::prelude::{
+    Credential, CredentialBundle, OpenMlsCryptoProvider, Signature, TlsDeserializeTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{codec, error::NodeError};
+
+const MARKER: u8 = 0xF3;
+const SIGNATURE_LABEL: &[u8] = b"p2p-mls leader offline";
+
+/// A join request the leader had not answered yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HandedJoin {
+    pub peer: String,
+    /// The encoded `JoinRequest`, signed by the joiner.
+    pub request: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaderOffline {
+    pub group_id: Vec<u8>,
+    /// The credential identity of the member taking over.
+    pub successor: String,
+    pub joins: Vec<HandedJoin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedLeaderOffline {
+    content: String,
+    signature: Vec<u8>,
+}
+
+impl SignedLeaderOffline {
+    pub fn is_leader_offline(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn sign(
+        notice: &LeaderOffline,
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<SignedLeaderOffline, NodeError> {
+        let content = codec::to_json_string(notice, "leader offline notice")?;
+        let (_, private_key) = credential_bundle.into_parts();
+        let signature = private_key
+            .sign(backend, &signed_bytes(&content))
+            .map_err(|e| NodeError::Other(format!("Could not sign leader notice: {:?}", e)))?;
+        Ok(SignedLeaderOffline {
+            content,
+            signature: codec::to_tls(&signature, "signature")?,
+        })
+    }
+
+    /// Checks that `leader` signed the notice for `group_id`.
+    pub fn verify(
+        &self,
+        leader: &Credential,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+    ) -> Result<LeaderOffline, NodeError> {
+        let signature = Signature::tls_deserialize(&mut self.signature.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed leader notice signature".to_string()))?;
+        leader
+            .verify(backend, &signed_bytes(&self.content), &signature)
+            .map_err(|_| NodeError::Other("Invalid leader notice signature".to_string()))?;
+        let notice: LeaderOffline = serde_json::from_str(&self.content)
+            .map_err(|e| NodeError::Parse(format!("Invalid leader notice: {}", e)))?;
+        if notice.group_id != group_id {
+            return Err(NodeError::Other(
+                "Leader notice is for another group".to_string(),
+            ));
+        }
+        Ok(notice)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_json(self, "leader offline notice")?);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SignedLeaderOffline, NodeError> {
+        if !SignedLeaderOffline::is_leader_offline(bytes) {
+            return Err(NodeError::Parse("Not a leader notice".to_string()));
+        }
+        serde_json::from_slice(&bytes[1..])
+            .map_err(|e| NodeError::Parse(format!("Invalid leader notice: {}", e)))
+    }
+}
+
+fn signed_bytes(content: &str) -> Vec<u8> {
+    let mut bytes = SIGNATURE_LABEL.to_vec();
+    bytes.extend_from_slice(content.as_bytes());
+    bytes
+}
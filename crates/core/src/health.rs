@@ -0,0 +1,108 @@
+//! Whether a group is fit to send to, checked before a message is encrypted.
+//!
+//! A message encrypted while our own commit is unmerged, while members are
+//! already in a later epoch, or while no other member is connected is
+//! encrypted into the void: nobody can or will read it. [`GroupHealth`]
+//! lists what is wrong, and the [`SendPolicy`] decides whether we send
+//! anyway with a warning, refuse, or hold the message until the group is
+//! healthy again.
+
+use std::fmt::Display;
+
+use openmls::prelude::MlsMessageOut;
+
+use crate::error::NodeError;
+use crate::outbox::PendingSend;
+
+/// Messages held across all groups; further ones are refused.
+pub const MAX_HELD: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthIssue {
+    /// A commit of ours waits to be merged.
+    PendingCommit,
+    /// Traffic from members is for a later epoch than ours.
+    Behind { epoch: u64, observed: u64 },
+    /// The group has other members but none of them is connected.
+    NoMembersConnected,
+}
+
+impl Display for HealthIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthIssue::PendingCommit => write!(f, "our last commit is not merged yet"),
+            HealthIssue::Behind { epoch, observed } => write!(
+                f,
+                "we are at epoch {} but members are already at {}",
+                epoch, observed
+            ),
+            HealthIssue::NoMembersConnected => write!(f, "no other member is connected"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupHealth {
+    pub issues: Vec<HealthIssue>,
+}
+
+impl GroupHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl Display for GroupHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_healthy() {
+            return write!(f, "healthy");
+        }
+        let issues: Vec<String> = self.issues.iter().map(|issue| issue.to_string()).collect();
+        write!(f, "{}", issues.join(", "))
+    }
+}
+
+/// What to do with a message for an unhealthy group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SendPolicy {
+    #[default]
+    Warn,
+    Block,
+    Queue,
+}
+
+impl SendPolicy {
+    pub fn parse(policy: &str) -> Result<SendPolicy, NodeError> {
+        match policy {
+            "warn" => Ok(SendPolicy::Warn),
+            "block" => Ok(SendPolicy::Block),
+            "queue" => Ok(SendPolicy::Queue),
+            _ => Err(NodeError::Other(
+                "--send-health must be warn, block or queue".to_string(),
+            )),
+        }
+    }
+}
+
+/// What became of a message we were asked to send.
+#[derive(Debug)]
+pub enum SendOutcome {
+    /// Encrypted, to be published; `health` lists what we warned about.
+    Sent {
+        message: MlsMessageOut,
+        health: GroupHealth,
+    },
+    /// Encrypted and waiting for `Node::take_outgoing`, as
+    /// `Node::broadcast` leaves it.
+    Queued { health: GroupHealth },
+    /// Left for the encryption worker, see `Node::encrypt_pending`.
+    Pending {
+        send: PendingSend,
+        health: GroupHealth,
+    },
+    /// Held until the group is healthy, see `Node::release_held`.
+    Held(GroupHealth),
+}
+
+/// What became of a message `Node::broadcast` sent to each group, by name.
+pub type Broadcast = Vec<(String, Result<SendOutcome, NodeError>)>;
@@ -0,0 +1,122 @@
+//! Messages kept across restarts, for `node history`.
+//!
+//! With a message history file, see `Node::keep_messages`, every text we
+//! send or receive is appended to it as a JSON line after a schema header,
+//! see `schema`. Lines are sealed one by one like backup sections, with
+//! ChaCha20-Poly1305 under a key derived from the history passphrase and
+//! our identity, so the file can grow without being rewritten and reveals
+//! nothing but how many messages it holds. Unlike sealed audit logs, the
+//! key does not depend on any group, so history outlives the groups.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use openmls::prelude::OpenMlsCryptoProvider;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup::{open, seal},
+    codec,
+    error::NodeError,
+    schema,
+};
+
+// What lines are sealed under, as backup sections are under their name.
+const OBJECT_NAME: &str = "message";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    /// What we called the group.
+    pub group: String,
+    pub group_id: Vec<u8>,
+    /// The credential identity of the author.
+    pub sender: String,
+    pub epoch: u64,
+    /// Seconds since the UNIX epoch, when we sent or received it.
+    pub at: u64,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedLine {
+    sealed: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct MessageHistory {
+    path: PathBuf,
+    key: Vec<u8>,
+}
+
+impl MessageHistory {
+    /// Keeps messages in the file at `path` under `key`. A file already
+    /// there must open with it.
+    pub fn open(
+        path: PathBuf,
+        key: Vec<u8>,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<MessageHistory, NodeError> {
+        let history = MessageHistory { path, key };
+        if history.path.exists() {
+            history.read(backend).map_err(|_| {
+                NodeError::Other(format!(
+                    "Could not open message history {}, is the passphrase right?",
+                    history.path.display()
+                ))
+            })?;
+        }
+        Ok(history)
+    }
+
+    pub fn append(
+        &self,
+        message: &StoredMessage,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(), NodeError> {
+        let json = codec::to_json(message, "stored message")?;
+        let sealed = SealedLine {
+            sealed: seal(backend, &self.key, OBJECT_NAME, &json)?,
+        };
+        let mut line = codec::to_json(&sealed, "sealed message")?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(&schema::history_header())?;
+        }
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Every message in the file, oldest first.
+    pub fn read(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<Vec<StoredMessage>, NodeError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        schema::decode_history::<SealedLine>(&std::fs::read(&self.path)?)?
+            .into_iter()
+            .map(|line| {
+                let json = open(backend, &self.key, OBJECT_NAME, &line.sealed)?;
+                serde_json::from_slice(&json)
+                    .map_err(|e| NodeError::Parse(format!("Invalid stored message: {}", e)))
+            })
+            .collect()
+    }
+
+    /// The last `count` messages, oldest first.
+    pub fn last(
+        &self,
+        count: usize,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<Vec<StoredMessage>, NodeError> {
+        let mut messages = self.read(backend)?;
+        messages.drain(..messages.len().saturating_sub(count));
+        Ok(messages)
+    }
+}
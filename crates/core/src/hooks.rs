@@ -0,0 +1,112 @@
+//! Callbacks run after every commit a group merges, ours or a member's, so
+//! embedders can rotate secrets of their own exactly when the MLS key
+//! schedule moves on, e.g. tokens derived from the exporter secret with
+//! [`EpochChange::export_secret`]. Hooks run in the order they were added,
+//! inside the call that merged the commit, and should be quick.
+//!
+//! Join policies decide join requests that passed admission control under
+//! `admission::JoinApproval::Manual` instead of a person, see
+//! `Node::on_join_request`: the first to approve or decline one settles it,
+//! and requests none of them decide wait for `Node::accept_join`.
+
+use std::fmt::Debug;
+
+use openmls::prelude::MlsGroup;
+
+use crate::{crypto::key_store::Backend, error::NodeError, identity::PeerId};
+
+pub type EpochHook = Box<dyn FnMut(&EpochChange) + Send>;
+
+/// A group's new epoch, as hooks see it.
+pub struct EpochChange<'a> {
+    pub group_id: &'a [u8],
+    /// What we call the group.
+    pub group: &'a str,
+    pub epoch: u64,
+    /// Credential identities the commit removed.
+    pub removed: &'a [String],
+    pub(crate) mls_group: &'a MlsGroup,
+    pub(crate) backend: &'a Backend,
+}
+
+impl EpochChange<'_> {
+    /// Derives `length` bytes from the new epoch's exporter secret; every
+    /// member gets the same bytes for the same `label` and `context`.
+    pub fn export_secret(
+        &self,
+        label: &str,
+        context: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>, NodeError> {
+        self.mls_group
+            .export_secret(self.backend, label, context, length)
+            .map_err(|e| NodeError::Other(format!("Could not export secret: {:?}", e)))
+    }
+}
+
+#[derive(Default)]
+pub struct EpochHooks {
+    hooks: Vec<EpochHook>,
+}
+
+impl Debug for EpochHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EpochHooks({})", self.hooks.len())
+    }
+}
+
+impl EpochHooks {
+    pub fn add(&mut self, hook: EpochHook) {
+        self.hooks.push(hook);
+    }
+
+    pub fn run(&mut self, change: &EpochChange) {
+        for hook in &mut self.hooks {
+            hook(change);
+        }
+    }
+}
+
+/// A join request waiting for approval, as join policies see it.
+pub struct JoinCandidate<'a> {
+    pub peer: &'a PeerId,
+    /// What we call the group it asks to join.
+    pub group: &'a str,
+    /// The credential identity of the joiner.
+    pub identity: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinDecision {
+    Approve,
+    Decline,
+    /// Leave it to the next policy, or to whoever accepts joins.
+    Defer,
+}
+
+pub type JoinPolicy = Box<dyn FnMut(&JoinCandidate) -> JoinDecision + Send>;
+
+#[derive(Default)]
+pub struct JoinPolicies {
+    policies: Vec<JoinPolicy>,
+}
+
+impl Debug for JoinPolicies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JoinPolicies({})", self.policies.len())
+    }
+}
+
+impl JoinPolicies {
+    pub fn add(&mut self, policy: JoinPolicy) {
+        self.policies.push(policy);
+    }
+
+    pub fn decide(&mut self, candidate: &JoinCandidate) -> JoinDecision {
+        self.policies
+            .iter_mut()
+            .map(|policy| policy(candidate))
+            .find(|decision| *decision != JoinDecision::Defer)
+            .unwrap_or(JoinDecision::Defer)
+    }
+}
@@ -0,0 +1,248 @@
+//! The node's network identity: an Ed25519 keypair and the peer id derived
+//! from it.
+//!
+//! These are the libp2p encodings, byte for byte, so the peer ids in
+//! credentials, frames and stored files are the ones the swarm dials, but
+//! the core crate needs none of libp2p for them. The network crate converts
+//! at its boundary, by way of [`PeerId::to_bytes`] and
+//! [`Keypair::to_protobuf_encoding`]. Only Ed25519 keys exist here, the
+//! only kind nodes ever generate.
+
+use std::{fmt, str::FromStr};
+
+use ed25519_dalek::{Signer, Verifier};
+use openmls::prelude::OpenMlsCryptoProvider;
+use openmls_rust_crypto::OpenMlsRustCrypto;
+use openmls_traits::random::OpenMlsRand;
+
+use crate::error::NodeError;
+
+// The protobuf `KeyType` of Ed25519, field 1 of both key messages.
+const ED25519: u8 = 1;
+// Multihash codes a peer id may carry, see `PeerId::from_bytes`.
+const IDENTITY: u8 = 0x00;
+const SHA2_256: u8 = 0x12;
+// Keys whose protobuf encoding is longer are hashed into their peer id.
+const MAX_INLINE_KEY_LEN: usize = 42;
+const MAX_DIGEST_LEN: usize = 64;
+
+/// An Ed25519 keypair, as libp2p's `identity::Keypair` holds it.
+pub struct Keypair(ed25519_dalek::Keypair);
+
+impl Keypair {
+    pub fn generate_ed25519() -> Keypair {
+        let secret: [u8; 32] = OpenMlsRustCrypto::default()
+            .rand()
+            .random_array()
+            .expect("the system RNG failed");
+        let secret = ed25519_dalek::SecretKey::from_bytes(&secret).expect("32 bytes");
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Keypair(ed25519_dalek::Keypair { secret, public })
+    }
+
+    pub fn public(&self) -> PublicKey {
+        PublicKey(self.0.public)
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.0.sign(message).to_bytes().to_vec()
+    }
+
+    /// libp2p's `PrivateKey` message: `Type: 1 | Data: secret ‖ public`.
+    pub fn to_protobuf_encoding(&self) -> Vec<u8> {
+        protobuf_key(&self.0.to_bytes())
+    }
+
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<Keypair, NodeError> {
+        ed25519_dalek::Keypair::from_bytes(protobuf_key_data(bytes, 64)?)
+            .map(Keypair)
+            .map_err(|e| NodeError::Parse(format!("Malformed Ed25519 keypair: {}", e)))
+    }
+}
+
+impl Clone for Keypair {
+    fn clone(&self) -> Keypair {
+        Keypair(ed25519_dalek::Keypair::from_bytes(&self.0.to_bytes()).expect("a valid keypair"))
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &self.public())
+            .finish()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct PublicKey(ed25519_dalek::PublicKey);
+
+impl PublicKey {
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_bytes(signature)
+            .and_then(|signature| self.0.verify(message, &signature))
+            .is_ok()
+    }
+
+    /// libp2p's `PublicKey` message: `Type: 1 | Data: key`.
+    pub fn to_protobuf_encoding(&self) -> Vec<u8> {
+        protobuf_key(self.0.as_bytes())
+    }
+
+    pub fn from_protobuf_encoding(bytes: &[u8]) -> Result<PublicKey, NodeError> {
+        ed25519_dalek::PublicKey::from_bytes(protobuf_key_data(bytes, 32)?)
+            .map(PublicKey)
+            .map_err(|e| NodeError::Parse(format!("Malformed Ed25519 public key: {}", e)))
+    }
+
+    pub fn to_peer_id(&self) -> PeerId {
+        PeerId::from_public_key(self)
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PublicKey({})",
+            crate::crypto::hex_encode(self.0.as_bytes())
+        )
+    }
+}
+
+fn protobuf_key(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x08, ED25519, 0x12, data.len() as u8];
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn protobuf_key_data(bytes: &[u8], len: usize) -> Result<&[u8], NodeError> {
+    match bytes {
+        [0x08, ED25519, 0x12, data_len, data @ ..]
+            if *data_len as usize == len && data.len() == len =>
+        {
+            Ok(data)
+        }
+        [0x08, ED25519, ..] => Err(NodeError::Parse("Malformed Ed25519 key".to_string())),
+        _ => Err(NodeError::Parse(
+            "Only Ed25519 network keys are supported".to_string(),
+        )),
+    }
+}
+
+/// A multihash of the peer's public key, shown in base58 as libp2p does.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId {
+    len: u8,
+    bytes: [u8; 2 + MAX_DIGEST_LEN],
+}
+
+impl PeerId {
+    /// Ed25519 keys are short enough to be their own multihash.
+    pub fn from_public_key(key: &PublicKey) -> PeerId {
+        let key = key.to_protobuf_encoding();
+        debug_assert!(key.len() <= MAX_INLINE_KEY_LEN);
+        let mut bytes = [0; 2 + MAX_DIGEST_LEN];
+        bytes[..2].copy_from_slice(&[IDENTITY, key.len() as u8]);
+        bytes[2..2 + key.len()].copy_from_slice(&key);
+        PeerId {
+            len: 2 + key.len() as u8,
+            bytes,
+        }
+    }
+
+    /// The peer id of a fresh keypair, for tests.
+    pub fn random() -> PeerId {
+        Keypair::generate_ed25519().public().to_peer_id()
+    }
+
+    /// Takes the identity and SHA2-256 multihashes libp2p takes, the latter
+    /// for the peer ids of keys too long to inline.
+    pub fn from_bytes(bytes: &[u8]) -> Result<PeerId, NodeError> {
+        match bytes {
+            [IDENTITY, len, digest @ ..]
+                if *len as usize == digest.len() && digest.len() <= MAX_INLINE_KEY_LEN => {}
+            [SHA2_256, 32, digest @ ..] if digest.len() == 32 => {}
+            _ => return Err(NodeError::Parse("Malformed peer id".to_string())),
+        }
+        let mut id = [0; 2 + MAX_DIGEST_LEN];
+        id[..bytes.len()].copy_from_slice(bytes);
+        Ok(PeerId {
+            len: bytes.len() as u8,
+            bytes: id,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    pub fn to_base58(&self) -> String {
+        bs58::encode(self.as_bytes()).into_string()
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+impl From<PublicKey> for PeerId {
+    fn from(key: PublicKey) -> PeerId {
+        PeerId::from_public_key(&key)
+    }
+}
+
+impl From<PeerId> for Vec<u8> {
+    fn from(peer: PeerId) -> Vec<u8> {
+        peer.to_bytes()
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_base58())
+    }
+}
+
+impl fmt::Debug for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PeerId").field(&self.to_base58()).finish()
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = NodeError;
+
+    fn from_str(s: &str) -> Result<PeerId, NodeError> {
+        let bytes = bs58::decode(s)
+            .into_vec()
+            .map_err(|_| NodeError::Parse(format!("{} is not base58", s)))?;
+        PeerId::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_peer_ids_round_trip_their_encodings() {
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().to_peer_id();
+        assert!(peer.to_string().starts_with("12D3KooW"));
+        assert_eq!(peer.to_string().parse::<PeerId>().unwrap(), peer);
+        assert_eq!(PeerId::from_bytes(&peer.to_bytes()).unwrap(), peer);
+        assert!("not a peer".parse::<PeerId>().is_err());
+
+        let restored = Keypair::from_protobuf_encoding(&keypair.to_protobuf_encoding()).unwrap();
+        assert_eq!(restored.public(), keypair.public());
+        let public =
+            PublicKey::from_protobuf_encoding(&keypair.public().to_protobuf_encoding()).unwrap();
+        let signature = restored.sign(b"hello");
+        assert!(public.verify(b"hello", &signature));
+        assert!(!public.verify(b"hullo", &signature));
+        assert!(!Keypair::generate_ed25519()
+            .public()
+            .verify(b"hello", &signature));
+    }
+}
@@ -0,0 +1,144 @@
+//! Address books shared by members, so a newcomer introduced by one member
+//! quickly reaches the rest of the group.
+//!
+//! Members who opt in with `--share-addresses` send the addresses they can
+//! dial other members at, plus their own listen addresses, as an application
+//! message `0xFB | JSON`. The book is signed with the sender's credential key
+//! over the group id, so it cannot be replayed into another group, and
+//! receivers keep only entries for peers that are members of the group.
+
+use multiaddr::Multiaddr;
+use openmls::prelude::{
+    Credential, CredentialBundle, OpenMlsCryptoProvider, Signature, TlsDeserializeTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{codec, error::NodeError, identity::PeerId};
+
+const MARKER: u8 = 0xFB;
+const SIGNATURE_LABEL: &[u8] = b"p2p-mls address book";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressEntry {
+    pub peer: String,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressBook {
+    pub group_id: Vec<u8>,
+    pub entries: Vec<AddressEntry>,
+}
+
+impl AddressBook {
+    pub fn new(group_id: &[u8], entries: Vec<(PeerId, Vec<Multiaddr>)>) -> AddressBook {
+        AddressBook {
+            group_id: group_id.to_vec(),
+            entries: entries
+                .into_iter()
+                .filter(|(_, addresses)| !addresses.is_empty())
+                .map(|(peer, addresses)| AddressEntry {
+                    peer: peer.to_string(),
+                    addresses: addresses.iter().map(Multiaddr::to_string).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The entries that parse, skipping malformed peers and addresses.
+    pub fn peers(&self) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                let peer = entry.peer.parse().ok()?;
+                let addresses = entry
+                    .addresses
+                    .iter()
+                    .filter_map(|address| address.parse().ok())
+                    .collect();
+                Some((peer, addresses))
+            })
+            .collect()
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&PeerId) -> bool) {
+        self.entries
+            .retain(|entry| entry.peer.parse().is_ok_and(|peer| keep(&peer)));
+    }
+}
+
+impl std::fmt::Display for AddressBook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shared addresses for {} members", self.entries.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedAddressBook {
+    content: String,
+    signature: Vec<u8>,
+}
+
+impl SignedAddressBook {
+    pub fn is_address_book(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn sign(
+        book: &AddressBook,
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<SignedAddressBook, NodeError> {
+        let content = codec::to_json_string(book, "address book")?;
+        let (_, private_key) = credential_bundle.into_parts();
+        let signature = private_key
+            .sign(backend, &signed_bytes(&content))
+            .map_err(|e| NodeError::Other(format!("Could not sign address book: {:?}", e)))?;
+        Ok(SignedAddressBook {
+            content,
+            signature: codec::to_tls(&signature, "signature")?,
+        })
+    }
+
+    /// Checks that `sender` signed the book for `group_id`.
+    pub fn verify(
+        &self,
+        sender: &Credential,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+    ) -> Result<AddressBook, NodeError> {
+        let signature = Signature::tls_deserialize(&mut self.signature.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed address book signature".to_string()))?;
+        sender
+            .verify(backend, &signed_bytes(&self.content), &signature)
+            .map_err(|_| NodeError::Other("Invalid address book signature".to_string()))?;
+        let book: AddressBook = serde_json::from_str(&self.content)
+            .map_err(|e| NodeError::Parse(format!("Invalid address book: {}", e)))?;
+        if book.group_id != group_id {
+            return Err(NodeError::Other(
+                "Address book is for another group".to_string(),
+            ));
+        }
+        Ok(book)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_json(self, "address book")?);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SignedAddressBook, NodeError> {
+        if !SignedAddressBook::is_address_book(bytes) {
+            return Err(NodeError::Other("Not an address book".to_string()));
+        }
+        serde_json::from_slice(&bytes[1..])
+            .map_err(|e| NodeError::Parse(format!("Invalid address book: {}", e)))
+    }
+}
+
+fn signed_bytes(content: &str) -> Vec<u8> {
+    let mut bytes = SIGNATURE_LABEL.to_vec();
+    bytes.extend_from_slice(content.as_bytes());
+    bytes
+}
@@ -0,0 +1,336 @@
+//! Group state as a log of events.
+//!
+//! Every change to the groups we are in, from our own commands or from
+//! members' commits, is appended to the node's [`Journal`] as a
+//! [`GroupEvent`], and [`Groups::apply`] is the one place membership,
+//! epochs, admins and the active group change. Replaying the events from
+//! the start rebuilds the same [`Groups`]; replaying a prefix shows the
+//! groups as they were after any event, which is what `node journal
+//! --at=<seq>` prints.
+//!
+//! The journal holds what the node knows about its groups, not their key
+//! schedules: MLS deletes an epoch's secrets once it is over, so the
+//! `MlsGroup`s themselves come back from a backup and the journal replays
+//! next to them. It is backed up as a section of its own.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{admins::Rights, node::group_name};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupEvent {
+    /// We created or joined `group_id`, which is at `epoch` with `members`.
+    Entered {
+        group_id: Vec<u8>,
+        name: String,
+        leader: bool,
+        epoch: u64,
+        members: Vec<String>,
+    },
+    /// A commit, ours or a member's, moved the group on to `epoch`.
+    Merged {
+        group_id: Vec<u8>,
+        epoch: u64,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    /// The leader set who else may add and remove members, see `admins`.
+    AdminsSet {
+        group_id: Vec<u8>,
+        admins: Vec<(String, Rights)>,
+    },
+    /// The leader or an admin committed to another name, see `metadata`.
+    Renamed { group_id: Vec<u8>, name: String },
+    /// Commands that name no group act on this one from now on.
+    Activated { group_id: Vec<u8> },
+    /// We left the group, were removed from it or archived it.
+    Dropped { group_id: Vec<u8> },
+}
+
+impl GroupEvent {
+    pub fn group_id(&self) -> &[u8] {
+        match self {
+            GroupEvent::Entered { group_id, .. }
+            | GroupEvent::Merged { group_id, .. }
+            | GroupEvent::AdminsSet { group_id, .. }
+            | GroupEvent::Renamed { group_id, .. }
+            | GroupEvent::Activated { group_id }
+            | GroupEvent::Dropped { group_id } => group_id,
+        }
+    }
+}
+
+impl Display for GroupEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let group = group_name(self.group_id());
+        match self {
+            GroupEvent::Entered {
+                name,
+                leader,
+                epoch,
+                members,
+                ..
+            } => write!(
+                f,
+                "{} entered {} at epoch {} with {} members{}",
+                group,
+                name,
+                epoch,
+                members.len(),
+                if *leader { ", as leader" } else { "" }
+            ),
+            GroupEvent::Merged {
+                epoch,
+                added,
+                removed,
+                ..
+            } => {
+                write!(f, "{} moved to epoch {}", group, epoch)?;
+                if !added.is_empty() {
+                    write!(f, ", added {}", added.join(", "))?;
+                }
+                if !removed.is_empty() {
+                    write!(f, ", removed {}", removed.join(", "))?;
+                }
+                Ok(())
+            }
+            GroupEvent::AdminsSet { admins, .. } => {
+                write!(f, "{} admins set to {}", group, admins.len())
+            }
+            GroupEvent::Renamed { name, .. } => write!(f, "{} renamed {}", group, name),
+            GroupEvent::Activated { .. } => write!(f, "{} made active", group),
+            GroupEvent::Dropped { .. } => write!(f, "{} dropped", group),
+        }
+    }
+}
+
+/// An event and where it sits in the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Numbered from 1, in the order events were appended.
+    pub seq: u64,
+    /// Seconds since the Unix epoch when it was appended.
+    pub at: u64,
+    pub event: GroupEvent,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} at {}: {}", self.seq, self.at, self.event)
+    }
+}
+
+/// One group as the journal has it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupView {
+    pub name: String,
+    pub leader: bool,
+    pub epoch: u64,
+    pub members: BTreeSet<String>,
+    pub admins: Vec<(String, Rights)>,
+}
+
+/// The groups the events so far leave us in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Groups {
+    pub groups: BTreeMap<Vec<u8>, GroupView>,
+    pub active: Option<Vec<u8>>,
+}
+
+impl Groups {
+    pub fn apply(&mut self, event: &GroupEvent) {
+        match event {
+            GroupEvent::Entered {
+                group_id,
+                name,
+                leader,
+                epoch,
+                members,
+            } => {
+                let view = GroupView {
+                    name: name.clone(),
+                    leader: *leader,
+                    epoch: *epoch,
+                    members: members.iter().cloned().collect(),
+                    admins: Vec::new(),
+                };
+                self.groups.insert(group_id.clone(), view);
+                if self.active.is_none() {
+                    self.active = Some(group_id.clone());
+                }
+            }
+            GroupEvent::Merged {
+                group_id,
+                epoch,
+                added,
+                removed,
+            } => {
+                if let Some(view) = self.groups.get_mut(group_id) {
+                    view.epoch = *epoch;
+                    view.members.extend(added.iter().cloned());
+                    for member in removed {
+                        view.members.remove(member);
+                    }
+                }
+            }
+            GroupEvent::AdminsSet { group_id, admins } => {
+                if let Some(view) = self.groups.get_mut(group_id) {
+                    view.admins = admins.clone();
+                }
+            }
+            GroupEvent::Renamed { group_id, name } => {
+                if let Some(view) = self.groups.get_mut(group_id) {
+                    view.name = name.clone();
+                }
+            }
+            GroupEvent::Activated { group_id } => {
+                if self.groups.contains_key(group_id) {
+                    self.active = Some(group_id.clone());
+                }
+            }
+            GroupEvent::Dropped { group_id } => {
+                self.groups.remove(group_id);
+                if self.active.as_ref() == Some(group_id) {
+                    self.active = self.groups.keys().next().cloned();
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, group_id: &[u8]) -> Option<&GroupView> {
+        self.groups.get(group_id)
+    }
+}
+
+impl Display for Groups {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.groups.is_empty() {
+            return write!(f, "In no groups");
+        }
+        let lines: Vec<String> = self
+            .groups
+            .iter()
+            .map(|(group_id, view)| {
+                format!(
+                    "{} {} ({}): epoch {}, {} members{}",
+                    if self.active.as_ref() == Some(group_id) {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    view.name,
+                    group_name(group_id),
+                    view.epoch,
+                    view.members.len(),
+                    if view.leader { ", leader" } else { "" }
+                )
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+    groups: Groups,
+}
+
+impl Journal {
+    /// Replays `entries`, from a backup for example, to pick up where they
+    /// left off.
+    pub fn from_entries(entries: Vec<Entry>) -> Journal {
+        let mut groups = Groups::default();
+        for entry in &entries {
+            groups.apply(&entry.event);
+        }
+        Journal { entries, groups }
+    }
+
+    /// Appends `event` and applies it, returning its sequence number.
+    pub fn append(&mut self, event: GroupEvent) -> u64 {
+        let seq = self.entries.last().map_or(1, |entry| entry.seq + 1);
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.groups.apply(&event);
+        self.entries.push(Entry { seq, at, event });
+        seq
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The groups as of now.
+    pub fn groups(&self) -> &Groups {
+        &self.groups
+    }
+
+    /// The groups rebuilt from the events up to and including `seq`.
+    pub fn replay(&self, seq: u64) -> Groups {
+        let mut groups = Groups::default();
+        for entry in self.entries.iter().take_while(|entry| entry.seq <= seq) {
+            groups.apply(&entry.event);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_rebuilds_the_groups_at_any_event() {
+        let (work, home) = (vec![1; 16], vec![2; 16]);
+        let mut journal = Journal::default();
+        journal.append(GroupEvent::Entered {
+            group_id: work.clone(),
+            name: "work".to_string(),
+            leader: true,
+            epoch: 0,
+            members: vec!["alice".to_string()],
+        });
+        journal.append(GroupEvent::Merged {
+            group_id: work.clone(),
+            epoch: 1,
+            added: vec!["bob".to_string()],
+            removed: Vec::new(),
+        });
+        journal.append(GroupEvent::Entered {
+            group_id: home.clone(),
+            name: "home".to_string(),
+            leader: false,
+            epoch: 4,
+            members: vec!["alice".to_string(), "carol".to_string()],
+        });
+        journal.append(GroupEvent::Activated {
+            group_id: home.clone(),
+        });
+        let seq = journal.append(GroupEvent::Dropped {
+            group_id: home.clone(),
+        });
+        assert_eq!(seq, 5);
+
+        let now = journal.groups();
+        assert_eq!(now.active, Some(work.clone()));
+        assert_eq!(now.groups.len(), 1);
+        assert_eq!(now.get(&work).unwrap().members.len(), 2);
+        assert_eq!(&journal.replay(seq), now);
+        assert_eq!(
+            &Journal::from_entries(journal.entries().to_vec()).groups,
+            now
+        );
+
+        let earlier = journal.replay(4);
+        assert_eq!(earlier.active, Some(home.clone()));
+        assert_eq!(earlier.get(&home).unwrap().epoch, 4);
+        assert_eq!(journal.replay(1).get(&work).unwrap().members.len(), 1);
+        assert!(journal.replay(0).groups.is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod ack;
+pub mod admins;
+pub mod admission;
+pub mod archive;
+pub mod audit;
+pub mod backfill;
+pub mod backup;
+pub mod capabilities;
+pub mod codec;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod crypto;
+pub mod diagnostics;
+pub mod ds;
+pub mod error;
+pub mod events;
+pub mod external;
+pub mod features;
+pub mod guests;
+pub mod handover;
+pub mod health;
+pub mod history;
+pub mod hooks;
+pub mod identity;
+pub mod introduction;
+pub mod journal;
+pub mod lifetime;
+pub mod limits;
+pub mod manifest;
+pub mod media;
+pub mod membership;
+pub mod metadata;
+pub mod names;
+pub mod node;
+pub mod outbox;
+pub mod peers;
+pub mod pending;
+#[cfg(feature = "pki")]
+pub mod pki;
+pub mod policy;
+pub mod protocol;
+pub mod provision;
+pub mod psk;
+pub mod qos;
+pub mod receipt;
+pub mod recovery;
+pub mod rooms;
+pub mod schema;
+pub mod security;
+pub mod telemetry;
+pub mod transfer;
+pub mod transparency;
+pub mod typing;
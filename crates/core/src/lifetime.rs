@@ -0,0 +1,267 @@
+//! Key package lifetimes, checked before a leader adds anyone.
+//!
+//! Every key package carries a lifetime extension, `not_before` and
+//! `not_after` in seconds since the Unix epoch, and every member decoding
+//! the commit that adds it checks that lifetime against its own clock. A
+//! package that expires while the commit is on its way, or that starts
+//! ahead of a member's clock, leaves that member unable to process the
+//! commit. Leaders therefore want a package to be valid for a tolerance
+//! either side of now before adding it.
+//!
+//! OpenMLS refuses to decode packages outside their lifetime at all, so
+//! those are read field by field instead, just far enough to find the
+//! identity and lifetime. Either way the leader publishes a [`JoinRefusal`]
+//! telling the joiner why, so it can make a fresh package. Refusals are not
+//! signed: the worst a forged one does is make a joiner regenerate its key
+//! package.
+//!
+//! A refusal is `identity<u16> | reason: u8 | time: u64`, where the reason
+//! is 1 for not yet valid and 2 for expired, and the time is the bound that
+//! was missed.
+
+use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openmls::prelude::{Extension, KeyPackage, TlsSerializeTrait};
+
+use crate::error::NodeError;
+
+const LIFETIME_EXTENSION: u16 = 2;
+const JOIN_REQUEST_MARKER: u8 = 0xFD;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lifetime {
+    pub not_before: u64,
+    pub not_after: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifetimeError {
+    NotYetValid { not_before: u64 },
+    Expired { not_after: u64 },
+}
+
+impl Display for LifetimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LifetimeError::NotYetValid { not_before } => write!(
+                f,
+                "Key package is not valid before {}, check the joiner's clock",
+                not_before
+            ),
+            LifetimeError::Expired { not_after } => write!(
+                f,
+                "Key package expires at {}, generate a new one",
+                not_after
+            ),
+        }
+    }
+}
+
+impl Lifetime {
+    /// The lifetime extension of `key_package`, if it has one.
+    pub fn of(key_package: &KeyPackage) -> Option<Lifetime> {
+        key_package
+            .extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                Extension::LifeTime(lifetime) => lifetime
+                    .tls_serialize_detached()
+                    .ok()
+                    .and_then(|bytes| Lifetime::decode(&bytes)),
+                _ => None,
+            })
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Lifetime> {
+        Some(Lifetime {
+            not_before: u64::from_be_bytes(bytes.get(..8)?.try_into().ok()?),
+            not_after: u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?),
+        })
+    }
+
+    /// Whether the package is valid from `tolerance` before `now` until
+    /// `tolerance` after it.
+    pub fn check(&self, now: SystemTime, tolerance: Duration) -> Result<(), LifetimeError> {
+        let now = unix_seconds(now);
+        let tolerance = tolerance.as_secs();
+        if self.not_before.saturating_add(tolerance) > now {
+            Err(LifetimeError::NotYetValid {
+                not_before: self.not_before,
+            })
+        } else if self.not_after < now.saturating_add(tolerance) {
+            Err(LifetimeError::Expired {
+                not_after: self.not_after,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs()
+}
+
+/// Reads the credential identity and lifetime out of the key package in a
+/// join request body, see `admission`, without decoding the package.
+pub fn read_join_request(body: &[u8]) -> Option<(Vec<u8>, Lifetime)> {
+    let mut reader = Reader(body);
+    if body.first() == Some(&JOIN_REQUEST_MARKER) {
+        reader.take(1 + 8)?;
+        let tag_len = reader.take(1)?[0] as usize;
+        reader.take(tag_len)?;
+    }
+    reader.take(1 + 2)?; // protocol version, ciphersuite
+    reader.vec_u16()?; // HPKE init key
+    reader.take(2)?; // credential type, only basic credentials exist
+    let identity = reader.vec_u16()?.to_vec();
+    reader.take(2)?; // signature scheme
+    reader.vec_u16()?; // signature key
+    let extensions_len = u32::from_be_bytes(reader.take(4)?.try_into().ok()?) as usize;
+    let mut extensions = Reader(reader.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let extension_type = u16::from_be_bytes(extensions.take(2)?.try_into().ok()?);
+        let len = u32::from_be_bytes(extensions.take(4)?.try_into().ok()?) as usize;
+        let data = extensions.take(len)?;
+        if extension_type == LIFETIME_EXTENSION {
+            return Some((identity, Lifetime::decode(data)?));
+        }
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let field = self.0.get(..len)?;
+        self.0 = &self.0[len..];
+        Some(field)
+    }
+
+    fn vec_u16(&mut self) -> Option<&'a [u8]> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().ok()?) as usize;
+        self.take(len)
+    }
+}
+
+/// A leader telling the owner of `identity` why its key package was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinRefusal {
+    pub identity: Vec<u8>,
+    pub reason: LifetimeError,
+}
+
+impl JoinRefusal {
+    pub fn encode(&self) -> Vec<u8> {
+        let (reason, time) = match self.reason {
+            LifetimeError::NotYetValid { not_before } => (1, not_before),
+            LifetimeError::Expired { not_after } => (2, not_after),
+        };
+        let mut bytes = (self.identity.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.identity);
+        bytes.push(reason);
+        bytes.extend_from_slice(&time.to_be_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<JoinRefusal, NodeError> {
+        let malformed = || NodeError::Parse("Malformed join refusal".to_string());
+        let mut reader = Reader(bytes);
+        let identity = reader.vec_u16().ok_or_else(malformed)?.to_vec();
+        let reason = reader.take(1).ok_or_else(malformed)?[0];
+        let time = u64::from_be_bytes(
+            reader
+                .take(8)
+                .and_then(|time| time.try_into().ok())
+                .ok_or_else(malformed)?,
+        );
+        let reason = match reason {
+            1 => LifetimeError::NotYetValid { not_before: time },
+            2 => LifetimeError::Expired { not_after: time },
+            _ => return Err(malformed()),
+        };
+        Ok(JoinRefusal { identity, reason })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admission::{AdmissionConfig, JoinRequest};
+    use crate::node::Node;
+    use crate::protocol::{split, WireMessage};
+
+    #[test]
+    fn lifetimes_are_read_with_or_without_openmls() {
+        let mut node = Node::default();
+        let key_package = node.get_key_package();
+        let lifetime = Lifetime::of(&key_package).unwrap();
+        let now = SystemTime::now();
+        assert!(lifetime.check(now, Duration::from_secs(60)).is_ok());
+        assert!(matches!(
+            lifetime.check(now, Duration::from_secs(2 * 60 * 60)),
+            Err(LifetimeError::NotYetValid { .. })
+        ));
+        let later = UNIX_EPOCH + Duration::from_secs(lifetime.not_after);
+        assert_eq!(
+            lifetime.check(later, Duration::from_secs(60)),
+            Err(LifetimeError::Expired {
+                not_after: lifetime.not_after
+            })
+        );
+
+        let identity = key_package.credential().identity().to_vec();
+        let bare = WireMessage::from(
+            JoinRequest::new(
+                key_package,
+                &AdmissionConfig::default(),
+                &openmls_rust_crypto::OpenMlsRustCrypto::default(),
+            )
+            .unwrap(),
+        )
+        .encode()
+        .unwrap();
+        let signed = WireMessage::from(node.create_join_request().unwrap())
+            .encode()
+            .unwrap();
+        for frame in [&bare, &signed] {
+            let (_, body) = split(frame).unwrap();
+            assert_eq!(read_join_request(body), Some((identity.clone(), lifetime)));
+        }
+
+        let refusal = JoinRefusal {
+            identity,
+            reason: LifetimeError::Expired {
+                not_after: lifetime.not_after,
+            },
+        };
+        assert_eq!(JoinRefusal::decode(&refusal.encode()).unwrap(), refusal);
+
+        // A leader refuses before committing anything, and the joiner replaces its package.
+        let mut leader = Node::default();
+        leader.join_new_group();
+        leader.set_admission_config(AdmissionConfig {
+            lifetime_tolerance: Duration::from_secs(2 * 60 * 60),
+            ..AdmissionConfig::default()
+        });
+        let mut joiner = node;
+        let request = joiner.create_join_request().unwrap();
+        let peer = crate::identity::PeerId::random();
+        assert!(leader.handle_join_request(&peer, request).is_err());
+        assert_eq!(leader.group_info().unwrap().epoch, 0);
+        assert_eq!(leader.admission_metrics().lifetime, 1);
+        let refusals = leader.take_refusals();
+        assert_eq!(refusals.len(), 1);
+        let before = joiner.get_key_package();
+        assert!(matches!(
+            joiner.handle_join_refusal(&refusals[0]).unwrap(),
+            Some(LifetimeError::NotYetValid { .. })
+        ));
+        assert!(joiner.get_key_package() != before);
+        assert_eq!(leader.handle_join_refusal(&refusals[0]).unwrap(), None);
+    }
+}
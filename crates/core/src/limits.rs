@@ -0,0 +1,239 @@
+//! Size and rate bounds for frames arriving on the floodsub topic.
+//!
+//! Frames are classified from their envelope (see `protocol`) and the first
+//! few bytes of their body, so an oversized commit or Welcome can be dropped
+//! before any length-prefixed field is deserialized:
+//!
+//! * Join requests and control messages are bounded like key packages, as
+//!   is anything that is not a frame of our wire version.
+//! * An MLS message body is `0x02 | group_id<u8> | epoch: u64 |
+//!   content_type ..` for a ciphertext, where content type 1 is an
+//!   application message and anything else a handshake, or an MLS plaintext
+//!   starting `0x01`, which only carries handshakes. Both carry the group id
+//!   right after their first byte, which is how frames are routed to their
+//!   group.
+//! * A file chunk, control kind 5 (see `transfer`), is bounded and routed
+//!   like an application message; its group id follows the control kind.
+//!
+//! Anyone on the topic can also flood it, and every frame costs us up to
+//! three deserializations, so [`PeerRateLimits`] drops frames from a peer
+//! beyond its token bucket before anything is parsed.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    admission::{RateLimit, TokenBucket, MAX_TRACKED_PEERS},
+    error::NodeError,
+    identity::PeerId,
+    protocol::{self, WireKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    KeyPackage,
+    Handshake,
+    Application,
+    Welcome,
+}
+
+impl FrameKind {
+    pub fn classify(bytes: &[u8]) -> FrameKind {
+        match protocol::split(bytes) {
+            Some((WireKind::Welcome, _)) => FrameKind::Welcome,
+            Some((WireKind::MlsMessage, body @ [0x02, group_id_len, ..])) => {
+                match body.get(2 + *group_id_len as usize + 8) {
+                    Some(1) => FrameKind::Application,
+                    _ => FrameKind::Handshake,
+                }
+            }
+            Some((WireKind::MlsMessage, _)) => FrameKind::Handshake,
+            Some((WireKind::Control, [5, ..])) => FrameKind::Application,
+            Some((WireKind::KeyPackage | WireKind::Control, _)) | None => FrameKind::KeyPackage,
+        }
+    }
+
+    /// The group an MLS message or file chunk is for.
+    pub fn group_id(bytes: &[u8]) -> Option<&[u8]> {
+        match protocol::split(bytes) {
+            Some((WireKind::MlsMessage, [0x01 | 0x02, len, rest @ ..]))
+            | Some((WireKind::Control, [5, len, rest @ ..])) => rest.get(..*len as usize),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeLimits {
+    pub key_package: usize,
+    pub handshake: usize,
+    pub application: usize,
+    pub welcome: usize,
+    /// Drop oversized frames; otherwise they are only reported.
+    pub enforce: bool,
+}
+
+impl Default for SizeLimits {
+    fn default() -> SizeLimits {
+        SizeLimits {
+            key_package: 16 * 1024,
+            handshake: 256 * 1024,
+            application: 64 * 1024,
+            welcome: 1024 * 1024,
+            enforce: true,
+        }
+    }
+}
+
+impl SizeLimits {
+    pub fn limit(&self, kind: FrameKind) -> usize {
+        match kind {
+            FrameKind::KeyPackage => self.key_package,
+            FrameKind::Handshake => self.handshake,
+            FrameKind::Application => self.application,
+            FrameKind::Welcome => self.welcome,
+        }
+    }
+
+    /// Returns an error describing the frame if it exceeds the bound for its kind.
+    pub fn check(&self, bytes: &[u8]) -> Result<FrameKind, NodeError> {
+        let kind = FrameKind::classify(bytes);
+        if bytes.len() > self.limit(kind) {
+            return Err(NodeError::Other(format!(
+                "{:?} frame of {} bytes exceeds the {} byte limit",
+                kind,
+                bytes.len(),
+                self.limit(kind)
+            )));
+        }
+        Ok(kind)
+    }
+}
+
+/// A token bucket per peer for frames from the topic, off when `None`.
+#[derive(Debug, Default)]
+pub struct PeerRateLimits {
+    limit: Option<RateLimit>,
+    peers: HashMap<PeerId, PeerBucket>,
+}
+
+#[derive(Debug)]
+struct PeerBucket {
+    bucket: TokenBucket,
+    // Frames dropped since the last one let through.
+    dropped: u64,
+}
+
+impl PeerRateLimits {
+    pub fn new(limit: Option<RateLimit>) -> PeerRateLimits {
+        PeerRateLimits {
+            limit,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// `per_second` frames a second from each peer, with bursts of as many;
+    /// none when 0.
+    pub fn per_second(per_second: u32) -> PeerRateLimits {
+        PeerRateLimits::new((per_second > 0).then_some(RateLimit {
+            burst: per_second,
+            per: Duration::from_secs(1),
+        }))
+    }
+
+    /// Counts a frame from `peer` at `now`. Returns `Err` with how many
+    /// frames in a row were dropped, this one included, if it is over the
+    /// limit.
+    pub fn check(&mut self, peer: &PeerId, now: Instant) -> Result<(), u64> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        if self.peers.len() >= MAX_TRACKED_PEERS && !self.peers.contains_key(peer) {
+            self.peers.retain(|_, peer| {
+                peer.bucket.refill(limit, now);
+                peer.bucket.tokens < limit.burst as f64
+            });
+            // Everyone still busy: forget the one closest to a full bucket,
+            // which loses it the least.
+            if self.peers.len() >= MAX_TRACKED_PEERS {
+                let idlest = self
+                    .peers
+                    .iter()
+                    .max_by(|a, b| a.1.bucket.tokens.total_cmp(&b.1.bucket.tokens))
+                    .map(|(peer, _)| *peer);
+                self.peers
+                    .remove(&idlest.expect("at the cap, so not empty"));
+            }
+        }
+        let peer = self.peers.entry(*peer).or_insert_with(|| PeerBucket {
+            bucket: TokenBucket::new(limit, now),
+            dropped: 0,
+        });
+        if peer.bucket.try_take(limit, now) {
+            peer.dropped = 0;
+            return Ok(());
+        }
+        peer.dropped += 1;
+        Err(peer.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use crate::protocol::WireMessage;
+
+    #[test]
+    fn frames_are_classified_before_parsing() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let key_package = WireMessage::from(bob.create_join_request().unwrap())
+            .encode()
+            .unwrap();
+        let (commit, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        let commit = WireMessage::from(commit).encode().unwrap();
+        let welcome = WireMessage::from(welcome).encode().unwrap();
+        let message = WireMessage::from(alice.create_message("hello").unwrap())
+            .encode()
+            .unwrap();
+
+        assert_eq!(FrameKind::classify(&key_package), FrameKind::KeyPackage);
+        assert_eq!(FrameKind::classify(&commit), FrameKind::Handshake);
+        assert_eq!(FrameKind::classify(&welcome), FrameKind::Welcome);
+        assert_eq!(FrameKind::classify(&message), FrameKind::Application);
+
+        let limits = SizeLimits {
+            welcome: welcome.len() - 1,
+            ..SizeLimits::default()
+        };
+        assert!(limits.check(&welcome).is_err());
+        assert_eq!(limits.check(&message).unwrap(), FrameKind::Application);
+    }
+
+    #[test]
+    fn floods_are_dropped_per_peer() {
+        let (flooder, neighbour) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+        let mut limits = PeerRateLimits::per_second(2);
+        assert!(limits.check(&flooder, now).is_ok());
+        assert!(limits.check(&flooder, now).is_ok());
+        assert_eq!(limits.check(&flooder, now), Err(1));
+        assert_eq!(limits.check(&flooder, now), Err(2));
+        assert!(limits.check(&neighbour, now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        assert!(limits.check(&flooder, later).is_ok());
+        assert_eq!(limits.check(&flooder, later), Err(1));
+
+        for _ in 0..MAX_TRACKED_PEERS {
+            assert!(limits.check(&PeerId::random(), later).is_ok());
+        }
+        assert_eq!(limits.peers.len(), MAX_TRACKED_PEERS);
+
+        let mut off = PeerRateLimits::per_second(0);
+        assert!((0..100).all(|_| off.check(&flooder, now).is_ok()));
+    }
+}
@@ -0,0 +1,182 @@
+//! Static group bootstrap from a manifest of member key packages, e.g.
+//!
+//! ```toml
+//! welcome_out = "welcomes"
+//!
+//! [[member]]
+//! identity = "12D3KooW..."
+//! key_package = "alice.kp"
+//! ```
+//!
+//! Relative paths are resolved against the manifest's own directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openmls::prelude::KeyPackage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{credential_identity, fingerprint::credential_has_identity},
+    error::NodeError,
+    protocol::Invite,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ManifestMember {
+    /// Expected PeerId of the member, checked against the key package credential.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Path to the TLS-serialized key package.
+    pub key_package: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Directory to write Welcomes to; when absent they go to the delivery service.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub welcome_out: Option<PathBuf>,
+    #[serde(default, rename = "member")]
+    pub members: Vec<ManifestMember>,
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+impl Manifest {
+    pub fn new(members: Vec<ManifestMember>, welcome_out: Option<PathBuf>) -> Manifest {
+        Manifest {
+            welcome_out,
+            members,
+            base_dir: PathBuf::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), NodeError> {
+        let contents = toml::to_string(self)
+            .map_err(|e| NodeError::Other(format!("Could not write manifest: {}", e)))?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, NodeError> {
+        let contents = fs::read_to_string(path)?;
+        let mut manifest: Manifest = toml::from_str(&contents)
+            .map_err(|e| NodeError::Parse(format!("Invalid manifest {}: {}", path.display(), e)))?;
+        manifest.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    fn resolve(&self, path: &Path) -> PathBuf {
+        self.base_dir.join(path)
+    }
+
+    /// Reads every member key package, making sure it belongs to the listed identity.
+    pub fn key_packages(&self) -> Result<Vec<KeyPackage>, NodeError> {
+        self.members
+            .iter()
+            .map(|member| {
+                let path = self.resolve(&member.key_package);
+                let bytes = fs::read(&path)?;
+                let key_package = KeyPackage::try_from(bytes.as_slice()).map_err(|e| {
+                    NodeError::Parse(format!("Invalid key package {}: {:?}", path.display(), e))
+                })?;
+                if let Some(identity) = &member.identity {
+                    if !credential_has_identity(key_package.credential(), identity) {
+                        return Err(NodeError::Other(format!(
+                            "Key package {} does not belong to {}",
+                            path.display(),
+                            identity
+                        )));
+                    }
+                }
+                Ok(key_package)
+            })
+            .collect()
+    }
+
+    /// Writes one copy of the invite per member, named after its identity.
+    pub fn write_welcomes(
+        &self,
+        key_packages: &[KeyPackage],
+        invite: &Invite,
+    ) -> Result<Vec<PathBuf>, NodeError> {
+        let out_dir = match &self.welcome_out {
+            Some(dir) => self.resolve(dir),
+            None => return Ok(Vec::new()),
+        };
+        fs::create_dir_all(&out_dir)?;
+        let serialized = invite.encode()?;
+        key_packages
+            .iter()
+            .map(|key_package| {
+                let path = out_dir.join(format!("{}.welcome", key_package_identity(key_package)));
+                fs::write(&path, &serialized)?;
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+pub fn key_package_identity(key_package: &KeyPackage) -> String {
+    credential_identity(key_package.credential())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls::prelude::TlsSerializeTrait;
+
+    #[test]
+    fn bootstrap_from_manifest() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let mut bob = Node::default();
+        let carol = Node::default();
+        let bob_identity = key_package_identity(&bob.get_key_package());
+        for (name, node) in [("bob", &bob), ("carol", &carol)] {
+            let serialized = node.get_key_package().tls_serialize_detached().unwrap();
+            fs::write(dir.join(format!("{}.kp", name)), serialized).unwrap();
+        }
+        let manifest_path = dir.join("members.toml");
+        fs::write(
+            &manifest_path,
+            format!(
+                "welcome_out = \"welcomes\"\n\n[[member]]\nidentity = \"{}\"\nkey_package = \"bob.kp\"\n\n[[member]]\nkey_package = \"carol.kp\"\n",
+                bob_identity
+            ),
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&manifest_path).unwrap();
+        let key_packages = manifest.key_packages().unwrap();
+        assert_eq!(key_packages.len(), 2);
+        let (_, welcome) = alice.add_members_to_group(&key_packages).unwrap();
+        let written = manifest.write_welcomes(&key_packages, &welcome).unwrap();
+        assert_eq!(written.len(), 2);
+
+        let bytes = fs::read(
+            dir.join("welcomes")
+                .join(format!("{}.welcome", bob_identity)),
+        )
+        .unwrap();
+        bob.join_existing_group(Invite::decode(&bytes).unwrap())
+            .unwrap();
+        let msg_out = alice.create_message("hi all").unwrap();
+        assert_eq!(bob.parse_message(msg_out).unwrap().unwrap(), "hi all");
+
+        fs::write(
+            &manifest_path,
+            "[[member]]\nidentity = \"someone-else\"\nkey_package = \"bob.kp\"\n",
+        )
+        .unwrap();
+        assert!(Manifest::load(&manifest_path)
+            .unwrap()
+            .key_packages()
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
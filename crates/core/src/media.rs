@@ -0,0 +1,329 @@
+//! An experimental channel for low-latency media and real-time data.
+//!
+//! Floodsub, and MLS framing with it, is built for chat: every message is
+//! signed, padded and flooded to every peer. Packets of a voice call or a
+//! game state instead go straight to each connected member over the
+//! `/p2p-mls/media/1.0.0` protocol, see `media` in `p2p-mls-net`, sealed
+//! with keys in the manner of SRTP. Each epoch the group exports a media secret;
+//! from it every member derives a key and salt per sender leaf, and a
+//! packet's nonce is the sender's salt with its sequence number mixed in.
+//! A packet reads
+//!
+//! `group id length: 1 byte | group id | epoch: 8 | sender leaf: 4 | seq: 8 | ciphertext`
+//!
+//! with everything before the ciphertext authenticated along with it.
+//! Keys rotate with the epoch; the previous epoch's stay until the next
+//! change so packets in flight still open. Members can tell whether a
+//! packet comes from the group, not which member sealed it, so receivers
+//! also check that the peer who sent it holds the sender leaf, and a
+//! window per sender refuses replayed packets.
+
+use std::collections::HashMap;
+
+use openmls::prelude::{MlsGroup, OpenMlsCryptoProvider};
+use openmls_traits::{
+    crypto::OpenMlsCrypto,
+    types::{AeadType, HashType},
+};
+
+use crate::error::NodeError;
+
+const MEDIA_LABEL: &str = "p2p-mls media";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 12;
+/// Bytes after the group id: epoch, sender leaf and sequence number.
+const HEADER_LEN: usize = 8 + 4 + 8;
+/// How far behind the newest packet of a sender one may arrive, as SRTP's
+/// default replay window.
+const REPLAY_WINDOW: u64 = 64;
+
+/// A packet's header, read before it is opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub sender: u32,
+    pub seq: u64,
+}
+
+impl Header {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.group_id.len() as u8];
+        bytes.extend_from_slice(&self.group_id);
+        bytes.extend_from_slice(&self.epoch.to_be_bytes());
+        bytes.extend_from_slice(&self.sender.to_be_bytes());
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        bytes
+    }
+
+    /// Splits `packet` into its header, the bytes it authenticates and the
+    /// ciphertext.
+    pub fn decode(packet: &[u8]) -> Result<(Header, &[u8], &[u8]), NodeError> {
+        let malformed = || NodeError::Parse("Malformed media packet".to_string());
+        let (&len, rest) = packet.split_first().ok_or_else(malformed)?;
+        let len = len as usize;
+        if rest.len() < len + HEADER_LEN {
+            return Err(malformed());
+        }
+        let (aad, ciphertext) = packet.split_at(1 + len + HEADER_LEN);
+        let fields = &aad[1 + len..];
+        let header = Header {
+            group_id: rest[..len].to_vec(),
+            epoch: u64::from_be_bytes(fields[..8].try_into().expect("eight bytes")),
+            sender: u32::from_be_bytes(fields[8..12].try_into().expect("four bytes")),
+            seq: u64::from_be_bytes(fields[12..].try_into().expect("eight bytes")),
+        };
+        Ok((header, aad, ciphertext))
+    }
+}
+
+// Which sequence numbers of one sender we opened, SRTP style: the newest,
+// and a bit for each of the `REPLAY_WINDOW` before it.
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    newest: Option<u64>,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, seq: u64) -> bool {
+        match self.newest {
+            None => true,
+            Some(newest) if seq > newest => true,
+            Some(newest) => {
+                let behind = newest - seq;
+                behind < REPLAY_WINDOW && self.seen & (1 << behind) == 0
+            }
+        }
+    }
+
+    fn mark(&mut self, seq: u64) {
+        match self.newest {
+            Some(newest) if seq <= newest => self.seen |= 1 << (newest - seq),
+            newest => {
+                let ahead = newest.map_or(REPLAY_WINDOW, |newest| seq - newest);
+                self.seen = match ahead {
+                    ahead if ahead >= REPLAY_WINDOW => 0,
+                    ahead => self.seen << ahead,
+                } | 1;
+                self.newest = Some(seq);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EpochSecret {
+    epoch: u64,
+    secret: Vec<u8>,
+}
+
+impl EpochSecret {
+    fn export(mls_group: &MlsGroup, backend: &impl OpenMlsCryptoProvider) -> Option<EpochSecret> {
+        Some(EpochSecret {
+            epoch: mls_group.epoch().as_u64(),
+            secret: mls_group
+                .export_secret(backend, MEDIA_LABEL, &[], KEY_LEN)
+                .ok()?,
+        })
+    }
+
+    // The key and nonce `sender` seals packet `seq` of this epoch with.
+    fn key_and_nonce(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+        sender: u32,
+        seq: u64,
+    ) -> Result<(Vec<u8>, Vec<u8>), NodeError> {
+        let expand = |label: &[u8], len| {
+            let mut info = label.to_vec();
+            info.extend_from_slice(&sender.to_be_bytes());
+            backend
+                .crypto()
+                .hkdf_expand(HashType::Sha2_256, &self.secret, &info, len)
+                .map_err(|e| NodeError::Crypto(format!("Could not derive media key: {:?}", e)))
+        };
+        let key = expand(b"key", KEY_LEN)?;
+        let mut nonce = expand(b"salt", SALT_LEN)?;
+        for (byte, seq) in nonce[SALT_LEN - 8..].iter_mut().zip(seq.to_be_bytes()) {
+            *byte ^= seq;
+        }
+        Ok((key, nonce))
+    }
+}
+
+/// A group's media keys, for the current epoch and the one before.
+#[derive(Debug)]
+pub struct MediaKeys {
+    current: EpochSecret,
+    previous: Option<EpochSecret>,
+    next_seq: u64,
+    windows: HashMap<(u64, u32), ReplayWindow>,
+}
+
+impl MediaKeys {
+    pub fn new(mls_group: &MlsGroup, backend: &impl OpenMlsCryptoProvider) -> Option<MediaKeys> {
+        Some(MediaKeys {
+            current: EpochSecret::export(mls_group, backend)?,
+            previous: None,
+            next_seq: 0,
+            windows: HashMap::new(),
+        })
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.current.epoch
+    }
+
+    /// Moves on to the group's current epoch, if it changed.
+    pub fn rotate(&mut self, mls_group: &MlsGroup, backend: &impl OpenMlsCryptoProvider) {
+        if mls_group.epoch().as_u64() == self.current.epoch {
+            return;
+        }
+        if let Some(current) = EpochSecret::export(mls_group, backend) {
+            let previous = std::mem::replace(&mut self.current, current);
+            self.windows
+                .retain(|(epoch, _), _| *epoch == previous.epoch);
+            self.previous = Some(previous);
+            self.next_seq = 0;
+        }
+    }
+
+    /// Seals `payload` as our next packet, from leaf `sender` of `group_id`.
+    pub fn seal(
+        &mut self,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+        sender: u32,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, NodeError> {
+        let header = Header {
+            group_id: group_id.to_vec(),
+            epoch: self.current.epoch,
+            sender,
+            seq: self.next_seq,
+        };
+        let (key, nonce) = self.current.key_and_nonce(backend, sender, header.seq)?;
+        let mut packet = header.encode();
+        let ciphertext = backend
+            .crypto()
+            .aead_encrypt(AeadType::ChaCha20Poly1305, &key, payload, &nonce, &packet)
+            .map_err(|e| NodeError::Crypto(format!("Could not seal media packet: {:?}", e)))?;
+        self.next_seq += 1;
+        packet.extend(ciphertext);
+        Ok(packet)
+    }
+
+    /// Opens a packet split by [`Header::decode`], once.
+    pub fn open(
+        &mut self,
+        backend: &impl OpenMlsCryptoProvider,
+        header: &Header,
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, NodeError> {
+        let secret = [Some(&self.current), self.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|secret| secret.epoch == header.epoch)
+            .ok_or_else(|| {
+                NodeError::Crypto(format!(
+                    "Media packet from epoch {}, we hold keys for epoch {}",
+                    header.epoch, self.current.epoch
+                ))
+            })?;
+        let window = (header.epoch, header.sender);
+        if !self.windows.entry(window).or_default().accepts(header.seq) {
+            return Err(NodeError::Crypto(format!(
+                "Media packet {} from leaf {} replayed or too late",
+                header.seq, header.sender
+            )));
+        }
+        let (key, nonce) = secret.key_and_nonce(backend, header.sender, header.seq)?;
+        let payload = backend
+            .crypto()
+            .aead_decrypt(AeadType::ChaCha20Poly1305, &key, ciphertext, &nonce, aad)
+            .map_err(|_| NodeError::Crypto("Media packet does not open".to_string()))?;
+        // Only packets that opened move the window, so forged ones cannot.
+        self.windows
+            .get_mut(&window)
+            .expect("inserted above")
+            .mark(header.seq);
+        Ok(payload)
+    }
+}
+
+/// A packet a member streamed to us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaFrame {
+    pub group: String,
+    /// The sender's identity, see `crypto::credential_identity`.
+    pub sender: String,
+    pub epoch: u64,
+    pub seq: u64,
+    pub payload: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+
+    #[test]
+    fn packets_open_once_and_keys_follow_the_epoch() {
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        alice.enable_media();
+        bob.enable_media();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let bob_peer = bob.get_network_keypair().public().to_peer_id();
+        alice
+            .peers_mut()
+            .connected(bob_peer, "/ip4/127.0.0.1/tcp/1".parse().unwrap());
+
+        assert_eq!(alice.stream(b"frame 0").unwrap(), 1);
+        let (to, packet) = alice.take_media().remove(0);
+        assert_eq!(to, bob_peer);
+        let frame = bob.open_media(&alice_peer, &packet).unwrap();
+        assert_eq!((frame.epoch, frame.seq), (1, 0));
+        assert_eq!(frame.payload, b"frame 0");
+        // Replayed, or claiming to come from another member's leaf.
+        assert!(bob.open_media(&alice_peer, &packet).is_err());
+        alice.stream(b"frame 1").unwrap();
+        let (_, packet) = alice.take_media().remove(0);
+        assert!(bob.open_media(&bob_peer, &packet).is_err());
+        let mut forged = packet.clone();
+        *forged.last_mut().unwrap() ^= 1;
+        assert!(bob.open_media(&alice_peer, &forged).is_err());
+
+        // A packet in flight across one epoch change still opens, but not
+        // across two.
+        alice.stream(b"frame 2").unwrap();
+        let (_, late) = alice.take_media().remove(0);
+        let commit = alice.self_update().unwrap();
+        bob.parse_application_message(commit).unwrap();
+        alice.stream(b"frame 3").unwrap();
+        let (_, packet) = alice.take_media().remove(0);
+        let frame = bob.open_media(&alice_peer, &packet).unwrap();
+        assert_eq!((frame.epoch, frame.seq), (2, 0));
+        let commit = alice.self_update().unwrap();
+        bob.parse_application_message(commit).unwrap();
+        assert!(bob.open_media(&alice_peer, &late).is_err());
+        assert!(bob.open_media(&alice_peer, &packet).is_err());
+    }
+
+    #[test]
+    fn the_replay_window_slides_with_the_newest_packet() {
+        let mut window = ReplayWindow::default();
+        for seq in [5, 3, 70] {
+            assert!(window.accepts(seq));
+            window.mark(seq);
+        }
+        assert!(!window.accepts(70));
+        assert!(!window.accepts(5));
+        assert!(window.accepts(69));
+        assert!(!window.accepts(6));
+    }
+}
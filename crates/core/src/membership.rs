@@ -0,0 +1,295 @@
+//! Proofs that an identity was a member of a group at a given epoch.
+//!
+//! A proof carries the member's leaf key package and the path from that leaf
+//! to the root of the ratchet tree: for every ancestor its serialized parent
+//! node and the tree hash of the sibling subtree. Rehashing along the path
+//! must reproduce the group's tree hash, which the issuing member signs
+//! together with the group id and epoch. Verifiers outside the group only
+//! need to trust the issuer's credential.
+
+use openmls::prelude::{
+    Credential, CredentialBundle, KeyPackage, Node, OpenMlsCrypto, OpenMlsCryptoProvider,
+    Signature, TlsDeserializeTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec,
+    crypto::{credential_identity, fingerprint::same_signature_key, CIPHERSUITE},
+    error::NodeError,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathStep {
+    pub node_index: u32,
+    /// TLS serialized `optional<ParentNode>` of this ancestor.
+    pub parent_node: Vec<u8>,
+    pub sibling_hash: Vec<u8>,
+    pub sibling_is_left: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MembershipProof {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub tree_hash: Vec<u8>,
+    pub leaf_index: u32,
+    pub key_package: Vec<u8>,
+    pub path: Vec<PathStep>,
+    pub issuer: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// What a verified [`MembershipProof`] establishes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MembershipStatement {
+    pub identity: String,
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub issuer: String,
+}
+
+impl MembershipProof {
+    /// Builds a proof for `leaf_index` from an exported ratchet tree.
+    pub fn issue(
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+        epoch: u64,
+        tree: &[Option<Node>],
+        leaf_index: u32,
+    ) -> Result<MembershipProof, NodeError> {
+        let key_package = match tree.get(leaf_index as usize * 2) {
+            Some(Some(Node::LeafNode(leaf))) => leaf.key_package().clone(),
+            _ => {
+                return Err(NodeError::Other(format!(
+                    "No member at leaf {}",
+                    leaf_index
+                )))
+            }
+        };
+
+        // Walk down from the root, recording each ancestor and the subtree we
+        // did not descend into.
+        let target = leaf_index * 2;
+        let size = tree.len() as u32;
+        let mut node = root(size);
+        let mut path = Vec::new();
+        while node != target {
+            let (left, right) = (left(node), right(node, size));
+            let (next, sibling, sibling_is_left) = if target < node {
+                (left, right, false)
+            } else {
+                (right, left, true)
+            };
+            path.push(PathStep {
+                node_index: node,
+                parent_node: serialize_parent(tree, node)?,
+                sibling_hash: subtree_hash(backend, tree, sibling)?,
+                sibling_is_left,
+            });
+            node = next;
+        }
+        path.reverse();
+        let tree_hash = subtree_hash(backend, tree, root(size))?;
+
+        let (credential, private_key) = credential_bundle.into_parts();
+        let signature = private_key
+            .sign(backend, &signed_content(group_id, epoch, &tree_hash))
+            .map_err(|e| NodeError::Other(format!("Could not sign membership proof: {:?}", e)))?;
+        Ok(MembershipProof {
+            group_id: group_id.to_vec(),
+            epoch,
+            tree_hash,
+            leaf_index,
+            key_package: codec::to_tls(&key_package, "key package")?,
+            path,
+            issuer: codec::to_tls(&credential, "credential")?,
+            signature: codec::to_tls(&signature, "signature")?,
+        })
+    }
+
+    /// Checks the path against the signed tree hash and that the issuer is one
+    /// of `trusted_issuers`.
+    pub fn verify(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+        trusted_issuers: &[Credential],
+    ) -> Result<MembershipStatement, NodeError> {
+        let issuer = Credential::tls_deserialize(&mut self.issuer.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed proof issuer".to_string()))?;
+        if !trusted_issuers
+            .iter()
+            .any(|trusted| same_signature_key(trusted, &issuer))
+        {
+            return Err(NodeError::Other(format!(
+                "Proof issuer {} is not trusted",
+                credential_identity(&issuer)
+            )));
+        }
+        let signature = Signature::tls_deserialize(&mut self.signature.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed proof signature".to_string()))?;
+        issuer
+            .verify(
+                backend,
+                &signed_content(&self.group_id, self.epoch, &self.tree_hash),
+                &signature,
+            )
+            .map_err(|_| NodeError::Other("Invalid membership proof signature".to_string()))?;
+
+        let key_package = KeyPackage::tls_deserialize(&mut self.key_package.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed proof key package".to_string()))?;
+        let mut hash = leaf_hash(backend, self.leaf_index * 2, Some(&key_package))?;
+        for step in &self.path {
+            let (left, right) = if step.sibling_is_left {
+                (&step.sibling_hash, &hash)
+            } else {
+                (&hash, &step.sibling_hash)
+            };
+            hash = parent_hash(backend, step.node_index, &step.parent_node, left, right)?;
+        }
+        if hash != self.tree_hash {
+            return Err(NodeError::Other(
+                "Membership path does not match the signed tree hash".to_string(),
+            ));
+        }
+        Ok(MembershipStatement {
+            identity: credential_identity(key_package.credential()),
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            issuer: credential_identity(&issuer),
+        })
+    }
+
+    pub fn encode(&self) -> Result<String, NodeError> {
+        codec::to_json_pretty(self, "membership proof")
+    }
+
+    pub fn decode(contents: &str) -> Result<MembershipProof, NodeError> {
+        serde_json::from_str(contents)
+            .map_err(|e| NodeError::Parse(format!("Invalid membership proof: {}", e)))
+    }
+}
+
+fn signed_content(group_id: &[u8], epoch: u64, tree_hash: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(2 + group_id.len() + 8 + tree_hash.len());
+    content.extend_from_slice(&(group_id.len() as u16).to_be_bytes());
+    content.extend_from_slice(group_id);
+    content.extend_from_slice(&epoch.to_be_bytes());
+    content.extend_from_slice(tree_hash);
+    content
+}
+
+// MlsGroup only exposes its tree hash to OpenMLS' own tests, so we recompute
+// it the way OpenMLS does: leaves hash `node_index | optional<KeyPackage>`,
+// parents hash `node_index | optional<ParentNode> | left<u8> | right<u8>`.
+
+fn hash(backend: &impl OpenMlsCryptoProvider, input: &[u8]) -> Result<Vec<u8>, NodeError> {
+    backend
+        .crypto()
+        .hash(CIPHERSUITE.hash_algorithm(), input)
+        .map_err(|e| NodeError::Other(format!("Could not hash tree node: {:?}", e)))
+}
+
+fn leaf_hash(
+    backend: &impl OpenMlsCryptoProvider,
+    node_index: u32,
+    key_package: Option<&KeyPackage>,
+) -> Result<Vec<u8>, NodeError> {
+    let mut input = node_index.to_be_bytes().to_vec();
+    match key_package {
+        Some(key_package) => {
+            input.push(1);
+            input.extend(codec::to_tls(key_package, "key package")?);
+        }
+        None => input.push(0),
+    }
+    hash(backend, &input)
+}
+
+fn parent_hash(
+    backend: &impl OpenMlsCryptoProvider,
+    node_index: u32,
+    parent_node: &[u8],
+    left: &[u8],
+    right: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    if left.len() > u8::MAX as usize || right.len() > u8::MAX as usize {
+        return Err(NodeError::Other("Subtree hash too long".to_string()));
+    }
+    let mut input = node_index.to_be_bytes().to_vec();
+    input.extend_from_slice(parent_node);
+    input.push(left.len() as u8);
+    input.extend_from_slice(left);
+    input.push(right.len() as u8);
+    input.extend_from_slice(right);
+    hash(backend, &input)
+}
+
+fn serialize_parent(tree: &[Option<Node>], node_index: u32) -> Result<Vec<u8>, NodeError> {
+    match tree.get(node_index as usize) {
+        Some(Some(node @ Node::ParentNode(_))) => {
+            // Swap the node type byte for the optional's presence byte.
+            let mut bytes = codec::to_tls(node, "ratchet tree node")?;
+            bytes[0] = 1;
+            Ok(bytes)
+        }
+        Some(None) => Ok(vec![0]),
+        _ => Err(NodeError::Other(format!(
+            "Expected parent node at {}",
+            node_index
+        ))),
+    }
+}
+
+fn subtree_hash(
+    backend: &impl OpenMlsCryptoProvider,
+    tree: &[Option<Node>],
+    node_index: u32,
+) -> Result<Vec<u8>, NodeError> {
+    if level(node_index) == 0 {
+        let key_package = match tree.get(node_index as usize) {
+            Some(Some(Node::LeafNode(leaf))) => Some(leaf.key_package()),
+            Some(None) => None,
+            _ => {
+                return Err(NodeError::Other(format!(
+                    "Expected leaf node at {}",
+                    node_index
+                )))
+            }
+        };
+        return leaf_hash(backend, node_index, key_package);
+    }
+    let size = tree.len() as u32;
+    let left = subtree_hash(backend, tree, left(node_index))?;
+    let right = subtree_hash(backend, tree, right(node_index, size))?;
+    parent_hash(
+        backend,
+        node_index,
+        &serialize_parent(tree, node_index)?,
+        &left,
+        &right,
+    )
+}
+
+// Array-based left-balanced tree math, see the MLS spec's appendix.
+
+fn level(node_index: u32) -> u32 {
+    node_index.trailing_ones()
+}
+
+fn root(size: u32) -> u32 {
+    (1 << (31 - size.leading_zeros())) - 1
+}
+
+fn left(node_index: u32) -> u32 {
+    node_index ^ (1 << (level(node_index) - 1))
+}
+
+fn right(node_index: u32, size: u32) -> u32 {
+    let mut right = node_index ^ (3 << (level(node_index) - 1));
+    while right >= size {
+        right = left(right);
+    }
+    right
+}
@@ -0,0 +1,202 @@
+//! Group metadata bound to the group's epochs.
+//!
+//! MLS would keep application metadata in a GroupContext extension, part of
+//! the state every member agrees on after a commit. openmls 0.4 leaves no
+//! room for one: extension types are a closed set, context extensions are
+//! fixed when a group is created, and GroupContextExtensions proposals are
+//! never applied. What a commit does bind is its authenticated data, which
+//! is signed with it and hashed into the confirmed transcript hash, and so
+//! into the GroupContext of the epoch the commit starts.
+//!
+//! Members who know the group's policy therefore put [`GroupMetadata`] in
+//! the authenticated data of everything they send. Authenticated data
+//! travels in the clear, so it goes sealed, `3 | nonce | ciphertext`, under
+//! a secret exported from the epoch it is sent in, and floodsub peers
+//! outside the group learn neither name, policy nor features. Sealed, it
+//! holds `2 | policy<u8> | features: u8 | name<u8>`, which is also what
+//! older members send in the clear, as even older ones send the bare
+//! policy of `policy`. The policy itself travels rather than a hash of it,
+//! since members who joined by welcome learn it from here, and the
+//! transcript hash already covers it. Members take the policy only from the
+//! leader or an admin, see `admins`, and never one that drops a pre-shared
+//! key; the name and feature flags are taken from their commits, so after
+//! each such commit members hold what the committer held, whatever became
+//! of the `0xEF` messages of `features`.
+
+use openmls::prelude::{MlsGroup, OpenMlsCryptoProvider};
+
+use crate::{backup, error::NodeError, features::FeatureFlags, policy::GroupPolicy};
+
+const METADATA_VERSION: u8 = 2;
+const SEALED_VERSION: u8 = 3;
+const METADATA_LABEL: &str = "p2p-mls group metadata";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupMetadata {
+    /// The name the group was given, if it has one and may tell it.
+    pub name: Option<String>,
+    pub policy: GroupPolicy,
+    pub features: FeatureFlags,
+}
+
+impl GroupMetadata {
+    /// Fails for a name too long for its length byte, which names checked by
+    /// `create_group` never are.
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let policy = self.policy.encode();
+        let name = self.name.as_deref().unwrap_or_default().as_bytes();
+        let mut bytes = vec![METADATA_VERSION, prefix(&policy)?];
+        bytes.extend(policy);
+        bytes.push(self.features.bits());
+        bytes.push(prefix(name)?);
+        bytes.extend(name);
+        Ok(bytes)
+    }
+
+    /// The metadata as members send it, readable only in `group`'s current
+    /// epoch.
+    pub fn seal(
+        &self,
+        group: &MlsGroup,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<Vec<u8>, NodeError> {
+        let key = metadata_key(group, backend)
+            .ok_or_else(|| NodeError::Crypto("Could not export the metadata key".to_string()))?;
+        let mut bytes = vec![SEALED_VERSION];
+        bytes.extend(backup::seal(
+            backend,
+            &key,
+            METADATA_LABEL,
+            &self.encode()?,
+        )?);
+        Ok(bytes)
+    }
+
+    /// Opens what [`GroupMetadata::seal`] sealed in `group`'s current epoch,
+    /// or decodes what older members send in the clear.
+    pub fn open(
+        bytes: &[u8],
+        group: &MlsGroup,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Option<GroupMetadata> {
+        match bytes {
+            [SEALED_VERSION, object @ ..] => {
+                let key = metadata_key(group, backend)?;
+                GroupMetadata::decode(&backup::open(backend, &key, METADATA_LABEL, object).ok()?)
+            }
+            bytes => GroupMetadata::decode(bytes),
+        }
+    }
+
+    /// `None` for anything else, such as a bare policy.
+    pub fn decode(bytes: &[u8]) -> Option<GroupMetadata> {
+        let rest = match bytes {
+            [METADATA_VERSION, rest @ ..] => rest,
+            _ => return None,
+        };
+        let (policy, rest) = split_prefixed(rest)?;
+        let (features, rest) = rest.split_first()?;
+        let (name, rest) = split_prefixed(rest)?;
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(GroupMetadata {
+            name: match name {
+                [] => None,
+                name => Some(String::from_utf8(name.to_vec()).ok()?),
+            },
+            policy: GroupPolicy::decode(policy)?,
+            features: FeatureFlags::from_bits(*features),
+        })
+    }
+}
+
+fn metadata_key(group: &MlsGroup, backend: &impl OpenMlsCryptoProvider) -> Option<Vec<u8>> {
+    group.export_secret(backend, METADATA_LABEL, &[], 32).ok()
+}
+
+fn prefix(field: &[u8]) -> Result<u8, NodeError> {
+    u8::try_from(field.len()).map_err(|_| NodeError::Serialization {
+        what: "group metadata".to_string(),
+        detail: format!("a field of {} bytes, at most 255 fit", field.len()),
+    })
+}
+
+// A field of `len: u8 | bytes`, and what follows it.
+fn split_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = bytes.split_first()?;
+    (rest.len() >= *len as usize).then(|| rest.split_at(*len as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ciphertext_authenticated_data;
+    use crate::features::Feature;
+    use crate::node::Node;
+    use crate::protocol::Invite;
+
+    #[test]
+    fn members_converge_on_the_metadata_of_commits() {
+        let mut features = FeatureFlags::default();
+        features.set(Feature::Presence, false);
+        let metadata = GroupMetadata {
+            name: Some("work".to_string()),
+            policy: GroupPolicy::default(),
+            features,
+        };
+        assert_eq!(
+            GroupMetadata::decode(&metadata.encode().unwrap()),
+            Some(metadata.clone())
+        );
+        assert_eq!(
+            GroupMetadata::decode(&GroupPolicy::default().encode()),
+            None
+        );
+        // Too long for its length byte, and for a group.
+        let long = "a".repeat(256);
+        assert!(GroupMetadata {
+            name: Some(long.clone()),
+            ..metadata
+        }
+        .encode()
+        .is_err());
+        assert!(Node::default()
+            .create_group(&long, GroupPolicy::default())
+            .is_err());
+
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.create_group("work", GroupPolicy::default()).unwrap();
+        let (_, invite) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        // A Welcome without the name, and feature flags that never arrive.
+        bob.join_existing_group(Invite {
+            name: None,
+            ..invite
+        })
+        .unwrap();
+        alice.set_feature(Feature::Presence, false).unwrap();
+        assert_ne!(bob.active_group_name().as_deref(), Some("work"));
+        assert!(bob.features().unwrap().enabled(Feature::Presence));
+
+        // Peers outside the group read nothing of it.
+        let commit = alice.self_update().unwrap();
+        let aad = ciphertext_authenticated_data(&commit).unwrap();
+        assert_eq!(GroupMetadata::decode(&aad), None);
+        assert!(!aad.windows(4).any(|window| window == b"work"));
+
+        bob.parse_message(commit).unwrap();
+        assert_eq!(bob.active_group_name().as_deref(), Some("work"));
+        assert_eq!(bob.features().unwrap(), alice.features().unwrap());
+        let journal = bob.journal().groups();
+        assert_eq!(
+            journal.get(&journal.active.clone().unwrap()).unwrap().name,
+            "work"
+        );
+
+        // Commits by members without rights change nothing.
+        alice.set_feature(Feature::Presence, true).unwrap();
+        alice.parse_message(bob.self_update().unwrap()).unwrap();
+        assert!(alice.features().unwrap().enabled(Feature::Presence));
+    }
+}
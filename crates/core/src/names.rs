@@ -0,0 +1,202 @@
+//! How peers are named in output.
+//!
+//! A PeerId is 52 characters and every Ed25519 one starts the same way, so
+//! by default peers are named by the end of their PeerId instead: the
+//! shortest suffix of at least [`MIN_NAME_LEN`] characters that no other
+//! peer we have named shares. A name only grows when a peer sharing its
+//! suffix turns up, so the same peer reads the same everywhere.
+//!
+//! Members started with `--name` also go by a nickname, sent to each group
+//! with their capabilities as an application message `0xF2 | name`, which
+//! MLS authenticates as coming from the member whose credential names the
+//! peer. Nicknames are claims, not identities: two peers claiming the same
+//! one are shown with their short names too, and `--names=full` shows
+//! PeerIds alone.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{error::NodeError, identity::PeerId};
+
+pub const MIN_NAME_LEN: usize = 6;
+
+pub const MAX_NICKNAME_LEN: usize = 32;
+
+const PROFILE_MARKER: u8 = 0xF2;
+
+/// Checks that `name` is 1 to [`MAX_NICKNAME_LEN`] letters, digits, `-`,
+/// `_` or `.`, so it cannot pass for anything else in output.
+pub fn check_nickname(name: &str) -> Result<(), NodeError> {
+    let allowed = |c: char| c.is_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || name.chars().count() > MAX_NICKNAME_LEN || !name.chars().all(allowed) {
+        return Err(NodeError::Other(format!(
+            "Names are 1 to {} letters, digits, -, _ or .",
+            MAX_NICKNAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+pub fn is_profile(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&PROFILE_MARKER)
+}
+
+pub fn encode_profile(name: &str) -> Vec<u8> {
+    let mut bytes = vec![PROFILE_MARKER];
+    bytes.extend_from_slice(name.as_bytes());
+    bytes
+}
+
+pub fn decode_profile(bytes: &[u8]) -> Result<String, NodeError> {
+    let name = match bytes {
+        [PROFILE_MARKER, name @ ..] => std::str::from_utf8(name)
+            .map_err(|_| NodeError::Parse("Malformed profile".to_string()))?,
+        _ => return Err(NodeError::Parse("Not a profile".to_string())),
+    };
+    check_nickname(name)?;
+    Ok(name.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameStyle {
+    Short,
+    Full,
+}
+
+impl NameStyle {
+    pub fn parse(style: &str) -> Result<NameStyle, NodeError> {
+        match style {
+            "short" => Ok(NameStyle::Short),
+            "full" => Ok(NameStyle::Full),
+            _ => Err(NodeError::Other(format!(
+                "Unknown name style {}, expected short or full",
+                style
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DisplayNames {
+    style: NameStyle,
+    named: BTreeSet<String>,
+    nicknames: HashMap<String, String>, // by PeerId
+}
+
+impl Default for DisplayNames {
+    fn default() -> DisplayNames {
+        DisplayNames::new(NameStyle::Short)
+    }
+}
+
+impl DisplayNames {
+    pub fn new(style: NameStyle) -> DisplayNames {
+        DisplayNames {
+            style,
+            named: BTreeSet::new(),
+            nicknames: HashMap::new(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: NameStyle) {
+        self.style = style;
+    }
+
+    /// Records the nickname `peer` goes by, returning whether it is new.
+    pub fn set_nickname(&mut self, peer: &PeerId, name: String) -> bool {
+        self.nicknames.insert(peer.to_string(), name.clone()) != Some(name)
+    }
+
+    pub fn nickname(&self, peer: &PeerId) -> Option<&str> {
+        self.nicknames.get(&peer.to_string()).map(String::as_str)
+    }
+
+    /// The name to show `peer` under, remembering it so later names stay
+    /// apart from it.
+    pub fn name(&mut self, peer: &PeerId) -> String {
+        let id = peer.to_string();
+        if self.style == NameStyle::Full {
+            return id;
+        }
+        let short = self.short_name(id);
+        match self.nicknames.get(&peer.to_string()) {
+            None => short,
+            Some(nickname) if self.nicknames.values().filter(|n| *n == nickname).count() > 1 => {
+                format!("{} ({})", nickname, short)
+            }
+            Some(nickname) => nickname.clone(),
+        }
+    }
+
+    fn short_name(&mut self, id: String) -> String {
+        self.named.insert(id.clone());
+        let len = (MIN_NAME_LEN..id.len())
+            .find(|&len| {
+                let suffix = &id[id.len() - len..];
+                self.named
+                    .iter()
+                    .filter(|other| other.ends_with(suffix))
+                    .count()
+                    == 1
+            })
+            .unwrap_or(id.len());
+        id[id.len() - len..].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_short_and_unambiguous() {
+        let mut names = DisplayNames::default();
+        let alice = PeerId::random();
+        let name = names.name(&alice);
+        assert_eq!(name.len(), MIN_NAME_LEN);
+        assert!(alice.to_string().ends_with(&name));
+        assert_eq!(names.name(&alice), name);
+
+        // Two peers sharing a suffix are told apart by a longer one.
+        let mut named = BTreeSet::new();
+        let id = alice.to_string();
+        named.insert(id.clone());
+        // Differs from the id just before the shared nine characters.
+        let other = match &id[id.len() - 10..id.len() - 9] {
+            "X" => 'Y',
+            _ => 'X',
+        };
+        named.insert(format!("{}{}{}", &id[..8], other, &id[id.len() - 9..]));
+        names.named = named;
+        assert_eq!(names.name(&alice).len(), 10);
+
+        names.set_style(NameStyle::Full);
+        assert_eq!(names.name(&alice), id);
+        assert!(NameStyle::parse("nick").is_err());
+    }
+
+    #[test]
+    fn nicknames_replace_short_names_unless_shared() {
+        let mut names = DisplayNames::default();
+        let (alice, mallory) = (PeerId::random(), PeerId::random());
+        let profile = encode_profile("alice");
+        assert!(is_profile(&profile));
+        let name = decode_profile(&profile).unwrap();
+        assert!(names.set_nickname(&alice, name.clone()));
+        assert!(!names.set_nickname(&alice, name));
+        assert_eq!(names.name(&alice), "alice");
+
+        // A second claim to the name shows who is who.
+        names.set_nickname(&mallory, "alice".to_string());
+        let shown = names.name(&alice);
+        assert!(shown.starts_with("alice (") && shown != names.name(&mallory));
+
+        for name in [
+            "",
+            "al ice",
+            "alice\u{1b}[31m",
+            &"a".repeat(MAX_NICKNAME_LEN + 1),
+        ] {
+            assert!(decode_profile(&encode_profile(name)).is_err());
+        }
+    }
+}
@@ -0,0 +1,6707 @@
+use openmls::{
+    group::MlsGroup,
+    prelude::{
+        Ciphersuite, Credential, Extension, HashType, KeyPackage, KeyPackageBundle, MlsMessageOut,
+        Node as OpenMlsNode, OpenMlsCrypto, OpenMlsCryptoProvider, ProcessedMessage, Proposal,
+        QueuedProposal, Sender, TlsDeserializeTrait, VerifiablePublicGroupState,
+    },
+};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "pki")]
+use crate::pki::{CertificateChain, PkiTrust};
+use crate::{
+    ack::{self, Deliveries, Delivery, MessageId},
+    admins::{AdminRoster, Rights},
+    admission::{
+        AdmissionConfig, AdmissionControl, AdmissionMetrics, JoinApproval, JoinRequest,
+        PendingJoin, PendingJoins, WelcomePolicy,
+    },
+    archive::{self, GroupArchive},
+    audit::{AuditEntry, AuditLog},
+    backfill::{Absence, Backlog, Disclosure, Recent, Retention, SharedMessage},
+    backup::{derive_key, BackupService, DEFAULT_ITERATIONS},
+    capabilities::{self, Capabilities, ClientInfo, ClientStats},
+    codec,
+    crypto::{
+        ciphertext_authenticated_data, clone_mls_group, credential_identity,
+        fingerprint::{
+            credential_has_identity, credential_matches_peer, ct_eq, same_signature_key,
+            Fingerprint,
+        },
+        generate_credential_bundle_from_identity, generate_group_id, generate_key_package_bundle,
+        generate_key_package_bundle_with, generate_mls_group,
+        generate_mls_group_from_external_commit, generate_mls_group_from_welcome, hex_encode,
+        key_store::Backend,
+        psk_epoch_key, read_credential_bundle, read_key_package_bundle, store_credential_bundle,
+        store_key_package_bundle, with_padding_size, CIPHERSUITE,
+    },
+    diagnostics::DecryptFailure,
+    error::NodeError,
+    events::NodeEvent,
+    external::{SharedGroup, MAX_SHARED_GROUPS},
+    features::{Feature, FeatureFlags},
+    guests::{GuestEntry, Guests, Remaining},
+    handover::{HandedJoin, LeaderOffline, SignedLeaderOffline},
+    health::{Broadcast, GroupHealth, HealthIssue, SendOutcome, SendPolicy, MAX_HELD},
+    history::{MessageHistory, StoredMessage},
+    hooks::{EpochChange, EpochHooks, JoinCandidate, JoinDecision, JoinPolicies},
+    identity::{Keypair, PeerId},
+    introduction::{AddressBook, SignedAddressBook},
+    journal::{GroupEvent, Journal},
+    lifetime::{self, JoinRefusal, Lifetime, LifetimeError},
+    limits::FrameKind,
+    media::{Header, MediaFrame, MediaKeys},
+    membership::MembershipProof,
+    metadata::GroupMetadata,
+    names::{self, DisplayNames, NameStyle},
+    outbox::{DeliveryState, Outbox, OutboxEntry, PendingSend},
+    peers::{control_topic, PeerTable},
+    pending::PendingMessages,
+    policy::{GroupPolicy, TIMESTAMP_GRANULARITY_SECS},
+    protocol::{self, ControlMessage, Invite, WireKind, WireMessage},
+    provision::ProvisionedIdentity,
+    psk::{self, GroupPsk, PskId},
+    qos::{Plane, Qos},
+    receipt::SignedPayload,
+    recovery::{self, RecoveryKind, RecoveryMessage, RecoveryReport, StateDigest},
+    rooms::{JoinPolicy, RoomDirectory, SignedAnnouncement},
+    schema::{self, Artifact},
+    security::SecurityState,
+    telemetry::{DecoderRegistry, Telemetry, TelemetryFrame},
+    transfer::{Offer, Progress, SealedChunk, Transfers},
+    transparency::{AllowAll, KeyTransparency},
+    typing::{self, TypingLimiter},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// The group `node create` starts when not given a name.
+pub const DEFAULT_GROUP_NAME: &str = "Test Group";
+
+// Consecutive processing failures before the channel is shown as degraded.
+const DEGRADED_AFTER_FAILURES: u32 = 3;
+
+const IDENTITY_SECTION: &str = "identity";
+// Each group is backed up as `group-<hex id>`; single-group backups have `group`.
+const GROUP_SECTION: &str = "group";
+const AUDIT_SECTION: &str = "audit";
+const JOURNAL_SECTION: &str = "journal";
+
+const EPOCH_DIGEST_LABEL: &str = "p2p-mls epoch digest";
+const TOPIC_LABEL: &str = "p2p-mls topic";
+const HISTORY_LABEL: &str = "p2p-mls history";
+const MESSAGES_LABEL: &str = "p2p-mls messages";
+// Adverts handed out and not yet published, to send on the control plane.
+const CONTROL_FRAMES_KEPT: usize = 256;
+// Older topics stay subscribed so commits sent just before a rotation,
+// including a leader's stale-leaf removal followed by an add, still arrive.
+const TOPICS_KEPT: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+struct GroupBackup {
+    state: Vec<u8>,
+    is_group_leader: bool,
+    policy: Vec<u8>,
+    topics: Vec<String>,
+    history_secret: Vec<u8>,
+    name: String,
+    admins: Vec<u8>,
+    #[serde(default)]
+    features: Vec<u8>,
+    #[serde(default)]
+    guests: Vec<GuestEntry>,
+    #[serde(default)]
+    given_name: Option<String>,
+}
+
+#[derive(Debug)]
+struct Identity {
+    network_key: Keypair,
+    key_package: KeyPackage,
+}
+
+/// Everything we keep about one of the groups we are in.
+#[derive(Debug)]
+struct GroupState {
+    mls_group: MlsGroup,
+    name: String,                // what the user calls the group; ids are random
+    is_group_leader: bool,       // Only group leader can add new members to the group
+    sent_generation: (u64, u32), // (epoch, messages we sent in that epoch)
+    policy: GroupPolicy,
+    failed_messages: u32, // consecutive messages we could not process
+    desynced: bool,
+    observed_epoch: u64, // latest epoch seen in members' traffic, see `health`
+    epoch_digests: Vec<(u64, Vec<u8>)>, // newest last, see `recovery`
+    topics: Vec<String>, // group topics, newest last
+    recovery_answers: HashMap<String, StateDigest>,
+    joined_epoch: u64,       // traffic from earlier epochs was never meant for us
+    history_secret: Vec<u8>, // seals our history of the group, see `audit`
+    advertised_epoch: Option<u64>, // when we last sent our capabilities
+    admins: AdminRoster,     // rights the leader granted, see `admins`
+    join_window: Duration,   // to coalesce join requests in, see `Node::set_join_window`
+    media: Option<MediaKeys>, // with --media, see `media`
+    successor: Option<String>, // named by the leader going offline, see `handover`
+    recent: Recent,          // texts kept for joiners, see `backfill`
+    features: FeatureFlags,  // what admins turned on, see `features`
+    guests: Guests,          // members let in for a while, see `guests`
+    given_name: Option<String>, // which `name` may tell apart, see `metadata`
+}
+
+impl GroupState {
+    // Until traffic tells us otherwise, the group runs the default policy.
+    fn new(mls_group: MlsGroup, is_group_leader: bool, backend: &Backend) -> GroupState {
+        let mut state = GroupState {
+            name: group_name(mls_group.group_id().as_slice()),
+            mls_group,
+            is_group_leader,
+            sent_generation: (0, 0),
+            policy: GroupPolicy::default(),
+            failed_messages: 0,
+            desynced: false,
+            observed_epoch: 0,
+            epoch_digests: Vec::new(),
+            topics: Vec::new(),
+            recovery_answers: HashMap::new(),
+            joined_epoch: 0,
+            history_secret: Vec::new(),
+            advertised_epoch: None,
+            admins: AdminRoster::default(),
+            join_window: Duration::ZERO,
+            media: None,
+            successor: None,
+            recent: Recent::default(),
+            features: FeatureFlags::default(),
+            guests: Guests::default(),
+            given_name: None,
+        };
+        state.joined_epoch = state.mls_group.epoch().as_u64();
+        state.history_secret = state
+            .mls_group
+            .export_secret(backend, HISTORY_LABEL, &[], 32)
+            .unwrap_or_default();
+        state.record_epoch_digest(backend);
+        state.rotate_topic(backend);
+        state
+    }
+
+    fn group_id(&self) -> Vec<u8> {
+        self.mls_group.group_id().as_slice().to_vec()
+    }
+
+    // The leader created the group, so it sits at leaf 0.
+    fn leader_key(&self) -> Option<Vec<u8>> {
+        self.leader_credential()
+            .map(|credential| credential.signature_key().as_slice().to_vec())
+    }
+
+    fn leader_credential(&self) -> Option<Credential> {
+        match self.mls_group.export_ratchet_tree().first() {
+            Some(Some(OpenMlsNode::LeafNode(leaf))) => {
+                Some(leaf.key_package().credential().clone())
+            }
+            _ => None,
+        }
+    }
+
+    // What the member with `signature_key` may do to the membership.
+    fn rights_of(&self, signature_key: &[u8]) -> Rights {
+        match self.leader_key() {
+            Some(leader) if ct_eq(&leader, signature_key) => Rights::ALL,
+            _ => self.admins.rights(signature_key),
+        }
+    }
+
+    // Both the policy and the group's features have to allow them.
+    fn allows_read_receipts(&self) -> bool {
+        self.policy.allows_read_receipts() && self.features.enabled(Feature::ReadReceipts)
+    }
+
+    fn adopt_policy(&mut self, policy: GroupPolicy, backend: &Backend) {
+        self.policy = policy;
+        self.seal_metadata(backend);
+    }
+
+    fn metadata(&self) -> GroupMetadata {
+        GroupMetadata {
+            name: self.given_name.clone(),
+            policy: self.policy,
+            features: self.features,
+        }
+    }
+
+    // Sends the metadata as it is now, sealed for this epoch, once we know
+    // the policy to send.
+    fn bind_metadata(&mut self, backend: &Backend) {
+        if !self.mls_group.aad().is_empty() {
+            self.seal_metadata(backend);
+        }
+    }
+
+    fn seal_metadata(&mut self, backend: &Backend) {
+        match self.metadata().seal(&self.mls_group, backend) {
+            Ok(aad) => self.mls_group.set_aad(&aad),
+            Err(e) => log::warn!("Could not seal the metadata of {}: {}", self.name, e),
+        }
+    }
+
+    // Remembers a hash of this epoch's exporter secret, which members share
+    // only if they took the same path through the group's history.
+    fn record_epoch_digest(&mut self, backend: &Backend) {
+        let epoch = self.mls_group.epoch().as_u64();
+        if self.epoch_digests.last().map(|(e, _)| *e) == Some(epoch) {
+            return;
+        }
+        let digest = self
+            .mls_group
+            .export_secret(backend, EPOCH_DIGEST_LABEL, &[], 32)
+            .ok()
+            .and_then(|secret| backend.crypto().hash(HashType::Sha2_256, &secret).ok());
+        if let Some(digest) = digest {
+            self.epoch_digests.push((epoch, digest));
+            if self.epoch_digests.len() > recovery::HISTORY_LEN {
+                self.epoch_digests.remove(0);
+            }
+        }
+    }
+
+    // Derives the group's pubsub topic from this epoch's exporter secret.
+    // Called on epochs that change membership, which are the ones a newcomer
+    // can join at, so everyone in the group lands on the same topic.
+    fn rotate_topic(&mut self, backend: &Backend) {
+        let mut preimage = self.group_id();
+        let secret = match self.mls_group.export_secret(backend, TOPIC_LABEL, &[], 32) {
+            Ok(secret) => secret,
+            Err(_) => return,
+        };
+        preimage.extend(secret);
+        if let Ok(hash) = backend.crypto().hash(HashType::Sha2_256, &preimage) {
+            let topic = format!("p2p-mls-{}", hex_encode(&hash[..16]));
+            if self.topics.last() != Some(&topic) {
+                self.topics.push(topic);
+            }
+            if self.topics.len() > TOPICS_KEPT {
+                self.topics.remove(0);
+            }
+        }
+    }
+
+    // Our sender ratchet restarts at generation 0 on every epoch change.
+    fn next_generation(&self) -> u32 {
+        match self.sent_generation {
+            (epoch, sent) if self.mls_group.epoch().as_u64() == epoch => sent,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Node {
+    backend: Backend,
+    groups: HashMap<Vec<u8>, GroupState>,
+    journal: Journal, // changes to our groups, which decide the active one, see `journal`
+    identity: Identity,
+    telemetry_decoders: DecoderRegistry,
+    audit_log: AuditLog,
+    messages: Option<MessageHistory>, // texts kept across restarts, see `history`
+    key_transparency: Box<dyn KeyTransparency>,
+    #[cfg(feature = "pki")]
+    pki: Option<PkiTrust>, // roots key packages must be certified by
+    #[cfg(feature = "pki")]
+    certificate: Option<CertificateChain>, // ours, carried in our key packages
+    epoch_hooks: EpochHooks,
+    join_policies: JoinPolicies, // deciding join requests under manual approval
+    admission: AdmissionControl,
+    join_approval: JoinApproval,
+    pending_joins: PendingJoins, // waiting for accept_join or decline_join
+    join_window: Duration,       // for the groups we start or join from now on
+    join_batches: HashMap<Vec<u8>, Instant>, // by group, when its waiting joiners are added
+    verified_members: HashSet<Vec<u8>>, // signature keys checked out of band
+    backup: Option<BackupService>,
+    peers: PeerTable,
+    share_addresses: bool, // consent to send our address book to the group
+    rooms: RoomDirectory,
+    pending: PendingMessages, // group traffic ahead of us, see `pending`
+    // By group name, with the identity of their author.
+    replayed: Vec<(String, Option<String>, ApplicationPayload)>,
+    author: Option<Credential>, // of the message last processed, as MLS authenticated it
+    events: Vec<NodeEvent>,     // from commits, see `events`
+    outgoing: Vec<WireMessage>, // answers to frames, to publish
+    direct: Vec<(PeerId, WireMessage)>, // to send to one peer each
+    refusals: Vec<JoinRefusal>, // to publish, see `lifetime`
+    outbox: Outbox,
+    deliveries: Deliveries,            // who acknowledged our text, see `ack`
+    read_receipts: bool,               // consent to tell senders we read their text
+    typing: TypingLimiter,             // when we last said we were typing, see `typing`
+    unread: Vec<(Vec<u8>, MessageId)>, // texts received, by group, to send receipts for
+    media: bool,                       // stream channel enabled, see `media`
+    media_out: Vec<(PeerId, Vec<u8>)>, // packets to stream to one peer each
+    transfers: Transfers,              // files sent to us, see `transfer`
+    bulk: Vec<SealedChunk>,            // file chunks, to publish as bulk traffic
+    names: DisplayNames,
+    nickname: Option<String>, // sent with our capabilities, see `names`
+    send_policy: SendPolicy,
+    mailbox: bool, // frames wait in a mailbox while no peers are connected
+    held: VecDeque<(Vec<u8>, String)>, // text for unhealthy groups, see `health`
+    awaiting_welcome: bool, // we asked to join, so traffic of unknown groups may be ours
+    welcome_policy: WelcomePolicy,
+    expected_leader: Option<String>, // named by our join request, see `WelcomePolicy`
+    async_encryption: bool,
+    ciphersuite: Ciphersuite, // of our key packages, so of groups we start
+    default_group_name: String,
+    encrypting: VecDeque<(PendingSend, String)>, // oldest first, see `outbox`
+    shared_groups: HashMap<Vec<u8>, SharedGroup>, // to join by external commit, see `external`
+    external_commits: HashSet<Vec<u8>>,          // frame ids, published on the rendezvous topic
+    capabilities: Capabilities,
+    member_capabilities: HashMap<Vec<u8>, Capabilities>, // by signature key
+    client_info: Option<ClientInfo>, // sent with our capabilities if we opted in
+    member_clients: HashMap<Vec<u8>, ClientInfo>, // by signature key
+    adverts: Vec<MlsMessageOut>,     // our capabilities, to publish
+    control_frames: VecDeque<Vec<u8>>, // frame ids of adverts, see `qos::Plane`
+    invites: Vec<Invite>,            // from batch commits, to publish
+    psks: HashMap<PskId, GroupPsk>,  // pre-shared keys we hold, see `psk`
+}
+
+/// A decrypted application message.
+#[derive(Debug, PartialEq)]
+pub enum ApplicationPayload {
+    Text(String),
+    Telemetry(Telemetry),
+    /// A file arriving in chunks, see `transfer`.
+    File(Progress),
+    /// Addresses of other members, to dial those we are not connected to.
+    AddressBook(AddressBook),
+    /// A member told us what it understands, see `capabilities`.
+    Capabilities {
+        identity: String,
+        capabilities: Capabilities,
+    },
+    /// A member told us the nickname it goes by, see `names`.
+    Named {
+        identity: String,
+        name: String,
+    },
+    /// The leader set the admin roster: each admin and its rights.
+    Admins(Vec<(String, Rights)>),
+    /// The leader or an admin set which features the group has on.
+    Features(FeatureFlags),
+    /// The member `identity` is a guest for `remaining`, see `guests`.
+    GuestAccess {
+        identity: String,
+        remaining: Duration,
+    },
+    /// The leader went offline, naming who takes over and handing over
+    /// `joins` waiting join requests, see `handover`.
+    LeaderOffline {
+        successor: String,
+        joins: usize,
+    },
+    /// Texts from before we joined, shared by `from`, and what we missed
+    /// while removed if `from` only summarises it, see `backfill`.
+    Backlog {
+        from: String,
+        messages: Vec<SharedMessage>,
+        absence: Option<Absence>,
+    },
+    /// A member proposed a change, to be committed later.
+    Proposal(PendingProposal),
+    /// A commit removed us; the group has been dropped.
+    Removed,
+    /// A member asked to leave. As leader we commit their removal, and the
+    /// commit has to be published.
+    Left {
+        identity: String,
+        commit: Option<MlsMessageOut>,
+    },
+}
+
+impl Display for ApplicationPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplicationPayload::Text(text) => write!(f, "{}", text),
+            ApplicationPayload::Telemetry(telemetry) => write!(f, "{}", telemetry),
+            ApplicationPayload::File(progress) => write!(f, "{}", progress),
+            ApplicationPayload::AddressBook(book) => write!(f, "{}", book),
+            ApplicationPayload::Capabilities { capabilities, .. } => {
+                write!(f, "understands {}", capabilities)
+            }
+            ApplicationPayload::Backlog {
+                messages, absence, ..
+            } => {
+                write!(f, "caught us up on {} earlier messages", messages.len())?;
+                for message in messages {
+                    write!(f, "\n{}", message)?;
+                }
+                match absence {
+                    Some(absence) => write!(f, "\nand {}", absence),
+                    None => Ok(()),
+                }
+            }
+            ApplicationPayload::Proposal(proposal) => write!(f, "proposes: {}", proposal),
+            ApplicationPayload::Removed => write!(f, "removed us from the group"),
+            ApplicationPayload::Left { identity, .. } => write!(f, "{} left the group", identity),
+            ApplicationPayload::Named { name, .. } => write!(f, "goes by {}", name),
+            ApplicationPayload::LeaderOffline {
+                successor,
+                joins: 0,
+            } => {
+                write!(f, "went offline, ask {} for invites", successor)
+            }
+            ApplicationPayload::LeaderOffline { successor, joins } => write!(
+                f,
+                "went offline, ask {} for invites; handed {} join requests to them",
+                successor, joins
+            ),
+            ApplicationPayload::Features(features) => write!(f, "set the features: {}", features),
+            ApplicationPayload::GuestAccess {
+                identity,
+                remaining,
+            } => write!(
+                f,
+                "lets {} stay as a guest for {}",
+                identity,
+                Remaining(*remaining)
+            ),
+            ApplicationPayload::Admins(admins) if admins.is_empty() => {
+                write!(f, "made nobody an admin")
+            }
+            ApplicationPayload::Admins(admins) => {
+                let admins: Vec<String> = admins
+                    .iter()
+                    .map(|(identity, rights)| format!("{} {}", identity, rights))
+                    .collect();
+                write!(f, "made admins: {}", admins.join(", "))
+            }
+        }
+    }
+}
+
+/// What an application message would look like on the wire, without sending it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MessageInspection {
+    pub plaintext_size: usize,
+    pub ciphertext_size: usize,
+    pub padding_block: usize,
+    pub padding_applied: usize,
+    pub epoch: u64,
+    pub generation: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProposalKind {
+    Add,
+    Remove,
+    /// A member removing itself.
+    Leave,
+    /// A member replacing its leaf keys.
+    Update,
+}
+
+/// A proposal waiting for a commit, see [`Node::pending_proposals`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingProposal {
+    pub kind: ProposalKind,
+    /// Who is added, removed, leaving or updating.
+    pub subject: String,
+    pub proposer: String,
+}
+
+impl Display for PendingProposal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ProposalKind::Add => write!(f, "add {}", self.subject)?,
+            ProposalKind::Remove => write!(f, "remove {}", self.subject)?,
+            ProposalKind::Leave => write!(f, "{} leaves", self.subject)?,
+            ProposalKind::Update => write!(f, "{} updates its keys", self.subject)?,
+        }
+        if matches!(self.kind, ProposalKind::Add | ProposalKind::Remove) {
+            write!(f, ", proposed by {}", self.proposer)?;
+        }
+        Ok(())
+    }
+}
+
+// What `proposal` would do, if it is one we know and its sender a member.
+fn describe_proposal(group: &MlsGroup, proposal: &QueuedProposal) -> Option<PendingProposal> {
+    let sender = match proposal.sender() {
+        Sender::Member(sender) => sender,
+        _ => return None,
+    };
+    let proposer = credential_identity(group.member(sender)?.credential());
+    let (kind, subject) = match proposal.proposal() {
+        Proposal::Add(add) => (
+            ProposalKind::Add,
+            credential_identity(add.key_package().credential()),
+        ),
+        Proposal::Remove(remove) if remove.removed() == sender => {
+            (ProposalKind::Leave, proposer.clone())
+        }
+        Proposal::Remove(remove) => (
+            ProposalKind::Remove,
+            credential_identity(group.member(remove.removed())?.credential()),
+        ),
+        Proposal::Update(_) => (ProposalKind::Update, proposer.clone()),
+        _ => return None,
+    };
+    Some(PendingProposal {
+        kind,
+        subject,
+        proposer,
+    })
+}
+
+// Whether a member with `rights` may propose or commit a `kind` change.
+fn allows(rights: Rights, kind: ProposalKind) -> bool {
+    match kind {
+        ProposalKind::Add => rights.contains(Rights::ADD),
+        ProposalKind::Remove => rights.contains(Rights::REMOVE),
+        ProposalKind::Leave | ProposalKind::Update => true,
+    }
+}
+
+/// One leaf of the group's ratchet tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub leaf_index: u32,
+    /// The network identity the credential names, if it names one.
+    pub peer_id: Option<PeerId>,
+    pub identity: Vec<u8>,
+    /// The nickname the member goes by, see `names`.
+    pub name: Option<String>,
+    pub verified: bool,
+    pub is_us: bool,
+    /// What the member advertised, see `capabilities`.
+    pub capabilities: Option<Capabilities>,
+    pub is_leader: bool,
+    /// What the leader lets the member do, see `admins`.
+    pub rights: Rights,
+}
+
+impl Display for Member {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let peer = self
+            .peer_id
+            .map(|peer| peer.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        write!(
+            f,
+            "leaf {} {} identity {}",
+            self.leaf_index,
+            peer,
+            hex_encode(&self.identity)
+        )?;
+        if let Some(name) = &self.name {
+            write!(f, " \"{}\"", name)?;
+        }
+        if let Some(capabilities) = self.capabilities {
+            write!(f, ", {}", capabilities)?;
+        }
+        if self.is_leader {
+            write!(f, ", leader")?;
+        } else if !self.rights.is_empty() {
+            write!(f, ", admin who {}", self.rights)?;
+        }
+        if self.verified {
+            write!(f, " (verified)")?;
+        }
+        if self.is_us {
+            write!(f, " (us)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where we stand in one of our groups, for `node status`, `node groups`
+/// and `node info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub group_id: Vec<u8>,
+    pub name: String,
+    pub epoch: u64,
+    /// Our leaf in the ratchet tree, see [`Member::leaf_index`].
+    pub own_leaf_index: u32,
+    pub members: usize,
+    pub ciphersuite: Ciphersuite,
+    /// Proposals waiting for the next commit, see [`Node::pending_proposals`].
+    pub pending_proposals: usize,
+    pub is_leader: bool,
+    /// Commands without a group name act on this group.
+    pub active: bool,
+}
+
+impl Display for GroupInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} epoch {}, {} members, we are leaf {}",
+            if self.active { "*" } else { " " },
+            self.name,
+            self.epoch,
+            self.members,
+            self.own_leaf_index
+        )?;
+        if self.is_leader {
+            write!(f, " (leader)")?;
+        }
+        Ok(())
+    }
+}
+
+fn member_identities(mls_group: &MlsGroup) -> BTreeSet<String> {
+    mls_group
+        .members()
+        .iter()
+        .map(|key_package| credential_identity(key_package.credential()))
+        .collect()
+}
+
+// The admins of `group` by identity; keys of members who left are skipped.
+fn admin_list(group: &GroupState) -> Vec<(String, Rights)> {
+    let mut admins: Vec<(String, Rights)> = group
+        .mls_group
+        .members()
+        .iter()
+        .map(|key_package| key_package.credential())
+        .map(|credential| {
+            (
+                credential_identity(credential),
+                group.admins.rights(credential.signature_key().as_slice()),
+            )
+        })
+        .filter(|(_, rights)| !rights.is_empty())
+        .collect();
+    admins.sort();
+    admins
+}
+
+// Seconds since the UNIX epoch, as guest entries count them.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Longest group name, in bytes.
+pub const MAX_GROUP_NAME_LEN: usize = 64;
+
+/// How a group we have no name for is shown: its id when that is text, as
+/// for groups started before ids were random, else hex.
+pub fn group_name(group_id: &[u8]) -> String {
+    match std::str::from_utf8(group_id) {
+        Ok(name) if !name.is_empty() && !name.chars().any(char::is_control) => name.to_string(),
+        _ => hex_encode(group_id),
+    }
+}
+
+pub(crate) fn check_group_name(name: &str) -> Result<(), NodeError> {
+    if name.is_empty() || name.len() > MAX_GROUP_NAME_LEN || name.chars().any(char::is_control) {
+        return Err(NodeError::Other(format!(
+            "Group names are 1 to {} bytes without control characters",
+            MAX_GROUP_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+impl Default for Node {
+    fn default() -> Node {
+        let backend = Backend::default();
+        let network_key = Keypair::generate_ed25519();
+        let peer_id = PeerId::from_public_key(&network_key.public());
+        let credential = generate_credential_bundle_from_identity(peer_id.into(), &backend)
+            .expect("error creating credential");
+        let key_package = generate_key_package_bundle(CIPHERSUITE, &credential, &backend)
+            .expect("should have no problem with key package");
+        Node::new(backend, network_key, key_package)
+    }
+}
+
+impl Node {
+    fn new(backend: Backend, network_key: Keypair, key_package: KeyPackage) -> Node {
+        Node {
+            backend,
+            groups: HashMap::new(),
+            journal: Journal::default(),
+            telemetry_decoders: DecoderRegistry::default(),
+            audit_log: AuditLog::default(),
+            messages: None,
+            key_transparency: Box::new(AllowAll),
+            #[cfg(feature = "pki")]
+            pki: None,
+            #[cfg(feature = "pki")]
+            certificate: None,
+            epoch_hooks: EpochHooks::default(),
+            join_policies: JoinPolicies::default(),
+            admission: AdmissionControl::default(),
+            join_approval: JoinApproval::default(),
+            pending_joins: PendingJoins::default(),
+            join_window: Duration::ZERO,
+            join_batches: HashMap::new(),
+            verified_members: HashSet::new(),
+            backup: None,
+            peers: PeerTable::default(),
+            share_addresses: false,
+            rooms: RoomDirectory::default(),
+            pending: PendingMessages::default(),
+            replayed: Vec::new(),
+            author: None,
+            events: Vec::new(),
+            outgoing: Vec::new(),
+            direct: Vec::new(),
+            refusals: Vec::new(),
+            outbox: Outbox::default(),
+            deliveries: Deliveries::default(),
+            read_receipts: false,
+            typing: TypingLimiter::default(),
+            unread: Vec::new(),
+            media: false,
+            media_out: Vec::new(),
+            transfers: Transfers::default(),
+            bulk: Vec::new(),
+            names: DisplayNames::default(),
+            nickname: None,
+            send_policy: SendPolicy::default(),
+            mailbox: false,
+            held: VecDeque::new(),
+            awaiting_welcome: false,
+            welcome_policy: WelcomePolicy::default(),
+            expected_leader: None,
+            async_encryption: false,
+            ciphersuite: CIPHERSUITE,
+            default_group_name: DEFAULT_GROUP_NAME.to_string(),
+            encrypting: VecDeque::new(),
+            shared_groups: HashMap::new(),
+            external_commits: HashSet::new(),
+            capabilities: capabilities::SUPPORTED,
+            member_capabilities: HashMap::new(),
+            client_info: None,
+            member_clients: HashMap::new(),
+            adverts: Vec::new(),
+            control_frames: VecDeque::new(),
+            invites: Vec::new(),
+            psks: HashMap::new(),
+            identity: Identity {
+                network_key,
+                key_package,
+            },
+        }
+    }
+
+    /// The faults injected into this node's crypto backend, see
+    /// `crypto::faults`.
+    #[cfg(feature = "test-utils")]
+    pub fn crypto_faults(&self) -> crate::crypto::faults::Faults {
+        self.backend.faults().clone()
+    }
+
+    /// Starts a node with an identity produced by `provision::provision`.
+    pub fn with_provisioned_identity(identity: ProvisionedIdentity) -> Result<Node, NodeError> {
+        let backend = Backend::default();
+        let network_key = identity.network_keypair()?;
+        store_credential_bundle(&identity.credential_bundle, &backend)?;
+        store_key_package_bundle(&identity.key_package_bundle, &backend)?;
+        let key_package = identity.key_package_bundle.key_package().clone();
+        Ok(Node::new(backend, network_key, key_package))
+    }
+
+    /// Starts with the identity kept sealed at `path` under `passphrase`,
+    /// so our PeerId and credential stay the same across restarts. Without
+    /// a file there, a fresh identity is saved to it. Each start makes a
+    /// fresh key package, so none is used twice.
+    pub fn with_identity(path: &Path, passphrase: &str) -> Result<Node, NodeError> {
+        Node::with_sealed_identity(path, passphrase, DEFAULT_ITERATIONS)
+    }
+
+    fn with_sealed_identity(
+        path: &Path,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<Node, NodeError> {
+        if !path.exists() {
+            let node = Node::default();
+            node.export_identity()?
+                .save_sealed(path, passphrase, iterations, &node.backend)?;
+            return Ok(node);
+        }
+        let identity = ProvisionedIdentity::load_sealed(path, passphrase, &Backend::default())?;
+        let mut node = Node::with_provisioned_identity(identity)?;
+        node.identity.key_package = node.fresh_key_package()?;
+        Ok(node)
+    }
+
+    /// Rebuilds a node from the sections of a backup made by [`Node::run_backup`].
+    pub fn from_backup(sections: &HashMap<String, Vec<u8>>) -> Result<Node, NodeError> {
+        let section = |name: &str| {
+            sections
+                .get(name)
+                .ok_or_else(|| NodeError::Other(format!("Backup has no {} section", name)))
+        };
+        let invalid = |e: NodeError| NodeError::Other(format!("Invalid backup: {}", e));
+        let identity: ProvisionedIdentity =
+            schema::decode(Artifact::Identity, section(IDENTITY_SECTION)?).map_err(invalid)?;
+        let mut node = Node::with_provisioned_identity(identity)?;
+        let mut names: Vec<&String> = sections
+            .keys()
+            .filter(|name| {
+                *name == GROUP_SECTION || name.starts_with(&format!("{}-", GROUP_SECTION))
+            })
+            .collect();
+        names.sort();
+        for name in names {
+            let group: GroupBackup =
+                schema::decode(Artifact::Group, &sections[name]).map_err(invalid)?;
+            let mls_group = MlsGroup::load(group.state.as_slice())?;
+            let mut state = GroupState::new(mls_group, group.is_group_leader, &node.backend);
+            state.adopt_policy(
+                GroupPolicy::decode(&group.policy).unwrap_or_default(),
+                &node.backend,
+            );
+            if !group.topics.is_empty() {
+                state.topics = group.topics;
+            }
+            if !group.history_secret.is_empty() {
+                state.history_secret = group.history_secret;
+            }
+            if !group.name.is_empty() {
+                state.name = group.name;
+            }
+            if !group.admins.is_empty() {
+                state.admins = AdminRoster::decode(&group.admins).map_err(invalid)?;
+            }
+            if !group.features.is_empty() {
+                state.features = FeatureFlags::decode(&group.features).map_err(invalid)?;
+            }
+            state.guests = Guests::from_entries(group.guests);
+            state.given_name = group.given_name;
+            state.bind_metadata(&node.backend);
+            node.add_group(state);
+        }
+        let entries =
+            schema::decode(Artifact::History, section(AUDIT_SECTION)?).map_err(invalid)?;
+        node.audit_log = AuditLog::from_entries(entries, None);
+        // Backups from before the journal start one from the restored groups.
+        if let Some(journal) = sections.get(JOURNAL_SECTION) {
+            let entries = schema::decode(Artifact::Journal, journal).map_err(invalid)?;
+            node.journal = Journal::from_entries(entries);
+        }
+        Ok(node)
+    }
+
+    pub fn set_backup(&mut self, backup: BackupService) {
+        self.backup = Some(backup);
+    }
+
+    /// Uploads whatever changed since the previous backup.
+    pub fn run_backup(&mut self) -> Result<usize, NodeError> {
+        let sections = self.backup_sections()?;
+        let backup = self
+            .backup
+            .as_mut()
+            .ok_or_else(|| NodeError::Other("No backup configured".to_string()))?;
+        backup.run(sections, &self.backend)
+    }
+
+    // Our network key, credential and a key package, to restore from.
+    fn export_identity(&self) -> Result<ProvisionedIdentity, NodeError> {
+        let credential = self.identity.key_package.credential();
+        let credential_bundle = read_credential_bundle(credential, &self.backend)
+            .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        // Joining consumes our key package bundle, so a restored node gets a fresh one.
+        let key_package_bundle =
+            match read_key_package_bundle(&self.identity.key_package, &self.backend) {
+                Some(bundle) => bundle,
+                None => KeyPackageBundle::new(
+                    &[self.ciphersuite],
+                    &credential_bundle,
+                    &self.backend,
+                    vec![],
+                )
+                .map_err(|e| NodeError::Other(format!("Could not create key package: {:?}", e)))?,
+            };
+        Ok(ProvisionedIdentity {
+            network_key: self.identity.network_key.to_protobuf_encoding(),
+            credential_bundle,
+            key_package_bundle,
+        })
+    }
+
+    fn backup_sections(&mut self) -> Result<Vec<(String, Vec<u8>)>, NodeError> {
+        let identity = self.export_identity()?;
+        let mut sections = vec![(
+            IDENTITY_SECTION.to_string(),
+            schema::encode(Artifact::Identity, &identity)?,
+        )];
+        for (group_id, group) in self.groups.iter_mut() {
+            let mut state = Vec::new();
+            group.mls_group.save(&mut state)?;
+            let backup = GroupBackup {
+                state,
+                is_group_leader: group.is_group_leader,
+                policy: group.policy.encode(),
+                topics: group.topics.clone(),
+                history_secret: group.history_secret.clone(),
+                name: group.name.clone(),
+                admins: group.admins.encode(),
+                features: group.features.encode(),
+                guests: group.guests.entries(),
+                given_name: group.given_name.clone(),
+            };
+            sections.push((
+                format!("{}-{}", GROUP_SECTION, hex_encode(group_id)),
+                schema::encode(Artifact::Group, &backup)?,
+            ));
+        }
+        sections.push((
+            AUDIT_SECTION.to_string(),
+            schema::encode(Artifact::History, self.audit_log.entries())?,
+        ));
+        sections.push((
+            JOURNAL_SECTION.to_string(),
+            schema::encode(Artifact::Journal, self.journal.entries())?,
+        ));
+        Ok(sections)
+    }
+
+    pub fn join_new_group(&mut self) {
+        self.join_new_group_with_policy(GroupPolicy::default())
+            .expect("default policy is valid");
+    }
+
+    pub fn join_new_group_with_policy(&mut self, policy: GroupPolicy) -> Result<(), NodeError> {
+        let name = self.default_group_name.clone();
+        self.create_group(&name, policy)
+    }
+
+    /// Starts the group `name`, led by us, under a random id, and makes it
+    /// the active group.
+    pub fn create_group(&mut self, name: &str, policy: GroupPolicy) -> Result<(), NodeError> {
+        policy.validate()?;
+        check_group_name(name)?;
+        if self.groups.values().any(|group| group.name == name) {
+            return Err(NodeError::Other(format!(
+                "Already in a group named {}",
+                name
+            )));
+        }
+        // Creating the group uses up our key package bundle, even when it fails.
+        let bundle = read_key_package_bundle(&self.identity.key_package, &self.backend);
+        let mls_group = generate_group_id(&self.backend).and_then(|group_id| {
+            generate_mls_group(&self.backend, self.identity.key_package.clone(), &group_id)
+        });
+        let mls_group = match (mls_group, bundle) {
+            (Ok(mls_group), _) => mls_group,
+            (Err(e), Some(bundle)) => {
+                store_key_package_bundle(&bundle, &self.backend)?;
+                return Err(e);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        let mut state = GroupState::new(mls_group, true, &self.backend);
+        state.name = name.to_string();
+        state.given_name = Some(name.to_string());
+        state.adopt_policy(policy, &self.backend);
+        let group_id = self.add_group(state);
+        self.journal.append(GroupEvent::Activated { group_id });
+        self.refresh_key_package()
+    }
+
+    // Tracks `state`, which becomes the active group if there is none.
+    fn add_group(&mut self, mut state: GroupState) -> Vec<u8> {
+        state.join_window = self.join_window;
+        if self.media {
+            state.media = MediaKeys::new(&state.mls_group, &self.backend);
+        }
+        let group_id = state.group_id();
+        self.journal.append(GroupEvent::Entered {
+            group_id: group_id.clone(),
+            name: state.name.clone(),
+            leader: state.is_group_leader,
+            epoch: state.mls_group.epoch().as_u64(),
+            members: member_identities(&state.mls_group).into_iter().collect(),
+        });
+        self.groups.insert(group_id.clone(), state);
+        group_id
+    }
+
+    // Where every commit we merge ends up: remembers the epoch for `recovery`,
+    // moves to a fresh topic when membership changed and journals the change.
+    fn merged(&mut self, group_id: &[u8], membership_changed: bool) {
+        let group = self.groups.get_mut(group_id).expect("group expected");
+        group.record_epoch_digest(&self.backend);
+        group.bind_metadata(&self.backend);
+        if membership_changed {
+            group.rotate_topic(&self.backend);
+        }
+        if let Some(media) = group.media.as_mut() {
+            media.rotate(&group.mls_group, &self.backend);
+        }
+        let members = member_identities(&group.mls_group);
+        let known = self
+            .journal
+            .groups()
+            .get(group_id)
+            .map(|view| view.members.clone())
+            .unwrap_or_default();
+        let removed: Vec<String> = known.difference(&members).cloned().collect();
+        for identity in &removed {
+            group.guests.forget(identity);
+            group.recent.departed(identity.clone());
+        }
+        let epoch = group.mls_group.epoch().as_u64();
+        self.epoch_hooks.run(&EpochChange {
+            group_id,
+            group: &group.name,
+            epoch,
+            removed: &removed,
+            mls_group: &group.mls_group,
+            backend: &self.backend,
+        });
+        self.journal.append(GroupEvent::Merged {
+            group_id: group_id.to_vec(),
+            epoch,
+            added: members.difference(&known).cloned().collect(),
+            removed,
+        });
+    }
+
+    // A key package for our credential and ciphersuite, carrying our
+    // certificate chain if we have one.
+    fn fresh_key_package(&self) -> Result<KeyPackage, NodeError> {
+        generate_key_package_bundle_with(
+            self.ciphersuite,
+            self.identity.key_package.credential(),
+            self.certificate_extensions(),
+            &self.backend,
+        )
+    }
+
+    #[cfg(feature = "pki")]
+    fn certificate_extensions(&self) -> Vec<Extension> {
+        self.certificate
+            .iter()
+            .map(CertificateChain::extension)
+            .collect()
+    }
+
+    #[cfg(not(feature = "pki"))]
+    fn certificate_extensions(&self) -> Vec<Extension> {
+        Vec::new()
+    }
+
+    // Creating or joining a group consumes the key package bundle it used, so
+    // the next group gets a fresh key package.
+    fn refresh_key_package(&mut self) -> Result<(), NodeError> {
+        if read_key_package_bundle(&self.identity.key_package, &self.backend).is_some() {
+            return Ok(());
+        }
+        self.identity.key_package = self.fresh_key_package()?;
+        Ok(())
+    }
+
+    // The group commands without a group name act on.
+    fn group(&self) -> Option<&GroupState> {
+        self.journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get(id))
+    }
+
+    // The id of the group the user calls `name`, or whose id that is.
+    fn find_group(&self, name: &str) -> Result<Vec<u8>, NodeError> {
+        self.groups
+            .iter()
+            .find(|(group_id, group)| group.name == name || archive::names_group(group_id, name))
+            .map(|(group_id, _)| group_id.clone())
+            .ok_or_else(|| NodeError::Other(format!("Not a member of group {}", name)))
+    }
+
+    /// Makes `name` the group commands act on.
+    pub fn use_group(&mut self, name: &str) -> Result<(), NodeError> {
+        let group_id = self.find_group(name)?;
+        self.journal.append(GroupEvent::Activated { group_id });
+        Ok(())
+    }
+
+    pub fn active_group_name(&self) -> Option<String> {
+        self.group().map(|group| group.name.clone())
+    }
+
+    /// What we call group `group_id`; groups we are not in go by their id.
+    pub fn group_name(&self, group_id: &[u8]) -> String {
+        match self.groups.get(group_id) {
+            Some(group) => group.name.clone(),
+            None => group_name(group_id),
+        }
+    }
+
+    /// The active group's id, epoch, our leaf, its size and ciphersuite,
+    /// and how many proposals wait for a commit.
+    pub fn group_info(&self) -> Option<GroupInfo> {
+        self.journal
+            .groups()
+            .active
+            .as_deref()
+            .map(|group_id| self.info_of(group_id))
+    }
+
+    /// The groups we are in, by name.
+    pub fn groups(&self) -> Vec<GroupInfo> {
+        let mut groups: Vec<GroupInfo> = self
+            .groups
+            .keys()
+            .map(|group_id| self.info_of(group_id))
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        groups
+    }
+
+    fn info_of(&self, group_id: &[u8]) -> GroupInfo {
+        let group = &self.groups[group_id];
+        let own_key = self.identity.key_package.credential().signature_key();
+        let own_leaf_index = group
+            .mls_group
+            .export_ratchet_tree()
+            .iter()
+            .step_by(2)
+            .position(|leaf| match leaf {
+                Some(OpenMlsNode::LeafNode(leaf)) => ct_eq(
+                    leaf.key_package().credential().signature_key().as_slice(),
+                    own_key.as_slice(),
+                ),
+                _ => false,
+            })
+            .expect("our own leaf is in the tree");
+        GroupInfo {
+            group_id: group_id.to_vec(),
+            name: group.name.clone(),
+            epoch: group.mls_group.epoch().as_u64(),
+            own_leaf_index: own_leaf_index as u32,
+            members: group.mls_group.members().len(),
+            ciphersuite: group.mls_group.ciphersuite(),
+            pending_proposals: group.mls_group.pending_proposals().count(),
+            is_leader: group.is_group_leader,
+            active: self.journal.groups().active.as_deref() == Some(group_id),
+        }
+    }
+
+    pub fn group_count(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_group_leader(&self) -> bool {
+        self.group().is_some_and(|group| group.is_group_leader)
+    }
+
+    pub fn psk_from_passphrase(
+        &self,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<GroupPsk, NodeError> {
+        GroupPsk::from_passphrase(&self.backend, passphrase, iterations)
+    }
+
+    /// Keeps `psk` to read and send in groups that require it.
+    pub fn add_psk(&mut self, psk: GroupPsk) -> PskId {
+        let id = psk.id();
+        self.psks.insert(id, psk);
+        id
+    }
+
+    /// Makes the active group require `psk`: from now on its payloads are
+    /// sealed under it, and members without it can no longer take part.
+    pub fn inject_psk(&mut self, psk: GroupPsk) -> Result<PskId, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let group = self.groups.get_mut(&group_id).expect("active group");
+        if !group.is_group_leader {
+            return Err(NodeError::NotLeader("can add a pre-shared key"));
+        }
+        let id = psk.id();
+        group.adopt_policy(
+            GroupPolicy {
+                psk_id: Some(id),
+                ..group.policy
+            },
+            &self.backend,
+        );
+        Ok(self.add_psk(psk))
+    }
+
+    // This epoch's key for payloads of a group that requires the PSK `id`.
+    fn psk_key(&self, group_id: &[u8], id: &PskId) -> Result<Vec<u8>, NodeError> {
+        let psk = self.psks.get(id).ok_or_else(|| {
+            NodeError::Other(format!(
+                "The group requires pre-shared key {}, which we do not hold",
+                hex_encode(id)
+            ))
+        })?;
+        let group = self.groups.get(group_id).ok_or(NodeError::NoGroup)?;
+        psk_epoch_key(&self.backend, &group.mls_group, psk).ok_or_else(|| {
+            NodeError::Other("Could not derive the pre-shared key's epoch key".to_string())
+        })
+    }
+
+    pub fn add_member_to_group(
+        &mut self,
+        key_package: KeyPackage,
+    ) -> Result<(MlsMessageOut, Invite), NodeError> {
+        self.add_members_to_group(&[key_package])
+    }
+
+    // Adds all members in a single commit, with one Welcome covering all of them.
+    // Every credential must pass the key transparency check first.
+    pub fn add_members_to_group(
+        &mut self,
+        key_packages: &[KeyPackage],
+    ) -> Result<(MlsMessageOut, Invite), NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .expect("group expected");
+        self.add_members_to(&group_id, key_packages)
+    }
+
+    fn add_members_to(
+        &mut self,
+        group_id: &[u8],
+        key_packages: &[KeyPackage],
+    ) -> Result<(MlsMessageOut, Invite), NodeError> {
+        self.check_lifetimes(key_packages)?;
+        for key_package in key_packages {
+            self.key_transparency.check(key_package.credential())?;
+            self.check_certified(key_package)?;
+        }
+        let group = self.groups.get_mut(group_id).expect("group expected");
+        let (m_out, welcome) = group
+            .mls_group
+            .add_members(&self.backend, key_packages)
+            .map_err(|e| NodeError::Other(format!("Could not add members: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        let invite = Invite {
+            welcome,
+            name: Some(group.name.clone()),
+        };
+        self.merged(group_id, true);
+        self.send_roster(group_id);
+        self.send_features(group_id);
+        self.send_backlog(group_id, key_packages);
+        Ok((m_out, invite))
+    }
+
+    // Under a retention policy, joiners get the texts kept for them, and
+    // members added back what the group's disclosure allows; joiners sent
+    // the same share one backlog.
+    fn send_backlog(&mut self, group_id: &[u8], key_packages: &[KeyPackage]) {
+        let now = unix_secs(SystemTime::now());
+        let group = match self.groups.get_mut(group_id) {
+            Some(group) if group.features.enabled(Feature::HistorySync) => group,
+            _ => return,
+        };
+        let mut backlogs: Vec<Backlog> = Vec::new();
+        for key_package in key_packages {
+            let identity = credential_identity(key_package.credential());
+            let (messages, absence) = group.recent.share_with(&identity, now);
+            if messages.is_empty() && absence.is_none() {
+                continue;
+            }
+            match backlogs
+                .iter_mut()
+                .find(|backlog| backlog.messages == messages && backlog.absence == absence)
+            {
+                Some(backlog) => backlog.recipients.push(identity),
+                None => backlogs.push(Backlog {
+                    recipients: vec![identity],
+                    messages,
+                    absence,
+                }),
+            }
+        }
+        for backlog in backlogs {
+            match backlog
+                .encode()
+                .and_then(|bytes| self.create_message_in(group_id, &bytes))
+            {
+                Ok(message) => self.adverts.push(message),
+                Err(e) => log::debug!("Could not send backlog: {}", e),
+            }
+        }
+    }
+
+    /// Keeps the active group's latest texts for members we add, see
+    /// `backfill`; `Retention::default()` stops and forgets them.
+    pub fn set_retention(&mut self, retention: Retention) -> Result<(), NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        if group.policy.max_privacy && !retention.is_off() {
+            return Err(NodeError::Other(
+                "Groups under the maximum privacy policy keep no history".to_string(),
+            ));
+        }
+        group.recent.set_retention(retention);
+        Ok(())
+    }
+
+    pub fn retention(&self) -> Result<Retention, NodeError> {
+        Ok(self.group().ok_or(NodeError::NoGroup)?.recent.retention())
+    }
+
+    /// Sets what we send members of the active group that we add back after
+    /// their removal of the texts kept since, see `backfill`.
+    pub fn set_disclosure(&mut self, disclosure: Disclosure) -> Result<(), NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        group.recent.set_disclosure(disclosure);
+        Ok(())
+    }
+
+    pub fn disclosure(&self) -> Result<Disclosure, NodeError> {
+        Ok(self.group().ok_or(NodeError::NoGroup)?.recent.disclosure())
+    }
+
+    // Newcomers learn features turned off from whoever added them.
+    fn send_features(&mut self, group_id: &[u8]) {
+        let flags = match self.groups.get(group_id) {
+            Some(group) if group.features != FeatureFlags::default() => group.features.encode(),
+            _ => return,
+        };
+        match self.create_message_in(group_id, &flags) {
+            Ok(message) => self.adverts.push(message),
+            Err(e) => log::debug!("Could not send feature flags: {}", e),
+        }
+    }
+
+    /// Turns `feature` on or off for everyone in the active group, see
+    /// `features`; only the leader and admins may. Returns the message
+    /// telling the group.
+    pub fn set_feature(
+        &mut self,
+        feature: Feature,
+        enabled: bool,
+    ) -> Result<MlsMessageOut, NodeError> {
+        let own_key = self
+            .identity
+            .key_package
+            .credential()
+            .signature_key()
+            .as_slice()
+            .to_vec();
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let group = self.groups.get_mut(&group_id).expect("active group");
+        if group.rights_of(&own_key).is_empty() {
+            return Err(NodeError::NotLeader(
+                "and admins can change the group's features",
+            ));
+        }
+        group.features.set(feature, enabled);
+        group.bind_metadata(&self.backend);
+        let flags = group.features.encode();
+        self.create_message_in(&group_id, &flags)
+    }
+
+    /// What the active group has turned on.
+    pub fn features(&self) -> Result<FeatureFlags, NodeError> {
+        Ok(self.group().ok_or(NodeError::NoGroup)?.features)
+    }
+
+    /// Whether `feature` is on in the group `group_id`, for applications
+    /// gating behaviour of their own, such as reactions.
+    pub fn feature_enabled(&self, group_id: &[u8], feature: Feature) -> bool {
+        self.groups
+            .get(group_id)
+            .is_some_and(|group| group.features.enabled(feature))
+    }
+
+    // Newcomers learn the admins from the leader, which queues the roster
+    // after each add.
+    fn send_roster(&mut self, group_id: &[u8]) {
+        let roster = match self.groups.get(group_id) {
+            Some(group) if group.is_group_leader && !group.admins.is_empty() => {
+                group.admins.encode()
+            }
+            _ => return,
+        };
+        match self.create_message_in(group_id, &roster) {
+            Ok(message) => self.adverts.push(message),
+            Err(e) => log::debug!("Could not send admin roster: {}", e),
+        }
+    }
+
+    // Refuses key packages that members might not accept by the time the
+    // commit reaches them, queueing a refusal for their owners.
+    fn check_lifetimes(&mut self, key_packages: &[KeyPackage]) -> Result<(), NodeError> {
+        for key_package in key_packages {
+            let lifetime = Lifetime::of(key_package);
+            if let Err(reason) = self.admission.check_lifetime(lifetime, SystemTime::now()) {
+                self.refusals.push(JoinRefusal {
+                    identity: key_package.credential().identity().to_vec(),
+                    reason,
+                });
+                return Err(NodeError::Other(reason.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `frame` is a join request OpenMLS could not decode because
+    /// its key package is outside its lifetime. If we lead a group, the
+    /// refusal is queued for the joiner and its reason returned.
+    pub fn refuse_unreadable_join(&mut self, frame: &[u8]) -> Option<LifetimeError> {
+        self.join_target()?;
+        let (identity, lifetime) = match protocol::split(frame)? {
+            (WireKind::KeyPackage, body) => lifetime::read_join_request(body)?,
+            _ => return None,
+        };
+        let reason = self
+            .admission
+            .check_lifetime(Some(lifetime), SystemTime::now())
+            .err()?;
+        self.refusals.push(JoinRefusal { identity, reason });
+        Some(reason)
+    }
+
+    /// Refusals of join requests the network event loop should publish.
+    pub fn take_refusals(&mut self) -> Vec<JoinRefusal> {
+        std::mem::take(&mut self.refusals)
+    }
+
+    /// Handles a leader refusing a key package. Returns the reason if the
+    /// package was ours, after replacing it with a fresh one.
+    pub fn handle_join_refusal(
+        &mut self,
+        refusal: &JoinRefusal,
+    ) -> Result<Option<LifetimeError>, NodeError> {
+        if refusal.identity != self.identity.key_package.credential().identity() {
+            return Ok(None);
+        }
+        self.identity.key_package = self.fresh_key_package()?;
+        Ok(Some(refusal.reason))
+    }
+
+    pub fn set_key_transparency(&mut self, key_transparency: Box<dyn KeyTransparency>) {
+        self.key_transparency = key_transparency;
+    }
+
+    /// Requires key packages of new members to carry a certificate chain
+    /// leading to one of `trust`'s roots, see `pki`.
+    #[cfg(feature = "pki")]
+    pub fn set_pki_trust(&mut self, trust: PkiTrust) {
+        self.pki = Some(trust);
+    }
+
+    /// Makes our key packages carry `chain`, which must certify our
+    /// credential's signature key.
+    #[cfg(feature = "pki")]
+    pub fn set_certificate(&mut self, chain: CertificateChain) -> Result<(), NodeError> {
+        let credential = self.identity.key_package.credential();
+        if !chain.certifies(credential.signature_key().as_slice()) {
+            return Err(NodeError::Other(
+                "The certificate is not for our signature key".to_string(),
+            ));
+        }
+        self.certificate = Some(chain);
+        self.identity.key_package = self.fresh_key_package()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "pki")]
+    fn check_certified(&self, key_package: &KeyPackage) -> Result<(), NodeError> {
+        match &self.pki {
+            Some(trust) => trust.check(key_package, SystemTime::now()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "pki"))]
+    fn check_certified(&self, _key_package: &KeyPackage) -> Result<(), NodeError> {
+        Ok(())
+    }
+
+    /// Runs `hook` after every commit merged in any of our groups, see `hooks`.
+    pub fn on_epoch_change(&mut self, hook: impl FnMut(&EpochChange) + Send + 'static) {
+        self.epoch_hooks.add(Box::new(hook));
+    }
+
+    /// Runs `policy` on each join request that passes admission control
+    /// under [`JoinApproval::Manual`], to approve or decline it rather than
+    /// report it as [`NodeEvent::JoinRequested`], see `hooks`.
+    pub fn on_join_request(
+        &mut self,
+        policy: impl FnMut(&JoinCandidate) -> JoinDecision + Send + 'static,
+    ) {
+        self.join_policies.add(Box::new(policy));
+    }
+
+    /// Admits `request` from `peer` through rate limits and any required
+    /// proofs before doing the work of adding the member. A request from
+    /// someone already in the group is a re-admission after a fork: their
+    /// stale leaf is removed first, so the commits must be published in order.
+    pub fn handle_join_request(
+        &mut self,
+        peer: &PeerId,
+        request: JoinRequest,
+    ) -> Result<(Vec<MlsMessageOut>, Invite), NodeError> {
+        let group_id = self.check_join_request(peer, &request)?;
+        self.admit_to(&group_id, request)
+    }
+
+    // Runs admission control on `request` and returns the group it is for.
+    fn check_join_request(
+        &mut self,
+        peer: &PeerId,
+        request: &JoinRequest,
+    ) -> Result<Vec<u8>, NodeError> {
+        let group_id = self
+            .join_target()
+            .ok_or(NodeError::NotLeader("admits members"))?;
+        self.admission
+            .admit(peer, request, &self.backend, Instant::now())?;
+        self.admission.check_freshness(request, SystemTime::now())?;
+        // Before any stale leaf is removed, so a refused package commits nothing.
+        self.check_lifetimes(std::slice::from_ref(&request.key_package))?;
+        Ok(group_id)
+    }
+
+    fn admit_to(
+        &mut self,
+        group_id: &[u8],
+        request: JoinRequest,
+    ) -> Result<(Vec<MlsMessageOut>, Invite), NodeError> {
+        let mut commits = Vec::new();
+        if let Some(removal) =
+            self.remove_stale_leaves(group_id, request.key_package.credential())?
+        {
+            commits.push(removal);
+        }
+        let (commit, invite) = self.add_members_to(group_id, &[request.key_package])?;
+        commits.push(commit);
+        Ok((commits, invite))
+    }
+
+    /// Adds the member whose request `handle_incoming` reported as
+    /// [`NodeEvent::JoinRequested`]. The Welcome and commits wait for
+    /// [`Node::take_outgoing`] and [`Node::take_direct`].
+    pub fn accept_join(&mut self, id: u64) -> Result<(), NodeError> {
+        self.accept_joins(&[id])
+    }
+
+    /// Adds the members of several waiting requests for the same group in
+    /// one commit, with one Welcome for all of them, see [`Node::accept_join`].
+    pub fn accept_joins(&mut self, ids: &[u64]) -> Result<(), NodeError> {
+        let mut group_id = None;
+        for id in ids {
+            let pending = self.pending_joins.get(*id)?;
+            match &group_id {
+                None => group_id = Some(pending.group_id.clone()),
+                Some(group_id) if *group_id != pending.group_id => {
+                    return Err(NodeError::Other(
+                        "Join requests for different groups need a commit each".to_string(),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        let group_id = match group_id {
+            Some(group_id) => group_id,
+            None => return Ok(()),
+        };
+        if !self.groups.contains_key(&group_id) {
+            return Err(NodeError::Other(
+                "No longer in the group these join requests were for".to_string(),
+            ));
+        }
+        let accepted = ids
+            .iter()
+            .map(|id| self.pending_joins.take(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key_packages: Vec<_> = accepted
+            .iter()
+            .map(|pending| pending.request.key_package.clone())
+            .collect();
+        // They may have been waiting long enough to expire.
+        self.check_lifetimes(&key_packages)?;
+        for pending in &accepted {
+            if let Some(removal) =
+                self.remove_stale_leaves(&group_id, pending.request.key_package.credential())?
+            {
+                self.outgoing.push(removal.into());
+            }
+        }
+        let (commit, invite) = self.add_members_to(&group_id, &key_packages)?;
+        let joiners: Vec<_> = accepted.iter().map(|pending| Some(pending.peer)).collect();
+        if let Some(invite) = self.route_welcome(invite, &joiners) {
+            self.outgoing.push(invite.into());
+        }
+        self.outgoing.push(commit.into());
+        if self.share_addresses {
+            match self.create_address_book_message() {
+                Ok(msg_out) => self.outgoing.push(msg_out.into()),
+                Err(e) => log::warn!("Could not share addresses: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the joiners of waiting requests as guests for `access`, see
+    /// [`Node::accept_joins`], and tells the group when they have to go;
+    /// as leader we then remove them on time, see [`Node::expire_guests`].
+    /// Only members who may remove others let guests in.
+    pub fn accept_guests(&mut self, ids: &[u64], access: Duration) -> Result<(), NodeError> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let mut joiners = Vec::new();
+        for id in ids {
+            let pending = self.pending_joins.get(*id)?;
+            match self.groups.get(&pending.group_id) {
+                Some(group) if group.rights_of(own_key.as_slice()).contains(Rights::REMOVE) => {}
+                _ => {
+                    return Err(NodeError::NotLeader(
+                        "and admins it allows to remove members let guests in",
+                    ))
+                }
+            }
+            joiners.push((
+                pending.group_id.clone(),
+                credential_identity(pending.request.key_package.credential()),
+            ));
+        }
+        self.accept_joins(ids)?;
+        let expires = unix_secs(SystemTime::now()) + access.as_secs();
+        for (group_id, identity) in joiners {
+            let entry = GuestEntry { identity, expires };
+            let group = self.groups.get_mut(&group_id).expect("group");
+            group.guests.set(entry.clone());
+            let msg_out = self.create_message_in(&group_id, &entry.encode())?;
+            self.outgoing.push(msg_out.into());
+        }
+        Ok(())
+    }
+
+    // One Welcome serves everyone a commit adds. It is addressed to each
+    // joiner we have a peer id for, see `NetworkService::send_to`; if anyone
+    // is left it is returned, to publish once on the topic.
+    fn route_welcome(&mut self, invite: Invite, joiners: &[Option<PeerId>]) -> Option<Invite> {
+        let mut broadcast = false;
+        for peer in joiners {
+            match peer {
+                Some(peer) => self.direct.push((*peer, invite.clone().into())),
+                None => broadcast = true,
+            }
+        }
+        broadcast.then_some(invite)
+    }
+
+    /// Drops a waiting join request; the joiner hears nothing back.
+    pub fn decline_join(&mut self, id: u64) -> Result<PendingJoin, NodeError> {
+        self.pending_joins.take(id)
+    }
+
+    pub fn pending_joins(&self) -> impl Iterator<Item = &PendingJoin> {
+        self.pending_joins.iter()
+    }
+
+    pub fn set_join_approval(&mut self, approval: JoinApproval) {
+        self.join_approval = approval;
+    }
+
+    /// With automatic approval, join requests arriving within `window` of
+    /// the first are added together in one commit by
+    /// [`Node::flush_join_batches`], rather than one commit each. Applies
+    /// to the groups we are in from now on; zero adds joiners right away.
+    pub fn set_join_window(&mut self, window: Duration) {
+        self.join_window = window;
+    }
+
+    /// Sets the join window of the active group alone.
+    pub fn set_group_join_window(&mut self, window: Duration) -> Result<(), NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        group.join_window = window;
+        Ok(())
+    }
+
+    /// Adds the joiners of every batch whose window closed by `now`, each
+    /// batch in one commit with one Welcome, see [`Node::accept_joins`].
+    pub fn flush_join_batches(&mut self, now: Instant) -> Vec<NodeEvent> {
+        let due: Vec<Vec<u8>> = self
+            .join_batches
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(group_id, _)| group_id.clone())
+            .collect();
+        let mut events = Vec::new();
+        for group_id in due {
+            self.join_batches.remove(&group_id);
+            let batch: Vec<(u64, PeerId)> = self
+                .pending_joins
+                .iter()
+                .filter(|pending| pending.group_id == group_id)
+                .map(|pending| (pending.id, pending.peer))
+                .collect();
+            let ids: Vec<u64> = batch.iter().map(|(id, _)| *id).collect();
+            let group = self.group_name(&group_id);
+            match self.accept_joins(&ids) {
+                Ok(()) => {
+                    events.extend(batch.into_iter().map(|(_, peer)| NodeEvent::MemberJoined {
+                        peer,
+                        group: group.clone(),
+                    }))
+                }
+                Err(error) => events.extend(batch.into_iter().map(|(_, peer)| NodeEvent::Error {
+                    peer,
+                    context: "Could not add batched joiners",
+                    error: NodeError::Other(error.to_string()),
+                })),
+            }
+        }
+        events
+    }
+
+    // Holds `request` for the batch of its group, opening one if need be.
+    fn batch_join(&mut self, peer: &PeerId, request: JoinRequest) -> Result<(), NodeError> {
+        let group_id = self.check_join_request(peer, &request)?;
+        let window = self.groups[&group_id].join_window;
+        self.join_batches
+            .entry(group_id.clone())
+            .or_insert_with(|| Instant::now() + window);
+        self.pending_joins.push(*peer, group_id, request);
+        Ok(())
+    }
+
+    // The group join requests are for: the active group if we may add to
+    // it, otherwise the first group we lead, or else may add to.
+    fn join_target(&self) -> Option<Vec<u8>> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let may_add =
+            |group: &GroupState| group.rights_of(own_key.as_slice()).contains(Rights::ADD);
+        if self.group().is_some_and(may_add) {
+            return self.journal.groups().active.clone();
+        }
+        self.groups
+            .iter()
+            .filter(|(_, group)| may_add(group))
+            .min_by_key(|(group_id, group)| (!group.is_group_leader, *group_id))
+            .map(|(group_id, _)| group_id.clone())
+    }
+
+    // Removes every leaf carrying the signature key of `credential`.
+    fn remove_stale_leaves(
+        &mut self,
+        group_id: &[u8],
+        credential: &Credential,
+    ) -> Result<Option<MlsMessageOut>, NodeError> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let group = self.groups.get_mut(group_id).expect("group expected");
+        let stale = group
+            .mls_group
+            .members()
+            .into_iter()
+            .filter(|key_package| same_signature_key(key_package.credential(), credential))
+            .map(|key_package| key_package.hash_ref(self.backend.crypto()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| NodeError::Other(format!("Could not reference member: {:?}", e)))?;
+        if stale.is_empty() {
+            return Ok(None);
+        }
+        if !group.rights_of(own_key.as_slice()).contains(Rights::REMOVE) {
+            return Err(NodeError::NotLeader(
+                "and admins it allows to remove members re-admit members",
+            ));
+        }
+        let (m_out, _) = group
+            .mls_group
+            .remove_members(&self.backend, &stale)
+            .map_err(|e| NodeError::Other(format!("Could not remove stale member: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        self.merged(group_id, true);
+        Ok(Some(m_out))
+    }
+
+    /// Removes the member with `identity` and rotates the group's keys, so
+    /// they cannot read anything sent after the commit.
+    pub fn remove_member(&mut self, identity: &str) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        self.remove_member_from(&group_id, identity)
+    }
+
+    fn remove_member_from(
+        &mut self,
+        group_id: &[u8],
+        identity: &str,
+    ) -> Result<MlsMessageOut, NodeError> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let group = match self.groups.get_mut(group_id) {
+            Some(group) if group.rights_of(own_key.as_slice()).contains(Rights::REMOVE) => group,
+            _ => return Err(NodeError::NotLeader("and admins it allows remove members")),
+        };
+        if credential_has_identity(self.identity.key_package.credential(), identity) {
+            return Err(NodeError::Other(
+                "Cannot remove ourselves from the group".to_string(),
+            ));
+        }
+        let removed = group
+            .mls_group
+            .members()
+            .into_iter()
+            .filter(|key_package| credential_has_identity(key_package.credential(), identity))
+            .map(|key_package| key_package.hash_ref(self.backend.crypto()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| NodeError::Other(format!("Could not reference member: {:?}", e)))?;
+        if removed.is_empty() {
+            return Err(NodeError::Other(format!(
+                "{} is not a member of the group",
+                identity
+            )));
+        }
+        let (m_out, _) = group
+            .mls_group
+            .remove_members(&self.backend, &removed)
+            .map_err(|e| NodeError::Other(format!("Could not remove member: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        let group_id = group.group_id();
+        self.merged(&group_id, true);
+        Ok(m_out)
+    }
+
+    /// The guests of the active group and when each has to go.
+    pub fn guests(&self) -> Result<Vec<GuestEntry>, NodeError> {
+        Ok(self.group().ok_or(NodeError::NoGroup)?.guests.entries())
+    }
+
+    /// Lets the guest `identity` of the active group stay `by` longer, and
+    /// returns the new end to send the group, see `guests`.
+    pub fn extend_guest(
+        &mut self,
+        identity: &str,
+        by: Duration,
+    ) -> Result<(GuestEntry, MlsMessageOut), NodeError> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let group = self.groups.get_mut(&group_id).ok_or(NodeError::NoGroup)?;
+        if !group.rights_of(own_key.as_slice()).contains(Rights::REMOVE) {
+            return Err(NodeError::NotLeader(
+                "and admins it allows to remove members extend guests",
+            ));
+        }
+        let entry = group
+            .guests
+            .extend(identity, by, unix_secs(SystemTime::now()))
+            .ok_or_else(|| NodeError::Other(format!("{} is not a guest", identity)))?;
+        let msg_out = self.create_message_in(&group_id, &entry.encode())?;
+        Ok((entry, msg_out))
+    }
+
+    /// In the groups we lead, warns guests whose access ends soon and
+    /// removes those whose access ended by `now`, see `guests`. The frames
+    /// wait for [`Node::take_outgoing`]; returns how many there are.
+    pub fn expire_guests(&mut self, now: SystemTime) -> usize {
+        let now = unix_secs(now);
+        let mut led: Vec<Vec<u8>> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.is_group_leader)
+            .map(|(group_id, _)| group_id.clone())
+            .collect();
+        led.sort();
+        let mut queued = 0;
+        for group_id in led {
+            let group = self.groups.get_mut(&group_id).expect("group");
+            let (warnings, expired) = (group.guests.to_warn(now), group.guests.expired(now));
+            for entry in warnings {
+                match self.create_message_in(&group_id, &entry.encode()) {
+                    Ok(msg_out) => {
+                        self.outgoing.push(msg_out.into());
+                        queued += 1;
+                    }
+                    Err(e) => log::debug!("Could not warn guest {}: {}", entry.identity, e),
+                }
+            }
+            for identity in expired {
+                match self.remove_member_from(&group_id, &identity) {
+                    Ok(commit) => {
+                        self.outgoing.push(commit.into());
+                        queued += 1;
+                    }
+                    // Gone already, or for the leader to remove by hand.
+                    Err(e) => {
+                        log::warn!("Could not remove guest {}: {}", identity, e);
+                        let group = self.groups.get_mut(&group_id).expect("group");
+                        group.guests.forget(&identity);
+                    }
+                }
+            }
+        }
+        queued
+    }
+
+    /// Grants the member with `identity` `rights` in the active group, or
+    /// revokes what it had with no rights, and returns the roster to send
+    /// the group. Only the leader grants rights, see `admins`.
+    pub fn set_rights(
+        &mut self,
+        identity: &str,
+        rights: Rights,
+    ) -> Result<MlsMessageOut, NodeError> {
+        let group_id = match self.group() {
+            Some(group) if group.is_group_leader => group.group_id(),
+            _ => return Err(NodeError::NotLeader("grants rights")),
+        };
+        if credential_has_identity(self.identity.key_package.credential(), identity) {
+            return Err(NodeError::Other(
+                "The leader holds every right already".to_string(),
+            ));
+        }
+        let group = self.groups.get_mut(&group_id).expect("group");
+        let key = group
+            .mls_group
+            .members()
+            .iter()
+            .find(|key_package| credential_has_identity(key_package.credential(), identity))
+            .map(|key_package| key_package.credential().signature_key().as_slice().to_vec())
+            .ok_or_else(|| {
+                NodeError::Other(format!("{} is not a member of the group", identity))
+            })?;
+        group.admins.set(&key, rights);
+        let roster = group.admins.encode();
+        let admins = admin_list(group);
+        self.journal.append(GroupEvent::AdminsSet {
+            group_id: group_id.clone(),
+            admins,
+        });
+        self.create_message_in(&group_id, &roster)
+    }
+
+    /// Hands over the groups we lead before we exit: each gets a successor
+    /// with every right and a signed notice naming it, carrying the join
+    /// requests waiting there, see `handover`. The successor is the first
+    /// admin who may add, or else the first member. Returns the messages to
+    /// send, roster before notice.
+    pub fn go_offline(&mut self) -> Result<Vec<MlsMessageOut>, NodeError> {
+        let own_key = self
+            .identity
+            .key_package
+            .credential()
+            .signature_key()
+            .as_slice()
+            .to_vec();
+        let mut led: Vec<Vec<u8>> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.is_group_leader)
+            .map(|(group_id, _)| group_id.clone())
+            .collect();
+        led.sort();
+        let mut messages = Vec::new();
+        for group_id in led {
+            let group = self.groups.get_mut(&group_id).expect("group");
+            let successor = group
+                .mls_group
+                .members()
+                .into_iter()
+                .map(|key_package| key_package.credential().clone())
+                .filter(|credential| !ct_eq(credential.signature_key().as_slice(), &own_key))
+                .min_by_key(|credential| {
+                    !group
+                        .admins
+                        .rights(credential.signature_key().as_slice())
+                        .contains(Rights::ADD)
+                });
+            let successor = match successor {
+                Some(successor) => successor,
+                None => continue,
+            };
+            group
+                .admins
+                .set(successor.signature_key().as_slice(), Rights::ALL);
+            let roster = group.admins.encode();
+            let admins = admin_list(group);
+            self.journal.append(GroupEvent::AdminsSet {
+                group_id: group_id.clone(),
+                admins,
+            });
+            messages.push(self.create_message_in(&group_id, &roster)?);
+
+            let joins = self
+                .pending_joins
+                .iter()
+                .filter(|pending| pending.group_id == group_id)
+                .map(|pending| {
+                    Ok(HandedJoin {
+                        peer: pending.peer.to_string(),
+                        request: pending.request.encode()?,
+                    })
+                })
+                .collect::<Result<Vec<_>, NodeError>>()?;
+            let notice = LeaderOffline {
+                group_id: group_id.clone(),
+                successor: credential_identity(&successor),
+                joins,
+            };
+            let credential_bundle =
+                read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                    .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+            let signed = SignedLeaderOffline::sign(&notice, credential_bundle, &self.backend)?;
+            messages.push(self.create_message_in(&group_id, &signed.encode()?)?);
+        }
+        Ok(messages)
+    }
+
+    // Takes over the join requests the leader handed us, to be accepted
+    // like those arriving here; automatic approval accepts them right away.
+    fn leader_offline(
+        &mut self,
+        group_id: &[u8],
+        notice: LeaderOffline,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        self.groups.get_mut(group_id).expect("group").successor = Some(notice.successor.clone());
+        if credential_has_identity(self.identity.key_package.credential(), &notice.successor) {
+            for join in &notice.joins {
+                match (join.peer.parse(), JoinRequest::decode(&join.request)) {
+                    (Ok(peer), Ok(request)) => {
+                        self.pending_joins.push(peer, group_id.to_vec(), request);
+                    }
+                    _ => log::debug!("Skipped a malformed handed-over join request"),
+                }
+            }
+            if !notice.joins.is_empty() && self.join_approval == JoinApproval::Auto {
+                self.join_batches.insert(group_id.to_vec(), Instant::now());
+            }
+        }
+        Ok(Some(ApplicationPayload::LeaderOffline {
+            successor: notice.successor,
+            joins: notice.joins.len(),
+        }))
+    }
+
+    /// Asks the group to remove us and drops the group here. The leader
+    /// commits the removal, so it cannot leave a group itself; it can archive
+    /// the group instead.
+    pub fn leave_group(&mut self) -> Result<MlsMessageOut, NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        if group.is_group_leader {
+            return Err(NodeError::Other(
+                "The group leader cannot leave, archive the group instead".to_string(),
+            ));
+        }
+        let proposal = group
+            .mls_group
+            .leave_group(&self.backend)
+            .map_err(|e| NodeError::Other(format!("Could not leave group: {:?}", e)))?;
+        let group_id = group.group_id();
+        self.drop_group(&group_id);
+        Ok(proposal)
+    }
+
+    /// Replaces our leaf keys with fresh ones in an Update commit, so keys
+    /// taken from this device stop opening later epochs. Other members
+    /// merge the commit like any other.
+    pub fn self_update(&mut self) -> Result<MlsMessageOut, NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        let (commit, _) = group
+            .mls_group
+            .self_update(&self.backend, None)
+            .map_err(|e| NodeError::Other(format!("Could not update our keys: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        let group_id = group.group_id();
+        self.merged(&group_id, false);
+        Ok(commit)
+    }
+
+    // Proposals are stored for the commit that will cover them if their
+    // sender may make them: anyone may leave or update its own leaf, adds and
+    // removals take the right to. The leader commits leaves right away,
+    // unless other proposals wait for `commit_pending_proposals`.
+    fn handle_proposal(
+        &mut self,
+        group_id: &[u8],
+        proposal: QueuedProposal,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        let group = self.groups.get_mut(group_id).expect("group");
+        let pending = describe_proposal(&group.mls_group, &proposal)
+            .ok_or_else(|| NodeError::Other("Unsupported proposal".to_string()))?;
+        let proposer_rights = match proposal.sender() {
+            Sender::Member(sender) => group
+                .mls_group
+                .member(sender)
+                .map(|kp| group.rights_of(kp.credential().signature_key().as_slice()))
+                .unwrap_or_default(),
+            _ => Rights::default(),
+        };
+        if !allows(proposer_rights, pending.kind) {
+            return Err(NodeError::Unauthorized(format!(
+                "Refused a proposal by {}, who may not make it",
+                pending.proposer
+            )));
+        }
+        group.mls_group.store_pending_proposal(proposal);
+        if pending.kind != ProposalKind::Leave {
+            return Ok(Some(ApplicationPayload::Proposal(pending)));
+        }
+        let identity = pending.subject;
+        if !group.is_group_leader || group.mls_group.pending_proposals().count() > 1 {
+            return Ok(Some(ApplicationPayload::Left {
+                identity,
+                commit: None,
+            }));
+        }
+        let (commit, _) = group
+            .mls_group
+            .commit_to_pending_proposals(&self.backend)
+            .map_err(|e| NodeError::Other(format!("Could not commit leave: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        self.merged(group_id, true);
+        Ok(Some(ApplicationPayload::Left {
+            identity,
+            commit: Some(commit),
+        }))
+    }
+
+    // The id of the active group, if we may make `kind` changes to it.
+    fn group_allowing(&self, kind: ProposalKind) -> Result<Vec<u8>, NodeError> {
+        let own_key = self.identity.key_package.credential().signature_key();
+        let group = self.group().ok_or(NodeError::NoGroup)?;
+        if !allows(group.rights_of(own_key.as_slice()), kind) {
+            return Err(NodeError::NotLeader(
+                "and admins it allows change the membership",
+            ));
+        }
+        Ok(group.group_id())
+    }
+
+    /// Proposes adding the owner of `key_package` to the active group. Like
+    /// the other proposals it changes nothing until someone commits it, see
+    /// [`Node::commit_pending_proposals`], so several changes can go out in
+    /// one commit.
+    pub fn propose_add(&mut self, key_package: KeyPackage) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self.group_allowing(ProposalKind::Add)?;
+        self.check_lifetimes(std::slice::from_ref(&key_package))?;
+        self.key_transparency.check(key_package.credential())?;
+        self.check_certified(&key_package)?;
+        self.groups
+            .get_mut(&group_id)
+            .expect("group")
+            .mls_group
+            .propose_add_member(&self.backend, &key_package)
+            .map_err(|e| NodeError::Other(format!("Could not propose add: {:?}", e)))
+    }
+
+    /// Proposes removing the member with `identity` from the active group.
+    pub fn propose_remove(&mut self, identity: &str) -> Result<MlsMessageOut, NodeError> {
+        if credential_has_identity(self.identity.key_package.credential(), identity) {
+            return Err(NodeError::Other(
+                "Cannot remove ourselves from the group".to_string(),
+            ));
+        }
+        let group_id = self.group_allowing(ProposalKind::Remove)?;
+        let group = self.groups.get_mut(&group_id).expect("group");
+        let removed = group
+            .mls_group
+            .members()
+            .into_iter()
+            .find(|key_package| credential_has_identity(key_package.credential(), identity))
+            .map(|key_package| key_package.hash_ref(self.backend.crypto()))
+            .transpose()
+            .map_err(|e| NodeError::Other(format!("Could not reference member: {:?}", e)))?
+            .ok_or_else(|| {
+                NodeError::Other(format!("{} is not a member of the group", identity))
+            })?;
+        group
+            .mls_group
+            .propose_remove_member(&self.backend, &removed)
+            .map_err(|e| NodeError::Other(format!("Could not propose removal: {:?}", e)))
+    }
+
+    /// Proposes replacing our leaf keys in the active group.
+    pub fn propose_update(&mut self) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self.group_allowing(ProposalKind::Update)?;
+        self.groups
+            .get_mut(&group_id)
+            .expect("group")
+            .mls_group
+            .propose_self_update(&self.backend, None)
+            .map_err(|e| NodeError::Other(format!("Could not propose update: {:?}", e)))
+    }
+
+    /// The proposals the active group holds for its next commit, ours and
+    /// those members sent, oldest first.
+    pub fn pending_proposals(&self) -> Result<Vec<PendingProposal>, NodeError> {
+        let group = self.group().ok_or(NodeError::NoGroup)?;
+        Ok(group
+            .mls_group
+            .pending_proposals()
+            .filter_map(|proposal| describe_proposal(&group.mls_group, proposal))
+            .collect())
+    }
+
+    /// Commits every pending proposal of the active group at once and
+    /// returns the commit. The one Welcome for everyone it adds is queued
+    /// for each of them, see [`Node::take_direct`] and [`Node::take_invites`].
+    pub fn commit_pending_proposals(&mut self) -> Result<MlsMessageOut, NodeError> {
+        let proposals = self.pending_proposals()?;
+        if proposals.is_empty() {
+            return Err(NodeError::Other("No proposals to commit".to_string()));
+        }
+        for proposal in &proposals {
+            self.group_allowing(proposal.kind)?;
+        }
+        let group_id = self.group_allowing(ProposalKind::Update)?;
+        let group = self.groups.get_mut(&group_id).expect("group");
+        let added: Vec<KeyPackage> = group
+            .mls_group
+            .pending_proposals()
+            .filter_map(|proposal| match proposal.proposal() {
+                Proposal::Add(add) => Some(add.key_package().clone()),
+                _ => None,
+            })
+            .collect();
+        let (commit, welcome) = group
+            .mls_group
+            .commit_to_pending_proposals(&self.backend)
+            .map_err(|e| NodeError::Other(format!("Could not commit proposals: {:?}", e)))?;
+        group
+            .mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        let name = group.name.clone();
+        let membership_changed = proposals
+            .iter()
+            .any(|proposal| proposal.kind != ProposalKind::Update);
+        self.merged(&group_id, membership_changed);
+        if let Some(welcome) = welcome {
+            let invite = Invite {
+                welcome,
+                name: Some(name),
+            };
+            // Members added by key package file are reached by their credential's peer id.
+            let joiners: Vec<_> = added
+                .iter()
+                .map(|key_package| PeerId::from_bytes(key_package.credential().identity()).ok())
+                .collect();
+            if let Some(invite) = self.route_welcome(invite, &joiners) {
+                self.invites.push(invite);
+            }
+            self.send_roster(&group_id);
+        }
+        Ok(commit)
+    }
+
+    /// Welcomes from batch commits waiting to be published.
+    pub fn take_invites(&mut self) -> Vec<Invite> {
+        std::mem::take(&mut self.invites)
+    }
+
+    /// Our key package, wrapped with whatever proofs the admission config
+    /// asks for and signed as fresh. Until a Welcome arrives, traffic of
+    /// groups we are not in is held back in case it is for the group we join.
+    pub fn create_join_request(&mut self) -> Result<JoinRequest, NodeError> {
+        let request = JoinRequest::new(
+            self.identity.key_package.clone(),
+            self.admission.config(),
+            &self.backend,
+        )?;
+        self.sign_join_request(request)
+    }
+
+    fn sign_join_request(&mut self, request: JoinRequest) -> Result<JoinRequest, NodeError> {
+        if self.welcome_policy == WelcomePolicy::Leader && request.leader.is_none() {
+            return Err(NodeError::Other(
+                "With --welcome-from=leader we only join a leader we name, pick a room with `node join <n>`"
+                    .to_string(),
+            ));
+        }
+        self.awaiting_welcome = true;
+        self.expected_leader = request.leader.clone();
+        request.sign(&self.identity.network_key, SystemTime::now(), &self.backend)
+    }
+
+    /// Whether we should handle `request`: we lead a group and the request
+    /// is not meant for another room. Members of that group who ask without
+    /// naming us are joining some other group; re-admissions name the leader.
+    /// Admins only handle requests naming them, leaving the rest to the leader
+    /// or, while it is offline, its successor, see `handover`.
+    pub fn is_join_target(&self, request: &JoinRequest) -> bool {
+        let group = match self.join_target().and_then(|id| self.groups.get(&id)) {
+            Some(group) => group,
+            None => return false,
+        };
+        let is_member = group.mls_group.members().iter().any(|member| {
+            same_signature_key(member.credential(), request.key_package.credential())
+        });
+        let own_identity = credential_identity(self.identity.key_package.credential());
+        let leads = group.is_group_leader || group.successor.as_ref() == Some(&own_identity);
+        request.is_for(&own_identity) && (request.leader.is_some() || (!is_member && leads))
+    }
+
+    /// A join request for room `#index` of `node rooms`, carrying the proof of
+    /// work its leader asks for.
+    pub fn create_room_join_request(&mut self, index: usize) -> Result<JoinRequest, NodeError> {
+        self.rooms.expire(Instant::now());
+        let room = self
+            .rooms
+            .get(index)
+            .ok_or_else(|| NodeError::Other(format!("No room #{}, see `node rooms`", index)))?;
+        let mut config = self.admission.config().clone();
+        if room.announcement.policy.psk && config.psk.is_none() {
+            return Err(NodeError::Other(
+                "This room is invite only, start with --join-psk".to_string(),
+            ));
+        }
+        config.proof_of_work = config
+            .proof_of_work
+            .max(room.announcement.policy.proof_of_work);
+        let leader = room.announcement.leader.clone();
+        let request = JoinRequest::new(self.identity.key_package.clone(), &config, &self.backend)?
+            .for_leader(&leader);
+        self.sign_join_request(request)
+    }
+
+    /// Announces the group we lead for `node rooms` on other nodes.
+    pub fn create_room_announcement(&self) -> Result<SignedAnnouncement, NodeError> {
+        let group = self
+            .join_target()
+            .and_then(|group_id| self.groups.get(&group_id))
+            .ok_or(NodeError::NotLeader("announces a room"))?;
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        SignedAnnouncement::sign(
+            &group.group_id(),
+            &group.name,
+            group.mls_group.members().len(),
+            JoinPolicy::of(self.admission.config()),
+            credential_bundle,
+            &self.backend,
+            SystemTime::now(),
+        )
+    }
+
+    /// Lists a verified announcement heard from `peer`.
+    pub fn record_room(
+        &mut self,
+        peer: &PeerId,
+        announcement: &SignedAnnouncement,
+    ) -> Result<(), NodeError> {
+        let announcement = announcement.verify(&self.backend, SystemTime::now())?;
+        if announcement.leader != credential_identity(self.identity.key_package.credential()) {
+            let source = self.peers.source(peer);
+            self.rooms.record(announcement, source, Instant::now());
+        }
+        Ok(())
+    }
+
+    pub fn rooms(&mut self) -> &RoomDirectory {
+        self.rooms.expire(Instant::now());
+        &self.rooms
+    }
+
+    pub fn set_admission_config(&mut self, config: AdmissionConfig) {
+        self.admission = AdmissionControl::new(config);
+    }
+
+    /// As [`Node::set_admission_config`], but keeps the rate limit state
+    /// and counts, for a config reload.
+    pub fn reconfigure_admission(&mut self, config: AdmissionConfig) {
+        self.admission.reconfigure(config);
+    }
+
+    pub fn set_welcome_policy(&mut self, policy: WelcomePolicy) {
+        self.welcome_policy = policy;
+    }
+
+    pub fn admission_metrics(&self) -> &AdmissionMetrics {
+        self.admission.metrics()
+    }
+
+    /// Joins the group `invite` is for and returns its name. A Welcome for a
+    /// group we are already in replaces our state, as after a re-admission.
+    pub fn join_existing_group(&mut self, invite: Invite) -> Result<String, NodeError> {
+        if !self.is_welcome_for_us(&invite)? {
+            return Err(NodeError::Other(
+                "Welcome is not for our key package".to_string(),
+            ));
+        }
+        // Processing the Welcome uses up our key package bundle.
+        let bundle = read_key_package_bundle(&self.identity.key_package, &self.backend);
+        let mls_group = generate_mls_group_from_welcome(&self.backend, invite.welcome)?;
+        let mut state = GroupState::new(mls_group, false, &self.backend);
+        let checked = self
+            .check_welcome(&state)
+            .and_then(|_| self.check_members_certified(&state));
+        if let Err(e) = checked {
+            // Keep it for the Welcome we are waiting for.
+            if let Some(bundle) = bundle {
+                store_key_package_bundle(&bundle, &self.backend)?;
+            }
+            return Err(e);
+        }
+        state.given_name = invite.name.clone();
+        state.name = self.unique_name(&state.group_id(), invite.name);
+        let group_id = self.add_group(state);
+        self.awaiting_welcome = false;
+        self.expected_leader = None;
+        self.refresh_key_package()?;
+        self.replay_pending(&group_id);
+        // Traffic of the groups we did not join can never be ours now.
+        let groups = &self.groups;
+        self.pending.retain_groups(|id| groups.contains_key(id));
+        Ok(self.group_name(&group_id))
+    }
+
+    /// Whether `invite` carries group secrets for our current key package.
+    pub fn is_welcome_for_us(&self, invite: &Invite) -> Result<bool, NodeError> {
+        let ours = self
+            .identity
+            .key_package
+            .hash_ref(self.backend.crypto())
+            .map_err(|e| {
+                NodeError::Other(format!("Could not reference our key package: {:?}", e))
+            })?;
+        Ok(invite
+            .welcome
+            .secrets()
+            .iter()
+            .any(|secrets| secrets.new_member() == ours))
+    }
+
+    // With PKI roots set, every member of a group we are welcomed into must
+    // be certified, see `pki`.
+    fn check_members_certified(&self, group: &GroupState) -> Result<(), NodeError> {
+        for key_package in group.mls_group.members() {
+            self.check_certified(key_package)?;
+        }
+        Ok(())
+    }
+
+    // Under `WelcomePolicy::Leader`, the group must be led by the member our
+    // join request named.
+    fn check_welcome(&self, group: &GroupState) -> Result<(), NodeError> {
+        if self.welcome_policy == WelcomePolicy::Any {
+            return Ok(());
+        }
+        let expected = match (&self.expected_leader, self.awaiting_welcome) {
+            (Some(leader), true) => leader,
+            _ => return Err(NodeError::Other("Not waiting for a Welcome".to_string())),
+        };
+        let leader = group
+            .leader_credential()
+            .map(|credential| credential_identity(&credential));
+        if leader.as_ref() != Some(expected) {
+            return Err(NodeError::Other(format!(
+                "Welcome is for a group led by {}, not {} whom we asked",
+                leader.unwrap_or_else(|| "nobody".to_string()),
+                expected
+            )));
+        }
+        Ok(())
+    }
+
+    // The name a group we join goes by: the one it was given, told apart by
+    // the start of its id when another of our groups has it already.
+    fn unique_name(&self, group_id: &[u8], name: Option<String>) -> String {
+        let name = match name {
+            Some(name) => name,
+            None => return group_name(group_id),
+        };
+        let taken = self
+            .groups
+            .iter()
+            .any(|(id, group)| id.as_slice() != group_id && group.name == name);
+        if taken {
+            format!(
+                "{} ({})",
+                name,
+                hex_encode(&group_id[..group_id.len().min(4)])
+            )
+        } else {
+            name
+        }
+    }
+
+    // Takes the name and features the leader or an admin committed to.
+    fn adopt_metadata(&mut self, group_id: &[u8], metadata: GroupMetadata) {
+        if let Some(given) = metadata.name {
+            let name = self.unique_name(group_id, Some(given.clone()));
+            let group = self.groups.get_mut(group_id).expect("group");
+            group.given_name = Some(given);
+            if group.name != name {
+                group.name = name.clone();
+                self.journal.append(GroupEvent::Renamed {
+                    group_id: group_id.to_vec(),
+                    name,
+                });
+            }
+        }
+        let group = self.groups.get_mut(group_id).expect("group");
+        group.features = metadata.features;
+        group.bind_metadata(&self.backend);
+    }
+
+    /// The active group's public state and tree, for others to join it by
+    /// external commit, see `external`.
+    pub fn share_group(&self) -> Result<SharedGroup, NodeError> {
+        let group = self.group().ok_or(NodeError::NoGroup)?;
+        let public_group_state = codec::to_tls(
+            &group
+                .mls_group
+                .export_public_group_state(&self.backend)
+                .map_err(|e| NodeError::Other(format!("Could not export group state: {:?}", e)))?,
+            "public group state",
+        )?;
+        Ok(SharedGroup {
+            group_id: group.group_id(),
+            public_group_state: VerifiablePublicGroupState::tls_deserialize(
+                &mut public_group_state.as_slice(),
+            )
+            .map_err(|e| NodeError::Other(format!("Could not export group state: {:?}", e)))?,
+            tree: group.mls_group.export_ratchet_tree(),
+            name: Some(group.name.clone()),
+        })
+    }
+
+    /// Remembers a group someone shared, replacing what it shared before.
+    /// Returns whether it is new to us; groups we are in are ignored.
+    pub fn record_shared_group(&mut self, shared: SharedGroup) -> bool {
+        if self.groups.contains_key(&shared.group_id)
+            || (self.shared_groups.len() >= MAX_SHARED_GROUPS
+                && !self.shared_groups.contains_key(&shared.group_id))
+        {
+            return false;
+        }
+        self.shared_groups
+            .insert(shared.group_id.clone(), shared)
+            .is_none()
+    }
+
+    /// Joins a shared group by external commit, the one named `name` or the
+    /// only one shared with us. Returns the group's name and the commit,
+    /// which members need to add us.
+    pub fn join_shared_group(
+        &mut self,
+        name: Option<&str>,
+    ) -> Result<(String, MlsMessageOut), NodeError> {
+        let group_id = match name {
+            Some(name) => self
+                .shared_groups
+                .iter()
+                .find(|(group_id, shared)| {
+                    shared.name.as_deref() == Some(name) || archive::names_group(group_id, name)
+                })
+                .map(|(group_id, _)| group_id.clone())
+                .ok_or_else(|| NodeError::Other(format!("Nobody shared group {}", name)))?,
+            None => match self.shared_groups.values().collect::<Vec<_>>().as_slice() {
+                [shared] => shared.group_id.clone(),
+                [] => {
+                    return Err(NodeError::Other(
+                        "Nobody shared a group with us".to_string(),
+                    ))
+                }
+                shared => {
+                    let names: Vec<String> = shared.iter().map(|shared| shared.name()).collect();
+                    return Err(NodeError::Other(format!(
+                        "Several groups were shared, name one of {}",
+                        names.join(", ")
+                    )));
+                }
+            },
+        };
+        let shared = self.shared_groups.remove(&group_id).expect("shared group");
+        let name = shared.name();
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        let (mut mls_group, commit) = generate_mls_group_from_external_commit(
+            &self.backend,
+            &shared.tree,
+            shared.public_group_state,
+            &credential_bundle,
+        )
+        .map_err(|e| NodeError::Other(format!("Could not join {}: {}", name, e)))?;
+        mls_group
+            .merge_pending_commit()
+            .map_err(|e| NodeError::Other(format!("Could not merge our commit: {:?}", e)))?;
+        let mut state = GroupState::new(mls_group, false, &self.backend);
+        state.given_name = shared.name.clone();
+        state.name = self.unique_name(&group_id, shared.name);
+        let group_id = self.add_group(state);
+        let id = self.frame_id(&WireMessage::from(commit.clone()).encode()?);
+        self.external_commits.insert(id);
+        self.replay_pending(&group_id);
+        self.queue_advert(&group_id);
+        Ok((self.group_name(&group_id), commit))
+    }
+
+    /// The active group's pubsub topics, current last. Empty outside a group.
+    pub fn group_topics(&self) -> &[String] {
+        self.group()
+            .map(|group| group.topics.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// The topics of every group we are in, control topics included, to
+    /// subscribe to.
+    pub fn all_group_topics(&self) -> Vec<String> {
+        let mut topics: Vec<String> = self
+            .groups
+            .values()
+            .flat_map(|group| group.topics.iter())
+            .flat_map(|topic| [topic.clone(), control_topic(topic)])
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    /// The topics of the group an outgoing `frame` is for, or of the active
+    /// group for frames that name no group: its control topics for frames of
+    /// the control plane, see [`Node::frame_plane`].
+    pub fn frame_topics(&self, frame: &[u8]) -> Vec<String> {
+        // Members are not on the topic of the epoch our external commit starts.
+        if self.external_commits.contains(&self.frame_id(frame)) {
+            return Vec::new();
+        }
+        let group = match FrameKind::group_id(frame) {
+            Some(group_id) => self.groups.get(group_id),
+            None => self.group(),
+        };
+        let topics = group.map(|group| group.topics.as_slice()).unwrap_or(&[]);
+        match self.frame_plane(frame) {
+            Plane::Control => topics.iter().map(|topic| control_topic(topic)).collect(),
+            Plane::Data => topics.to_vec(),
+        }
+    }
+
+    /// The plane `frame` goes out on: that of its kind, or the control
+    /// plane for our adverts.
+    pub fn frame_plane(&self, frame: &[u8]) -> Plane {
+        match Plane::of(frame) {
+            Plane::Data if self.control_frames.contains(&self.frame_id(frame)) => Plane::Control,
+            plane => plane,
+        }
+    }
+
+    fn state_digest_of(&self, group_id: &[u8]) -> Result<StateDigest, NodeError> {
+        let group = self.groups.get(group_id).ok_or(NodeError::NoGroup)?;
+        Ok(StateDigest {
+            identity: credential_identity(self.identity.key_package.credential()),
+            group_id: group_id.to_vec(),
+            is_leader: group.is_group_leader,
+            history: group.epoch_digests.clone(),
+        })
+    }
+
+    pub fn state_digest(&self) -> Result<StateDigest, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .ok_or(NodeError::NoGroup)?;
+        self.state_digest_of(group_id)
+    }
+
+    fn sign_recovery_message(
+        &self,
+        group_id: &[u8],
+        kind: RecoveryKind,
+    ) -> Result<RecoveryMessage, NodeError> {
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        RecoveryMessage::sign(
+            kind,
+            self.state_digest_of(group_id)?,
+            credential_bundle,
+            &self.backend,
+        )
+    }
+
+    /// Starts a recovery round in the active group, forgetting the answers to
+    /// any earlier one. The returned probe should be broadcast to the group.
+    pub fn start_recovery(&mut self) -> Result<RecoveryMessage, NodeError> {
+        let group = self
+            .journal
+            .groups()
+            .active
+            .as_ref()
+            .and_then(|id| self.groups.get_mut(id))
+            .ok_or(NodeError::NoGroup)?;
+        group.recovery_answers.clear();
+        let group_id = group.group_id();
+        self.sign_recovery_message(&group_id, RecoveryKind::Probe)
+    }
+
+    /// Records the state of the member who sent `message`, and answers with
+    /// ours if it was a probe. Only members of our view of the group count.
+    pub fn handle_recovery_message(
+        &mut self,
+        message: RecoveryMessage,
+    ) -> Result<Option<RecoveryMessage>, NodeError> {
+        let (credential, kind, mut digest) = message.verify(&self.backend)?;
+        let group = self
+            .groups
+            .get_mut(&digest.group_id)
+            .ok_or(NodeError::NoGroup)?;
+        if !group
+            .mls_group
+            .members()
+            .iter()
+            .any(|member| same_signature_key(member.credential(), &credential))
+        {
+            return Err(NodeError::Other(
+                "Recovery message from a non-member".to_string(),
+            ));
+        }
+        if same_signature_key(&credential, self.identity.key_package.credential()) {
+            return Ok(None);
+        }
+        // Trust the signed credential, not the claimed identity or leadership.
+        digest.identity = credential_identity(&credential);
+        digest.is_leader =
+            group.leader_key().as_deref() == Some(credential.signature_key().as_slice());
+        let group_id = digest.group_id.clone();
+        group
+            .recovery_answers
+            .insert(digest.identity.clone(), digest);
+        match kind {
+            RecoveryKind::Probe => Ok(Some(
+                self.sign_recovery_message(&group_id, RecoveryKind::Status)?,
+            )),
+            RecoveryKind::Status => Ok(None),
+        }
+    }
+
+    /// Compares our state in the active group with the members who answered
+    /// so far.
+    pub fn recovery_report(&self) -> Result<RecoveryReport, NodeError> {
+        let ours = self.state_digest()?;
+        let answers: Vec<StateDigest> = self
+            .group()
+            .map(|group| group.recovery_answers.values().cloned().collect())
+            .unwrap_or_default();
+        recovery::analyze(&ours, &answers)
+    }
+
+    /// Drops our side of a fork of the active group and returns a join
+    /// request, with a fresh key package, for the leader of the canonical
+    /// branch, named when it answered the recovery probe.
+    pub fn rejoin(&mut self) -> Result<JoinRequest, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        if self.is_group_leader() {
+            return Err(NodeError::Other(
+                "The group leader cannot rejoin its own group".to_string(),
+            ));
+        }
+        let leader = self.groups[&group_id]
+            .recovery_answers
+            .values()
+            .find(|digest| digest.is_leader)
+            .map(|digest| digest.identity.clone());
+        let request = JoinRequest::new(
+            self.identity.key_package.clone(),
+            self.admission.config(),
+            &self.backend,
+        )?;
+        let request = self.sign_join_request(match leader {
+            Some(leader) => request.for_leader(&leader),
+            None => request,
+        })?;
+        self.drop_group(&group_id);
+        Ok(request)
+    }
+
+    // Forgets the group. Another group, if any, becomes the active one.
+    fn drop_group(&mut self, group_id: &[u8]) {
+        self.groups.remove(group_id);
+        self.journal.append(GroupEvent::Dropped {
+            group_id: group_id.to_vec(),
+        });
+    }
+
+    /// Freezes the group named `group` into a sealed archive, see `archive`.
+    /// The group stays active until `forget_group`, so callers drop it only
+    /// once the archive is safely stored.
+    pub fn seal_group(
+        &mut self,
+        group: &str,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<Vec<u8>, NodeError> {
+        if self.groups.is_empty() {
+            return Err(NodeError::NoGroup);
+        }
+        let group_id = self.find_group(group)?;
+        let state = self.groups.get_mut(&group_id).expect("group");
+        let mut mls_state = Vec::new();
+        state.mls_group.save(&mut mls_state)?;
+        let history = self
+            .audit_log
+            .entries()
+            .iter()
+            .filter(|entry| match entry {
+                AuditEntry::SignedMessage { group_id: id, .. } => *id == group_id,
+            })
+            .cloned()
+            .collect();
+        let sealed = GroupArchive {
+            epoch: state.mls_group.epoch().as_u64(),
+            group_id: group_id.clone(),
+            archived_at: GroupArchive::now(),
+            was_leader: state.is_group_leader,
+            policy: state.policy.encode(),
+            state: mls_state,
+            epoch_digests: state.epoch_digests.clone(),
+            history,
+        }
+        .seal(passphrase, iterations, &self.backend)?;
+        Ok(sealed)
+    }
+
+    /// Drops the group named `group` from the node, as after archiving it.
+    pub fn forget_group(&mut self, group: &str) -> Result<(), NodeError> {
+        let group_id = self.find_group(group)?;
+        self.drop_group(&group_id);
+        Ok(())
+    }
+
+    /// Opens an archive read-only; the node's own state is left alone.
+    pub fn open_archive(&self, bytes: &[u8], passphrase: &str) -> Result<GroupArchive, NodeError> {
+        GroupArchive::open(bytes, passphrase, &self.backend)
+    }
+
+    pub fn create_message(&mut self, msg: &str) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        self.create_text_message(&group_id, msg)
+    }
+
+    /// Sends `msg` to the group named `group`, whichever group is active.
+    pub fn create_group_message(
+        &mut self,
+        group: &str,
+        msg: &str,
+    ) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self.find_group(group)?;
+        self.create_text_message(&group_id, msg)
+    }
+
+    // Text goes into the outbox, to be shown once it is published.
+    fn create_text_message(
+        &mut self,
+        group_id: &[u8],
+        msg: &str,
+    ) -> Result<MlsMessageOut, NodeError> {
+        let delivery = MessageId::generate(&self.backend)?;
+        let msg_out = self.create_message_in(group_id, &ack::tracked(delivery, msg))?;
+        let id = self.frame_id(&WireMessage::from(msg_out.clone()).encode()?);
+        let seq = self.outbox.push(id, group_id, msg);
+        self.track_delivery(delivery, group_id, seq, msg);
+        self.remember_own(group_id, msg);
+        Ok(msg_out)
+    }
+
+    // Follows text we sent until the members it went to acknowledge it.
+    fn track_delivery(&mut self, id: MessageId, group_id: &[u8], seq: u64, text: &str) {
+        let mut recipients = match self.groups.get(group_id) {
+            Some(group) => member_identities(&group.mls_group),
+            None => return,
+        };
+        recipients.remove(&credential_identity(self.identity.key_package.credential()));
+        self.deliveries.track(Delivery {
+            id,
+            group_id: group_id.to_vec(),
+            seq,
+            text: text.to_string(),
+            recipients,
+            acked: BTreeSet::new(),
+            read: BTreeSet::new(),
+            attempts: 1,
+            sent_at: Instant::now(),
+        });
+    }
+
+    /// How long to wait for every member to acknowledge a text message
+    /// before sending it again; zero never resends.
+    pub fn set_ack_timeout(&mut self, timeout: Duration) {
+        self.deliveries.set_timeout(timeout);
+    }
+
+    /// Sends again, with the same id, the text messages members have not
+    /// acknowledged within the ack timeout at `now`, up to
+    /// `ack::MAX_ATTEMPTS` times each. The frames wait for
+    /// [`Node::take_outgoing`]; returns how many there are.
+    pub fn retransmit_unacked(&mut self, now: Instant) -> usize {
+        let mut resent = 0;
+        for id in self.deliveries.due(now) {
+            let delivery = self.deliveries.get(id).expect("due delivery");
+            let (group_id, bytes) = (delivery.group_id.clone(), ack::tracked(id, &delivery.text));
+            if !self.groups.contains_key(&group_id) {
+                continue;
+            }
+            match self.create_message_in(&group_id, &bytes) {
+                Ok(msg_out) => {
+                    self.outgoing.push(WireMessage::from(msg_out));
+                    resent += 1;
+                }
+                Err(e) => log::debug!("Could not resend message {}: {}", id, e),
+            }
+        }
+        resent
+    }
+
+    /// Who acknowledged the text message `id`, while it is followed.
+    pub fn delivery(&self, id: MessageId) -> Option<&Delivery> {
+        self.deliveries.get(id)
+    }
+
+    pub fn deliveries(&self) -> &Deliveries {
+        &self.deliveries
+    }
+
+    /// Tells senders when we have read their text messages, see
+    /// [`Node::mark_read`]; groups under the maximum privacy policy are
+    /// never told.
+    pub fn set_read_receipts(&mut self, enabled: bool) {
+        self.read_receipts = enabled;
+        if !enabled {
+            self.unread.clear();
+        }
+    }
+
+    /// Sends read receipts for the text messages received since the last
+    /// call, once the application has shown them. Returns how many went out;
+    /// the frames wait for [`Node::take_outgoing`].
+    pub fn mark_read(&mut self) -> usize {
+        let mut sent = 0;
+        for (group_id, id) in std::mem::take(&mut self.unread) {
+            match self.create_message_in(&group_id, &ack::read(id)) {
+                Ok(msg_out) => {
+                    self.outgoing.push(WireMessage::from(msg_out));
+                    sent += 1;
+                }
+                Err(e) => log::debug!("Could not send read receipt for {}: {}", id, e),
+            }
+        }
+        sent
+    }
+
+    // Acknowledges a tracked text message to the group it came from.
+    fn queue_ack(&mut self, group_id: &[u8], id: MessageId) {
+        match self.create_message_in(group_id, &ack::ack(id)) {
+            Ok(msg_out) => self.outgoing.push(WireMessage::from(msg_out)),
+            Err(e) => log::debug!("Could not acknowledge message {}: {}", id, e),
+        }
+    }
+
+    /// Keeps media keys for every group, so members can `stream` to each
+    /// other; experimental, see `media`.
+    pub fn enable_media(&mut self) {
+        self.media = true;
+        for group in self.groups.values_mut() {
+            if group.media.is_none() {
+                group.media = MediaKeys::new(&group.mls_group, &self.backend);
+            }
+        }
+    }
+
+    /// Seals `payload` for each connected member of the active group. The
+    /// packets wait for [`Node::take_media`]; returns how many there are.
+    pub fn stream(&mut self, payload: &[u8]) -> Result<usize, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let sender = self.info_of(&group_id).own_leaf_index;
+        let own_peer = PeerId::from(self.identity.network_key.public());
+        let group = self.groups.get_mut(&group_id).expect("active group");
+        let media = group.media.as_mut().ok_or_else(|| {
+            NodeError::Other("Streaming needs the media channel, see --media".to_string())
+        })?;
+        let packet = media.seal(&self.backend, &group_id, sender, payload)?;
+        let peers: Vec<PeerId> = group
+            .mls_group
+            .members()
+            .iter()
+            .filter_map(|member| PeerId::from_bytes(member.credential().identity()).ok())
+            .filter(|peer| *peer != own_peer && self.peers.is_connected(peer))
+            .collect();
+        for peer in &peers {
+            self.media_out.push((*peer, packet.clone()));
+        }
+        Ok(peers.len())
+    }
+
+    /// Opens a media packet `peer` streamed to us, which must come from the
+    /// member at the leaf it names.
+    pub fn open_media(&mut self, peer: &PeerId, packet: &[u8]) -> Result<MediaFrame, NodeError> {
+        let (header, aad, ciphertext) = Header::decode(packet)?;
+        let group = self
+            .groups
+            .get_mut(&header.group_id)
+            .ok_or(NodeError::NoGroup)?;
+        let credential = match group
+            .mls_group
+            .export_ratchet_tree()
+            .iter()
+            .step_by(2)
+            .nth(header.sender as usize)
+        {
+            Some(Some(OpenMlsNode::LeafNode(leaf))) => leaf.key_package().credential().clone(),
+            _ => {
+                return Err(NodeError::Other(format!(
+                    "Media packet from empty leaf {}",
+                    header.sender
+                )))
+            }
+        };
+        if !credential_matches_peer(&credential, peer) {
+            return Err(NodeError::Other(format!(
+                "Media packet for leaf {} sent by another peer",
+                header.sender
+            )));
+        }
+        let media = group.media.as_mut().ok_or_else(|| {
+            NodeError::Other("Media packet while the media channel is off".to_string())
+        })?;
+        let payload = media.open(&self.backend, &header, aad, ciphertext)?;
+        Ok(MediaFrame {
+            group: group.name.clone(),
+            sender: credential_identity(&credential),
+            epoch: header.epoch,
+            seq: header.seq,
+            payload,
+        })
+    }
+
+    /// Media packets to send to one peer each, with
+    /// `NetworkService::send_media`.
+    pub fn take_media(&mut self) -> Vec<(PeerId, Vec<u8>)> {
+        std::mem::take(&mut self.media_out)
+    }
+
+    /// Sends `msg` to the group named `group`, or the active one, after
+    /// checking its health; see `health` for what the policy does when the
+    /// group is unhealthy.
+    pub fn send_text(&mut self, group: Option<&str>, msg: &str) -> Result<SendOutcome, NodeError> {
+        let group_id = match group {
+            Some(group) => self.find_group(group)?,
+            None => self
+                .journal
+                .groups()
+                .active
+                .clone()
+                .ok_or(NodeError::NoGroup)?,
+        };
+        self.send_text_to(group_id, msg)
+    }
+
+    /// Sends `msg` to every group we are in, as `send_text` would, and
+    /// says how it went in each, by group name. Messages encrypted right
+    /// away wait for [`Node::take_outgoing`].
+    pub fn broadcast(&mut self, msg: &str) -> Result<Broadcast, NodeError> {
+        if self.groups.is_empty() {
+            return Err(NodeError::NoGroup);
+        }
+        let mut outcomes = Vec::new();
+        for info in self.groups() {
+            let outcome = match self.send_text_to(info.group_id, msg) {
+                Ok(SendOutcome::Sent { message, health }) => {
+                    self.outgoing.push(message.into());
+                    Ok(SendOutcome::Queued { health })
+                }
+                outcome => outcome,
+            };
+            outcomes.push((info.name, outcome));
+        }
+        Ok(outcomes)
+    }
+
+    fn send_text_to(&mut self, group_id: Vec<u8>, msg: &str) -> Result<SendOutcome, NodeError> {
+        let health = self.group_health(&group_id);
+        if !health.is_healthy() {
+            match self.send_policy {
+                SendPolicy::Warn => {}
+                SendPolicy::Block => return Err(NodeError::Other(format!("Not sent: {}", health))),
+                SendPolicy::Queue if self.held.len() >= MAX_HELD => {
+                    return Err(NodeError::Other(format!(
+                        "Not sent, {} messages are already held: {}",
+                        MAX_HELD, health
+                    )))
+                }
+                SendPolicy::Queue => {
+                    self.held.push_back((group_id, msg.to_string()));
+                    return Ok(SendOutcome::Held(health));
+                }
+            }
+        }
+        if self.async_encryption {
+            let seq = self.outbox.reserve(&group_id, msg);
+            let send = PendingSend { group_id, seq };
+            self.encrypting.push_back((send.clone(), msg.to_string()));
+            return Ok(SendOutcome::Pending { send, health });
+        }
+        let message = self.create_text_message(&group_id, msg)?;
+        Ok(SendOutcome::Sent { message, health })
+    }
+
+    /// What is wrong with the group `group_id` for sending, see `health`.
+    pub fn group_health(&self, group_id: &[u8]) -> GroupHealth {
+        let mut health = GroupHealth::default();
+        let group = match self.groups.get(group_id) {
+            Some(group) => group,
+            None => return health,
+        };
+        if group.mls_group.pending_commit().is_some() {
+            health.issues.push(HealthIssue::PendingCommit);
+        }
+        let epoch = group.mls_group.epoch().as_u64();
+        if group.observed_epoch > epoch {
+            health.issues.push(HealthIssue::Behind {
+                epoch,
+                observed: group.observed_epoch,
+            });
+        }
+        let own = self.identity.key_package.credential();
+        let others: Vec<&KeyPackage> = group
+            .mls_group
+            .members()
+            .into_iter()
+            .filter(|member| !same_signature_key(member.credential(), own))
+            .collect();
+        // With a mailbox, frames sent while nobody is connected wait there.
+        let waits_in_mailbox = self.mailbox && self.peers.is_empty();
+        let connected = self.peers.peer_ids().any(|peer| {
+            others
+                .iter()
+                .any(|member| credential_matches_peer(member.credential(), peer))
+        });
+        if !others.is_empty() && !connected && !waits_in_mailbox {
+            health.issues.push(HealthIssue::NoMembersConnected);
+        }
+        health
+    }
+
+    /// `mailbox` says whether the delivery service keeps frames while no
+    /// peers are connected.
+    pub fn set_send_policy(&mut self, policy: SendPolicy, mailbox: bool) {
+        self.send_policy = policy;
+        self.mailbox = mailbox;
+    }
+
+    /// Encrypts the held messages of groups that are healthy again, oldest
+    /// first. Messages for groups we are no longer in are dropped.
+    pub fn release_held(&mut self) -> Vec<MlsMessageOut> {
+        let mut released = Vec::new();
+        for (group_id, msg) in std::mem::take(&mut self.held) {
+            if !self.groups.contains_key(&group_id) {
+                continue;
+            }
+            // Later messages of a group stay behind an earlier one still held.
+            let blocked = self.held.iter().any(|(held, _)| held == &group_id)
+                || !self.group_health(&group_id).is_healthy();
+            if blocked {
+                self.held.push_back((group_id, msg));
+                continue;
+            }
+            match self.create_text_message(&group_id, &msg) {
+                Ok(message) => released.push(message),
+                Err(e) => log::debug!("Dropped a held message: {}", e),
+            }
+        }
+        released
+    }
+
+    /// With `enabled`, `send_text` only numbers a message and returns, and
+    /// `encrypt_pending` encrypts it later, off the sender's path.
+    pub fn set_async_encryption(&mut self, enabled: bool) {
+        self.async_encryption = enabled;
+    }
+
+    /// Makes our key packages, and so the groups we start, use
+    /// `ciphersuite`, see `crypto::parse_ciphersuite`. Joiners need key
+    /// packages of the same ciphersuite.
+    pub fn set_ciphersuite(&mut self, ciphersuite: Ciphersuite) -> Result<(), NodeError> {
+        if ciphersuite == self.identity.key_package.ciphersuite() {
+            return Ok(());
+        }
+        self.ciphersuite = ciphersuite;
+        self.identity.key_package = self.fresh_key_package()?;
+        Ok(())
+    }
+
+    /// The group `node create` starts when not given a name.
+    pub fn default_group_name(&self) -> &str {
+        &self.default_group_name
+    }
+
+    pub fn set_default_group_name(&mut self, name: &str) -> Result<(), NodeError> {
+        check_group_name(name)?;
+        self.default_group_name = name.to_string();
+        Ok(())
+    }
+
+    pub fn encryptions_pending(&self) -> usize {
+        self.encrypting.len()
+    }
+
+    /// Encrypts the oldest message `send_text` left pending. Messages are
+    /// taken in the order they were accepted, so each group's follow its
+    /// outbox sequence numbers.
+    pub fn encrypt_pending(&mut self) -> Option<(PendingSend, Result<MlsMessageOut, NodeError>)> {
+        let (send, msg) = self.encrypting.pop_front()?;
+        let encrypted = MessageId::generate(&self.backend).and_then(|delivery| {
+            let msg_out = self.create_message_in(&send.group_id, &ack::tracked(delivery, &msg))?;
+            Ok((
+                delivery,
+                WireMessage::from(msg_out.clone()).encode()?,
+                msg_out,
+            ))
+        });
+        match encrypted {
+            Ok((delivery, frame, msg_out)) => {
+                let id = self.frame_id(&frame);
+                self.outbox.encrypted(&send.group_id, send.seq, id);
+                self.track_delivery(delivery, &send.group_id, send.seq, &msg);
+                self.remember_own(&send.group_id, &msg);
+                Some((send, Ok(msg_out)))
+            }
+            Err(e) => {
+                self.outbox.abandon(&send.group_id, send.seq);
+                Some((send, Err(e)))
+            }
+        }
+    }
+
+    /// How a message `send_text` accepted is doing, while the outbox
+    /// remembers it.
+    pub fn send_state(&self, send: &PendingSend) -> Option<DeliveryState> {
+        self.outbox.state(&send.group_id, send.seq)
+    }
+
+    fn frame_id(&self, frame: &[u8]) -> Vec<u8> {
+        self.backend
+            .crypto()
+            .hash(HashType::Sha2_256, frame)
+            .expect("SHA-256 is supported")
+    }
+
+    /// Records how the network event loop sent `frame`. Returns our text
+    /// message the first time it goes out, to show as the local echo.
+    pub fn record_published(&mut self, frame: &[u8], state: DeliveryState) -> Option<OutboxEntry> {
+        let id = self.frame_id(frame);
+        self.outbox.published(id, state).cloned()
+    }
+
+    /// Whether `frame` is one we published coming back to us.
+    pub fn is_own_echo(&mut self, frame: &[u8]) -> bool {
+        let id = self.frame_id(frame);
+        self.outbox.is_echo(&id)
+    }
+
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
+    /// How `peer` is shown in output, see `names`.
+    pub fn display_name(&mut self, peer: &PeerId) -> String {
+        self.names.name(peer)
+    }
+
+    pub fn set_name_style(&mut self, style: NameStyle) {
+        self.names.set_style(style);
+    }
+
+    /// Goes by `name` in the groups we are in from now on, see `names`.
+    pub fn set_nickname(&mut self, name: &str) -> Result<(), NodeError> {
+        names::check_nickname(name)?;
+        let own_peer = PeerId::from(self.identity.network_key.public());
+        self.names.set_nickname(&own_peer, name.to_string());
+        self.nickname = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn nickname(&self) -> Option<&str> {
+        self.nickname.as_deref()
+    }
+
+    /// Shares what we run with our capabilities from the next epoch on,
+    /// see `capabilities`; `None` stops.
+    pub fn set_client_info(&mut self, client: Option<ClientInfo>) {
+        self.client_info = client;
+    }
+
+    /// What the members of the active group run, for its leader and
+    /// admins to plan upgrades with.
+    pub fn client_stats(&self) -> Result<ClientStats, NodeError> {
+        let group = self.group().ok_or(NodeError::NoGroup)?;
+        let own_key = self.identity.key_package.credential().signature_key();
+        if group.rights_of(own_key.as_slice()).is_empty() {
+            return Err(NodeError::NotLeader("and admins see what members run"));
+        }
+        let mut stats = ClientStats::default();
+        for member in group.mls_group.members() {
+            let key = member.credential().signature_key();
+            stats.count(match ct_eq(key.as_slice(), own_key.as_slice()) {
+                true => self.client_info.as_ref(),
+                false => self.member_clients.get(key.as_slice()),
+            });
+        }
+        Ok(stats)
+    }
+
+    pub fn create_telemetry_message(
+        &mut self,
+        frame: &TelemetryFrame,
+    ) -> Result<MlsMessageOut, NodeError> {
+        self.create_application_message(&frame.encode())
+    }
+
+    /// Tells the active group we are typing, see `typing`. `None` when we
+    /// told it less than `TYPING_INTERVAL` before `now`, or the group has
+    /// presence turned off; publish the message as realtime traffic.
+    pub fn create_typing_message(
+        &mut self,
+        now: Instant,
+    ) -> Result<Option<MlsMessageOut>, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        if !self.feature_enabled(&group_id, Feature::Presence) || !self.typing.allow(&group_id, now)
+        {
+            return Ok(None);
+        }
+        self.create_message_in(&group_id, &typing::typing())
+            .map(Some)
+    }
+
+    /// Offers the file at `path` to the active group, see `transfer`.
+    /// Returns the offer to publish and how many chunks follow it; those
+    /// wait for [`Node::take_bulk`].
+    pub fn send_file(&mut self, path: &Path) -> Result<(MlsMessageOut, u32), NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let contents = std::fs::read(path)?;
+        let offer = Offer::new(&self.backend, path, &contents)?;
+        let key = offer.key(&self.groups[&group_id].mls_group, &self.backend)?;
+        let chunks = offer.seal(&self.backend, &key, &group_id, &contents)?;
+        let message = self.create_message_in(&group_id, &offer.encode())?;
+        self.bulk.extend(chunks);
+        Ok((message, offer.count))
+    }
+
+    /// File chunks to publish as bulk traffic, see `qos`.
+    pub fn take_bulk(&mut self) -> Vec<SealedChunk> {
+        std::mem::take(&mut self.bulk)
+    }
+
+    /// Takes a chunk of a file offered to us, see `transfer`.
+    pub fn receive_chunk(&mut self, chunk: SealedChunk) -> Result<Option<Progress>, NodeError> {
+        self.transfers.receive(&self.backend, chunk)
+    }
+
+    /// Where files members send us are saved; without one they are refused.
+    pub fn set_download_dir(&mut self, dir: PathBuf) {
+        self.transfers.set_dir(dir);
+    }
+
+    /// Encrypts an already encoded payload for the active group; receivers
+    /// decode payloads starting with the telemetry marker as telemetry and
+    /// the rest as text.
+    pub fn create_application_message(&mut self, bytes: &[u8]) -> Result<MlsMessageOut, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        self.create_message_in(&group_id, bytes)
+    }
+
+    fn create_message_in(
+        &mut self,
+        group_id: &[u8],
+        bytes: &[u8],
+    ) -> Result<MlsMessageOut, NodeError> {
+        let payload = self.outgoing_payload(group_id, bytes)?;
+        let group = self.groups.get_mut(group_id).ok_or(NodeError::NoGroup)?;
+        let generation = group.next_generation();
+        let msg_out = group
+            .mls_group
+            .create_message(&self.backend, &payload)
+            .map_err(|e| NodeError::Other(format!("Could not create message: {:?}", e)))?;
+        group.sent_generation = (group.mls_group.epoch().as_u64(), generation + 1);
+        Ok(msg_out)
+    }
+
+    // Applies the group policy to an outgoing payload before encryption,
+    // sealing it last when the group requires a pre-shared key.
+    fn outgoing_payload(&self, group_id: &[u8], bytes: &[u8]) -> Result<Vec<u8>, NodeError> {
+        let payload = self.policy_payload(group_id, bytes)?;
+        let psk_id = self
+            .groups
+            .get(group_id)
+            .and_then(|group| group.policy.psk_id);
+        match psk_id {
+            Some(id) => psk::seal(&self.backend, &self.psk_key(group_id, &id)?, &id, &payload),
+            None => Ok(payload),
+        }
+    }
+
+    fn policy_payload(&self, group_id: &[u8], bytes: &[u8]) -> Result<Vec<u8>, NodeError> {
+        let group = match self.groups.get(group_id) {
+            Some(group) => group,
+            None => return Ok(bytes.to_vec()),
+        };
+        if group.policy.max_privacy {
+            if let Ok(mut frame) = TelemetryFrame::decode(bytes) {
+                frame.round_timestamp(TIMESTAMP_GRANULARITY_SECS);
+                return Ok(frame.encode());
+            }
+            return Ok(bytes.to_vec());
+        }
+        if !group.policy.non_repudiation {
+            return Ok(bytes.to_vec());
+        }
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        SignedPayload::sign(
+            credential_bundle,
+            &self.backend,
+            group_id,
+            group.mls_group.epoch().as_u64(),
+            bytes,
+        )?
+        .encode()
+    }
+
+    /// Encrypts `msg` against a scratch copy of the group, so nothing is
+    /// published and the real sender ratchet does not advance.
+    pub fn inspect_message(&mut self, msg: &str) -> Result<MessageInspection, NodeError> {
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        // Sized as sent, with a message id of its own.
+        let tracked = ack::tracked(MessageId::generate(&self.backend)?, msg);
+        let payload = self.outgoing_payload(&group_id, &tracked)?;
+        let group = self.groups.get_mut(&group_id).expect("active group");
+        let generation = group.next_generation();
+        let mls_group = &mut group.mls_group;
+        let padding_block = mls_group.configuration().padding_size();
+
+        let mut padded = clone_mls_group(mls_group)?;
+        let mut unpadded = clone_mls_group(mls_group)?;
+        unpadded.set_configuration(&with_padding_size(mls_group.configuration(), 0));
+
+        let padded_size = codec::to_tls(
+            &padded
+                .create_message(&self.backend, &payload)
+                .map_err(|e| NodeError::Other(format!("Could not create message: {:?}", e)))?,
+            "message",
+        )?
+        .len();
+        let unpadded_size = codec::to_tls(
+            &unpadded
+                .create_message(&self.backend, &payload)
+                .map_err(|e| NodeError::Other(format!("Could not create message: {:?}", e)))?,
+            "message",
+        )?
+        .len();
+
+        Ok(MessageInspection {
+            plaintext_size: msg.len(),
+            ciphertext_size: padded_size,
+            padding_block,
+            padding_applied: padded_size - unpadded_size,
+            epoch: mls_group.epoch().as_u64(),
+            generation,
+        })
+    }
+
+    /// Issues a proof, signed with our credential, that `identity` is a
+    /// member of the group in the current epoch.
+    pub fn membership_proof(&self, identity: &str) -> Result<MembershipProof, NodeError> {
+        let group = &self.group().ok_or(NodeError::NoGroup)?.mls_group;
+        let tree = group.export_ratchet_tree();
+        let leaf_index = tree
+            .iter()
+            .step_by(2)
+            .position(|leaf| match leaf {
+                Some(OpenMlsNode::LeafNode(leaf)) => {
+                    credential_has_identity(leaf.key_package().credential(), identity)
+                }
+                _ => false,
+            })
+            .ok_or_else(|| {
+                NodeError::Other(format!("{} is not a member of the group", identity))
+            })?;
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        MembershipProof::issue(
+            credential_bundle,
+            &self.backend,
+            group.group_id().as_slice(),
+            group.epoch().as_u64(),
+            &tree,
+            leaf_index as u32,
+        )
+    }
+
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of_credential(self.identity.key_package.credential(), &self.backend)
+    }
+
+    /// Marks `identity` as verified if `fingerprint`, as read out by the
+    /// member, matches their credential in the group.
+    pub fn verify_member(&mut self, identity: &str, fingerprint: &str) -> Result<(), NodeError> {
+        let group = &self.group().ok_or(NodeError::NoGroup)?.mls_group;
+        let credential = group
+            .members()
+            .into_iter()
+            .map(KeyPackage::credential)
+            .find(|credential| credential_has_identity(credential, identity))
+            .ok_or_else(|| {
+                NodeError::Other(format!("{} is not a member of the group", identity))
+            })?;
+        let expected = Fingerprint::of_credential(credential, &self.backend).to_string();
+        let normalize = |f: &str| f.replace(' ', "").to_lowercase();
+        if !ct_eq(
+            normalize(&expected).as_bytes(),
+            normalize(fingerprint).as_bytes(),
+        ) {
+            return Err(NodeError::Other(format!(
+                "Fingerprint mismatch for {}",
+                identity
+            )));
+        }
+        let key = credential.signature_key().as_slice().to_vec();
+        self.verified_members.insert(key);
+        Ok(())
+    }
+
+    pub fn security_state(&self) -> SecurityState {
+        let group = match self.group() {
+            Some(group) => group,
+            None => {
+                return SecurityState {
+                    epoch: None,
+                    members: 0,
+                    verified_members: 0,
+                    policy: GroupPolicy::default(),
+                    degraded: false,
+                    desynced: false,
+                }
+            }
+        };
+        let own_key = self.identity.key_package.credential().signature_key();
+        let members = group.mls_group.members();
+        let verified_members = members
+            .iter()
+            .map(|key_package| key_package.credential().signature_key())
+            .filter(|key| {
+                ct_eq(key.as_slice(), own_key.as_slice())
+                    || self.verified_members.contains(key.as_slice())
+            })
+            .count();
+        SecurityState {
+            epoch: Some(group.mls_group.epoch().as_u64()),
+            members: members.len(),
+            verified_members,
+            policy: group.policy,
+            degraded: group.failed_messages >= DEGRADED_AFTER_FAILURES,
+            desynced: group.desynced,
+        }
+    }
+
+    /// Everyone who can read what we send to the group, by leaf.
+    pub fn list_members(&self) -> Result<Vec<Member>, NodeError> {
+        let group = self.group().ok_or(NodeError::NoGroup)?;
+        let own_key = self.identity.key_package.credential().signature_key();
+        Ok(group
+            .mls_group
+            .export_ratchet_tree()
+            .iter()
+            .step_by(2)
+            .enumerate()
+            .filter_map(|(leaf_index, leaf)| match leaf {
+                Some(OpenMlsNode::LeafNode(leaf)) => Some((leaf_index, leaf.key_package())),
+                _ => None,
+            })
+            .map(|(leaf_index, key_package)| {
+                let credential = key_package.credential();
+                let key = credential.signature_key().as_slice();
+                let is_us = ct_eq(key, own_key.as_slice());
+                let capabilities = match is_us {
+                    true => Some(self.capabilities),
+                    false => self.member_capabilities.get(key).copied(),
+                };
+                let peer_id = PeerId::from_bytes(credential.identity()).ok();
+                Member {
+                    leaf_index: leaf_index as u32,
+                    peer_id,
+                    identity: credential.identity().to_vec(),
+                    name: peer_id
+                        .and_then(|peer| self.names.nickname(&peer))
+                        .map(str::to_string),
+                    verified: is_us || self.verified_members.contains(key),
+                    is_us,
+                    capabilities,
+                    is_leader: leaf_index == 0,
+                    rights: group.rights_of(key),
+                }
+            })
+            .collect())
+    }
+
+    /// Whether every other member of the group advertised `capability`.
+    pub fn group_supports(&self, group_id: &[u8], capability: capabilities::Capability) -> bool {
+        let own_key = self.identity.key_package.credential().signature_key();
+        self.groups.get(group_id).is_some_and(|group| {
+            group.mls_group.members().iter().all(|member| {
+                let key = member.credential().signature_key();
+                ct_eq(key.as_slice(), own_key.as_slice())
+                    || self
+                        .member_capabilities
+                        .get(key.as_slice())
+                        .is_some_and(|capabilities| capabilities.contains(capability))
+            })
+        })
+    }
+
+    /// Our capability advertisements waiting to be published.
+    pub fn take_adverts(&mut self) -> Vec<MlsMessageOut> {
+        let adverts = std::mem::take(&mut self.adverts);
+        for advert in &adverts {
+            if let Ok(frame) = WireMessage::from(advert.clone()).encode() {
+                if self.control_frames.len() >= CONTROL_FRAMES_KEPT {
+                    self.control_frames.pop_front();
+                }
+                self.control_frames.push_back(self.frame_id(&frame));
+            }
+        }
+        adverts
+    }
+
+    // Advertises our capabilities, and nickname if we have one, to the
+    // group, once per epoch.
+    fn queue_advert(&mut self, group_id: &[u8]) {
+        let epoch = match self.groups.get(group_id) {
+            Some(group) if group.advertised_epoch != Some(group.mls_group.epoch().as_u64()) => {
+                group.mls_group.epoch().as_u64()
+            }
+            _ => return,
+        };
+        let advert = self.capabilities.encode_with(self.client_info.as_ref());
+        match self.create_message_in(group_id, &advert) {
+            Ok(advert) => {
+                self.adverts.push(advert);
+                self.groups
+                    .get_mut(group_id)
+                    .expect("group")
+                    .advertised_epoch = Some(epoch);
+            }
+            Err(e) => log::debug!("Could not advertise capabilities: {}", e),
+        }
+        if let Some(name) = &self.nickname {
+            match self.create_message_in(group_id, &names::encode_profile(name)) {
+                Ok(profile) => self.adverts.push(profile),
+                Err(e) => log::debug!("Could not send our name: {}", e),
+            }
+        }
+    }
+
+    pub fn peers(&self) -> &PeerTable {
+        &self.peers
+    }
+
+    pub fn peers_mut(&mut self) -> &mut PeerTable {
+        &mut self.peers
+    }
+
+    pub fn set_address_sharing(&mut self, consent: bool) {
+        self.share_addresses = consent;
+    }
+
+    pub fn shares_addresses(&self) -> bool {
+        self.share_addresses
+    }
+
+    /// Encrypts a signed address book for the active group: our listen
+    /// addresses and the addresses we dialed other members at. Needs consent
+    /// first.
+    pub fn create_address_book_message(&mut self) -> Result<MlsMessageOut, NodeError> {
+        if !self.share_addresses {
+            return Err(NodeError::Other(
+                "Address sharing is off, start with --share-addresses".to_string(),
+            ));
+        }
+        let group_id = self
+            .journal
+            .groups()
+            .active
+            .clone()
+            .ok_or(NodeError::NoGroup)?;
+        let own_peer = PeerId::from(self.identity.network_key.public());
+        let mut entries = vec![(own_peer, self.peers.listen_addresses().to_vec())];
+        for peer in self.peers.dialable_peers() {
+            if *peer != own_peer && self.is_member_peer(peer) {
+                entries.push((*peer, self.peers.dialable(peer).to_vec()));
+            }
+        }
+        let credential_bundle =
+            read_credential_bundle(self.identity.key_package.credential(), &self.backend)
+                .ok_or_else(|| NodeError::Other("Missing own credential bundle".to_string()))?;
+        let signed = SignedAddressBook::sign(
+            &AddressBook::new(&group_id, entries),
+            credential_bundle,
+            &self.backend,
+        )?;
+        self.create_message_in(&group_id, &signed.encode()?)
+    }
+
+    // Keeps the entries for other members of the group, and remembers the
+    // sender's own listen addresses so we can pass them on.
+    fn verify_address_book(
+        &mut self,
+        group_id: &[u8],
+        bytes: &[u8],
+        sender: &Credential,
+    ) -> Result<AddressBook, NodeError> {
+        let mut book = SignedAddressBook::decode(bytes)?.verify(sender, &self.backend, group_id)?;
+        let own_peer = PeerId::from(self.identity.network_key.public());
+        book.retain(|peer| *peer != own_peer && self.is_member_peer(peer));
+        for (peer, addresses) in book.peers() {
+            if credential_matches_peer(sender, &peer) {
+                for address in addresses {
+                    self.peers.dialed(peer, address);
+                }
+            }
+        }
+        Ok(book)
+    }
+
+    pub fn in_group(&self) -> bool {
+        !self.groups.is_empty()
+    }
+
+    /// Whether `peer` is the network identity of a member of any of our groups.
+    pub fn is_member_peer(&self, peer: &PeerId) -> bool {
+        self.groups.values().any(|group| {
+            group
+                .mls_group
+                .members()
+                .iter()
+                .any(|member| credential_matches_peer(member.credential(), peer))
+        })
+    }
+
+    pub fn get_key_package(&self) -> KeyPackage {
+        self.identity.key_package.clone()
+    }
+
+    pub fn get_network_keypair(&self) -> Keypair {
+        self.identity.network_key.clone()
+    }
+
+    pub fn group_policy(&self) -> GroupPolicy {
+        self.group().map(|group| group.policy).unwrap_or_default()
+    }
+
+    /// Every change to our groups, see `journal`.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit_log
+    }
+
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.audit_log = audit_log;
+    }
+
+    /// Seals what the audit log writes from now on under `passphrase`,
+    /// group by group, see `audit`.
+    pub fn seal_history(&mut self, passphrase: &str, iterations: u32) -> Result<(), NodeError> {
+        let mut salt = HISTORY_LABEL.as_bytes().to_vec();
+        salt.extend(self.identity.key_package.credential().identity());
+        let sealing_key = derive_key(&self.backend, passphrase, &salt, iterations)?;
+        self.audit_log.seal_with(sealing_key);
+        Ok(())
+    }
+
+    /// Keeps the texts we send and receive from now on in the file at
+    /// `path`, sealed under `passphrase`, see `history`.
+    pub fn keep_messages(
+        &mut self,
+        path: &Path,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<(), NodeError> {
+        let mut salt = MESSAGES_LABEL.as_bytes().to_vec();
+        salt.extend(self.identity.key_package.credential().identity());
+        let key = derive_key(&self.backend, passphrase, &salt, iterations)?;
+        self.messages = Some(MessageHistory::open(path.into(), key, &self.backend)?);
+        Ok(())
+    }
+
+    /// The last `count` texts of the message history, oldest first.
+    pub fn recent_messages(&self, count: usize) -> Result<Vec<StoredMessage>, NodeError> {
+        match &self.messages {
+            Some(messages) => messages.last(count, &self.backend),
+            None => Err(NodeError::Other(
+                "No message history is kept, see --history".to_string(),
+            )),
+        }
+    }
+
+    fn remember_own(&mut self, group_id: &[u8], text: &str) {
+        let own = credential_identity(self.identity.key_package.credential());
+        let epoch = match self.groups.get(group_id) {
+            Some(group) => group.mls_group.epoch().as_u64(),
+            None => return,
+        };
+        self.remember(group_id, own, epoch, text);
+    }
+
+    // Keeps a text for joiners and in the message history. Failing to keep
+    // it does not stop it being sent or shown.
+    fn remember(&mut self, group_id: &[u8], sender: String, epoch: u64, text: &str) {
+        let at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if let Some(group) = self.groups.get_mut(group_id) {
+            group.recent.push(SharedMessage {
+                sender: sender.clone(),
+                at,
+                text: text.to_string(),
+            });
+        }
+        let messages = match &self.messages {
+            Some(messages) => messages,
+            None => return,
+        };
+        let message = StoredMessage {
+            group: self.group_name(group_id),
+            group_id: group_id.to_vec(),
+            sender,
+            epoch,
+            at,
+            text: text.to_string(),
+        };
+        if let Err(e) = messages.append(&message, &self.backend) {
+            log::warn!("Could not keep message in history: {}", e);
+        }
+    }
+
+    /// Reads an audit log file, opening the sealed history of the groups
+    /// we are still in. Also returns how many sealed entries stayed shut.
+    pub fn read_history(&self, bytes: &[u8]) -> Result<(Vec<AuditEntry>, usize), NodeError> {
+        let secrets = self
+            .groups
+            .iter()
+            .map(|(group_id, group)| (group_id.clone(), group.history_secret.clone()))
+            .collect();
+        self.audit_log.read_file(bytes, &secrets, &self.backend)
+    }
+
+    /// Keeps our credential and key package bundles in the file at `path`,
+    /// see `crypto::key_store`.
+    pub fn persist_key_store(&mut self, path: &Path) -> Result<(), NodeError> {
+        self.backend.key_store_mut().persist_to(path)
+    }
+
+    /// Like `persist_key_store`, sealed under `passphrase`. Nodes whose
+    /// identity is sealed use this, as the store holds the same private key.
+    pub fn persist_sealed_key_store(
+        &mut self,
+        path: &Path,
+        passphrase: &str,
+    ) -> Result<(), NodeError> {
+        self.backend
+            .key_store_mut()
+            .persist_sealed_to(path, passphrase, DEFAULT_ITERATIONS)
+    }
+
+    // Checks the author's explicit signature, records the receipt and
+    // returns the inner payload.
+    fn verify_signed_payload(
+        &mut self,
+        group_id: &[u8],
+        bytes: &[u8],
+        credential: &Credential,
+        epoch: u64,
+    ) -> Result<Vec<u8>, NodeError> {
+        let signed = SignedPayload::decode(bytes)?;
+        signed.verify(credential, &self.backend, group_id, epoch)?;
+        // Acknowledgments are signed like any payload but are not messages.
+        if ack::is_ack(&signed.payload) {
+            return Ok(signed.payload);
+        }
+        let history_secret = self
+            .groups
+            .get(group_id)
+            .map(|group| group.history_secret.as_slice())
+            .unwrap_or_default();
+        self.audit_log.record(
+            AuditEntry::SignedMessage {
+                group_id: group_id.to_vec(),
+                epoch,
+                signer: credential_identity(credential),
+                message: signed.payload.clone(),
+                signature: signed.signature_bytes()?,
+            },
+            history_secret,
+            &self.backend,
+        )?;
+        Ok(signed.payload)
+    }
+
+    pub fn telemetry_decoders_mut(&mut self) -> &mut DecoderRegistry {
+        &mut self.telemetry_decoders
+    }
+
+    /// Handles a frame `peer` published, or left in the mailbox, and says
+    /// what came of it, see `events`. Frames to publish in answer wait for
+    /// [`Node::take_outgoing`].
+    pub fn handle_incoming(&mut self, peer: &PeerId, frame: &[u8]) -> Vec<NodeEvent> {
+        if self.is_own_echo(frame) {
+            log::debug!(
+                "Dropped our own frame relayed back by {}",
+                self.display_name(peer)
+            );
+            return Vec::new();
+        }
+        self.handle_frame(peer, frame, false)
+    }
+
+    /// Handles a frame `peer` sent to us alone, see `direct` in
+    /// `p2p-mls-net`: a join request, answered with a Welcome for
+    /// [`Node::take_direct`] whether or not it names us, or the Welcome for
+    /// ours.
+    pub fn handle_direct(&mut self, peer: &PeerId, frame: &[u8]) -> Vec<NodeEvent> {
+        self.handle_frame(peer, frame, true)
+    }
+
+    fn handle_frame(&mut self, peer: &PeerId, frame: &[u8], direct: bool) -> Vec<NodeEvent> {
+        let mut events = Vec::new();
+        let error = |context, error| NodeEvent::Error {
+            peer: *peer,
+            context,
+            error,
+        };
+        let message = match WireMessage::decode(frame) {
+            Ok(message) => message,
+            Err(e) => {
+                events.push(match self.refuse_unreadable_join(frame) {
+                    Some(reason) => {
+                        error("Refused key package", NodeError::Other(reason.to_string()))
+                    }
+                    None => error("Unreadable frame", e),
+                });
+                return events;
+            }
+        };
+        if direct
+            && !matches!(
+                message,
+                WireMessage::KeyPackage(_) | WireMessage::Welcome(_)
+            )
+        {
+            let unexpected =
+                NodeError::Other("Only join requests and Welcomes travel directly".into());
+            events.push(error("Ignored direct frame", unexpected));
+            return events;
+        }
+        match message {
+            WireMessage::KeyPackage(request) => {
+                if !direct && !self.is_join_target(&request) {
+                    return events;
+                }
+                let batched = self
+                    .join_target()
+                    .is_some_and(|id| !self.groups[&id].join_window.is_zero());
+                match self.join_approval {
+                    JoinApproval::Auto if batched => {
+                        if let Err(e) = self.batch_join(peer, request) {
+                            events.push(error("Refused key package", e));
+                        }
+                    }
+                    JoinApproval::Auto => self.auto_join(peer, request, &mut events),
+                    JoinApproval::Manual => match self.check_join_request(peer, &request) {
+                        Ok(group_id) => {
+                            let group = self.group_name(&group_id);
+                            let identity = credential_identity(request.key_package.credential());
+                            let decision = self.join_policies.decide(&JoinCandidate {
+                                peer,
+                                group: &group,
+                                identity: &identity,
+                            });
+                            match decision {
+                                JoinDecision::Defer => {
+                                    let id = self.pending_joins.push(*peer, group_id, request);
+                                    events.push(NodeEvent::JoinRequested {
+                                        peer: *peer,
+                                        group,
+                                        id,
+                                    })
+                                }
+                                JoinDecision::Approve => {
+                                    let id = self.pending_joins.push(*peer, group_id, request);
+                                    match self.accept_join(id) {
+                                        Ok(()) => events
+                                            .push(NodeEvent::MemberJoined { peer: *peer, group }),
+                                        Err(e) => events.push(error("Refused key package", e)),
+                                    }
+                                }
+                                // Never queued, so there is nothing to take back.
+                                JoinDecision::Decline => {}
+                            }
+                        }
+                        Err(e) => events.push(error("Refused key package", e)),
+                    },
+                }
+            }
+            WireMessage::Control(ControlMessage::RoomAnnouncement(announcement)) => {
+                if let Err(e) = self.record_room(peer, &announcement) {
+                    log::debug!(
+                        "Ignored room announcement from {}: {}",
+                        self.display_name(peer),
+                        e
+                    );
+                }
+            }
+            WireMessage::Control(ControlMessage::JoinRefusal(refusal)) => {
+                match self.handle_join_refusal(&refusal) {
+                    Ok(Some(reason)) => events.push(NodeEvent::KeyPackageRefused {
+                        peer: *peer,
+                        reason,
+                    }),
+                    Ok(None) => {}
+                    Err(e) => events.push(error("Could not replace refused key package", e)),
+                }
+            }
+            WireMessage::Control(ControlMessage::SharedGroup(shared)) => {
+                let (group, group_id) = (shared.name(), hex_encode(&shared.group_id));
+                if self.record_shared_group(shared) {
+                    events.push(NodeEvent::GroupShared {
+                        peer: *peer,
+                        group,
+                        group_id,
+                    });
+                }
+            }
+            WireMessage::Control(ControlMessage::FileChunk(chunk)) => {
+                let group = self.group_name(&chunk.group_id);
+                match self.receive_chunk(chunk) {
+                    // Chunks are sealed outside MLS, so nothing names their author.
+                    Ok(Some(progress)) => events.push(self.received(
+                        Some(*peer),
+                        None,
+                        group,
+                        ApplicationPayload::File(progress),
+                    )),
+                    Ok(None) => {}
+                    Err(e) => events.push(error("Ignored file chunk", e)),
+                }
+            }
+            WireMessage::Control(ControlMessage::Recovery(recovery)) => {
+                match self.handle_recovery_message(recovery) {
+                    Ok(Some(answer)) => self.outgoing.push(answer.into()),
+                    Ok(None) => events.push(NodeEvent::RecoveryReceived { peer: *peer }),
+                    Err(e) => events.push(error("Ignored recovery message", e)),
+                }
+            }
+            // Someone else's, published where we hear it too.
+            WireMessage::Welcome(invite) if !self.is_welcome_for_us(&invite).unwrap_or(true) => {
+                return events;
+            }
+            WireMessage::Welcome(invite) => match self.join_existing_group(invite) {
+                Ok(group) => {
+                    self.queue_address_book(peer, &mut events);
+                    events.push(NodeEvent::WelcomeReceived { peer: *peer, group });
+                }
+                Err(e) => events.push(error("Could not join group", e)),
+            },
+            WireMessage::MlsMessage(msg_out) => {
+                let group = self.group_name(msg_out.group_id().as_slice());
+                match self.parse_as(msg_out, protocol::qos(frame)) {
+                    Ok(Some(payload)) => {
+                        let author = self.author.take();
+                        if let Some(author) = author
+                            .as_ref()
+                            .filter(|author| !credential_matches_peer(author, peer))
+                        {
+                            let author = credential_identity(author);
+                            log::warn!("{} relayed a message {} wrote", peer, author);
+                            events.push(NodeEvent::SenderMismatch {
+                                peer: *peer,
+                                author,
+                                group: group.clone(),
+                            });
+                        }
+                        let author = author.as_ref().map(credential_identity);
+                        events.push(self.received(Some(*peer), author, group, payload))
+                    }
+                    Ok(None) => {}
+                    Err(NodeError::Decrypt(failure)) => events.push(NodeEvent::DecryptFailed {
+                        peer: *peer,
+                        failure: *failure,
+                    }),
+                    Err(e) => events.push(error("Could not parse message", e)),
+                }
+            }
+        }
+        events.append(&mut self.events);
+        // Messages that had to wait for their Welcome or commit.
+        for (group, author, payload) in self.take_replayed() {
+            events.push(self.received(None, author, group, payload));
+        }
+        // A commit or message may have brought a group back to health.
+        for msg_out in self.release_held() {
+            self.outgoing.push(msg_out.into());
+        }
+        events
+    }
+
+    fn auto_join(&mut self, peer: &PeerId, request: JoinRequest, events: &mut Vec<NodeEvent>) {
+        let group = self.join_target().map(|id| self.group_name(&id));
+        match self.handle_join_request(peer, request) {
+            Ok((commits, invite)) => {
+                // Addressed to the joiner alone, see `NetworkService::send_to`.
+                self.direct.push((*peer, invite.into()));
+                self.outgoing
+                    .extend(commits.into_iter().map(WireMessage::from));
+                // Introduce the newcomer to the members we know how to reach.
+                self.queue_address_book(peer, events);
+                events.push(NodeEvent::MemberJoined {
+                    peer: *peer,
+                    group: group.unwrap_or_default(),
+                });
+            }
+            Err(error) => events.push(NodeEvent::Error {
+                peer: *peer,
+                context: "Refused key package",
+                error,
+            }),
+        }
+    }
+
+    // A payload for the application; a leave we committed as leader has to
+    // be published.
+    fn received(
+        &mut self,
+        peer: Option<PeerId>,
+        author: Option<String>,
+        group: String,
+        payload: ApplicationPayload,
+    ) -> NodeEvent {
+        if let ApplicationPayload::Left {
+            commit: Some(commit),
+            ..
+        } = &payload
+        {
+            self.outgoing.push(commit.clone().into());
+        }
+        NodeEvent::MessageReceived {
+            peer,
+            author,
+            group,
+            payload,
+        }
+    }
+
+    /// Asks `peer` alone to admit us, see `direct` in `p2p-mls-net`. The
+    /// request names it as the leader, so with `--welcome-from=leader` only a
+    /// Welcome for a group it leads is taken.
+    pub fn request_join_from(&mut self, peer: &PeerId) -> Result<(), NodeError> {
+        let request = JoinRequest::new(
+            self.identity.key_package.clone(),
+            self.admission.config(),
+            &self.backend,
+        )?
+        .for_leader(&peer.to_string());
+        let request = self.sign_join_request(request)?;
+        self.direct.push((*peer, request.into()));
+        Ok(())
+    }
+
+    /// Frames to send to one peer each, with `NetworkService::send_to`.
+    pub fn take_direct(&mut self) -> Vec<(PeerId, WireMessage)> {
+        std::mem::take(&mut self.direct)
+    }
+
+    // Our address book for the group, if we agreed to share it.
+    fn queue_address_book(&mut self, peer: &PeerId, events: &mut Vec<NodeEvent>) {
+        if !self.share_addresses {
+            return;
+        }
+        match self.create_address_book_message() {
+            Ok(msg_out) => self.outgoing.push(msg_out.into()),
+            Err(error) => events.push(NodeEvent::Error {
+                peer: *peer,
+                context: "Could not share addresses",
+                error,
+            }),
+        }
+    }
+
+    /// Frames `handle_incoming` queued in answer, in order.
+    pub fn take_outgoing(&mut self) -> Vec<WireMessage> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn parse_message(&mut self, msg_out: MlsMessageOut) -> Result<Option<String>, NodeError> {
+        Ok(self
+            .parse_application_message(msg_out)?
+            .map(|payload| payload.to_string()))
+    }
+
+    /// Processes `msg_out` in the group it names. Messages for a group we
+    /// have not joined yet, or for an epoch we have not reached, are held
+    /// back and replayed once we get there; see [`Node::take_replayed`].
+    pub fn parse_application_message(
+        &mut self,
+        msg_out: MlsMessageOut,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        self.parse_as(msg_out, Qos::Normal)
+    }
+
+    // Realtime messages are never held back, see `qos`.
+    fn parse_as(
+        &mut self,
+        msg_out: MlsMessageOut,
+        qos: Qos,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        let group_id = msg_out.group_id().as_slice().to_vec();
+        let result = self.parse_in_order(&group_id, msg_out, qos);
+        // Replaying moves `author` on to the held back messages.
+        let author = self.author.take();
+        self.replay_pending(&group_id);
+        self.author = author;
+        result
+    }
+
+    /// How many messages are held back for a Welcome or commit.
+    pub fn pending_messages(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// How many messages of the active group wait for each later epoch,
+    /// until the commit starting it is merged.
+    pub fn held_back(&self) -> Vec<(u64, usize)> {
+        match &self.journal.groups().active {
+            Some(group_id) => self.pending.waiting(group_id),
+            None => Vec::new(),
+        }
+    }
+
+    /// Payloads of held back messages that have since been processed, with
+    /// the name of their group and the identity of their author.
+    pub fn take_replayed(&mut self) -> Vec<(String, Option<String>, ApplicationPayload)> {
+        std::mem::take(&mut self.replayed)
+    }
+
+    // Replays held back messages for as long as they move the group on.
+    fn replay_pending(&mut self, group_id: &[u8]) {
+        loop {
+            let epoch = match self.groups.get(group_id) {
+                Some(group) => group.mls_group.epoch().as_u64(),
+                None => return,
+            };
+            let ready = self.pending.take(group_id, epoch);
+            if ready.is_empty() {
+                return;
+            }
+            for msg_out in ready {
+                match self.parse_in_order(group_id, msg_out, Qos::Normal) {
+                    Ok(Some(payload)) => {
+                        let group = self.group_name(group_id);
+                        let author = self.author.take().as_ref().map(credential_identity);
+                        self.replayed.push((group, author, payload));
+                    }
+                    Ok(None) => {}
+                    Err(e) => log::debug!("Dropped a held back message: {}", e),
+                }
+            }
+        }
+    }
+
+    fn parse_in_order(
+        &mut self,
+        group_id: &[u8],
+        msg_out: MlsMessageOut,
+        qos: Qos,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        let message_epoch = msg_out.epoch();
+        let epoch = message_epoch.as_u64();
+        let group = match self.groups.get_mut(group_id) {
+            Some(group) => group,
+            None if qos == Qos::Realtime => return Ok(None),
+            // Only a group we asked to join can send us traffic before
+            // its Welcome; anything else is not for us and is not parsed.
+            None if self.awaiting_welcome => {
+                if !self.pending.push(group_id, epoch, msg_out) {
+                    log::debug!("Dropped traffic before our Welcome, too much is held back");
+                }
+                return Ok(None);
+            }
+            None => {
+                log::debug!("Dropped traffic of unknown group {}", hex_encode(group_id));
+                return Ok(None);
+            }
+        };
+        if epoch < group.joined_epoch {
+            return Ok(None);
+        }
+        group.observed_epoch = group.observed_epoch.max(epoch);
+        if epoch > group.mls_group.epoch().as_u64() {
+            // We are behind until the commit starting that epoch arrives.
+            group.desynced = true;
+            if qos == Qos::Realtime {
+                log::debug!("Dropped realtime message for epoch {}", epoch);
+            } else if !self.pending.push(group_id, epoch, msg_out) {
+                log::debug!("Dropped traffic for epoch {}, too much is held back", epoch);
+            }
+            return Ok(None);
+        }
+        let result = self.process_application_message(group_id, msg_out);
+        // A group that removed us is gone.
+        if let Some(group) = self.groups.get_mut(group_id) {
+            match &result {
+                Ok(_) => {
+                    group.failed_messages = 0;
+                    group.desynced = false;
+                }
+                Err(_) => {
+                    group.failed_messages += 1;
+                    group.desynced = group.mls_group.epoch() != message_epoch;
+                }
+            }
+        }
+        result
+    }
+
+    fn process_application_message(
+        &mut self,
+        group_id: &[u8],
+        msg_out: MlsMessageOut,
+    ) -> Result<Option<ApplicationPayload>, NodeError> {
+        let aad = ciphertext_authenticated_data(&msg_out);
+        let group = self.groups.get_mut(group_id).expect("group");
+        let failure = DecryptFailure::of(&group.name, &msg_out, group.mls_group.epoch().as_u64());
+        let unverified_message = group
+            .mls_group
+            .parse_message(msg_out.into(), &self.backend)
+            .map_err(|e| failure.clone().parse_failed(&e))?;
+        let sender_credential = unverified_message.credential().cloned();
+        let sender = unverified_message.sender().clone();
+        let epoch = unverified_message.epoch().as_u64();
+
+        let processed_message = group
+            .mls_group
+            .process_unverified_message(
+                unverified_message,
+                None, // No external signature key
+                &self.backend,
+            )
+            .map_err(|e| {
+                let sender = sender_credential
+                    .as_ref()
+                    .map_or_else(|| format!("{:?}", sender), credential_identity);
+                failure.verify_failed(sender, &e)
+            })?;
+        self.author = sender_credential.clone();
+
+        // Only the leader and admins set the policy and metadata; everyone
+        // else merely repeats them.
+        let sender_rights = sender_credential
+            .as_ref()
+            .map(|credential| group.rights_of(credential.signature_key().as_slice()))
+            .unwrap_or_default();
+        let metadata = aad
+            .as_deref()
+            .and_then(|aad| GroupMetadata::open(aad, &group.mls_group, &self.backend))
+            .filter(|_| !sender_rights.is_empty());
+        let policy = aad
+            .as_deref()
+            .and_then(GroupPolicy::decode)
+            .filter(|_| !sender_rights.is_empty())
+            .or_else(|| metadata.as_ref().map(|metadata| metadata.policy));
+        match policy {
+            // Once payloads are sealed, nobody unseals them again.
+            Some(policy) if group.policy.psk_id.is_some() && policy.psk_id.is_none() => {
+                log::warn!(
+                    "Refused a policy dropping the pre-shared key of {}",
+                    group.name
+                );
+            }
+            Some(policy) => group.adopt_policy(policy, &self.backend),
+            None => {}
+        }
+        let policy = group.policy;
+
+        if let ProcessedMessage::ApplicationMessage(application_message) = processed_message {
+            let mut bytes = application_message.into_bytes();
+            match policy.psk_id {
+                Some(id) if psk::is_sealed(&bytes) => {
+                    bytes = psk::open(&self.backend, &self.psk_key(group_id, &id)?, &id, &bytes)?;
+                }
+                Some(_) => {
+                    return Err(NodeError::Other(
+                        "Unsealed message rejected by pre-shared key policy".to_string(),
+                    ))
+                }
+                None if psk::is_sealed(&bytes) => {
+                    return Err(NodeError::Other(
+                        "Sealed message in a group without a pre-shared key".to_string(),
+                    ))
+                }
+                None => {}
+            }
+            // Only now do we know the group's policy, PSK included.
+            if self.groups[group_id].advertised_epoch.is_none() {
+                self.queue_advert(group_id);
+            }
+            if SignedPayload::is_signed(&bytes) && policy.max_privacy {
+                return Err(NodeError::Other(
+                    "Signed message rejected by maximum privacy policy".to_string(),
+                ));
+            } else if SignedPayload::is_signed(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Signed message without sender".to_string()))?;
+                bytes = self.verify_signed_payload(group_id, &bytes, credential, epoch)?;
+            } else if policy.non_repudiation {
+                return Err(NodeError::Other(
+                    "Unsigned message rejected by non-repudiation policy".to_string(),
+                ));
+            }
+            let from_leader = match (&sender_credential, self.groups[group_id].leader_key()) {
+                (Some(credential), Some(leader)) => {
+                    ct_eq(credential.signature_key().as_slice(), &leader)
+                }
+                _ => false,
+            };
+            if SignedLeaderOffline::is_leader_offline(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .filter(|_| from_leader)
+                    .ok_or_else(|| {
+                        NodeError::Unauthorized(
+                            "Leader notice from someone other than the leader".to_string(),
+                        )
+                    })?;
+                let notice = SignedLeaderOffline::decode(&bytes)?.verify(
+                    credential,
+                    &self.backend,
+                    group_id,
+                )?;
+                return self.leader_offline(group_id, notice);
+            }
+            if from_leader {
+                // The leader is back.
+                self.groups.get_mut(group_id).expect("group").successor = None;
+            }
+            if SignedAddressBook::is_address_book(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Address book without sender".to_string()))?;
+                return Ok(Some(ApplicationPayload::AddressBook(
+                    self.verify_address_book(group_id, &bytes, credential)?,
+                )));
+            }
+            if Capabilities::is_capabilities(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Capabilities without sender".to_string()))?;
+                let capabilities = Capabilities::decode(&bytes)?;
+                let key = credential.signature_key().as_slice().to_vec();
+                match ClientInfo::from_advert(&bytes) {
+                    Some(client) => self.member_clients.insert(key, client),
+                    None => self.member_clients.remove(&key),
+                };
+                let known = self
+                    .member_capabilities
+                    .insert(credential.signature_key().as_slice().to_vec(), capabilities)
+                    .is_some();
+                if !known {
+                    self.queue_advert(group_id);
+                }
+                return Ok(Some(ApplicationPayload::Capabilities {
+                    identity: credential_identity(credential),
+                    capabilities,
+                }));
+            }
+            if names::is_profile(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Profile without sender".to_string()))?;
+                let peer = PeerId::from_bytes(credential.identity()).map_err(|_| {
+                    NodeError::Other("Profile from a member without a PeerId".into())
+                })?;
+                let name = names::decode_profile(&bytes)?;
+                if !self.names.set_nickname(&peer, name.clone()) {
+                    return Ok(None);
+                }
+                return Ok(Some(ApplicationPayload::Named {
+                    identity: credential_identity(credential),
+                    name,
+                }));
+            }
+            if AdminRoster::is_roster(&bytes) {
+                let group = self.groups.get_mut(group_id).expect("group");
+                if !from_leader {
+                    return Err(NodeError::Unauthorized(
+                        "Admin roster from someone other than the leader".to_string(),
+                    ));
+                }
+                group.admins = AdminRoster::decode(&bytes)?;
+                let admins = admin_list(group);
+                self.journal.append(GroupEvent::AdminsSet {
+                    group_id: group_id.to_vec(),
+                    admins: admins.clone(),
+                });
+                return Ok(Some(ApplicationPayload::Admins(admins)));
+            }
+            if FeatureFlags::is_flags(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Feature flags without sender".to_string()))?;
+                let group = self.groups.get_mut(group_id).expect("group");
+                if group
+                    .rights_of(credential.signature_key().as_slice())
+                    .is_empty()
+                {
+                    return Err(NodeError::Unauthorized(format!(
+                        "Feature flags from {}, who is neither leader nor admin",
+                        credential_identity(credential)
+                    )));
+                }
+                group.features = FeatureFlags::decode(&bytes)?;
+                group.bind_metadata(&self.backend);
+                return Ok(Some(ApplicationPayload::Features(group.features)));
+            }
+            if GuestEntry::is_entry(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Guest entry without sender".to_string()))?;
+                let group = self.groups.get_mut(group_id).expect("group");
+                if !group
+                    .rights_of(credential.signature_key().as_slice())
+                    .contains(Rights::REMOVE)
+                {
+                    return Err(NodeError::Unauthorized(format!(
+                        "Guest entry from {}, who may not remove members",
+                        credential_identity(credential)
+                    )));
+                }
+                let entry = GuestEntry::decode(&bytes)?;
+                let remaining =
+                    Duration::from_secs(entry.expires.saturating_sub(unix_secs(SystemTime::now())));
+                let identity = entry.identity.clone();
+                group.guests.set(entry);
+                return Ok(Some(ApplicationPayload::GuestAccess {
+                    identity,
+                    remaining,
+                }));
+            }
+            if Backlog::is_backlog(&bytes) {
+                let backlog = Backlog::decode(&bytes)?;
+                let own = credential_identity(self.identity.key_package.credential());
+                if !backlog.recipients.contains(&own)
+                    || !self.groups[group_id].features.enabled(Feature::HistorySync)
+                {
+                    return Ok(None);
+                }
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Backlog without sender".to_string()))?;
+                return Ok(Some(ApplicationPayload::Backlog {
+                    from: credential_identity(credential),
+                    messages: backlog.messages,
+                    absence: backlog.absence,
+                }));
+            }
+            if ack::is_ack(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Acknowledgment without sender".to_string()))?;
+                self.deliveries
+                    .acked(ack::decode_ack(&bytes)?, credential_identity(credential));
+                return Ok(None);
+            }
+            if typing::is_typing(&bytes) {
+                let group = &self.groups[group_id];
+                if let (Some(credential), true) = (
+                    &sender_credential,
+                    group.features.enabled(Feature::Presence),
+                ) {
+                    self.events.push(NodeEvent::Typing {
+                        group: group.name.clone(),
+                        identity: credential_identity(credential),
+                    });
+                }
+                return Ok(None);
+            }
+            if ack::is_read(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("Read receipt without sender".to_string()))?;
+                let group = &self.groups[group_id];
+                if !group.allows_read_receipts() {
+                    return Ok(None);
+                }
+                let name = group.name.clone();
+                let reader = credential_identity(credential);
+                let id = ack::decode_read(&bytes)?;
+                if let Some(delivery) = self.deliveries.read(id, reader.clone()) {
+                    self.events.push(NodeEvent::ReadBy {
+                        group: name,
+                        id,
+                        seq: delivery.seq,
+                        reader,
+                    });
+                }
+                return Ok(None);
+            }
+            if ack::is_tracked(&bytes) {
+                let (id, text) = ack::untrack(&bytes)?;
+                let text = text.to_vec();
+                // Every copy is acknowledged, in case our earlier ack was lost.
+                self.queue_ack(group_id, id);
+                if !self.deliveries.first_receipt(id) {
+                    return Ok(None);
+                }
+                if self.read_receipts && self.groups[group_id].allows_read_receipts() {
+                    self.unread.push((group_id.to_vec(), id));
+                }
+                bytes = text;
+            }
+            if Offer::is_offer(&bytes) {
+                let credential = sender_credential
+                    .as_ref()
+                    .ok_or_else(|| NodeError::Other("File offer without sender".to_string()))?;
+                let offer = Offer::decode(&bytes)?;
+                let key = offer.key(&self.groups[group_id].mls_group, &self.backend)?;
+                let progress = self.transfers.offered(
+                    group_id,
+                    credential_identity(credential),
+                    offer,
+                    key,
+                )?;
+                return Ok(Some(ApplicationPayload::File(progress)));
+            }
+            if TelemetryFrame::is_telemetry(&bytes) {
+                return Ok(Some(ApplicationPayload::Telemetry(
+                    self.telemetry_decoders.decode(&bytes)?,
+                )));
+            }
+            let text = String::from_utf8(bytes)
+                .map_err(|_| NodeError::Other("Message is not valid UTF-8".to_string()))?;
+            if let Some(credential) = &sender_credential {
+                self.remember(group_id, credential_identity(credential), epoch, &text);
+            }
+            return Ok(Some(ApplicationPayload::Text(text)));
+        } else if let ProcessedMessage::ProposalMessage(proposal) = processed_message {
+            return self.handle_proposal(group_id, *proposal);
+        } else if let ProcessedMessage::StagedCommitMessage(staged_commit) = processed_message {
+            let group = self.groups.get_mut(group_id).expect("group");
+            // External commits add their sender, and need no rights.
+            if let (Sender::Member(_), Some(credential)) = (&sender, &sender_credential) {
+                let rights = group.rights_of(credential.signature_key().as_slice());
+                let adds = staged_commit.add_proposals().next().is_some();
+                let removes_others = staged_commit.remove_proposals().any(|proposal| {
+                    !matches!(proposal.sender(), Sender::Member(sender)
+                        if sender == proposal.remove_proposal().removed())
+                });
+                if (adds && !rights.contains(Rights::ADD))
+                    || (removes_others && !rights.contains(Rights::REMOVE))
+                {
+                    return Err(NodeError::Unauthorized(format!(
+                        "Refused a commit by {}, who may not change the membership that way",
+                        credential_identity(credential)
+                    )));
+                }
+            }
+            #[cfg(feature = "pki")]
+            if let Some(trust) = &self.pki {
+                for proposal in staged_commit.add_proposals() {
+                    trust
+                        .check(proposal.add_proposal().key_package(), SystemTime::now())
+                        .map_err(|e| NodeError::Other(format!("Refused a commit: {}", e)))?;
+                }
+            }
+            let removed: Vec<String> = staged_commit
+                .remove_proposals()
+                .filter_map(|proposal| {
+                    group
+                        .mls_group
+                        .member(proposal.remove_proposal().removed())
+                        .map(|member| credential_identity(member.credential()))
+                })
+                .collect();
+            // External commits add their sender without an Add proposal.
+            let members = group.mls_group.members().len();
+            let mut membership_changed = staged_commit.add_proposals().next().is_some()
+                || staged_commit.remove_proposals().next().is_some();
+            group
+                .mls_group
+                .merge_staged_commit(*staged_commit)
+                .map_err(|e| NodeError::Other(format!("Could not merge commit: {:?}", e)))?;
+            membership_changed |= group.mls_group.members().len() != members;
+            if !group.mls_group.is_active() {
+                self.drop_group(group_id);
+                return Ok(Some(ApplicationPayload::Removed));
+            }
+            if let Some(metadata) = metadata {
+                self.adopt_metadata(group_id, metadata);
+            }
+            let group = &self.groups[group_id];
+            let (name, epoch) = (group.name.clone(), group.mls_group.epoch().as_u64());
+            self.merged(group_id, membership_changed);
+            self.events.extend(
+                removed
+                    .into_iter()
+                    .map(|identity| NodeEvent::MemberRemoved {
+                        group: name.clone(),
+                        identity,
+                    }),
+            );
+            self.events
+                .push(NodeEvent::CommitApplied { group: name, epoch });
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peers;
+    use openmls::prelude::TlsSerializeTrait;
+
+    // Alice leads a group she added Bob and then Carol to, one commit each.
+    fn three_member_group() -> (Node, Node, Node) {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        (alice, bob, carol)
+    }
+
+    #[test]
+    fn smoke_test() {
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let mut bob = Node::default();
+        let bob_key_package = bob.get_key_package();
+        let serialized = bob_key_package.tls_serialize_detached().unwrap();
+        let bytes_array: &[u8] = &serialized;
+        let (_, welcome) = alice
+            .add_member_to_group(KeyPackage::try_from(bytes_array).unwrap())
+            .unwrap();
+        //bob.join_new_group(); TODO figure out why this causes an error
+        bob.join_existing_group(welcome).expect("");
+        let msg_out = alice.create_message("hi bob").unwrap();
+        let msg = bob.parse_message(msg_out).expect("message parsed").unwrap();
+        assert_eq!(msg, "hi bob");
+    }
+
+    #[test]
+    fn inspect_does_not_advance_ratchet() {
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let mut bob = Node::default();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        let inspection = alice.inspect_message("hi bob").unwrap();
+        assert_eq!(inspection.epoch, 1);
+        assert_eq!(inspection.generation, 0);
+        assert_eq!(inspection.padding_block, 100);
+        assert!(inspection.ciphertext_size > inspection.plaintext_size);
+
+        let msg_out = alice.create_message("hi bob").unwrap();
+        assert_eq!(
+            msg_out.tls_serialize_detached().unwrap().len(),
+            inspection.ciphertext_size
+        );
+        let msg = bob.parse_message(msg_out).unwrap().unwrap();
+        assert_eq!(msg, "hi bob");
+        assert_eq!(alice.inspect_message("hi bob").unwrap().generation, 1);
+    }
+
+    #[test]
+    fn telemetry_is_decoded_by_receiver() {
+        let mut sensor = Node::default();
+        sensor.join_new_group();
+        let mut gateway = Node::default();
+        let (_, welcome) = sensor
+            .add_member_to_group(gateway.get_key_package())
+            .unwrap();
+        gateway.join_existing_group(welcome).unwrap();
+
+        let msg_out = sensor
+            .create_telemetry_message(&TelemetryFrame::scalar_reading(3, -4.0))
+            .unwrap();
+        match gateway.parse_application_message(msg_out).unwrap() {
+            Some(ApplicationPayload::Telemetry(telemetry)) => {
+                assert_eq!(telemetry.schema, 1);
+                assert_eq!(telemetry.fields.len(), 3);
+            }
+            other => panic!("expected telemetry, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn non_repudiation_policy_records_receipts() {
+        let mut alice = Node::default();
+        alice
+            .join_new_group_with_policy(GroupPolicy {
+                non_repudiation: true,
+                ..GroupPolicy::default()
+            })
+            .unwrap();
+        let mut bob = Node::default();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        // Bob has not seen any traffic yet, so he still sends unsigned messages.
+        let unsigned = bob.create_message("too early").unwrap();
+        assert!(alice.parse_message(unsigned).is_err());
+
+        let msg_out = alice.create_message("on the record").unwrap();
+        assert_eq!(
+            bob.parse_message(msg_out).unwrap().unwrap(),
+            "on the record"
+        );
+        assert!(bob.group_policy().non_repudiation);
+        match &bob.audit_log().entries()[0] {
+            AuditEntry::SignedMessage {
+                signer, message, ..
+            } => {
+                assert_eq!(ack::text_of(message), b"on the record");
+                assert_eq!(
+                    *signer,
+                    credential_identity(alice.get_key_package().credential())
+                );
+            }
+        }
+
+        let reply = bob.create_message("agreed").unwrap();
+        assert_eq!(alice.parse_message(reply).unwrap().unwrap(), "agreed");
+        assert_eq!(alice.audit_log().entries().len(), 1);
+    }
+
+    #[test]
+    fn history_is_sealed_per_group() {
+        let policy = GroupPolicy {
+            non_repudiation: true,
+            ..GroupPolicy::default()
+        };
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        for name in ["work", "home"] {
+            alice.create_group(name, policy).unwrap();
+            let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+            bob.join_existing_group(welcome).unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("p2p-mls-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        bob.set_audit_log(AuditLog::with_path(path.clone()));
+        bob.seal_history("correct horse", 1).unwrap();
+
+        for name in ["work", "home"] {
+            let msg_out = alice.create_group_message(name, "on the record").unwrap();
+            bob.parse_message(msg_out).unwrap();
+        }
+        let file = std::fs::read(&path).unwrap();
+        assert!(!String::from_utf8_lossy(&file).contains("\"message\""));
+        let (entries, shut) = bob.read_history(&file).unwrap();
+        assert_eq!((entries.len(), shut), (2, 0));
+
+        // Once out of a group, its history stays shut.
+        let removal = alice
+            .remove_member(&credential_identity(bob.get_key_package().credential()))
+            .unwrap();
+        bob.parse_application_message(removal).unwrap();
+        let (entries, shut) = bob.read_history(&file).unwrap();
+        assert_eq!((entries.len(), shut), (1, 1));
+        assert_eq!(entries[0].group_id(), bob.groups()[0].group_id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn messages_are_kept_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-messages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (identity, history) = (dir.join("identity.sealed"), dir.join("history.jsonl"));
+        let mut alice = Node::default();
+        let mut bob = Node::with_sealed_identity(&identity, "correct horse", 1).unwrap();
+        assert!(bob.recent_messages(10).is_err());
+        bob.keep_messages(&history, "battery staple", 1).unwrap();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let hello = alice.create_message("hello bob").unwrap();
+        bob.parse_message(hello).unwrap();
+        bob.create_message("hi alice").unwrap();
+        assert!(!String::from_utf8_lossy(&std::fs::read(&history).unwrap()).contains("hello"));
+
+        let mut bob = Node::with_sealed_identity(&identity, "correct horse", 1).unwrap();
+        assert!(bob.keep_messages(&history, "wrong", 1).is_err());
+        bob.keep_messages(&history, "battery staple", 1).unwrap();
+        let messages = bob.recent_messages(10).unwrap();
+        let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["hello bob", "hi alice"]);
+        assert_eq!(
+            messages[0].sender,
+            credential_identity(alice.get_key_package().credential())
+        );
+        assert_eq!(messages[0].group, alice.active_group_name().unwrap());
+        assert_eq!(bob.recent_messages(1).unwrap()[0].text, "hi alice");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sealed_identity_survives_restarts() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-identity-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("identity.sealed");
+        let first = Node::with_sealed_identity(&path, "correct horse", 1).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(ProvisionedIdentity::is_sealed(&bytes));
+        assert!(schema::migrate_file(&path).is_err());
+
+        let mut second = Node::with_identity(&path, "correct horse").unwrap();
+        assert_eq!(
+            second.get_network_keypair().public(),
+            first.get_network_keypair().public()
+        );
+        assert_eq!(
+            second.get_key_package().credential(),
+            first.get_key_package().credential()
+        );
+        assert_ne!(second.get_key_package(), first.get_key_package());
+        // The restarted node can still join.
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(second.get_key_package()).unwrap();
+        second.join_existing_group(welcome).unwrap();
+
+        assert!(Node::with_identity(&path, "wrong horse").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_privacy_policy_strips_metadata() {
+        let mut alice = Node::default();
+        assert!(alice
+            .join_new_group_with_policy(GroupPolicy {
+                non_repudiation: true,
+                max_privacy: true,
+                ..GroupPolicy::default()
+            })
+            .is_err());
+        alice
+            .join_new_group_with_policy(GroupPolicy::max_privacy())
+            .unwrap();
+        let mut bob = Node::default();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        let msg_out = alice
+            .create_telemetry_message(&TelemetryFrame::scalar_reading(1, 2.0))
+            .unwrap();
+        match bob.parse_application_message(msg_out).unwrap() {
+            Some(ApplicationPayload::Telemetry(telemetry)) => match telemetry.fields[1].1 {
+                crate::telemetry::FieldValue::Unsigned(timestamp) => {
+                    assert_eq!(timestamp % TIMESTAMP_GRANULARITY_SECS as u64, 0)
+                }
+                _ => panic!("timestamp should be unsigned"),
+            },
+            other => panic!("expected telemetry, got {:?}", other),
+        }
+        assert!(bob.group_policy().max_privacy);
+        assert!(!bob.group_policy().allows_read_receipts());
+    }
+
+    #[test]
+    fn membership_proof_verifies_against_tree_hash() {
+        let mut alice = Node::default();
+        let bob = Node::default();
+        let carol = Node::default();
+        alice.join_new_group();
+        alice
+            .add_members_to_group(&[bob.get_key_package(), carol.get_key_package()])
+            .unwrap();
+
+        // The tree hash sits after version, ciphersuite, group id and epoch.
+        let group_state = alice
+            .group()
+            .unwrap()
+            .mls_group
+            .export_public_group_state(&alice.backend)
+            .unwrap()
+            .tls_serialize_detached()
+            .unwrap();
+        let tree_hash_at = 4 + group_state[3] as usize + 8;
+        let tree_hash_len = group_state[tree_hash_at] as usize;
+        let tree_hash = &group_state[tree_hash_at + 1..tree_hash_at + 1 + tree_hash_len];
+
+        let issuer = alice.get_key_package().credential().clone();
+        let verifier = Backend::default();
+        for member in [&bob, &carol] {
+            let identity = credential_identity(member.get_key_package().credential());
+            let proof = alice.membership_proof(&identity).unwrap();
+            assert_eq!(proof.tree_hash, tree_hash);
+            let statement = proof
+                .verify(&verifier, std::slice::from_ref(&issuer))
+                .unwrap();
+            assert_eq!(statement.identity, identity);
+            assert_eq!(statement.epoch, 1);
+        }
+
+        let identity = credential_identity(bob.get_key_package().credential());
+        let mut proof = alice.membership_proof(&identity).unwrap();
+        let untrusted = carol.get_key_package().credential().clone();
+        assert!(proof.verify(&verifier, &[untrusted]).is_err());
+        proof.epoch += 1;
+        assert!(proof.verify(&verifier, &[issuer]).is_err());
+    }
+
+    #[test]
+    fn recovery_takes_leadership_from_the_tree() {
+        let (mut alice, mut bob, mut carol) = three_member_group();
+        let dave = Node::default();
+        let (commit, _) = alice.add_member_to_group(dave.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+
+        // Carol lags behind and claims to lead, which would make her branch canonical.
+        carol.groups.values_mut().next().unwrap().is_group_leader = true;
+        let probe = carol.start_recovery().unwrap();
+        for member in [&mut alice, &mut bob] {
+            member.handle_recovery_message(probe.clone()).unwrap();
+        }
+        for member in [&alice, &bob] {
+            let report = member.recovery_report().unwrap();
+            assert_eq!(report.branches[0].has_leader, member.is_group_leader());
+            assert!(!report.branches[1].has_leader);
+            assert!(matches!(
+                report.action,
+                recovery::RecoveryAction::AwaitRejoin(ref members) if members.len() == 1
+            ));
+        }
+    }
+
+    #[test]
+    fn lagging_member_recovers_by_rejoining() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        let dave = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice
+            .add_members_to_group(&[bob.get_key_package(), carol.get_key_package()])
+            .unwrap();
+        bob.join_existing_group(welcome.clone()).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        // Carol misses the commit adding Dave, and is stuck in the old epoch.
+        let (commit, _) = alice.add_member_to_group(dave.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+
+        let probe = carol.start_recovery().unwrap();
+        for member in [&mut alice, &mut bob] {
+            let answer = member.handle_recovery_message(probe.clone()).unwrap();
+            assert!(carol
+                .handle_recovery_message(answer.unwrap())
+                .unwrap()
+                .is_none());
+        }
+        let report = carol.recovery_report().unwrap();
+        assert_eq!(report.fork_epoch, Some(1));
+        assert_eq!(report.action, recovery::RecoveryAction::Rejoin);
+        assert!(matches!(
+            alice.recovery_report().unwrap().action,
+            recovery::RecoveryAction::AwaitRejoin(ref members) if members.len() == 1
+        ));
+
+        // Re-admission replaces Carol's stale leaf instead of adding a second one.
+        let request = carol.rejoin().unwrap();
+        assert!(alice.is_join_target(&request));
+        let carol_peer = carol.get_network_keypair().public().to_peer_id();
+        let (commits, welcome) = alice.handle_join_request(&carol_peer, request).unwrap();
+        assert_eq!(commits.len(), 2);
+        for commit in commits {
+            bob.parse_message(commit).unwrap();
+        }
+        carol.join_existing_group(welcome).unwrap();
+        assert_eq!(alice.security_state().members, 4);
+
+        let msg = alice.create_message("welcome back").unwrap();
+        let msg_bytes = msg.tls_serialize_detached().unwrap();
+        assert_eq!(
+            carol.parse_message(msg).unwrap(),
+            Some("welcome back".to_string())
+        );
+        let msg = MlsMessageOut::try_from_bytes(&msg_bytes).unwrap();
+        assert_eq!(
+            bob.parse_message(msg).unwrap(),
+            Some("welcome back".to_string())
+        );
+    }
+
+    #[test]
+    fn members_share_address_books() {
+        let (mut alice, mut bob, carol) = three_member_group();
+
+        let peer = |node: &Node| node.get_network_keypair().public().to_peer_id();
+        let address = |port: u16| format!("/ip4/10.0.0.1/tcp/{}", port).parse().unwrap();
+        alice.peers_mut().listening(address(1));
+        alice.peers_mut().dialed(peer(&carol), address(3));
+        alice.peers_mut().dialed(PeerId::random(), address(4));
+        assert!(alice.create_address_book_message().is_err());
+
+        alice.set_address_sharing(true);
+        let msg = alice.create_address_book_message().unwrap();
+        let book = match bob.parse_application_message(msg).unwrap() {
+            Some(ApplicationPayload::AddressBook(book)) => book,
+            other => panic!("expected an address book, got {:?}", other),
+        };
+        let mut peers = book.peers();
+        peers.sort_by_key(|(peer, _)| peer.to_string());
+        let mut expected = vec![
+            (peer(&alice), vec![address(1)]),
+            (peer(&carol), vec![address(3)]),
+        ];
+        expected.sort_by_key(|(peer, _)| peer.to_string());
+        assert_eq!(peers, expected);
+    }
+
+    #[test]
+    fn group_topic_rotates_with_membership() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        let first = alice.group_topics().to_vec();
+        assert_eq!(first.len(), 1);
+        assert_ne!(first[0], crate::peers::RENDEZVOUS_TOPIC);
+
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        assert_eq!(bob.group_topics().last(), alice.group_topics().last());
+        assert_ne!(alice.group_topics().last(), first.last());
+
+        let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        let commit_frame = WireMessage::from(commit.clone()).encode().unwrap();
+        assert_eq!(
+            crate::peers::publish_topics(&commit_frame, alice.group_topics()).len(),
+            3
+        );
+        bob.parse_message(commit).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        assert_eq!(bob.group_topics().len(), 2);
+        assert_eq!(bob.group_topics().last(), alice.group_topics().last());
+        assert_eq!(carol.group_topics().last(), alice.group_topics().last());
+    }
+
+    #[test]
+    fn leader_removes_member() {
+        let (mut alice, mut bob, mut carol) = three_member_group();
+
+        let bob_identity = credential_identity(bob.get_key_package().credential());
+        assert!(carol.remove_member(&bob_identity).is_err());
+        assert!(alice.remove_member("nobody").is_err());
+        let commit = alice.remove_member(&bob_identity).unwrap();
+        let commit_bytes = commit.tls_serialize_detached().unwrap();
+        carol.parse_message(commit).unwrap();
+        assert!(!carol.is_member_peer(&bob.get_network_keypair().public().to_peer_id()));
+
+        let commit = MlsMessageOut::try_from_bytes(&commit_bytes).unwrap();
+        assert!(matches!(
+            bob.parse_application_message(commit),
+            Ok(Some(ApplicationPayload::Removed))
+        ));
+        assert!(!bob.in_group());
+
+        let msg = alice.create_message("after bob").unwrap();
+        assert_eq!(carol.parse_message(msg).unwrap().unwrap(), "after bob");
+    }
+
+    #[test]
+    fn admins_change_membership_as_far_as_the_leader_allows() {
+        let (mut alice, mut bob, mut carol) = three_member_group();
+        let (dave, mut erin, frank) = (Node::default(), Node::default(), Node::default());
+
+        let bob_identity = credential_identity(bob.get_key_package().credential());
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+        assert!(bob.set_rights(&carol_identity, Rights::ALL).is_err());
+        let roster = alice.set_rights(&bob_identity, Rights::ADD).unwrap();
+        for node in [&mut bob, &mut carol] {
+            assert_eq!(
+                node.parse_application_message(roster.clone()).unwrap(),
+                Some(ApplicationPayload::Admins(vec![(
+                    bob_identity.clone(),
+                    Rights::ADD
+                )]))
+            );
+        }
+        // Rosters only count from the leader.
+        let forged = carol
+            .create_application_message(&roster_bytes(&bob))
+            .unwrap();
+        assert!(alice.parse_application_message(forged).is_err());
+
+        // Bob may add, but not remove.
+        assert!(bob.remove_member(&carol_identity).is_err());
+        let (commit, _) = bob.add_member_to_group(dave.get_key_package()).unwrap();
+        alice.parse_message(commit.clone()).unwrap();
+        carol.parse_message(commit).unwrap();
+
+        // Newcomers the leader adds learn the roster.
+        let (commit, welcome) = alice.add_member_to_group(erin.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        erin.join_existing_group(welcome).unwrap();
+        let received: Vec<_> = alice
+            .take_adverts()
+            .into_iter()
+            .filter_map(|advert| erin.parse_application_message(advert).unwrap())
+            .collect();
+        assert!(received.contains(&ApplicationPayload::Admins(vec![(
+            bob_identity.clone(),
+            Rights::ADD
+        )])));
+        let members = erin.list_members().unwrap();
+        assert!(members[0].is_leader);
+        assert_eq!(members[1].rights, Rights::ADD);
+
+        // A commit by a member without the right is refused.
+        let (commit, _) = carol.add_member_to_group(frank.get_key_package()).unwrap();
+        assert!(alice.parse_message(commit).is_err());
+    }
+
+    #[test]
+    fn leader_going_offline_hands_over_to_a_successor() {
+        let (mut alice, mut bob, mut carol) = three_member_group();
+        let mut dave = Node::default();
+
+        // Dave's request waits for alice, who then exits.
+        let dave_peer = PeerId::from(dave.get_network_keypair().public());
+        let request = dave.create_join_request().unwrap();
+        let frame = WireMessage::KeyPackage(request).encode().unwrap();
+        assert!(matches!(
+            alice.handle_incoming(&dave_peer, &frame).as_slice(),
+            [NodeEvent::JoinRequested { .. }]
+        ));
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+        let roster = alice.set_rights(&carol_identity, Rights::ADD).unwrap();
+        bob.parse_application_message(roster.clone()).unwrap();
+        carol.parse_application_message(roster).unwrap();
+
+        let messages = alice.go_offline().unwrap();
+        assert_eq!(messages.len(), 2);
+        for node in [&mut bob, &mut carol] {
+            let received: Vec<_> = messages
+                .iter()
+                .map(|msg_out| node.parse_application_message(msg_out.clone()).unwrap())
+                .collect();
+            assert_eq!(
+                received[1],
+                Some(ApplicationPayload::LeaderOffline {
+                    successor: carol_identity.clone(),
+                    joins: 1,
+                })
+            );
+        }
+        assert_eq!(bob.pending_joins().count(), 0);
+        assert_eq!(carol.pending_joins().count(), 1);
+        assert_eq!(carol.list_members().unwrap()[2].rights, Rights::ALL);
+
+        // Carol now adds dave, and bob takes the commit.
+        let id = carol.pending_joins().next().unwrap().id;
+        carol.accept_join(id).unwrap();
+        let outgoing = carol.take_outgoing();
+        let commit = outgoing
+            .into_iter()
+            .find_map(|message| match message {
+                WireMessage::MlsMessage(msg_out) => Some(msg_out),
+                _ => None,
+            })
+            .unwrap();
+        bob.parse_message(commit).unwrap();
+        let (_, welcome) = carol.take_direct().pop().unwrap();
+        match welcome {
+            WireMessage::Welcome(invite) => {
+                dave.join_existing_group(invite).unwrap();
+            }
+            _ => panic!("expected a Welcome"),
+        }
+
+        // Members lead nothing to hand over.
+        assert!(bob.go_offline().unwrap().is_empty());
+    }
+
+    #[test]
+    fn proposals_are_batched_into_one_commit() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        let mut dave = Node::default();
+        let erin = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice
+            .add_members_to_group(&[bob.get_key_package(), erin.get_key_package()])
+            .unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        assert!(alice.commit_pending_proposals().is_err());
+        assert!(bob.propose_add(carol.get_key_package()).is_err());
+
+        let erin_identity = credential_identity(erin.get_key_package().credential());
+        let proposals = [
+            alice.propose_add(carol.get_key_package()).unwrap(),
+            alice.propose_add(dave.get_key_package()).unwrap(),
+            alice.propose_remove(&erin_identity).unwrap(),
+        ];
+        for proposal in proposals {
+            assert!(matches!(
+                bob.parse_application_message(proposal).unwrap(),
+                Some(ApplicationPayload::Proposal(_))
+            ));
+        }
+        let kinds: Vec<ProposalKind> = alice
+            .pending_proposals()
+            .unwrap()
+            .iter()
+            .map(|proposal| proposal.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [ProposalKind::Add, ProposalKind::Add, ProposalKind::Remove]
+        );
+        assert_eq!(bob.pending_proposals().unwrap().len(), 3);
+        let info = alice.group_info().unwrap();
+        assert_eq!((info.epoch, info.pending_proposals), (1, 3));
+        assert_eq!(info.ciphersuite, crate::crypto::CIPHERSUITE);
+
+        let commit = alice.commit_pending_proposals().unwrap();
+        bob.parse_message(commit).unwrap();
+        // One Welcome, addressed to each newcomer.
+        assert!(alice.take_invites().is_empty());
+        let invites: Vec<Invite> = alice
+            .take_direct()
+            .into_iter()
+            .map(|(_, message)| match message {
+                WireMessage::Welcome(invite) => invite,
+                other => panic!("not an invite: {:?}", other),
+            })
+            .collect();
+        assert_eq!(invites.len(), 2);
+        carol.join_existing_group(invites[0].clone()).unwrap();
+        dave.join_existing_group(invites[1].clone()).unwrap();
+        let info = alice.group_info().unwrap();
+        assert_eq!((info.epoch, info.members), (2, 4));
+        assert!(alice.pending_proposals().unwrap().is_empty());
+        let msg = alice.create_message("one commit").unwrap();
+        for node in [&mut bob, &mut carol, &mut dave] {
+            assert_eq!(
+                node.parse_message(msg.clone()).unwrap().unwrap(),
+                "one commit"
+            );
+        }
+    }
+
+    // A roster making `node` an admin with every right.
+    fn roster_bytes(node: &Node) -> Vec<u8> {
+        let mut roster = AdminRoster::default();
+        roster.set(
+            node.get_key_package()
+                .credential()
+                .signature_key()
+                .as_slice(),
+            Rights::ALL,
+        );
+        roster.encode()
+    }
+
+    // The journal replays to the groups the node is actually in.
+    fn assert_journal_matches(node: &Node) {
+        let journal = node.journal();
+        let replayed = journal.replay(journal.entries().len() as u64);
+        assert_eq!(&replayed, journal.groups());
+        assert_eq!(replayed.groups.len(), node.groups().len());
+        for info in node.groups() {
+            let view = replayed.get(&info.group_id).unwrap();
+            assert_eq!(view.name, info.name);
+            assert_eq!(view.epoch, info.epoch);
+            assert_eq!(
+                view.members,
+                member_identities(&node.groups[&info.group_id].mls_group)
+            );
+            assert_eq!(
+                replayed.active.as_ref() == Some(&info.group_id),
+                info.active
+            );
+        }
+    }
+
+    #[test]
+    fn journal_replays_every_change_to_our_groups() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.create_group("work", GroupPolicy::default()).unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+        alice.set_rights(&carol_identity, Rights::ADD).unwrap();
+        let before_removal = alice.journal().entries().len() as u64;
+        let commit = alice
+            .remove_member(&credential_identity(bob.get_key_package().credential()))
+            .unwrap();
+        carol.parse_message(commit).unwrap();
+        alice.create_group("home", GroupPolicy::default()).unwrap();
+        for node in [&alice, &bob, &carol] {
+            assert_journal_matches(node);
+        }
+
+        let removal = &alice.journal().entries()[before_removal as usize];
+        assert!(matches!(&removal.event, GroupEvent::Merged { removed, .. } if removed.len() == 1));
+        let work = alice
+            .groups()
+            .into_iter()
+            .find(|g| g.name == "work")
+            .unwrap()
+            .group_id;
+        let earlier = alice.journal().replay(before_removal);
+        assert_eq!(earlier.get(&work).unwrap().members.len(), 3);
+        assert_eq!(
+            earlier.get(&work).unwrap().admins,
+            vec![(carol_identity, Rights::ADD)]
+        );
+        assert_eq!(earlier.active, Some(work));
+        assert_eq!(alice.active_group_name().as_deref(), Some("home"));
+
+        let sections: HashMap<_, _> = alice.backup_sections().unwrap().into_iter().collect();
+        let restored = Node::from_backup(&sections).unwrap();
+        assert_eq!(restored.journal().entries(), alice.journal().entries());
+        assert_journal_matches(&restored);
+    }
+
+    #[test]
+    fn member_leaves_group() {
+        let (mut alice, mut bob, mut carol) = three_member_group();
+        assert!(alice.leave_group().is_err());
+
+        let proposal = bob.leave_group().unwrap();
+        assert!(!bob.in_group());
+        let bytes = proposal.tls_serialize_detached().unwrap();
+        let commit = match alice.parse_application_message(proposal).unwrap() {
+            Some(ApplicationPayload::Left {
+                commit: Some(commit),
+                ..
+            }) => commit,
+            other => panic!("expected a commit for the leave, got {:?}", other),
+        };
+        let proposal = MlsMessageOut::try_from_bytes(&bytes).unwrap();
+        assert!(matches!(
+            carol.parse_application_message(proposal),
+            Ok(Some(ApplicationPayload::Left { commit: None, .. }))
+        ));
+        carol.parse_message(commit).unwrap();
+        assert!(!carol.is_member_peer(&bob.get_network_keypair().public().to_peer_id()));
+        let msg = alice.create_message("without bob").unwrap();
+        assert_eq!(carol.parse_message(msg).unwrap().unwrap(), "without bob");
+    }
+
+    #[test]
+    fn member_updates_its_keys() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        assert!(bob.self_update().is_err());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let leaf_keys = |node: &Node| -> Vec<Vec<u8>> {
+            let group = &node.groups[node.journal.groups().active.as_ref().unwrap()];
+            group
+                .mls_group
+                .members()
+                .iter()
+                .map(|kp| kp.tls_serialize_detached().unwrap())
+                .collect()
+        };
+        let before = leaf_keys(&alice);
+
+        let commit = bob.self_update().unwrap();
+        assert_eq!(alice.parse_message(commit).unwrap(), None);
+        assert_eq!(alice.group_info().unwrap().epoch, 2);
+        assert_eq!(bob.group_info().unwrap().epoch, 2);
+        assert_ne!(leaf_keys(&alice), before);
+        assert_eq!(leaf_keys(&alice), leaf_keys(&bob));
+        let msg = bob.create_message("fresh keys").unwrap();
+        assert_eq!(alice.parse_message(msg).unwrap().unwrap(), "fresh keys");
+        let msg = alice.create_message("still here").unwrap();
+        assert_eq!(bob.parse_message(msg).unwrap().unwrap(), "still here");
+    }
+
+    #[test]
+    fn newcomer_joins_by_external_commit() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        // Bob shares the group while Alice, the leader, is away.
+        let frame = WireMessage::from(bob.share_group().unwrap())
+            .encode()
+            .unwrap();
+        let shared = match WireMessage::decode(&frame).unwrap() {
+            WireMessage::Control(protocol::ControlMessage::SharedGroup(shared)) => shared,
+            other => panic!("expected a shared group, got {:?}", other),
+        };
+        assert!(carol.join_shared_group(None).is_err());
+        assert!(!bob.record_shared_group(shared.clone()));
+        assert!(carol.record_shared_group(shared));
+        let (name, commit) = carol.join_shared_group(None).unwrap();
+        assert_eq!(name, DEFAULT_GROUP_NAME);
+        let frame = WireMessage::from(commit.clone()).encode().unwrap();
+        assert!(carol.frame_topics(&frame).is_empty());
+        assert_eq!(
+            peers::publish_topics(&frame, &carol.frame_topics(&frame)),
+            vec![peers::RENDEZVOUS_TOPIC.to_string()]
+        );
+
+        let bytes = commit.tls_serialize_detached().unwrap();
+        assert_eq!(bob.parse_message(commit).unwrap(), None);
+        assert_eq!(bob.group_info().unwrap().members, 3);
+        assert_eq!(bob.group_topics().last(), carol.group_topics().last());
+        let msg = carol.create_message("no leader needed").unwrap();
+        assert_eq!(bob.parse_message(msg).unwrap().unwrap(), "no leader needed");
+
+        let commit = MlsMessageOut::try_from_bytes(&bytes).unwrap();
+        assert_eq!(alice.parse_message(commit).unwrap(), None);
+        let msg = alice.create_message("welcome carol").unwrap();
+        assert_eq!(carol.parse_message(msg).unwrap().unwrap(), "welcome carol");
+    }
+
+    #[test]
+    fn groups_with_a_psk_shut_out_members_without_it() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        let psk = alice.psk_from_passphrase("correct horse", 1).unwrap();
+        carol.add_psk(carol.psk_from_passphrase("correct horse", 1).unwrap());
+        bob.add_psk(bob.psk_from_passphrase("battery staple", 1).unwrap());
+        let id = alice.inject_psk(psk).unwrap();
+        assert_eq!(alice.group_policy().psk_id, Some(id));
+        let (_, welcome) = alice
+            .add_members_to_group(&[bob.get_key_package(), carol.get_key_package()])
+            .unwrap();
+        bob.join_existing_group(welcome.clone()).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+
+        // Bob has not heard of the PSK yet, so he sends in the clear.
+        let unsealed = bob.create_message("hi").unwrap();
+        assert!(alice
+            .parse_application_message(unsealed)
+            .unwrap_err()
+            .to_string()
+            .contains("Unsealed"));
+
+        let sealed = alice.create_message("members only").unwrap();
+        assert_eq!(
+            carol.parse_application_message(sealed.clone()).unwrap(),
+            Some(ApplicationPayload::Text("members only".to_string()))
+        );
+        let refused = bob.parse_application_message(sealed).unwrap_err();
+        assert!(refused.to_string().contains(&hex_encode(&id)));
+        assert!(bob.create_message("let me in").is_err());
+
+        let reply = carol.create_message("welcome").unwrap();
+        assert_eq!(
+            alice.parse_application_message(reply).unwrap(),
+            Some(ApplicationPayload::Text("welcome".to_string()))
+        );
+        assert!(carol
+            .inject_psk(carol.psk_from_passphrase("x", 1).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn policies_come_only_from_the_leader_and_never_drop_the_psk() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice
+            .join_new_group_with_policy(GroupPolicy {
+                non_repudiation: true,
+                ..GroupPolicy::default()
+            })
+            .unwrap();
+        for node in [&mut bob, &mut carol] {
+            node.add_psk(node.psk_from_passphrase("correct horse", 1).unwrap());
+        }
+        let id = alice
+            .inject_psk(alice.psk_from_passphrase("correct horse", 1).unwrap())
+            .unwrap();
+        let (_, welcome) = alice
+            .add_members_to_group(&[bob.get_key_package(), carol.get_key_package()])
+            .unwrap();
+        bob.join_existing_group(welcome.clone()).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        let hello = alice.create_message("hello").unwrap();
+        bob.parse_application_message(hello.clone()).unwrap();
+        carol.parse_application_message(hello).unwrap();
+        let policy = carol.group_policy();
+        assert_eq!(policy.psk_id, Some(id));
+
+        // Bob has no rights, so his downgrade is not taken.
+        let downgrade = |node: &mut Node| {
+            let group = node.groups.values_mut().next().unwrap();
+            group.adopt_policy(GroupPolicy::default(), &node.backend);
+        };
+        downgrade(&mut bob);
+        let unsealed = bob.create_message("in the clear").unwrap();
+        assert!(carol.parse_application_message(unsealed).is_err());
+        assert_eq!(carol.group_policy(), policy);
+
+        // Not even the leader drops the pre-shared key.
+        downgrade(&mut alice);
+        let unsealed = alice.create_message("in the clear").unwrap();
+        assert!(carol.parse_application_message(unsealed).is_err());
+        assert_eq!(carol.group_policy(), policy);
+    }
+
+    #[test]
+    fn epoch_hooks_see_every_merged_commit() {
+        use std::sync::{Arc, Mutex};
+
+        type Seen = Arc<Mutex<Vec<(u64, Vec<String>, Vec<u8>)>>>;
+        fn record(node: &mut Node) -> Seen {
+            let seen = Seen::default();
+            let sink = Arc::clone(&seen);
+            node.on_epoch_change(move |change| {
+                let token = change.export_secret("app token", b"", 16).unwrap();
+                sink.lock()
+                    .unwrap()
+                    .push((change.epoch, change.removed.to_vec(), token));
+            });
+            seen
+        }
+
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let carol = Node::default();
+        let alice_seen = record(&mut alice);
+        let bob_seen = record(&mut bob);
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let (commit, _) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+        let commit = alice.remove_member(&carol_identity).unwrap();
+        bob.parse_message(commit).unwrap();
+
+        let alice_seen = alice_seen.lock().unwrap();
+        assert_eq!(alice_seen.len(), 3);
+        assert_eq!(alice_seen[2].1, vec![carol_identity]);
+        // Joining by Welcome merges no commit of ours.
+        assert_eq!(*bob_seen.lock().unwrap(), alice_seen[1..]);
+    }
+
+    #[test]
+    fn nicknames_travel_with_capabilities() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        assert!(bob.set_nickname("bob smith").is_err());
+        bob.set_nickname("bob").unwrap();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        let hello = alice.create_message("hello").unwrap();
+        bob.parse_application_message(hello).unwrap();
+        let received: Vec<_> = bob
+            .take_adverts()
+            .into_iter()
+            .map(|advert| alice.parse_application_message(advert).unwrap())
+            .collect();
+        let bob_identity = credential_identity(bob.get_key_package().credential());
+        assert_eq!(
+            received[1],
+            Some(ApplicationPayload::Named {
+                identity: bob_identity,
+                name: "bob".to_string()
+            })
+        );
+        let bob_peer = PeerId::from(bob.get_network_keypair().public());
+        assert_eq!(alice.display_name(&bob_peer), "bob");
+        assert_eq!(
+            alice.list_members().unwrap()[1].name.as_deref(),
+            Some("bob")
+        );
+    }
+
+    #[test]
+    fn joiners_are_caught_up_under_a_retention_policy() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        alice.create_message("before retention").unwrap();
+        alice
+            .set_retention(Retention::new(10, Some(Duration::from_secs(3600))).unwrap())
+            .unwrap();
+        alice.create_message("one").unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let backlog = alice.take_adverts().remove(0);
+        assert!(matches!(
+            bob.parse_application_message(backlog),
+            Ok(Some(ApplicationPayload::Backlog { messages, .. })) if messages.len() == 1
+        ));
+        let two = bob.create_message("two").unwrap();
+        alice.parse_message(two).unwrap();
+        alice.take_adverts();
+
+        let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        let backlog = alice.take_adverts().remove(0);
+        let backlog_bytes = backlog.tls_serialize_detached().unwrap();
+        match carol.parse_application_message(backlog) {
+            Ok(Some(ApplicationPayload::Backlog { from, messages, .. })) => {
+                assert_eq!(
+                    from,
+                    credential_identity(alice.get_key_package().credential())
+                );
+                let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+                assert_eq!(texts, ["one", "two"]);
+                assert_eq!(
+                    messages[1].sender,
+                    credential_identity(bob.get_key_package().credential())
+                );
+            }
+            payload => panic!("{:?}", payload),
+        }
+        // Only the joiners take it.
+        let backlog = MlsMessageOut::try_from_bytes(&backlog_bytes).unwrap();
+        assert_eq!(bob.parse_application_message(backlog).unwrap(), None);
+
+        let mut dave = Node::default();
+        dave.create_group("private", GroupPolicy::max_privacy())
+            .unwrap();
+        assert!(dave
+            .set_retention(Retention::new(10, None).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn members_added_back_are_told_of_their_absence_as_disclosed() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        alice
+            .set_retention(Retention::new(10, None).unwrap())
+            .unwrap();
+        alice.set_disclosure(Disclosure::Summary).unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        alice.create_message("while bob is here").unwrap();
+
+        let bob_id = credential_identity(bob.get_key_package().credential());
+        let commit = alice.remove_member(&bob_id).unwrap();
+        bob.parse_message(commit).unwrap();
+        alice.create_message("while bob is away").unwrap();
+        alice.take_adverts();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        match bob.parse_application_message(alice.take_adverts().remove(0)) {
+            Ok(Some(ApplicationPayload::Backlog {
+                messages,
+                absence: Some(absence),
+                ..
+            })) => {
+                let texts: Vec<_> = messages.iter().map(|m| m.text.as_str()).collect();
+                assert_eq!(texts, ["while bob is here"]);
+                assert_eq!(absence.messages, 1);
+            }
+            payload => panic!("{:?}", payload),
+        }
+    }
+
+    #[test]
+    fn admins_switch_features_for_the_whole_group() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        alice
+            .set_retention(Retention::new(10, None).unwrap())
+            .unwrap();
+        alice.create_message("kept").unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        alice.take_adverts();
+        let group_id = bob.group().unwrap().group_id();
+        assert!(bob.feature_enabled(&group_id, Feature::HistorySync));
+        assert!(bob.set_feature(Feature::HistorySync, false).is_err());
+
+        let flags = alice.set_feature(Feature::HistorySync, false).unwrap();
+        assert_eq!(
+            bob.parse_application_message(flags).unwrap(),
+            Some(ApplicationPayload::Features(alice.features().unwrap()))
+        );
+        assert!(!bob.feature_enabled(&group_id, Feature::HistorySync));
+
+        // Carol learns the flags instead of the history.
+        let (_, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+        match &alice.take_adverts()[..] {
+            [flags] => {
+                let flags = MlsMessageOut::try_from_bytes(&flags.tls_serialize_detached().unwrap())
+                    .unwrap();
+                carol.parse_application_message(flags).unwrap();
+            }
+            adverts => panic!("{:?}", adverts),
+        }
+        assert_eq!(
+            carol.features().unwrap().to_string(),
+            "reactions on, history-sync off, presence on, read-receipts on"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "pki")]
+    fn pki_roots_keep_out_uncertified_members() {
+        let ca = crate::pki::tests::TestCa::new();
+        let certified = || {
+            let mut node = Node::default();
+            let chain = ca.certify(node.get_key_package().credential());
+            node.set_certificate(chain).unwrap();
+            node
+        };
+        let mut alice = certified();
+        let mut bob = certified();
+        let mut carol = Node::default();
+        assert!(carol
+            .set_certificate(ca.certify(bob.get_key_package().credential()))
+            .is_err());
+        bob.set_pki_trust(ca.trust());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        // Bob refuses a commit adding Carol, whom Alice does not check.
+        let (commit, _) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        assert!(matches!(
+            bob.parse_message(commit),
+            Err(NodeError::Other(e)) if e.starts_with("Refused a commit")
+        ));
+
+        let mut dave = certified();
+        dave.set_pki_trust(ca.trust());
+        dave.join_new_group();
+        assert!(dave.add_member_to_group(carol.get_key_package()).is_err());
+        let mut erin = certified();
+        let (_, welcome) = dave.add_member_to_group(erin.get_key_package()).unwrap();
+        erin.join_existing_group(welcome).unwrap();
+    }
+
+    #[test]
+    fn admins_see_what_members_run() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.set_client_info(Some(ClientInfo::new("0.2.0", "linux").unwrap()));
+        bob.set_client_info(Some(ClientInfo::new("0.1.0", "macos").unwrap()));
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.parse_message(commit).unwrap();
+        carol.join_existing_group(welcome).unwrap();
+
+        let hello = alice.create_message("hello").unwrap();
+        let hello_bytes = hello.tls_serialize_detached().unwrap();
+        bob.parse_message(hello).unwrap();
+        carol
+            .parse_message(MlsMessageOut::try_from_bytes(&hello_bytes).unwrap())
+            .unwrap();
+        for advert in bob.take_adverts().into_iter().chain(carol.take_adverts()) {
+            alice.parse_application_message(advert).unwrap();
+        }
+        let stats = alice.client_stats().unwrap();
+        assert_eq!(stats.members, 3);
+        assert_eq!(stats.versions.len(), 2);
+        assert_eq!(stats.platforms["macos"], 1);
+        assert_eq!(stats.undisclosed, 1);
+        assert!(bob.client_stats().is_err());
+    }
+
+    #[test]
+    fn members_learn_each_others_capabilities() {
+        use crate::capabilities::Capability;
+
+        let mut alice = Node::default();
+        let mut bob = Node {
+            capabilities: Capabilities::default().with(Capability::Reactions),
+            ..Node::default()
+        };
+        alice.join_new_group();
+        let group_id = alice.journal.groups().active.clone().unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        assert!(!alice.group_supports(&group_id, Capability::Reactions));
+
+        // Bob advertises once he has heard from the group, and Alice answers.
+        assert!(bob.take_adverts().is_empty());
+        let hello = alice.create_message("hello").unwrap();
+        bob.parse_application_message(hello).unwrap();
+        let adverts = bob.take_adverts();
+        assert_eq!(adverts.len(), 1);
+        assert!(matches!(
+            alice.parse_application_message(adverts.into_iter().next().unwrap()),
+            Ok(Some(ApplicationPayload::Capabilities { capabilities, .. }))
+                if capabilities.contains(Capability::Reactions)
+        ));
+        assert!(alice.group_supports(&group_id, Capability::Reactions));
+        assert!(!alice.group_supports(&group_id, Capability::FileTransfer));
+        let reply = alice.take_adverts();
+        assert_eq!(reply.len(), 1);
+        assert!(matches!(
+            bob.parse_application_message(reply.into_iter().next().unwrap()),
+            Ok(Some(ApplicationPayload::Capabilities { capabilities, .. }))
+                if capabilities == capabilities::SUPPORTED
+        ));
+
+        // Both have advertised this epoch already.
+        assert!(bob.take_adverts().is_empty());
+        assert!(!bob.group_supports(&group_id, Capability::Reactions));
+        let members = bob.list_members().unwrap();
+        assert_eq!(members[0].capabilities, Some(capabilities::SUPPORTED));
+        assert_eq!(
+            members[1].capabilities,
+            Some(Capabilities::default().with(Capability::Reactions))
+        );
+
+        // Bits from newer builds survive.
+        let mut newer = Capabilities::default().with(Capability::CrdtKv).encode();
+        newer[1] = 0x80;
+        newer.push(7);
+        let decoded = Capabilities::decode(&newer).unwrap();
+        assert!(decoded.contains(Capability::CrdtKv));
+        assert_eq!(decoded.encode(), newer[..5]);
+        assert!(Capabilities::decode(&newer[..3]).is_err());
+    }
+
+    #[test]
+    fn unhealthy_groups_warn_block_or_hold() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let group_id = alice.journal.groups().active.clone().unwrap();
+        assert!(alice.group_health(&group_id).is_healthy());
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        assert_eq!(
+            alice.group_health(&group_id).issues,
+            vec![HealthIssue::NoMembersConnected]
+        );
+        assert!(matches!(
+            alice.send_text(None, "into the void"),
+            Ok(SendOutcome::Sent { health, .. }) if !health.is_healthy()
+        ));
+
+        alice.set_send_policy(SendPolicy::Queue, false);
+        assert!(matches!(
+            alice.send_text(None, "held"),
+            Ok(SendOutcome::Held(_))
+        ));
+        assert!(alice.release_held().is_empty());
+        let address = "/ip4/10.0.0.1/tcp/1".parse().unwrap();
+        alice
+            .peers_mut()
+            .connected(bob.get_network_keypair().public().to_peer_id(), address);
+        let released = alice.release_held();
+        assert_eq!(released.len(), 1);
+        let msg = MlsMessageOut::try_from_bytes(&released[0].tls_serialize_detached().unwrap());
+        assert_eq!(bob.parse_message(msg.unwrap()).unwrap().unwrap(), "held");
+
+        // Bob's traffic from an epoch we have not reached yet.
+        alice.set_send_policy(SendPolicy::Block, false);
+        let commit = bob.self_update().unwrap();
+        let msg = bob.create_message("from epoch 2").unwrap();
+        assert_eq!(alice.parse_message(msg).unwrap(), None);
+        assert_eq!(
+            alice.group_health(&group_id).issues,
+            vec![HealthIssue::Behind {
+                epoch: 1,
+                observed: 2
+            }]
+        );
+        assert!(alice.send_text(None, "blocked").is_err());
+        alice.parse_message(commit).unwrap();
+        assert!(alice.group_health(&group_id).is_healthy());
+        assert!(matches!(
+            alice.send_text(None, "caught up"),
+            Ok(SendOutcome::Sent { .. })
+        ));
+    }
+
+    #[test]
+    fn roster_lists_members_by_leaf() {
+        assert!(Node::default().list_members().is_err());
+        let (mut alice, bob, mut carol) = three_member_group();
+        let bob_identity = credential_identity(bob.get_key_package().credential());
+        let commit = alice.remove_member(&bob_identity).unwrap();
+        carol.parse_message(commit).unwrap();
+
+        let members = carol.list_members().unwrap();
+        let peer = |node: &Node| Some(node.get_network_keypair().public().to_peer_id());
+        let leaves: Vec<_> = members
+            .iter()
+            .map(|m| (m.leaf_index, m.peer_id, m.is_us))
+            .collect();
+        assert_eq!(
+            leaves,
+            vec![(0, peer(&alice), false), (2, peer(&carol), true)]
+        );
+        assert!(members[1].to_string().ends_with("(verified) (us)"));
+        let info = carol.group_info().unwrap();
+        assert_eq!(info.name, DEFAULT_GROUP_NAME);
+        assert_eq!(info.group_id.len(), crate::crypto::GROUP_ID_LEN);
+        assert_eq!((info.epoch, info.own_leaf_index, info.members), (3, 2, 2));
+        assert!(!info.is_leader && info.active);
+        assert!(alice.group_info().unwrap().is_leader);
+    }
+
+    #[test]
+    fn groups_run_side_by_side() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.create_group("work", GroupPolicy::default()).unwrap();
+        assert!(alice.create_group("work", GroupPolicy::default()).is_err());
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        assert_eq!(bob.join_existing_group(welcome).unwrap(), "work");
+        carol.create_group("home", GroupPolicy::default()).unwrap();
+        // Alice leaves Bob's request to join another group alone.
+        let request = bob.create_join_request().unwrap();
+        assert!(!alice.is_join_target(&request));
+        assert!(carol.is_join_target(&request));
+        let (_, welcome) = carol.add_member_to_group(bob.get_key_package()).unwrap();
+        assert_eq!(bob.join_existing_group(welcome).unwrap(), "home");
+
+        // The first group stays active until Bob switches.
+        assert_eq!(bob.active_group_name().as_deref(), Some("work"));
+        assert_eq!(bob.groups().len(), 2);
+        assert_eq!(bob.all_group_topics().len(), 4);
+        let msg = bob.create_group_message("home", "hi carol").unwrap();
+        let bytes = msg.tls_serialize_detached().unwrap();
+        let frame = WireMessage::from(msg.clone()).encode().unwrap();
+        assert_eq!(bob.frame_topics(&frame).last(), carol.group_topics().last());
+        assert_ne!(bob.frame_topics(&frame).last(), bob.group_topics().last());
+        assert_eq!(carol.parse_message(msg).unwrap().unwrap(), "hi carol");
+        let msg = MlsMessageOut::try_from_bytes(&bytes).unwrap();
+        assert_eq!(alice.parse_message(msg).unwrap(), None);
+
+        bob.use_group("home").unwrap();
+        assert!(bob.use_group("elsewhere").is_err());
+        let msg = carol.create_message("hi bob").unwrap();
+        assert_eq!(bob.parse_message(msg).unwrap().unwrap(), "hi bob");
+        bob.leave_group().unwrap();
+        assert_eq!(bob.active_group_name().as_deref(), Some("work"));
+        let msg = alice.create_message("still here").unwrap();
+        assert_eq!(bob.parse_message(msg).unwrap().unwrap(), "still here");
+    }
+
+    #[test]
+    fn broadcasts_reach_every_group() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        assert!(bob.broadcast("nobody").is_err());
+        alice.create_group("work", GroupPolicy::default()).unwrap();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        carol.create_group("home", GroupPolicy::default()).unwrap();
+        let (_, welcome) = carol.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        let outcomes = bob.broadcast("maintenance").unwrap();
+        let groups: Vec<&str> = outcomes.iter().map(|(group, _)| group.as_str()).collect();
+        assert_eq!(groups, ["home", "work"]);
+        assert!(outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Ok(SendOutcome::Queued { .. }))));
+        let frames: Vec<MlsMessageOut> = bob
+            .take_outgoing()
+            .into_iter()
+            .map(|frame| match frame {
+                WireMessage::MlsMessage(msg) => msg,
+                frame => panic!("unexpected frame {:?}", frame),
+            })
+            .collect();
+        assert_eq!(frames.len(), 2);
+        let [home, work] = <[MlsMessageOut; 2]>::try_from(frames).unwrap();
+        assert_eq!(carol.parse_message(home).unwrap().unwrap(), "maintenance");
+        assert_eq!(alice.parse_message(work).unwrap().unwrap(), "maintenance");
+
+        // Each group is judged on its own health.
+        bob.set_send_policy(SendPolicy::Block, false);
+        let outcomes = bob.broadcast("blocked").unwrap();
+        assert!(outcomes.iter().all(|(_, outcome)| outcome.is_err()));
+        assert!(bob.take_outgoing().is_empty());
+    }
+
+    #[test]
+    fn groups_get_random_ids_and_invites_carry_their_name() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        let mut dave = Node::default();
+        alice.join_new_group();
+        carol.join_new_group();
+        let (alice_id, carol_id) = (
+            alice.group_info().unwrap().group_id,
+            carol.group_info().unwrap().group_id,
+        );
+        assert_ne!(alice_id, carol_id);
+        assert_eq!(alice_id.len(), crate::crypto::GROUP_ID_LEN);
+
+        let (_, invite) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        let invite =
+            match WireMessage::decode(&WireMessage::from(invite).encode().unwrap()).unwrap() {
+                WireMessage::Welcome(invite) => invite,
+                other => panic!("not an invite: {:?}", other),
+            };
+        assert_eq!(invite.name.as_deref(), Some(DEFAULT_GROUP_NAME));
+        assert_eq!(bob.join_existing_group(invite).unwrap(), DEFAULT_GROUP_NAME);
+        // A second group of the same name is told apart by its id.
+        let (_, invite) = carol.add_member_to_group(bob.get_key_package()).unwrap();
+        let name = bob.join_existing_group(invite).unwrap();
+        assert_eq!(
+            name,
+            format!("{} ({})", DEFAULT_GROUP_NAME, hex_encode(&carol_id[..4]))
+        );
+        bob.use_group(&name).unwrap();
+        assert_eq!(bob.group_info().unwrap().group_id, carol_id);
+
+        // Traffic of a group Dave is not in is only held once he asks to join.
+        let msg = alice.create_message("not for dave").unwrap();
+        assert_eq!(dave.parse_message(msg).unwrap(), None);
+        assert_eq!(dave.pending.len(), 0);
+        dave.create_join_request().unwrap();
+        let msg = alice.create_message("maybe for dave").unwrap();
+        assert_eq!(dave.parse_message(msg).unwrap(), None);
+        assert_eq!(dave.pending.len(), 1);
+    }
+
+    #[test]
+    fn configured_ciphersuite_and_name_shape_new_groups() {
+        let aes = crate::crypto::parse_ciphersuite("MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519")
+            .unwrap();
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let carol = Node::default();
+        alice.set_ciphersuite(aes).unwrap();
+        alice.set_default_group_name("ops").unwrap();
+        assert!(alice.set_default_group_name("").is_err());
+        alice.join_new_group();
+        let info = alice.group_info().unwrap();
+        assert_eq!((info.name.as_str(), info.ciphersuite), ("ops", aes));
+
+        // Joiners need key packages of the group's ciphersuite.
+        assert!(alice.add_member_to_group(carol.get_key_package()).is_err());
+        bob.set_ciphersuite(aes).unwrap();
+        let (_, invite) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        assert_eq!(bob.join_existing_group(invite).unwrap(), "ops");
+    }
+
+    #[test]
+    fn welcome_policy_only_joins_the_named_leader() {
+        let mut alice = Node::default();
+        let mut mallory = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        mallory.join_new_group();
+        carol.set_welcome_policy(WelcomePolicy::Leader);
+        assert!(carol.create_join_request().is_err());
+
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        carol
+            .record_room(&alice_peer, &alice.create_room_announcement().unwrap())
+            .unwrap();
+        let request = carol.create_room_join_request(1).unwrap();
+
+        // Anyone who heard the request can send a Welcome; only Alice's counts.
+        let (_, invite) = mallory
+            .add_member_to_group(carol.get_key_package())
+            .unwrap();
+        let refused = carol.join_existing_group(invite).unwrap_err().to_string();
+        assert!(refused.contains("whom we asked"), "{}", refused);
+        assert!(!carol.in_group());
+
+        let carol_peer = carol.get_network_keypair().public().to_peer_id();
+        let (_, invite) = alice.handle_join_request(&carol_peer, request).unwrap();
+        assert_eq!(
+            carol.join_existing_group(invite).unwrap(),
+            DEFAULT_GROUP_NAME
+        );
+    }
+
+    #[test]
+    fn handle_incoming_reports_events_and_queues_answers() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let carol = Node::default();
+        alice.join_new_group();
+        alice.set_join_approval(JoinApproval::Auto);
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let bob_peer = bob.get_network_keypair().public().to_peer_id();
+        let frame = |message: WireMessage| message.encode().unwrap();
+
+        let request = frame(bob.create_join_request().unwrap().into());
+        let events = alice.handle_incoming(&bob_peer, &request);
+        assert!(matches!(events[..], [NodeEvent::MemberJoined { .. }]));
+        let (to, welcome) = alice.take_direct().remove(0);
+        assert_eq!(to, bob_peer);
+        assert!(matches!(welcome, WireMessage::Welcome(_)));
+        let events = bob.handle_incoming(&alice_peer, &frame(welcome));
+        assert!(matches!(events[..], [NodeEvent::WelcomeReceived { .. }]));
+
+        let msg = frame(alice.create_message("hi bob").unwrap().into());
+        match &bob.handle_incoming(&alice_peer, &msg)[..] {
+            [NodeEvent::MessageReceived {
+                peer: Some(peer),
+                payload,
+                ..
+            }] => {
+                assert_eq!(peer, &alice_peer);
+                assert_eq!(payload.to_string(), "hi bob");
+            }
+            events => panic!("{:?}", events),
+        }
+
+        let (commit, _) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+        bob.handle_incoming(&alice_peer, &frame(commit.into()));
+        let carol_identity = credential_identity(carol.get_key_package().credential());
+        let commit = alice.remove_member(&carol_identity).unwrap();
+        let events = bob.handle_incoming(&alice_peer, &frame(commit.into()));
+        assert!(matches!(
+            &events[..],
+            [NodeEvent::MemberRemoved { identity, .. }, NodeEvent::CommitApplied { .. }]
+                if identity == &carol_identity
+        ));
+
+        // Carol passing on Alice's message does not make it hers.
+        let carol_peer = carol.get_network_keypair().public().to_peer_id();
+        let alice_identity = credential_identity(alice.get_key_package().credential());
+        let msg = frame(alice.create_message("via carol").unwrap().into());
+        match &bob.handle_incoming(&carol_peer, &msg)[..] {
+            [NodeEvent::SenderMismatch { peer, author, .. }, NodeEvent::MessageReceived {
+                author: Some(sender),
+                ..
+            }] => {
+                assert_eq!(peer, &carol_peer);
+                assert_eq!(author, &alice_identity);
+                assert_eq!(sender, &alice_identity);
+            }
+            events => panic!("{:?}", events),
+        }
+
+        let events = bob.handle_incoming(&alice_peer, b"junk");
+        assert!(matches!(events[..], [NodeEvent::Error { .. }]));
+    }
+
+    #[test]
+    fn join_requests_wait_for_the_leader_to_accept_or_decline() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let mut carol = Node::default();
+        alice.join_new_group();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let bob_peer = bob.get_network_keypair().public().to_peer_id();
+        let carol_peer = carol.get_network_keypair().public().to_peer_id();
+        let frame = |message: WireMessage| message.encode().unwrap();
+
+        let request = frame(bob.create_join_request().unwrap().into());
+        let id = match &alice.handle_incoming(&bob_peer, &request)[..] {
+            [NodeEvent::JoinRequested { id, peer, .. }] if peer == &bob_peer => *id,
+            events => panic!("{:?}", events),
+        };
+        assert!(alice.take_outgoing().is_empty());
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 1);
+
+        alice.accept_join(id).unwrap();
+        assert!(alice.pending_joins().next().is_none());
+        let (_, welcome) = alice.take_direct().remove(0);
+        let events = bob.handle_incoming(&alice_peer, &frame(welcome));
+        assert!(matches!(events[..], [NodeEvent::WelcomeReceived { .. }]));
+
+        let request = frame(carol.create_join_request().unwrap().into());
+        let id = match &alice.handle_incoming(&carol_peer, &request)[..] {
+            [NodeEvent::JoinRequested { id, .. }] => *id,
+            events => panic!("{:?}", events),
+        };
+        assert_eq!(alice.decline_join(id).unwrap().peer, carol_peer);
+        assert!(alice.accept_join(id).is_err());
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 2);
+    }
+
+    #[test]
+    fn join_policies_decide_before_anyone_is_asked() {
+        let mut alice = Node::default();
+        let (mut bob, mut carol, mut dave) = (Node::default(), Node::default(), Node::default());
+        alice.join_new_group();
+        let peer = |node: &Node| node.get_network_keypair().public().to_peer_id();
+        let request = |node: &mut Node| {
+            WireMessage::from(node.create_join_request().unwrap())
+                .encode()
+                .unwrap()
+        };
+        let bob_id = credential_identity(bob.get_key_package().credential());
+        let carol_id = credential_identity(carol.get_key_package().credential());
+        alice.on_join_request(move |candidate| match candidate.identity {
+            identity if identity == bob_id => JoinDecision::Approve,
+            identity if identity == carol_id => JoinDecision::Decline,
+            _ => JoinDecision::Defer,
+        });
+
+        let events = alice.handle_incoming(&peer(&bob), &request(&mut bob));
+        assert!(matches!(events[..], [NodeEvent::MemberJoined { .. }]));
+        assert!(!alice.take_direct().is_empty());
+        assert!(alice
+            .handle_incoming(&peer(&carol), &request(&mut carol))
+            .is_empty());
+        let events = alice.handle_incoming(&peer(&dave), &request(&mut dave));
+        assert!(matches!(events[..], [NodeEvent::JoinRequested { .. }]));
+        assert_eq!(alice.pending_joins().count(), 1);
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 2);
+    }
+
+    #[test]
+    fn guests_are_warned_then_removed_on_time() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let bob_peer = bob.get_network_keypair().public().to_peer_id();
+        let bob_identity = bob_peer.to_string();
+        let frame = |message: WireMessage| message.encode().unwrap();
+
+        let request = frame(bob.create_join_request().unwrap().into());
+        let id = match &alice.handle_incoming(&bob_peer, &request)[..] {
+            [NodeEvent::JoinRequested { id, .. }] => *id,
+            events => panic!("{:?}", events),
+        };
+        let admitted = unix_secs(SystemTime::now());
+        alice
+            .accept_guests(&[id], Duration::from_secs(3600))
+            .unwrap();
+        let (_, welcome) = alice.take_direct().remove(0);
+        bob.handle_incoming(&alice_peer, &frame(welcome));
+        let entry = alice.take_outgoing().pop().unwrap();
+        match &bob.handle_incoming(&alice_peer, &frame(entry))[..] {
+            [NodeEvent::MessageReceived {
+                payload:
+                    ApplicationPayload::GuestAccess {
+                        identity,
+                        remaining,
+                    },
+                ..
+            }] => {
+                assert_eq!(identity, &bob_identity);
+                assert!(*remaining <= Duration::from_secs(3600));
+            }
+            events => panic!("{:?}", events),
+        }
+        assert!(bob
+            .extend_guest(&bob_identity, Duration::from_secs(60))
+            .is_err());
+
+        // The leader's timer survives a restart from backup.
+        let sections: HashMap<_, _> = alice.backup_sections().unwrap().into_iter().collect();
+        let restored = Node::from_backup(&sections).unwrap();
+        assert_eq!(restored.guests().unwrap(), alice.guests().unwrap());
+
+        let now = SystemTime::now();
+        assert_eq!(alice.expire_guests(now), 0);
+        assert_eq!(alice.expire_guests(now + Duration::from_secs(3400)), 1);
+        assert_eq!(alice.expire_guests(now + Duration::from_secs(3500)), 0);
+        alice.take_outgoing();
+        let (entry, _) = alice
+            .extend_guest(&bob_identity, Duration::from_secs(600))
+            .unwrap();
+        assert!(entry.expires >= admitted + 4200);
+        assert_eq!(alice.expire_guests(now + Duration::from_secs(3700)), 0);
+        assert_eq!(alice.expire_guests(now + Duration::from_secs(4300)), 1);
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 1);
+        assert!(alice.guests().unwrap().is_empty());
+    }
+
+    #[test]
+    fn direct_join_requests_are_answered_directly() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        alice.set_join_approval(JoinApproval::Auto);
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let bob_peer = bob.get_network_keypair().public().to_peer_id();
+
+        bob.request_join_from(&alice_peer).unwrap();
+        let (to, request) = bob.take_direct().remove(0);
+        assert_eq!(to, alice_peer);
+        let events = alice.handle_direct(&bob_peer, &request.encode().unwrap());
+        assert!(matches!(events[..], [NodeEvent::MemberJoined { .. }]));
+        assert!(!alice
+            .take_outgoing()
+            .iter()
+            .any(|frame| matches!(frame, WireMessage::Welcome(_))));
+
+        let (to, welcome) = alice.take_direct().remove(0);
+        assert_eq!(to, bob_peer);
+        let events = bob.handle_direct(&alice_peer, &welcome.encode().unwrap());
+        assert!(matches!(events[..], [NodeEvent::WelcomeReceived { .. }]));
+
+        let msg = WireMessage::from(alice.create_message("hi").unwrap());
+        let events = bob.handle_direct(&alice_peer, &msg.encode().unwrap());
+        assert!(matches!(events[..], [NodeEvent::Error { .. }]));
+    }
+
+    #[test]
+    fn batched_joins_share_one_welcome_sent_to_each_joiner() {
+        let mut alice = Node::default();
+        let mut joiners: Vec<Node> = (0..3).map(|_| Node::default()).collect();
+        alice.join_new_group();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let peers: Vec<PeerId> = joiners
+            .iter()
+            .map(|joiner| joiner.get_network_keypair().public().to_peer_id())
+            .collect();
+        joiners[0].request_join_from(&alice_peer).unwrap();
+        let mut requests = vec![joiners[0].take_direct().remove(0).1];
+        for joiner in &mut joiners[1..] {
+            requests.push(joiner.create_join_request().unwrap().into());
+        }
+        let mut ids = Vec::new();
+        for (index, request) in requests.into_iter().enumerate() {
+            let frame = request.encode().unwrap();
+            let events = match index {
+                0 => alice.handle_direct(&peers[0], &frame),
+                _ => alice.handle_incoming(&peers[index], &frame),
+            };
+            match &events[..] {
+                [NodeEvent::JoinRequested { id, .. }] => ids.push(*id),
+                events => panic!("{:?}", events),
+            }
+        }
+
+        alice.accept_joins(&ids).unwrap();
+        let outgoing = alice.take_outgoing();
+        assert!(matches!(outgoing[..], [WireMessage::MlsMessage(_)]));
+        let direct = alice.take_direct();
+        assert_eq!(
+            direct.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+            peers
+        );
+        let welcome = direct[0].1.encode().unwrap();
+        assert!(direct.iter().all(|(_, w)| w.encode().unwrap() == welcome));
+        for joiner in &mut joiners {
+            let events = joiner.handle_direct(&alice_peer, &welcome);
+            assert!(matches!(events[..], [NodeEvent::WelcomeReceived { .. }]));
+        }
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 4);
+    }
+
+    #[test]
+    fn joins_within_the_window_are_added_in_one_commit() {
+        let mut alice = Node::default();
+        let mut joiners: Vec<Node> = (0..3).map(|_| Node::default()).collect();
+        alice.set_join_approval(JoinApproval::Auto);
+        alice.join_new_group();
+        alice
+            .set_group_join_window(Duration::from_millis(500))
+            .unwrap();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let start = Instant::now();
+        for joiner in &mut joiners {
+            let peer = joiner.get_network_keypair().public().to_peer_id();
+            let frame = WireMessage::from(joiner.create_join_request().unwrap())
+                .encode()
+                .unwrap();
+            assert!(alice.handle_incoming(&peer, &frame).is_empty());
+        }
+        assert!(alice.take_outgoing().is_empty());
+        assert!(alice.flush_join_batches(start).is_empty());
+
+        let events = alice.flush_join_batches(Instant::now() + Duration::from_millis(500));
+        assert_eq!(events.len(), 3);
+        assert!(events
+            .iter()
+            .all(|event| matches!(event, NodeEvent::MemberJoined { .. })));
+        assert!(matches!(
+            alice.take_outgoing()[..],
+            [WireMessage::MlsMessage(_)]
+        ));
+        let direct = alice.take_direct();
+        assert_eq!(direct.len(), 3);
+        for (joiner, (_, welcome)) in joiners.iter_mut().zip(direct) {
+            let events = joiner.handle_direct(&alice_peer, &welcome.encode().unwrap());
+            assert!(matches!(events[..], [NodeEvent::WelcomeReceived { .. }]));
+        }
+        assert_eq!(alice.group().unwrap().mls_group.members().len(), 4);
+        assert_eq!(alice.pending_joins().count(), 0);
+    }
+
+    #[test]
+    fn welcomes_for_someone_else_are_dropped() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        let carol = Node::default();
+        alice.join_new_group();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+        let (_, invite) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+
+        assert!(!bob.is_welcome_for_us(&invite).unwrap());
+        assert!(bob.join_existing_group(invite.clone()).is_err());
+        let welcome = WireMessage::from(invite).encode().unwrap();
+        assert!(bob.handle_incoming(&alice_peer, &welcome).is_empty());
+        assert!(bob.groups().is_empty());
+
+        // Our own key package is still good for a Welcome that is ours.
+        let (_, invite) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        assert!(bob.join_existing_group(invite).is_ok());
+    }
+
+    #[cfg(feature = "test-utils")]
+    #[test]
+    fn crypto_failures_surface_as_errors() {
+        use crate::crypto::faults::Fault;
+
+        let mut alice = Node::default();
+        let faults = alice.crypto_faults();
+        faults.inject(Fault::Hash);
+        assert!(alice.create_group("work", GroupPolicy::default()).is_err());
+        assert!(alice.groups().is_empty());
+        faults.clear(Fault::Hash);
+        alice.create_group("work", GroupPolicy::default()).unwrap();
+
+        faults.inject(Fault::Sign);
+        assert!(alice.create_message("hi").is_err());
+        assert!(alice.self_update().is_err());
+        faults.clear(Fault::Sign);
+        assert!(alice.create_message("hi").is_ok());
+
+        // A refusal makes us replace our key package, which has to be stored.
+        let refusal = JoinRefusal {
+            identity: alice.get_key_package().credential().identity().to_vec(),
+            reason: LifetimeError::Expired { not_after: 0 },
+        };
+        faults.inject(Fault::KeyStoreWrite);
+        assert!(alice.handle_join_refusal(&refusal).is_err());
+        faults.clear(Fault::KeyStoreWrite);
+        assert!(alice.handle_join_refusal(&refusal).unwrap().is_some());
+    }
+}
@@ -0,0 +1,299 @@
+//! What we sent, so our own frames are recognised when they come back.
+//!
+//! Every frame we publish is recorded by message id, the SHA-256 of its
+//! bytes. Floodsub normally filters our own messages, but a peer can relay
+//! one back after it fell out of that filter, or the mailbox can hand it to
+//! us again, and our group state cannot process what it produced itself: an
+//! echoed application message fails to decrypt and an echoed commit is for
+//! an epoch we already left. Echoes are dropped before parsing instead.
+//!
+//! Text messages also keep their text, so they are shown once, when the
+//! network event loop hands them on, together with how they went out.
+//! Each one is numbered in its group when we accept it, and with
+//! `--async-encrypt` it is recorded before it is encrypted; texts are
+//! encrypted in that order, so members receive a group's messages in the
+//! order they were typed.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+
+/// Frames remembered, oldest forgotten first.
+pub const OUTBOX_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// Accepted, waiting for the encryption worker.
+    Encrypting,
+    /// Encrypted, waiting for the network event loop.
+    Queued,
+    /// Published over floodsub while connected to this many peers.
+    Sent { peers: usize },
+    /// Left in the delivery service mailbox since no peers were connected.
+    Mailbox,
+    /// Published with no peers connected and no mailbox to fall back on.
+    Undelivered,
+}
+
+impl Display for DeliveryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryState::Encrypting => write!(f, "encrypting"),
+            DeliveryState::Queued => write!(f, "queued"),
+            DeliveryState::Sent { peers: 1 } => write!(f, "sent to 1 peer"),
+            DeliveryState::Sent { peers } => write!(f, "sent to {} peers", peers),
+            DeliveryState::Mailbox => write!(f, "left in mailbox"),
+            DeliveryState::Undelivered => write!(f, "not delivered, no peers"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    /// Empty until the message is encrypted.
+    pub id: Vec<u8>,
+    pub group_id: Vec<u8>,
+    /// Position among our text messages to the group, from 1; 0 for
+    /// handshake and control frames.
+    pub seq: u64,
+    /// What we typed; `None` for handshake and control frames.
+    pub text: Option<String>,
+    pub state: DeliveryState,
+}
+
+impl Display for OutboxEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.text.as_deref().unwrap_or_default(),
+            self.state
+        )
+    }
+}
+
+/// A text message accepted for sending but not yet encrypted, see
+/// `Node::encrypt_pending`; `Node::send_state` follows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSend {
+    pub group_id: Vec<u8>,
+    pub seq: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Outbox {
+    entries: VecDeque<OutboxEntry>,
+    echoes: u64,
+    last_seq: HashMap<Vec<u8>, u64>,
+}
+
+impl Outbox {
+    /// Records a text message we just encrypted for `group_id`.
+    pub fn push(&mut self, id: Vec<u8>, group_id: &[u8], text: &str) -> u64 {
+        let seq = self.reserve(group_id, text);
+        self.encrypted(group_id, seq, id);
+        seq
+    }
+
+    /// Numbers a text message for `group_id` that is still to be encrypted.
+    pub fn reserve(&mut self, group_id: &[u8], text: &str) -> u64 {
+        let seq = self.last_seq.entry(group_id.to_vec()).or_default();
+        *seq += 1;
+        let seq = *seq;
+        self.insert(OutboxEntry {
+            id: Vec::new(),
+            group_id: group_id.to_vec(),
+            seq,
+            text: Some(text.to_string()),
+            state: DeliveryState::Encrypting,
+        });
+        seq
+    }
+
+    /// Records the frame id text message `seq` of `group_id` encrypted to.
+    pub fn encrypted(&mut self, group_id: &[u8], seq: u64, id: Vec<u8>) {
+        match self.position(group_id, seq) {
+            Some(i) => {
+                let entry = &mut self.entries[i];
+                entry.id = id;
+                entry.state = DeliveryState::Queued;
+            }
+            // Forgotten while it waited; still recognise its echo.
+            None => self.insert(OutboxEntry {
+                id,
+                group_id: group_id.to_vec(),
+                seq,
+                text: None,
+                state: DeliveryState::Queued,
+            }),
+        }
+    }
+
+    /// Forgets text message `seq` of `group_id`, which could not be encrypted.
+    pub fn abandon(&mut self, group_id: &[u8], seq: u64) {
+        if let Some(i) = self.position(group_id, seq) {
+            self.entries.remove(i);
+        }
+    }
+
+    fn position(&self, group_id: &[u8], seq: u64) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.seq == seq && entry.group_id == group_id)
+    }
+
+    /// The state of text message `seq` of `group_id`, while remembered.
+    pub fn state(&self, group_id: &[u8], seq: u64) -> Option<DeliveryState> {
+        self.position(group_id, seq).map(|i| self.entries[i].state)
+    }
+
+    fn insert(&mut self, entry: OutboxEntry) {
+        if self.entries.len() >= OUTBOX_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Records that the frame `id` went out as `state`. Returns the entry
+    /// the first time a text message goes out, for the local echo.
+    pub fn published(&mut self, id: Vec<u8>, state: DeliveryState) -> Option<&OutboxEntry> {
+        match self
+            .entries
+            .iter()
+            .position(|entry| !entry.id.is_empty() && entry.id == id)
+        {
+            Some(i) => {
+                let entry = &mut self.entries[i];
+                let first = entry.state == DeliveryState::Queued;
+                entry.state = state;
+                Some(&*entry).filter(|entry| first && entry.text.is_some())
+            }
+            None => {
+                self.insert(OutboxEntry {
+                    id,
+                    group_id: Vec::new(),
+                    seq: 0,
+                    text: None,
+                    state,
+                });
+                None
+            }
+        }
+    }
+
+    /// Whether the frame `id` is one of ours coming back, counting it if so.
+    pub fn is_echo(&mut self, id: &[u8]) -> bool {
+        let echo = self.entries.iter().any(|entry| entry.id == id);
+        if echo {
+            self.echoes += 1;
+        }
+        echo
+    }
+
+    /// Our text messages, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = &OutboxEntry> {
+        self.entries.iter().filter(|entry| entry.text.is_some())
+    }
+
+    pub fn echoes(&self) -> u64 {
+        self.echoes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::SendOutcome;
+    use crate::node::{ApplicationPayload, Node};
+    use crate::protocol::WireMessage;
+
+    #[test]
+    fn own_frames_are_echoed_once_and_suppressed() {
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let bytes = WireMessage::from(alice.create_message("hello").unwrap())
+            .encode()
+            .unwrap();
+        assert_eq!(
+            alice.outbox().messages().next().unwrap().state,
+            DeliveryState::Queued
+        );
+        let shown = alice.record_published(&bytes, DeliveryState::Sent { peers: 2 });
+        assert_eq!(shown.unwrap().to_string(), "hello (sent to 2 peers)");
+        assert!(alice
+            .record_published(&bytes, DeliveryState::Mailbox)
+            .is_none());
+        assert!(alice.is_own_echo(&bytes));
+
+        // Frames without text, such as commits, are recognised but not shown.
+        let commit = b"a commit we published".to_vec();
+        assert!(alice
+            .record_published(&commit, DeliveryState::Undelivered)
+            .is_none());
+        assert!(alice.is_own_echo(&commit));
+        assert!(!alice.is_own_echo(b"someone else's frame"));
+        assert_eq!(alice.outbox().messages().count(), 1);
+        assert_eq!(alice.outbox().echoes(), 2);
+
+        let mut outbox = Outbox::default();
+        for i in 0..=OUTBOX_LEN {
+            outbox.published(vec![i as u8, (i >> 8) as u8], DeliveryState::Undelivered);
+        }
+        assert!(!outbox.is_echo(&[0, 0]));
+        assert!(outbox.is_echo(&[1, 0]));
+    }
+
+    #[test]
+    fn pending_sends_are_encrypted_in_sequence() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        alice.set_async_encryption(true);
+
+        let mut sends = Vec::new();
+        for text in ["one", "two"] {
+            match alice.send_text(None, text).unwrap() {
+                SendOutcome::Pending { send, .. } => sends.push(send),
+                outcome => panic!("sent without waiting: {:?}", outcome),
+            }
+        }
+        assert_eq!(sends[0].seq, 1);
+        assert_eq!(sends[1].seq, 2);
+        assert_eq!(alice.send_state(&sends[1]), Some(DeliveryState::Encrypting));
+        assert_eq!(alice.encryptions_pending(), 2);
+
+        for (expected, text) in sends.iter().zip(["one", "two"]) {
+            let (send, msg_out) = alice.encrypt_pending().unwrap();
+            assert_eq!(&send, expected);
+            assert_eq!(alice.send_state(&send), Some(DeliveryState::Queued));
+            let bytes = WireMessage::from(msg_out.unwrap()).encode().unwrap();
+            assert_eq!(
+                alice
+                    .record_published(&bytes, DeliveryState::Sent { peers: 1 })
+                    .unwrap()
+                    .seq,
+                send.seq
+            );
+            let msg_out = match WireMessage::decode(&bytes).unwrap() {
+                WireMessage::MlsMessage(msg_out) => msg_out,
+                frame => panic!("unexpected frame {:?}", frame),
+            };
+            assert_eq!(
+                bob.parse_application_message(msg_out).unwrap(),
+                Some(ApplicationPayload::Text(text.to_string()))
+            );
+        }
+        assert!(alice.encryptions_pending() == 0 && alice.encrypt_pending().is_none());
+
+        // A message for a group we left by then is dropped from the outbox.
+        bob.set_async_encryption(true);
+        let send = match bob.send_text(None, "three").unwrap() {
+            SendOutcome::Pending { send, .. } => send,
+            outcome => panic!("sent without waiting: {:?}", outcome),
+        };
+        bob.leave_group().unwrap();
+        assert!(bob.encrypt_pending().unwrap().1.is_err());
+        assert_eq!(bob.send_state(&send), None);
+    }
+}
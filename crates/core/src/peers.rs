@@ -0,0 +1,353 @@
+//! What the node knows about the network without running it: the topics
+//! frames go to, the transports peers connect over, the connections they
+//! hold and the peers we blocked. The swarm that fills these in, and refuses
+//! blocked peers, lives in the `p2p-mls-net` crate.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Display;
+
+use multiaddr::{Multiaddr, Protocol};
+
+use crate::{error::NodeError, identity::PeerId, limits::FrameKind};
+
+/// The one predictable topic, carrying only what has to reach peers outside
+/// the group's key schedule: join requests, Welcomes for joiners we know no
+/// peer id of, recovery messages and room announcements.
+/// Everything else goes to the topics of its group, from `Node::frame_topics`.
+pub const RENDEZVOUS_TOPIC: &str = "chat";
+
+/// The control topic paired with the group topic `topic`, carrying commits,
+/// proposals and adverts, so chat on `topic` cannot crowd them out, see
+/// `qos::Plane`. It is as secret as the group topic it is named after.
+pub fn control_topic(topic: &str) -> String {
+    format!("{}/control", topic)
+}
+
+/// The topic only `peer` listens on, for Welcomes addressed to it while we
+/// have no connection to send them over directly.
+pub fn welcome_topic(peer: &PeerId) -> String {
+    format!("{}/welcome/{}", RENDEZVOUS_TOPIC, peer)
+}
+
+/// The topics to publish `frame` to. Commits go to every topic we still
+/// listen on, since members who have not processed the previous commit yet
+/// are on an older one; application messages only to the current topic.
+pub fn publish_topics(frame: &[u8], group_topics: &[String]) -> Vec<String> {
+    let current = match group_topics.last() {
+        Some(current) => current,
+        None => return vec![RENDEZVOUS_TOPIC.to_string()],
+    };
+    match FrameKind::classify(frame) {
+        FrameKind::KeyPackage | FrameKind::Welcome => vec![RENDEZVOUS_TOPIC.to_string()],
+        FrameKind::Handshake => group_topics.to_vec(),
+        FrameKind::Application => vec![current.clone()],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    WebSocket,
+    SecureWebSocket,
+    Quic,
+    Other,
+}
+
+impl Transport {
+    pub fn of(address: &Multiaddr) -> Transport {
+        let mut transport = Transport::Other;
+        for protocol in address.iter() {
+            transport = match protocol {
+                Protocol::Tcp(_) => Transport::Tcp,
+                Protocol::Ws(_) => Transport::WebSocket,
+                Protocol::Wss(_) => Transport::SecureWebSocket,
+                Protocol::Quic => Transport::Quic,
+                _ => continue,
+            };
+        }
+        transport
+    }
+
+    pub fn parse(name: &str) -> Result<Transport, NodeError> {
+        match name {
+            "tcp" => Ok(Transport::Tcp),
+            "ws" => Ok(Transport::WebSocket),
+            "wss" => Ok(Transport::SecureWebSocket),
+            "quic" => Ok(Transport::Quic),
+            _ => Err(NodeError::Network(format!(
+                "Unknown transport {}, expected tcp, ws, wss or quic",
+                name
+            ))),
+        }
+    }
+
+    /// Whether the swarm can listen and dial on this transport. The libp2p
+    /// release we build on has no QUIC support yet.
+    pub fn is_supported(&self) -> bool {
+        matches!(
+            self,
+            Transport::Tcp | Transport::WebSocket | Transport::SecureWebSocket
+        )
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Transport::Tcp => "tcp",
+            Transport::WebSocket => "ws",
+            Transport::SecureWebSocket => "wss",
+            Transport::Quic => "quic",
+            Transport::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Which peers may stay connected over a transport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportPolicy {
+    Allow,
+    /// Only peers whose credential is in our group.
+    Members,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransportPolicies {
+    // Transports missing here are allowed.
+    policies: HashMap<Transport, TransportPolicy>,
+}
+
+impl TransportPolicies {
+    /// Parses `transport=policy` settings such as `ws=members`.
+    pub fn parse(settings: &[&str]) -> Result<TransportPolicies, NodeError> {
+        let mut policies = HashMap::new();
+        for setting in settings {
+            let (transport, policy) = setting.split_once('=').ok_or_else(|| {
+                NodeError::Network(format!("Expected transport=policy, got {}", setting))
+            })?;
+            let policy = match policy {
+                "allow" => TransportPolicy::Allow,
+                "members" => TransportPolicy::Members,
+                "deny" => TransportPolicy::Deny,
+                _ => {
+                    return Err(NodeError::Network(format!(
+                        "Unknown transport policy {}, expected allow, members or deny",
+                        policy
+                    )))
+                }
+            };
+            policies.insert(Transport::parse(transport)?, policy);
+        }
+        Ok(TransportPolicies { policies })
+    }
+
+    pub fn policy(&self, transport: Transport) -> TransportPolicy {
+        self.policies
+            .get(&transport)
+            .copied()
+            .unwrap_or(TransportPolicy::Allow)
+    }
+
+    /// Whether a connection over `transport` may stay open.
+    pub fn admits(&self, transport: Transport, is_member: bool) -> bool {
+        match self.policy(transport) {
+            TransportPolicy::Allow => true,
+            TransportPolicy::Members => is_member,
+            TransportPolicy::Deny => false,
+        }
+    }
+}
+
+/// How we first learned about a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiscoverySource {
+    Mdns,
+    /// Given with `--dial` or `--bootstrap`.
+    Dialed,
+    /// Entered our Kademlia routing table.
+    Dht,
+    /// From a member's shared address book.
+    Introduced,
+    /// Only heard through the floodsub mesh.
+    Rendezvous,
+}
+
+impl Display for DiscoverySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DiscoverySource::Mdns => "mdns",
+            DiscoverySource::Dialed => "dial",
+            DiscoverySource::Dht => "dht",
+            DiscoverySource::Introduced => "introduction",
+            DiscoverySource::Rendezvous => "rendezvous",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The open connections of each peer, for `node peers`, the addresses we
+/// know to be dialable, for sharing with other members, and the peers whose
+/// traffic we drop, from `node block`.
+#[derive(Debug, Default)]
+pub struct PeerTable {
+    connections: HashMap<PeerId, Vec<Multiaddr>>,
+    sources: HashMap<PeerId, DiscoverySource>,
+    dialable: HashMap<PeerId, Vec<Multiaddr>>,
+    listen_addresses: Vec<Multiaddr>,
+    blocked: BTreeSet<PeerId>,
+}
+
+impl PeerTable {
+    pub fn connected(&mut self, peer: PeerId, address: Multiaddr) {
+        self.connections.entry(peer).or_default().push(address);
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId, address: &Multiaddr) {
+        if let Some(addresses) = self.connections.get_mut(peer) {
+            if let Some(i) = addresses.iter().position(|a| a == address) {
+                addresses.remove(i);
+            }
+            if addresses.is_empty() {
+                self.connections.remove(peer);
+            }
+        }
+    }
+
+    /// Records that we reached `peer` by dialing `address`. Kept after the
+    /// connection closes.
+    pub fn dialed(&mut self, peer: PeerId, address: Multiaddr) {
+        let addresses = self.dialable.entry(peer).or_default();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    pub fn dialable(&self, peer: &PeerId) -> &[Multiaddr] {
+        self.dialable.get(peer).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn dialable_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.dialable.keys()
+    }
+
+    /// Records where we found `peer`, unless we knew it already.
+    pub fn discovered(&mut self, peer: PeerId, source: DiscoverySource) {
+        self.sources.entry(peer).or_insert(source);
+    }
+
+    pub fn source(&self, peer: &PeerId) -> DiscoverySource {
+        self.sources
+            .get(peer)
+            .copied()
+            .unwrap_or(DiscoverySource::Rendezvous)
+    }
+
+    pub fn listening(&mut self, address: Multiaddr) {
+        self.listen_addresses.push(address);
+    }
+
+    pub fn stopped_listening(&mut self, address: &Multiaddr) {
+        self.listen_addresses.retain(|a| a != address);
+    }
+
+    pub fn listen_addresses(&self) -> &[Multiaddr] {
+        &self.listen_addresses
+    }
+
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.connections.contains_key(peer)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    pub fn peer_ids(&self) -> impl Iterator<Item = &PeerId> {
+        self.connections.keys()
+    }
+
+    /// Peers we have heard from through discovery, connected or not.
+    pub fn discovered_peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.sources.keys()
+    }
+
+    /// Whether `peer` was not blocked already.
+    pub fn block(&mut self, peer: PeerId) -> bool {
+        self.blocked.insert(peer)
+    }
+
+    /// Whether `peer` was blocked.
+    pub fn unblock(&mut self, peer: &PeerId) -> bool {
+        self.blocked.remove(peer)
+    }
+
+    pub fn is_blocked(&self, peer: &PeerId) -> bool {
+        self.blocked.contains(peer)
+    }
+
+    pub fn blocked(&self) -> impl Iterator<Item = &PeerId> {
+        self.blocked.iter()
+    }
+}
+
+impl Display for PeerTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut lines: Vec<String> = self
+            .connections
+            .iter()
+            .flat_map(|(peer, addresses)| {
+                addresses
+                    .iter()
+                    .map(move |address| format!("{} {} {}", peer, Transport::of(address), address))
+            })
+            .collect();
+        lines.sort();
+        if lines.is_empty() {
+            lines.push("no connected peers".to_string());
+        }
+        lines.extend(self.blocked.iter().map(|peer| format!("{} blocked", peer)));
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_policies_gate_connections() {
+        let ws: Multiaddr = "/ip4/10.0.0.1/tcp/80/ws".parse().unwrap();
+        let tcp: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let quic: Multiaddr = "/ip4/10.0.0.1/udp/4001/quic".parse().unwrap();
+        assert_eq!(Transport::of(&ws), Transport::WebSocket);
+        assert_eq!(Transport::of(&tcp), Transport::Tcp);
+        assert_eq!(Transport::of(&quic), Transport::Quic);
+        assert!(!Transport::Quic.is_supported());
+
+        let policies = TransportPolicies::parse(&["ws=members", "tcp=allow"]).unwrap();
+        assert!(policies.admits(Transport::Tcp, false));
+        assert!(!policies.admits(Transport::WebSocket, false));
+        assert!(policies.admits(Transport::WebSocket, true));
+        assert!(TransportPolicies::parse(&["ws"]).is_err());
+        assert!(TransportPolicies::parse(&["udp=allow"]).is_err());
+
+        let mut peers = PeerTable::default();
+        let peer = PeerId::random();
+        peers.connected(peer, ws.clone());
+        assert_eq!(peers.to_string(), format!("{} ws {}", peer, ws));
+        peers.disconnected(&peer, &ws);
+        assert!(peers.is_empty());
+
+        assert!(peers.block(peer));
+        assert!(!peers.block(peer));
+        assert!(peers.is_blocked(&peer));
+        assert_eq!(
+            peers.to_string(),
+            format!("no connected peers\n{} blocked", peer)
+        );
+        assert!(peers.unblock(&peer));
+        assert!(!peers.is_blocked(&peer));
+    }
+}
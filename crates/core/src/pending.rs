@@ -0,0 +1,240 @@
+//! Group traffic that arrived before we could process it.
+//!
+//! A leader publishes the Welcome and the commit that adds a newcomer, and
+//! members carry on in the new epoch, but neither floodsub nor the mailbox
+//! keeps frames in order. A member can see messages from the new epoch
+//! before the commit that starts it, and a newcomer can see group traffic
+//! before its Welcome. Such messages wait in [`PendingMessages`], keyed by
+//! group and epoch, and are replayed once the group reaches their epoch.
+//! `node status` shows what waits for the active group. Messages for epochs
+//! the group has moved past are dropped, and traffic of
+//! groups we are not in only waits while we have asked to join one. Once a
+//! Welcome lets us in, what other groups left behind is dropped, so it does
+//! not hold buffer space the groups we are in may need.
+
+use std::collections::BTreeMap;
+
+use openmls::prelude::MlsMessageOut;
+
+/// Messages held at once, across all groups.
+pub const MAX_PENDING: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct PendingMessages {
+    messages: BTreeMap<(Vec<u8>, u64), Vec<MlsMessageOut>>,
+    len: usize,
+}
+
+impl PendingMessages {
+    /// Holds `message` until group `group_id` reaches `epoch`. Returns false,
+    /// dropping the message, when the buffer is full.
+    pub fn push(&mut self, group_id: &[u8], epoch: u64, message: MlsMessageOut) -> bool {
+        if self.len >= MAX_PENDING {
+            return false;
+        }
+        self.messages
+            .entry((group_id.to_vec(), epoch))
+            .or_default()
+            .push(message);
+        self.len += 1;
+        true
+    }
+
+    /// The messages waiting for `epoch` of the group, in arrival order.
+    /// Anything held for an earlier epoch can no longer be used and is
+    /// dropped.
+    pub fn take(&mut self, group_id: &[u8], epoch: u64) -> Vec<MlsMessageOut> {
+        let stale: Vec<_> = self
+            .messages
+            .range((group_id.to_vec(), 0)..=(group_id.to_vec(), epoch))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut ready = Vec::new();
+        for key in stale {
+            let messages = self.messages.remove(&key).unwrap_or_default();
+            self.len -= messages.len();
+            if key.1 == epoch {
+                ready = messages;
+            }
+        }
+        ready
+    }
+
+    /// How many messages of the group wait for each epoch, earliest first.
+    pub fn waiting(&self, group_id: &[u8]) -> Vec<(u64, usize)> {
+        self.messages
+            .range((group_id.to_vec(), 0)..=(group_id.to_vec(), u64::MAX))
+            .map(|((_, epoch), messages)| (*epoch, messages.len()))
+            .collect()
+    }
+
+    /// Drops the messages of groups `keep` says no to.
+    pub fn retain_groups(&mut self, keep: impl Fn(&[u8]) -> bool) {
+        self.messages.retain(|(group_id, _), _| keep(group_id));
+        self.len = self.messages.values().map(Vec::len).sum();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::credential_identity;
+    use crate::node::{ApplicationPayload, Node};
+    use crate::protocol::Invite;
+    use openmls::prelude::TlsSerializeTrait;
+
+    enum Frame {
+        Welcome(Invite),
+        Mls(Vec<u8>),
+    }
+
+    fn permutations(items: Vec<usize>) -> Vec<Vec<usize>> {
+        if items.len() <= 1 {
+            return vec![items];
+        }
+        let mut orders = Vec::new();
+        for (i, first) in items.iter().enumerate() {
+            let mut rest = items.clone();
+            rest.remove(i);
+            for mut order in permutations(rest) {
+                order.insert(0, *first);
+                orders.push(order);
+            }
+        }
+        orders
+    }
+
+    // Hands `frames` to `node` in order, collecting every payload it yields.
+    fn deliver(node: &mut Node, frames: &[&Frame]) -> Vec<ApplicationPayload> {
+        let mut received = Vec::new();
+        for frame in frames {
+            match frame {
+                Frame::Welcome(welcome) => {
+                    node.join_existing_group(welcome.clone()).unwrap();
+                }
+                Frame::Mls(bytes) => {
+                    let msg = MlsMessageOut::try_from_bytes(bytes).unwrap();
+                    received.extend(node.parse_application_message(msg).unwrap());
+                }
+            }
+            received.extend(
+                node.take_replayed()
+                    .into_iter()
+                    .map(|(_, _, payload)| payload),
+            );
+        }
+        received
+    }
+
+    #[test]
+    fn every_arrival_order_converges() {
+        for order in permutations(vec![0, 1, 2]) {
+            let mut alice = Node::default();
+            let mut bob = Node::default();
+            let mut carol = Node::default();
+            // Carol asked to join, so she holds back the group's traffic.
+            carol.create_join_request().unwrap();
+            alice.join_new_group();
+            let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+            bob.join_existing_group(welcome).unwrap();
+            let (commit, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+            let message = alice.create_message("after the add").unwrap();
+            let frames = [
+                Frame::Welcome(welcome),
+                Frame::Mls(commit.tls_serialize_detached().unwrap()),
+                Frame::Mls(message.tls_serialize_detached().unwrap()),
+            ];
+            let carol_frames: Vec<&Frame> = order.iter().map(|&i| &frames[i]).collect();
+            let bob_frames: Vec<&Frame> = order
+                .iter()
+                .filter(|&&i| i != 0)
+                .map(|&i| &frames[i])
+                .collect();
+
+            for (node, frames) in [(&mut carol, carol_frames), (&mut bob, bob_frames)] {
+                assert_eq!(
+                    deliver(node, &frames),
+                    vec![ApplicationPayload::Text("after the add".to_string())],
+                    "arrival order {:?}",
+                    order
+                );
+                let state = node.security_state();
+                assert_eq!(state.epoch, Some(2));
+                assert!(!state.desynced && !state.degraded);
+            }
+        }
+    }
+
+    #[test]
+    fn traffic_of_groups_we_did_not_join_is_dropped_once_we_join() {
+        let (mut alice, mut dave, mut carol) = (Node::default(), Node::default(), Node::default());
+        carol.create_join_request().unwrap();
+        alice.join_new_group();
+        dave.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(carol.get_key_package()).unwrap();
+
+        let elsewhere = dave.create_message("not for carol").unwrap();
+        let ours = alice.create_message("before the welcome").unwrap();
+        assert_eq!(carol.parse_application_message(elsewhere).unwrap(), None);
+        assert_eq!(carol.parse_application_message(ours).unwrap(), None);
+        assert_eq!(carol.pending_messages(), 2);
+
+        carol.join_existing_group(welcome).unwrap();
+        assert_eq!(
+            carol.take_replayed()[0].2,
+            ApplicationPayload::Text("before the welcome".to_string())
+        );
+        assert_eq!(carol.pending_messages(), 0);
+    }
+
+    #[test]
+    fn messages_from_a_later_epoch_wait_for_its_commit() {
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let commit = alice.self_update().unwrap();
+        let ahead = alice.create_message("in epoch 2").unwrap();
+
+        assert_eq!(bob.parse_application_message(ahead).unwrap(), None);
+        assert_eq!(bob.held_back(), vec![(2, 1)]);
+        assert!(bob.security_state().desynced);
+        assert_eq!(bob.parse_application_message(commit).unwrap(), None);
+        assert_eq!(
+            bob.take_replayed(),
+            vec![(
+                bob.active_group_name().unwrap(),
+                Some(credential_identity(alice.get_key_package().credential())),
+                ApplicationPayload::Text("in epoch 2".to_string())
+            )]
+        );
+        assert!(bob.held_back().is_empty());
+        assert!(!bob.security_state().desynced);
+    }
+
+    #[test]
+    fn stale_epochs_are_dropped() {
+        let mut alice = Node::default();
+        alice.join_new_group();
+        let mut message = || alice.create_message("hi").unwrap();
+        let mut pending = PendingMessages::default();
+        assert!(pending.push(b"group", 3, message()));
+        assert!(pending.push(b"group", 4, message()));
+        assert!(pending.push(b"other", 3, message()));
+        assert_eq!(pending.take(b"group", 4).len(), 1);
+        assert!(pending.take(b"group", 3).is_empty());
+        assert_eq!(pending.len(), 1);
+        for _ in 1..MAX_PENDING {
+            assert!(pending.push(b"other", 5, message()));
+        }
+        assert!(!pending.push(b"other", 5, message()));
+    }
+}
@@ -0,0 +1,337 @@
+//! Certificate-backed credentials, binding MLS identities to an
+//! organization's PKI.
+//!
+//! OpenMLS 0.4 cannot encode or verify X.509 credentials, so members keep
+//! their Basic credential and carry an X.509 chain, leaf first, in the
+//! ExternalKeyId extension of their key packages, as
+//! `0xC5 | (length: u16 | DER)*`. Key packages are signed with the
+//! credential key, so a chain cannot be moved onto someone else's. A chain
+//! certifies a credential when the leaf's subject public key is the
+//! credential's Ed25519 signature key and the chain leads, valid now, to
+//! one of the trusted roots.
+//!
+//! With roots configured, see `Node::set_pki_trust`, the leader refuses key
+//! packages that are not certified, members refuse commits adding one, and
+//! joiners refuse a Welcome into a group with a member that is not.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openmls::prelude::{Credential, Extension, ExternalKeyIdExtension, KeyPackage};
+
+use crate::{crypto::credential_identity, error::NodeError};
+
+const MARKER: u8 = 0xC5;
+
+// An Ed25519 SubjectPublicKeyInfo up to the key itself, RFC 8410.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+// What certificates in a chain may be signed with.
+static ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ED25519,
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+];
+
+/// DER certificates, the one for our credential first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateChain(Vec<Vec<u8>>);
+
+impl CertificateChain {
+    pub fn new(certificates: Vec<Vec<u8>>) -> Result<CertificateChain, NodeError> {
+        if certificates.is_empty() {
+            return Err(NodeError::Other(
+                "A certificate chain needs a certificate".into(),
+            ));
+        }
+        if certificates.iter().any(|der| der.len() > u16::MAX as usize) {
+            return Err(NodeError::Other("Certificate too large".to_string()));
+        }
+        Ok(CertificateChain(certificates))
+    }
+
+    /// The chain `key_package` carries, if any.
+    pub fn of(key_package: &KeyPackage) -> Result<Option<CertificateChain>, NodeError> {
+        key_package
+            .extensions()
+            .iter()
+            .find_map(|extension| match extension {
+                Extension::ExternalKeyId(id) if id.as_slice().first() == Some(&MARKER) => {
+                    Some(CertificateChain::decode(id.as_slice()))
+                }
+                _ => None,
+            })
+            .transpose()
+    }
+
+    /// The key package extension carrying the chain.
+    pub fn extension(&self) -> Extension {
+        Extension::ExternalKeyId(ExternalKeyIdExtension::new(&self.encode()))
+    }
+
+    /// Whether the leaf certificate's subject key is `signature_key`.
+    pub fn certifies(&self, signature_key: &[u8]) -> bool {
+        let mut expected = ED25519_SPKI_PREFIX.to_vec();
+        expected.extend_from_slice(signature_key);
+        subject_public_key_info(&self.0[0]) == Some(expected.as_slice())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![MARKER];
+        for der in &self.0 {
+            bytes.extend_from_slice(&(der.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(der);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<CertificateChain, NodeError> {
+        let malformed = || NodeError::Parse("Malformed certificate chain".to_string());
+        let mut rest = match bytes {
+            [MARKER, rest @ ..] => rest,
+            _ => return Err(malformed()),
+        };
+        let mut certificates = Vec::new();
+        while let [high, low, tail @ ..] = rest {
+            let len = u16::from_be_bytes([*high, *low]) as usize;
+            if tail.len() < len {
+                return Err(malformed());
+            }
+            certificates.push(tail[..len].to_vec());
+            rest = &tail[len..];
+        }
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+        CertificateChain::new(certificates)
+    }
+}
+
+/// The roots certificates must lead to.
+#[derive(Debug, Clone)]
+pub struct PkiTrust {
+    roots: Vec<Vec<u8>>,
+}
+
+impl PkiTrust {
+    pub fn new(roots: Vec<Vec<u8>>) -> Result<PkiTrust, NodeError> {
+        if roots.is_empty() {
+            return Err(NodeError::Other("No trusted root certificates".to_string()));
+        }
+        for root in &roots {
+            webpki::TrustAnchor::try_from_cert_der(root)
+                .map_err(|e| NodeError::Parse(format!("Invalid root certificate: {:?}", e)))?;
+        }
+        Ok(PkiTrust { roots })
+    }
+
+    /// Checks that `key_package` carries a chain certifying its credential.
+    pub fn check(&self, key_package: &KeyPackage, now: SystemTime) -> Result<(), NodeError> {
+        let credential = key_package.credential();
+        match CertificateChain::of(key_package)? {
+            Some(chain) => self.verify(&chain, credential, now),
+            None => Err(NodeError::Other(format!(
+                "{} has no certificate",
+                credential_identity(credential)
+            ))),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        chain: &CertificateChain,
+        credential: &Credential,
+        now: SystemTime,
+    ) -> Result<(), NodeError> {
+        let identity = credential_identity(credential);
+        if !chain.certifies(credential.signature_key().as_slice()) {
+            return Err(NodeError::Other(format!(
+                "The certificate of {} is for another key",
+                identity
+            )));
+        }
+        let anchors = self
+            .roots
+            .iter()
+            .map(|root| webpki::TrustAnchor::try_from_cert_der(root))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("checked in PkiTrust::new");
+        let intermediates: Vec<&[u8]> = chain.0[1..].iter().map(Vec::as_slice).collect();
+        let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        webpki::EndEntityCert::try_from(chain.0[0].as_slice())
+            .and_then(|leaf| {
+                leaf.verify_is_valid_tls_client_cert(
+                    ALGORITHMS,
+                    &webpki::TlsClientTrustAnchors(&anchors),
+                    &intermediates,
+                    webpki::Time::from_seconds_since_unix_epoch(seconds),
+                )
+            })
+            .map_err(|e| {
+                NodeError::Other(format!(
+                    "The certificate of {} is invalid: {:?}",
+                    identity, e
+                ))
+            })
+    }
+}
+
+// The subjectPublicKeyInfo of a DER certificate, the seventh field of
+// tbsCertificate counting the optional version, RFC 5280.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, mut fields, _) = der_element(certificate)?;
+    let mut index = 0;
+    while let Some((element, _, rest)) = der_element(fields) {
+        // [0] version
+        if index == 0 && element[0] != 0xa0 {
+            index += 1;
+        }
+        if index == 6 {
+            return Some(element);
+        }
+        index += 1;
+        fields = rest;
+    }
+    None
+}
+
+// Splits off the DER element `der` starts with: its whole encoding, its
+// contents and what follows.
+fn der_element(der: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let (first, rest) = match der {
+        [_, first, rest @ ..] => (*first, rest),
+        _ => return None,
+    };
+    let (len, header) = match first {
+        0..=0x7f => (first as usize, 2),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let len_bytes = rest.get(..count)?;
+            let len = len_bytes
+                .iter()
+                .fold(0usize, |len, byte| len << 8 | *byte as usize);
+            (len, 2 + count)
+        }
+        _ => return None,
+    };
+    let end = header.checked_add(len).filter(|end| *end <= der.len())?;
+    Some((&der[..end], &der[header..end], &der[end..]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crypto::{
+        generate_credential_bundle_from_identity, generate_key_package_bundle_with,
+    };
+    use crate::crypto::{key_store::Backend, CIPHERSUITE};
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+        PublicKeyData, SignatureAlgorithm, PKCS_ED25519,
+    };
+
+    // A credential's raw Ed25519 signature key, for rcgen to certify.
+    struct SignatureKey<'a>(&'a [u8]);
+
+    impl PublicKeyData for SignatureKey<'_> {
+        fn der_bytes(&self) -> &[u8] {
+            self.0
+        }
+
+        fn algorithm(&self) -> &SignatureAlgorithm {
+            &PKCS_ED25519
+        }
+    }
+
+    /// A root and an intermediate issuing certificates for credential keys.
+    pub(crate) struct TestCa {
+        root: Certificate,
+        intermediate: Certificate,
+        intermediate_key: KeyPair,
+    }
+
+    impl TestCa {
+        pub(crate) fn new() -> TestCa {
+            let ca_params = || {
+                let mut params = CertificateParams::new(Vec::new()).unwrap();
+                params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+                params
+            };
+            let root_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+            let root = ca_params().self_signed(&root_key).unwrap();
+            let intermediate_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+            let intermediate = ca_params()
+                .signed_by(&intermediate_key, &root, &root_key)
+                .unwrap();
+            TestCa {
+                root,
+                intermediate,
+                intermediate_key,
+            }
+        }
+
+        pub(crate) fn trust(&self) -> PkiTrust {
+            PkiTrust::new(vec![self.root.der().to_vec()]).unwrap()
+        }
+
+        pub(crate) fn certify(&self, credential: &Credential) -> CertificateChain {
+            let mut params = CertificateParams::new(vec!["member".to_string()]).unwrap();
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+            let key = SignatureKey(credential.signature_key().as_slice());
+            let leaf = params
+                .signed_by(&key, &self.intermediate, &self.intermediate_key)
+                .unwrap();
+            CertificateChain::new(vec![leaf.der().to_vec(), self.intermediate.der().to_vec()])
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn chains_travel_in_key_packages() {
+        let backend = Backend::default();
+        let credential =
+            generate_credential_bundle_from_identity(b"alice".to_vec(), &backend).unwrap();
+        let chain = TestCa::new().certify(&credential);
+        assert_eq!(CertificateChain::decode(&chain.encode()).unwrap(), chain);
+        let truncated = chain.encode();
+        assert!(CertificateChain::decode(&truncated[..truncated.len() - 1]).is_err());
+
+        let plain =
+            generate_key_package_bundle_with(CIPHERSUITE, &credential, Vec::new(), &backend)
+                .unwrap();
+        assert_eq!(CertificateChain::of(&plain).unwrap(), None);
+        let carrying = generate_key_package_bundle_with(
+            CIPHERSUITE,
+            &credential,
+            vec![chain.extension()],
+            &backend,
+        )
+        .unwrap();
+        assert_eq!(CertificateChain::of(&carrying).unwrap(), Some(chain));
+    }
+
+    #[test]
+    fn chains_must_certify_the_credential_key_from_a_trusted_root() {
+        let backend = Backend::default();
+        let alice = generate_credential_bundle_from_identity(b"alice".to_vec(), &backend).unwrap();
+        let bob = generate_credential_bundle_from_identity(b"bob".to_vec(), &backend).unwrap();
+        let ca = TestCa::new();
+        let chain = ca.certify(&alice);
+        assert!(chain.certifies(alice.signature_key().as_slice()));
+        assert!(!chain.certifies(bob.signature_key().as_slice()));
+
+        let now = SystemTime::now();
+        ca.trust().verify(&chain, &alice, now).unwrap();
+        assert!(ca.trust().verify(&chain, &bob, now).is_err());
+        assert!(TestCa::new().trust().verify(&chain, &alice, now).is_err());
+        // Before the certificates were valid.
+        assert!(ca.trust().verify(&chain, &alice, UNIX_EPOCH).is_err());
+        assert!(PkiTrust::new(vec![b"not a certificate".to_vec()]).is_err());
+    }
+}
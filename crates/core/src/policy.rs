@@ -0,0 +1,85 @@
+//! Per-group policy chosen when the group is created.
+//!
+//! The creator puts the encoded policy in the group's additional
+//! authenticated data, so it rides along (authenticated, unencrypted) with
+//! every encrypted message and members adopt it from the traffic they
+//! receive.
+
+use crate::error::NodeError;
+use crate::psk::{PskId, PSK_ID_LEN};
+
+const POLICY_VERSION: u8 = 1;
+const NON_REPUDIATION: u8 = 0b0000_0001;
+const MAX_PRIVACY: u8 = 0b0000_0010;
+// Followed by the id of the pre-shared key.
+const PSK: u8 = 0b0000_0100;
+
+/// Timestamps in outgoing payloads are rounded down to this many seconds
+/// under the maximum privacy preset.
+pub const TIMESTAMP_GRANULARITY_SECS: u32 = 300;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupPolicy {
+    /// Application messages carry an explicit, storable signature by their author.
+    pub non_repudiation: bool,
+    /// Keep MLS deniability: no explicit signatures, coarse timestamps and no
+    /// read receipts.
+    pub max_privacy: bool,
+    /// Payloads are sealed under this pre-shared key, see `psk`.
+    pub psk_id: Option<PskId>,
+}
+
+impl GroupPolicy {
+    /// The "maximum privacy" preset.
+    pub fn max_privacy() -> GroupPolicy {
+        GroupPolicy {
+            max_privacy: true,
+            ..GroupPolicy::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), NodeError> {
+        if self.non_repudiation && self.max_privacy {
+            return Err(NodeError::Other(
+                "Non-repudiation and maximum privacy policies are mutually exclusive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn allows_read_receipts(&self) -> bool {
+        !self.max_privacy
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.non_repudiation {
+            flags |= NON_REPUDIATION;
+        }
+        if self.max_privacy {
+            flags |= MAX_PRIVACY;
+        }
+        if self.psk_id.is_some() {
+            flags |= PSK;
+        }
+        let mut bytes = vec![POLICY_VERSION, flags];
+        bytes.extend(self.psk_id.iter().flatten());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<GroupPolicy> {
+        let (flags, psk_id) = match bytes {
+            [POLICY_VERSION, flags] if flags & PSK == 0 => (*flags, None),
+            [POLICY_VERSION, flags, id @ ..] if flags & PSK != 0 && id.len() == PSK_ID_LEN => {
+                (*flags, Some(id.try_into().expect("length checked")))
+            }
+            _ => return None,
+        };
+        let policy = GroupPolicy {
+            non_repudiation: flags & NON_REPUDIATION != 0,
+            max_privacy: flags & MAX_PRIVACY != 0,
+            psk_id,
+        };
+        policy.validate().ok().map(|_| policy)
+    }
+}
@@ -0,0 +1,320 @@
+//! The envelope every frame travels in on floodsub and through the mailbox.
+//!
+//! A frame is `version: u8 | kind: u8 | body`, so the inbound handler routes
+//! on the kind instead of trying one deserializer after another, and a
+//! frame from a newer version is refused instead of misread. The top two
+//! bits of the kind byte carry the frame's QoS class, see `qos`. Bodies are:
+//!
+//! * key package: a join request, see `admission`;
+//! * Welcome: an [`Invite`], the Welcome's TLS serialization followed by
+//!   the group's name;
+//! * MLS message: its TLS serialization;
+//! * control: `control kind: u8 | body`, carrying recovery messages (see
+//!   `recovery`), room announcements (see `rooms`), join refusals (see
+//!   `lifetime`), groups shared for external joins (see `external`) and
+//!   file chunks (see `transfer`).
+
+use openmls::prelude::{MlsMessageOut, Welcome};
+
+use crate::{
+    admission::JoinRequest, codec, error::NodeError, external::SharedGroup, lifetime::JoinRefusal,
+    node::check_group_name, qos::Qos, recovery::RecoveryMessage, rooms::SignedAnnouncement,
+    transfer::SealedChunk,
+};
+
+pub const WIRE_VERSION: u8 = 1;
+const KIND_BITS: u8 = 0x3F;
+const QOS_SHIFT: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireKind {
+    KeyPackage,
+    Welcome,
+    MlsMessage,
+    Control,
+}
+
+impl WireKind {
+    fn tag(self) -> u8 {
+        match self {
+            WireKind::KeyPackage => 1,
+            WireKind::Welcome => 2,
+            WireKind::MlsMessage => 3,
+            WireKind::Control => 4,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<WireKind> {
+        match tag {
+            1 => Some(WireKind::KeyPackage),
+            2 => Some(WireKind::Welcome),
+            3 => Some(WireKind::MlsMessage),
+            4 => Some(WireKind::Control),
+            _ => None,
+        }
+    }
+}
+
+/// The kind and body of a frame of our version, read without parsing the
+/// body.
+pub fn split(frame: &[u8]) -> Option<(WireKind, &[u8])> {
+    match frame {
+        [WIRE_VERSION, tag, body @ ..] => {
+            WireKind::from_tag(tag & KIND_BITS).map(|kind| (kind, body))
+        }
+        _ => None,
+    }
+}
+
+/// The QoS class a frame was sent with; normal for frames of other versions.
+pub fn qos(frame: &[u8]) -> Qos {
+    match frame {
+        [WIRE_VERSION, tag, ..] => Qos::from_bits(tag >> QOS_SHIFT),
+        _ => Qos::Normal,
+    }
+}
+
+/// A Welcome and the name of the group it is for, which the random group id
+/// does not tell. Invites from before names travelled have none.
+#[derive(Debug, Clone)]
+pub struct Invite {
+    pub welcome: Welcome,
+    pub name: Option<String>,
+}
+
+impl Invite {
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = codec::to_tls(&self.welcome, "Welcome")?;
+        if let Some(name) = &self.name {
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        Ok(bytes)
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Invite, NodeError> {
+        let welcome = codec::from_tls::<Welcome>(&mut bytes, "Welcome")?;
+        let name = match bytes {
+            [] => None,
+            name => {
+                let name = std::str::from_utf8(name)
+                    .map_err(|_| NodeError::Parse("Malformed group name".to_string()))?;
+                check_group_name(name)?;
+                Some(name.to_string())
+            }
+        };
+        Ok(Invite { welcome, name })
+    }
+}
+
+impl From<Welcome> for Invite {
+    fn from(welcome: Welcome) -> Invite {
+        Invite {
+            welcome,
+            name: None,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum ControlMessage {
+    Recovery(RecoveryMessage),
+    RoomAnnouncement(SignedAnnouncement),
+    JoinRefusal(JoinRefusal),
+    SharedGroup(SharedGroup),
+    FileChunk(SealedChunk),
+}
+
+impl ControlMessage {
+    fn tag(&self) -> u8 {
+        match self {
+            ControlMessage::Recovery(_) => 1,
+            ControlMessage::RoomAnnouncement(_) => 2,
+            ControlMessage::JoinRefusal(_) => 3,
+            ControlMessage::SharedGroup(_) => 4,
+            ControlMessage::FileChunk(_) => 5,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum WireMessage {
+    /// A key package asking a leader to admit it.
+    KeyPackage(JoinRequest),
+    Welcome(Invite),
+    /// A commit, proposal or application message for one of our groups.
+    MlsMessage(MlsMessageOut),
+    Control(ControlMessage),
+}
+
+impl WireMessage {
+    pub fn kind(&self) -> WireKind {
+        match self {
+            WireMessage::KeyPackage(_) => WireKind::KeyPackage,
+            WireMessage::Welcome(_) => WireKind::Welcome,
+            WireMessage::MlsMessage(_) => WireKind::MlsMessage,
+            WireMessage::Control(_) => WireKind::Control,
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        self.encode_as(Qos::Normal)
+    }
+
+    /// Encodes the frame to be published, and processed, as `qos`.
+    pub fn encode_as(&self, qos: Qos) -> Result<Vec<u8>, NodeError> {
+        let tag = self.kind().tag() | qos.bits() << QOS_SHIFT;
+        let mut frame = vec![WIRE_VERSION, tag];
+        match self {
+            WireMessage::KeyPackage(request) => frame.extend(request.encode()?),
+            WireMessage::Welcome(invite) => frame.extend(invite.encode()?),
+            WireMessage::MlsMessage(message) => {
+                frame.extend(codec::to_tls(message, "MLS message")?)
+            }
+            WireMessage::Control(control) => {
+                frame.push(control.tag());
+                frame.extend(match control {
+                    ControlMessage::Recovery(message) => message.encode()?,
+                    ControlMessage::RoomAnnouncement(announcement) => announcement.encode()?,
+                    ControlMessage::JoinRefusal(refusal) => refusal.encode(),
+                    ControlMessage::SharedGroup(shared) => shared.encode()?,
+                    ControlMessage::FileChunk(chunk) => chunk.encode(),
+                });
+            }
+        }
+        Ok(frame)
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<WireMessage, NodeError> {
+        let (kind, body) = match frame {
+            [WIRE_VERSION, tag, body @ ..] => match WireKind::from_tag(tag & KIND_BITS) {
+                Some(kind) => (kind, body),
+                None => return Err(NodeError::Parse(format!("Unknown frame kind {}", tag))),
+            },
+            [] | [WIRE_VERSION] => return Err(NodeError::Parse("Truncated frame".to_string())),
+            [version, ..] => {
+                return Err(NodeError::Parse(format!(
+                    "Unsupported wire version {}, we speak {}",
+                    version, WIRE_VERSION
+                )))
+            }
+        };
+        let malformed = |what: &str| NodeError::Parse(format!("Malformed {}", what));
+        Ok(match kind {
+            WireKind::KeyPackage => WireMessage::KeyPackage(JoinRequest::decode(body)?),
+            WireKind::Welcome => WireMessage::Welcome(Invite::decode(body)?),
+            WireKind::MlsMessage => {
+                WireMessage::MlsMessage(codec::from_tls(&mut &*body, "MLS message")?)
+            }
+            WireKind::Control => WireMessage::Control(match body {
+                [1, rest @ ..] => ControlMessage::Recovery(RecoveryMessage::decode(rest)?),
+                [2, rest @ ..] => {
+                    ControlMessage::RoomAnnouncement(SignedAnnouncement::decode(rest)?)
+                }
+                [3, rest @ ..] => ControlMessage::JoinRefusal(JoinRefusal::decode(rest)?),
+                [4, rest @ ..] => ControlMessage::SharedGroup(SharedGroup::decode(rest)?),
+                [5, rest @ ..] => ControlMessage::FileChunk(SealedChunk::decode(rest)?),
+                _ => return Err(malformed("control message")),
+            }),
+        })
+    }
+}
+
+impl From<JoinRequest> for WireMessage {
+    fn from(request: JoinRequest) -> WireMessage {
+        WireMessage::KeyPackage(request)
+    }
+}
+
+impl From<Invite> for WireMessage {
+    fn from(invite: Invite) -> WireMessage {
+        WireMessage::Welcome(invite)
+    }
+}
+
+impl From<MlsMessageOut> for WireMessage {
+    fn from(message: MlsMessageOut) -> WireMessage {
+        WireMessage::MlsMessage(message)
+    }
+}
+
+impl From<RecoveryMessage> for WireMessage {
+    fn from(message: RecoveryMessage) -> WireMessage {
+        WireMessage::Control(ControlMessage::Recovery(message))
+    }
+}
+
+impl From<SignedAnnouncement> for WireMessage {
+    fn from(announcement: SignedAnnouncement) -> WireMessage {
+        WireMessage::Control(ControlMessage::RoomAnnouncement(announcement))
+    }
+}
+
+impl From<JoinRefusal> for WireMessage {
+    fn from(refusal: JoinRefusal) -> WireMessage {
+        WireMessage::Control(ControlMessage::JoinRefusal(refusal))
+    }
+}
+
+impl From<SharedGroup> for WireMessage {
+    fn from(shared: SharedGroup) -> WireMessage {
+        WireMessage::Control(ControlMessage::SharedGroup(shared))
+    }
+}
+
+impl From<SealedChunk> for WireMessage {
+    fn from(chunk: SealedChunk) -> WireMessage {
+        WireMessage::Control(ControlMessage::FileChunk(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+
+    #[test]
+    fn frames_route_on_their_kind() {
+        let mut alice = Node::default();
+        let mut bob = Node::default();
+        alice.join_new_group();
+        let request = WireMessage::from(bob.create_join_request().unwrap())
+            .encode()
+            .unwrap();
+        let (commit, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        let announcement = WireMessage::from(alice.create_room_announcement().unwrap())
+            .encode()
+            .unwrap();
+        let frames = [
+            (request, WireKind::KeyPackage),
+            (
+                WireMessage::from(commit).encode().unwrap(),
+                WireKind::MlsMessage,
+            ),
+            (
+                WireMessage::from(welcome).encode().unwrap(),
+                WireKind::Welcome,
+            ),
+            (announcement, WireKind::Control),
+        ];
+        for (frame, kind) in &frames {
+            assert_eq!(split(frame).unwrap().0, *kind);
+            assert_eq!(WireMessage::decode(frame).unwrap().kind(), *kind);
+        }
+        assert!(matches!(
+            WireMessage::decode(&frames[3].0).unwrap(),
+            WireMessage::Control(ControlMessage::RoomAnnouncement(_))
+        ));
+
+        // A Welcome labelled as an MLS message is refused, not misparsed.
+        let mut mislabelled = frames[2].0.clone();
+        mislabelled[1] = WireKind::MlsMessage.tag();
+        assert!(WireMessage::decode(&mislabelled).is_err());
+        let mut newer = frames[1].0.clone();
+        newer[0] = WIRE_VERSION + 1;
+        assert!(split(&newer).is_none());
+        assert!(WireMessage::decode(&newer).is_err());
+        assert!(WireMessage::decode(&[]).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Offline provisioning of identities for fleet deployments.
+//!
+//! `provision` writes one directory per device holding its private
+//! `identity.json` and public `key_package.bin`, plus a `members.toml`
+//! manifest listing every device so an admin can bootstrap a group with
+//! `node create --manifest`. Devices start with `--identity` and join
+//! automatically once the Welcome reaches them.
+//!
+//! A node that keeps its own identity across restarts, see
+//! `Node::with_identity`, stores the same [`ProvisionedIdentity`] sealed
+//! like an archive, with ChaCha20-Poly1305 under a passphrase-derived key:
+//! `MAGIC | iterations: u32 | salt | nonce | ciphertext`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use openmls::prelude::{
+    CredentialBundle, CredentialType, KeyPackageBundle, OpenMlsCryptoProvider, OpenMlsRand,
+    SignatureScheme,
+};
+use openmls_rust_crypto::OpenMlsRustCrypto;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup::{derive_key, open, seal, SALT_LEN},
+    codec,
+    crypto::CIPHERSUITE,
+    error::NodeError,
+    identity::{Keypair, PeerId},
+    manifest::{Manifest, ManifestMember},
+    schema::{self, Artifact},
+};
+
+pub const IDENTITY_FILE: &str = "identity.json";
+pub const KEY_PACKAGE_FILE: &str = "key_package.bin";
+pub const MANIFEST_FILE: &str = "members.toml";
+
+pub(crate) const MAGIC: &[u8; 8] = b"P2PMLSI1";
+const OBJECT_NAME: &str = "identity";
+
+/// Everything a node needs to come up with a pre-assigned identity.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProvisionedIdentity {
+    /// Protobuf-encoded libp2p keypair.
+    pub network_key: Vec<u8>,
+    pub credential_bundle: CredentialBundle,
+    pub key_package_bundle: KeyPackageBundle,
+}
+
+impl ProvisionedIdentity {
+    pub fn generate() -> Result<ProvisionedIdentity, NodeError> {
+        // Key material is exported, so a throwaway backend is enough here.
+        let backend = OpenMlsRustCrypto::default();
+        let network_key = Keypair::generate_ed25519();
+        let peer_id = PeerId::from_public_key(&network_key.public());
+        let credential_bundle = CredentialBundle::new(
+            peer_id.to_bytes(),
+            CredentialType::Basic,
+            SignatureScheme::ED25519,
+            &backend,
+        )
+        .map_err(|e| NodeError::Other(format!("Could not create credential: {:?}", e)))?;
+        let key_package_bundle =
+            KeyPackageBundle::new(&[CIPHERSUITE], &credential_bundle, &backend, vec![])
+                .map_err(|e| NodeError::Other(format!("Could not create key package: {:?}", e)))?;
+        Ok(ProvisionedIdentity {
+            network_key: network_key.to_protobuf_encoding(),
+            credential_bundle,
+            key_package_bundle,
+        })
+    }
+
+    pub fn network_keypair(&self) -> Result<Keypair, NodeError> {
+        Keypair::from_protobuf_encoding(&self.network_key)
+            .map_err(|e| NodeError::Other(e.to_string()))
+    }
+
+    pub fn peer_id(&self) -> Result<PeerId, NodeError> {
+        Ok(PeerId::from_public_key(&self.network_keypair()?.public()))
+    }
+
+    pub fn load(path: &Path) -> Result<ProvisionedIdentity, NodeError> {
+        let contents = fs::read(path)?;
+        schema::decode(Artifact::Identity, &contents)
+            .map_err(|e| NodeError::Parse(format!("Invalid identity {}: {}", path.display(), e)))
+    }
+
+    /// Writes the identity to `path`, readable by us alone.
+    pub fn save(&self, path: &Path) -> Result<(), NodeError> {
+        schema::write_private(path, &schema::encode(Artifact::Identity, self)?)
+    }
+
+    /// Whether `bytes` are an identity written by [`ProvisionedIdentity::save_sealed`].
+    pub fn is_sealed(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Writes the identity to `path`, readable by us alone, encrypted under
+    /// `passphrase`.
+    pub fn save_sealed(
+        &self,
+        path: &Path,
+        passphrase: &str,
+        iterations: u32,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(), NodeError> {
+        let salt = backend
+            .rand()
+            .random_vec(SALT_LEN)
+            .map_err(|e| NodeError::Other(format!("Could not generate salt: {:?}", e)))?;
+        let key = derive_key(backend, passphrase, &salt, iterations)?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&iterations.to_be_bytes());
+        bytes.extend_from_slice(&salt);
+        bytes.extend(seal(
+            backend,
+            &key,
+            OBJECT_NAME,
+            &schema::encode(Artifact::Identity, self)?,
+        )?);
+        schema::write_private(path, &bytes)
+    }
+
+    pub fn load_sealed(
+        path: &Path,
+        passphrase: &str,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<ProvisionedIdentity, NodeError> {
+        let bytes = fs::read(path)?;
+        let header_len = MAGIC.len() + 4 + SALT_LEN;
+        if bytes.len() < header_len || !ProvisionedIdentity::is_sealed(&bytes) {
+            return Err(NodeError::Parse(format!(
+                "{} is not a sealed identity",
+                path.display()
+            )));
+        }
+        let iterations = u32::from_be_bytes(bytes[8..12].try_into().expect("4 bytes"));
+        let key = derive_key(backend, passphrase, &bytes[12..header_len], iterations)?;
+        let json = open(backend, &key, OBJECT_NAME, &bytes[header_len..])?;
+        schema::decode(Artifact::Identity, &json)
+            .map_err(|e| NodeError::Parse(format!("Invalid identity {}: {}", path.display(), e)))
+    }
+}
+
+/// Provisions `count` devices under `out_dir` and returns the manifest path.
+pub fn provision(count: usize, out_dir: &Path) -> Result<PathBuf, NodeError> {
+    let mut members = Vec::with_capacity(count);
+    for index in 0..count {
+        let identity = ProvisionedIdentity::generate()?;
+        let device = PathBuf::from(format!("device-{}", index));
+        let device_dir = out_dir.join(&device);
+        fs::create_dir_all(&device_dir)?;
+        identity.save(&device_dir.join(IDENTITY_FILE))?;
+        let key_package = codec::to_tls(identity.key_package_bundle.key_package(), "key package")?;
+        fs::write(device_dir.join(KEY_PACKAGE_FILE), key_package)?;
+        members.push(ManifestMember {
+            identity: Some(identity.peer_id()?.to_string()),
+            key_package: device.join(KEY_PACKAGE_FILE),
+        });
+    }
+    let manifest_path = out_dir.join(MANIFEST_FILE);
+    Manifest::new(members, Some(PathBuf::from("welcomes"))).save(&manifest_path)?;
+    Ok(manifest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use crate::protocol::Invite;
+
+    #[test]
+    fn provisioned_device_joins_from_manifest() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-provision-{}", std::process::id()));
+        let manifest_path = provision(2, &dir).unwrap();
+
+        let mut admin = Node::default();
+        admin.join_new_group();
+        let manifest = Manifest::load(&manifest_path).unwrap();
+        let key_packages = manifest.key_packages().unwrap();
+        let (_, welcome) = admin.add_members_to_group(&key_packages).unwrap();
+        manifest.write_welcomes(&key_packages, &welcome).unwrap();
+
+        let identity_path = dir.join("device-0").join(IDENTITY_FILE);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&identity_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let identity = ProvisionedIdentity::load(&identity_path).unwrap();
+        let peer_id = identity.peer_id().unwrap();
+        let mut device = Node::with_provisioned_identity(identity).unwrap();
+        assert_eq!(
+            PeerId::from_public_key(&device.get_network_keypair().public()),
+            peer_id
+        );
+
+        let bytes = fs::read(dir.join("welcomes").join(format!("{}.welcome", peer_id))).unwrap();
+        device
+            .join_existing_group(Invite::decode(&bytes).unwrap())
+            .unwrap();
+        let msg_out = admin.create_message("reading please").unwrap();
+        assert_eq!(
+            device.parse_message(msg_out).unwrap().unwrap(),
+            "reading please"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
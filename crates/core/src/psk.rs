@@ -0,0 +1,115 @@
+//! Pre-shared keys a group can require on top of MLS.
+//!
+//! openmls keeps its PSK proposals private, so the key is mixed in at our
+//! layer instead of the MLS key schedule. A group's policy names the PSK by
+//! id, and every application payload in the group is sealed under a key
+//! extracted from the PSK and a secret exported from the current epoch, see
+//! `crypto::psk_epoch_key`, as `0xF8 | nonce | ciphertext`. Members without
+//! the PSK can process a Welcome and the group's commits, but cannot read
+//! or send anything, and members drop payloads that are not sealed.
+//!
+//! PSKs come from a passphrase shared out of band, stretched with PBKDF2.
+
+use openmls::prelude::{AeadType, HashType, OpenMlsCrypto, OpenMlsCryptoProvider, OpenMlsRand};
+
+use crate::backup::derive_key;
+use crate::crypto::hex_encode;
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xF8;
+const NONCE_LEN: usize = 12;
+pub const PSK_ID_LEN: usize = 8;
+const PASSPHRASE_SALT: &[u8] = b"p2p-mls group psk";
+
+pub type PskId = [u8; PSK_ID_LEN];
+
+#[derive(Clone)]
+pub struct GroupPsk {
+    id: PskId,
+    secret: Vec<u8>,
+}
+
+// Leaves the secret out of logs.
+impl std::fmt::Debug for GroupPsk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GroupPsk({})", hex_encode(&self.id))
+    }
+}
+
+impl GroupPsk {
+    /// The id is the start of the secret's SHA-256, so everyone holding the
+    /// same secret names it the same way.
+    pub fn new(
+        backend: &impl OpenMlsCryptoProvider,
+        secret: Vec<u8>,
+    ) -> Result<GroupPsk, NodeError> {
+        let hash = backend
+            .crypto()
+            .hash(HashType::Sha2_256, &secret)
+            .map_err(|e| NodeError::Other(format!("Could not hash pre-shared key: {:?}", e)))?;
+        let id = hash[..PSK_ID_LEN].try_into().expect("hash is long enough");
+        Ok(GroupPsk { id, secret })
+    }
+
+    pub fn from_passphrase(
+        backend: &impl OpenMlsCryptoProvider,
+        passphrase: &str,
+        iterations: u32,
+    ) -> Result<GroupPsk, NodeError> {
+        GroupPsk::new(
+            backend,
+            derive_key(backend, passphrase, PASSPHRASE_SALT, iterations)?,
+        )
+    }
+
+    pub fn id(&self) -> PskId {
+        self.id
+    }
+
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+}
+
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&MARKER)
+}
+
+/// Seals a payload under an epoch key; the PSK id is authenticated with it.
+pub(crate) fn seal(
+    backend: &impl OpenMlsCryptoProvider,
+    key: &[u8],
+    id: &PskId,
+    payload: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    let nonce = backend
+        .rand()
+        .random_vec(NONCE_LEN)
+        .map_err(|e| NodeError::Other(format!("Could not generate nonce: {:?}", e)))?;
+    let ciphertext = backend
+        .crypto()
+        .aead_encrypt(AeadType::ChaCha20Poly1305, key, payload, &nonce, id)
+        .map_err(|e| NodeError::Other(format!("Could not seal payload: {:?}", e)))?;
+    let mut sealed = vec![MARKER];
+    sealed.extend(nonce);
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+pub(crate) fn open(
+    backend: &impl OpenMlsCryptoProvider,
+    key: &[u8],
+    id: &PskId,
+    sealed: &[u8],
+) -> Result<Vec<u8>, NodeError> {
+    let (nonce, ciphertext) = match sealed {
+        [MARKER, rest @ ..] if rest.len() >= NONCE_LEN => rest.split_at(NONCE_LEN),
+        _ => return Err(NodeError::Parse("Malformed sealed payload".to_string())),
+    };
+    backend
+        .crypto()
+        .aead_decrypt(AeadType::ChaCha20Poly1305, key, ciphertext, nonce, id)
+        .map_err(|_| {
+            NodeError::Other("Payload is not sealed under the group's pre-shared key".to_string())
+        })
+}
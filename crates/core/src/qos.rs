@@ -0,0 +1,263 @@
+//! Quality-of-service classes, so telemetry, chat and bulk transfers can
+//! share a group without getting in each other's way.
+//!
+//! A frame's class travels in the top two bits of its envelope kind byte,
+//! see `protocol`; normal is zero, so frames from before classes existed
+//! read as normal. The network event loop publishes the frames waiting for
+//! it by class, realtime first, see [`OutboundQueue`], and bulk frames a
+//! burst at a time: floodsub opens a stream for each message, and a file's
+//! chunks at once would open a thousand. Receivers process a
+//! realtime message at once or not at all: one from an epoch we have not
+//! reached is dropped instead of held back for its commit, see `pending`,
+//! since a late reading is worth less than none.
+//!
+//! Frames also belong to a [`Plane`]: membership traffic, i.e. handshakes,
+//! join requests, Welcomes and the adverts that follow commits, is the
+//! control plane and goes to each group's control topic, see
+//! `peers::control_topic`; everything else is the data plane. The queue
+//! takes control frames ahead of normal ones, and each plane has a
+//! [`Budget`] of its own per pacing tick, so a flood on one plane delays
+//! neither the other nor itself beyond its budget.
+
+use std::collections::VecDeque;
+
+use crate::{limits::FrameKind, protocol};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Qos {
+    /// Telemetry and anything else that goes stale quickly.
+    Realtime,
+    /// Chat, handshakes and everything not marked otherwise.
+    Normal,
+    /// Large transfers that may wait for the rest.
+    Bulk,
+}
+
+impl Qos {
+    pub(crate) fn bits(self) -> u8 {
+        match self {
+            Qos::Normal => 0,
+            Qos::Realtime => 1,
+            Qos::Bulk => 2,
+        }
+    }
+
+    // Unknown bits, from a newer peer, are treated as normal.
+    pub(crate) fn from_bits(bits: u8) -> Qos {
+        match bits {
+            1 => Qos::Realtime,
+            2 => Qos::Bulk,
+            _ => Qos::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Plane {
+    /// Membership operations and what members announce along with them.
+    Control,
+    /// Chat, telemetry and files.
+    Data,
+}
+
+impl Plane {
+    /// The plane of `frame` by its kind alone; adverts are application
+    /// messages only their sender knows to be control, see
+    /// `Node::frame_plane`.
+    pub fn of(frame: &[u8]) -> Plane {
+        match FrameKind::classify(frame) {
+            FrameKind::Application => Plane::Data,
+            FrameKind::KeyPackage | FrameKind::Handshake | FrameKind::Welcome => Plane::Control,
+        }
+    }
+}
+
+/// How many frames of each plane, and bulk frames among the data, may still
+/// be published before the next pacing tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub control: usize,
+    pub data: usize,
+    pub bulk: usize,
+}
+
+/// Encoded frames waiting to be published, control frames and then the
+/// highest class first, and in arrival order within a class.
+#[derive(Debug, Default)]
+pub struct OutboundQueue {
+    // Control, then realtime, normal and bulk data.
+    queues: [VecDeque<Vec<u8>>; 4],
+}
+
+impl OutboundQueue {
+    pub fn push(&mut self, frame: Vec<u8>) {
+        self.push_on(Plane::of(&frame), frame);
+    }
+
+    /// As [`OutboundQueue::push`], for frames whose plane the sender knows
+    /// better than their kind tells.
+    pub fn push_on(&mut self, plane: Plane, frame: Vec<u8>) {
+        let class = match (plane, protocol::qos(&frame)) {
+            (Plane::Control, _) => 0,
+            (Plane::Data, Qos::Realtime) => 1,
+            (Plane::Data, Qos::Normal) => 2,
+            (Plane::Data, Qos::Bulk) => 3,
+        };
+        self.queues[class].push_back(frame);
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.queues.iter_mut().find_map(VecDeque::pop_front)
+    }
+
+    /// As [`OutboundQueue::pop`], but takes frames of each plane only while
+    /// its share of `budget`, counted down for each, lasts.
+    pub fn pop_within(&mut self, budget: &mut Budget) -> Option<Vec<u8>> {
+        if budget.control > 0 {
+            if let Some(frame) = self.queues[0].pop_front() {
+                budget.control -= 1;
+                return Some(frame);
+            }
+        }
+        if budget.data == 0 {
+            return None;
+        }
+        let frame = match self.queues[1..3].iter_mut().find_map(VecDeque::pop_front) {
+            Some(frame) => frame,
+            None if budget.bulk > 0 => {
+                let frame = self.queues[3].pop_front()?;
+                budget.bulk -= 1;
+                frame
+            }
+            None => return None,
+        };
+        budget.data -= 1;
+        Some(frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{ApplicationPayload, Node};
+    use crate::peers;
+    use crate::protocol::WireMessage;
+    use crate::telemetry::TelemetryFrame;
+
+    #[test]
+    fn realtime_frames_go_first_and_are_not_held_back() {
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+
+        let chat = |node: &mut Node, text: &str| {
+            WireMessage::from(node.create_message(text).unwrap())
+                .encode()
+                .unwrap()
+        };
+        let reading = |node: &mut Node| {
+            let msg_out = node
+                .create_telemetry_message(&TelemetryFrame::scalar_reading(1, 20.5))
+                .unwrap();
+            WireMessage::from(msg_out).encode_as(Qos::Realtime).unwrap()
+        };
+        let mut queue = OutboundQueue::default();
+        let (first, second, urgent) = (
+            chat(&mut alice, "1"),
+            chat(&mut alice, "2"),
+            reading(&mut alice),
+        );
+        let bulk = WireMessage::from(alice.create_message("3").unwrap())
+            .encode_as(Qos::Bulk)
+            .unwrap();
+        for frame in [bulk.clone(), first.clone(), urgent.clone(), second.clone()] {
+            queue.push(frame);
+        }
+        let mut no_bulk = Budget {
+            control: 0,
+            data: 8,
+            bulk: 0,
+        };
+        let paced: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop_within(&mut no_bulk)).collect();
+        assert_eq!(paced.len(), 3);
+        assert_eq!(queue.len(), 1);
+        for frame in paced {
+            queue.push(frame);
+        }
+        let order: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(order, vec![urgent.clone(), first, second, bulk]);
+        assert_eq!(protocol::qos(&urgent), Qos::Realtime);
+        assert!(WireMessage::decode(&urgent).is_ok());
+
+        // From an epoch bob has not reached: chat waits, the reading is dropped.
+        let commit = alice.self_update().unwrap();
+        let (late_chat, late_reading) = (chat(&mut alice, "ahead"), reading(&mut alice));
+        bob.handle_incoming(&alice_peer, &late_reading);
+        bob.handle_incoming(&alice_peer, &late_chat);
+        assert_eq!(bob.held_back(), vec![(2, 1)]);
+        bob.parse_application_message(commit).unwrap();
+        assert_eq!(
+            bob.take_replayed()[0].2,
+            ApplicationPayload::Text("ahead".to_string())
+        );
+    }
+
+    #[test]
+    fn membership_traffic_has_its_own_topic_and_budget() {
+        let (mut alice, bob) = (Node::default(), Node::default());
+        alice.join_new_group();
+        // The backlog for Bob goes out with the adverts.
+        alice
+            .set_retention(crate::backfill::Retention::new(5, None).unwrap())
+            .unwrap();
+        alice.create_message("before bob").unwrap();
+        let encode = |msg_out| WireMessage::from(msg_out).encode().unwrap();
+        let (commit, _) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        let commit = encode(commit);
+        let adverts: Vec<Vec<u8>> = alice.take_adverts().into_iter().map(encode).collect();
+        let chat: Vec<Vec<u8>> = (0..3)
+            .map(|i| encode(alice.create_message(&i.to_string()).unwrap()))
+            .collect();
+
+        let current = alice.group_topics().last().unwrap().clone();
+        assert_eq!(
+            peers::publish_topics(&chat[0], &alice.frame_topics(&chat[0])),
+            vec![current.clone()]
+        );
+        assert!(!adverts.is_empty());
+        for frame in adverts.iter().chain([&commit]) {
+            assert_eq!(alice.frame_plane(frame), Plane::Control);
+            assert!(alice
+                .frame_topics(frame)
+                .iter()
+                .all(|topic| topic.ends_with("/control")));
+        }
+        assert!(alice
+            .all_group_topics()
+            .contains(&peers::control_topic(&current)));
+
+        // Chat beyond its budget waits without holding up the commit.
+        let mut queue = OutboundQueue::default();
+        for frame in chat.iter().chain([&commit]) {
+            queue.push_on(alice.frame_plane(frame), frame.clone());
+        }
+        let mut budget = Budget {
+            control: 1,
+            data: 2,
+            bulk: 0,
+        };
+        let sent: Vec<Vec<u8>> = std::iter::from_fn(|| queue.pop_within(&mut budget)).collect();
+        assert_eq!(sent, vec![commit, chat[0].clone(), chat[1].clone()]);
+        assert_eq!(queue.len(), 1);
+    }
+}
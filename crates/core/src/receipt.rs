@@ -0,0 +1,93 @@
+//! Explicitly signed application payloads for non-repudiation groups.
+//!
+//! MLS only authenticates senders to the other members at the time of
+//! receipt. Under the non-repudiation policy the author additionally signs
+//! `len(group_id) | group_id | epoch | payload` with their credential key, producing
+//! `0xFE | signature | payload`, which a receiver can store and later show to
+//! third parties. Like telemetry, the marker byte can never start UTF-8 text.
+
+use openmls::prelude::{
+    Credential, CredentialBundle, OpenMlsCryptoProvider, Signature, TlsDeserializeTrait,
+};
+
+use crate::{codec, error::NodeError};
+
+const MARKER: u8 = 0xFE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedPayload {
+    pub signature: Signature,
+    pub payload: Vec<u8>,
+}
+
+impl SignedPayload {
+    pub fn is_signed(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn sign(
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+        epoch: u64,
+        payload: &[u8],
+    ) -> Result<SignedPayload, NodeError> {
+        let (_, private_key) = credential_bundle.into_parts();
+        let signature = private_key
+            .sign(backend, &signed_content(group_id, epoch, payload))
+            .map_err(|e| NodeError::Other(format!("Could not sign message: {:?}", e)))?;
+        Ok(SignedPayload {
+            signature,
+            payload: payload.to_vec(),
+        })
+    }
+
+    pub fn verify(
+        &self,
+        credential: &Credential,
+        backend: &impl OpenMlsCryptoProvider,
+        group_id: &[u8],
+        epoch: u64,
+    ) -> Result<(), NodeError> {
+        credential
+            .verify(
+                backend,
+                &signed_content(group_id, epoch, &self.payload),
+                &self.signature,
+            )
+            .map_err(|_| NodeError::Other("Invalid message signature".to_string()))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_tls(&self.signature, "signature")?);
+        bytes.extend_from_slice(&self.payload);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SignedPayload, NodeError> {
+        if !SignedPayload::is_signed(bytes) {
+            return Err(NodeError::Parse("Not a signed payload".to_string()));
+        }
+        let mut rest = &bytes[1..];
+        let signature = Signature::tls_deserialize(&mut rest)
+            .map_err(|_| NodeError::Parse("Malformed message signature".to_string()))?;
+        Ok(SignedPayload {
+            signature,
+            payload: rest.to_vec(),
+        })
+    }
+
+    pub fn signature_bytes(&self) -> Result<Vec<u8>, NodeError> {
+        codec::to_tls(&self.signature, "signature")
+    }
+}
+
+fn signed_content(group_id: &[u8], epoch: u64, payload: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(2 + group_id.len() + 8 + payload.len());
+    content.extend_from_slice(&(group_id.len() as u16).to_be_bytes());
+    content.extend_from_slice(group_id);
+    content.extend_from_slice(&epoch.to_be_bytes());
+    content.extend_from_slice(payload);
+    content
+}
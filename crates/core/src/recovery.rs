@@ -0,0 +1,279 @@
+//! Split-brain detection and recovery.
+//!
+//! Each node remembers a digest of the last few epochs it went through. A
+//! digest is the hash of an MLS exporter secret, so two members share it only
+//! if they derived the same key schedule, and it reveals nothing about the
+//! secret itself. `node recover` broadcasts a signed probe with this history;
+//! every member that can verify the sender answers with its own. Comparing
+//! the answers yields the branches the group has split into, the epoch they
+//! forked at, and which branch is canonical: the one holding the group
+//! leader, or failing that the largest. Members off the canonical branch
+//! drop their state and ask the leader to be re-admitted.
+//!
+//! Frames are `0xFC | JSON`, since the whole point is to talk across a fork
+//! where the other side can no longer decrypt our messages.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use openmls::prelude::{
+    Credential, CredentialBundle, OpenMlsCryptoProvider, Signature, TlsDeserializeTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{codec, crypto::hex_encode, error::NodeError};
+
+const MARKER: u8 = 0xFC;
+const SIGNATURE_LABEL: &[u8] = b"p2p-mls recovery";
+/// How many past epochs each node keeps digests for.
+pub const HISTORY_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDigest {
+    pub identity: String,
+    pub group_id: Vec<u8>,
+    /// As claimed by the sender. Receivers replace it by whether the
+    /// signer holds the leader's leaf in their own view of the group.
+    pub is_leader: bool,
+    /// `(epoch, digest)`, oldest first.
+    pub history: Vec<(u64, Vec<u8>)>,
+}
+
+impl StateDigest {
+    pub fn head(&self) -> Option<&(u64, Vec<u8>)> {
+        self.history.last()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RecoveryKind {
+    /// Asks every member for their state.
+    Probe,
+    /// An answer to a probe.
+    Status,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SignedContent {
+    kind: RecoveryKind,
+    digest: StateDigest,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecoveryMessage {
+    content: String,
+    credential: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl RecoveryMessage {
+    pub fn is_recovery(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn sign(
+        kind: RecoveryKind,
+        digest: StateDigest,
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<RecoveryMessage, NodeError> {
+        let content = codec::to_json_string(&SignedContent { kind, digest }, "recovery state")?;
+        let (credential, private_key) = credential_bundle.into_parts();
+        let signature = private_key
+            .sign(backend, &signed_bytes(&content))
+            .map_err(|e| NodeError::Other(format!("Could not sign recovery message: {:?}", e)))?;
+        Ok(RecoveryMessage {
+            content,
+            credential: codec::to_tls(&credential, "credential")?,
+            signature: codec::to_tls(&signature, "signature")?,
+        })
+    }
+
+    /// Checks the signature and returns the signer with what they sent. The
+    /// caller decides whether the signer's credential is one it trusts.
+    pub fn verify(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(Credential, RecoveryKind, StateDigest), NodeError> {
+        let credential = Credential::tls_deserialize(&mut self.credential.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed recovery credential".to_string()))?;
+        let signature = Signature::tls_deserialize(&mut self.signature.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed recovery signature".to_string()))?;
+        credential
+            .verify(backend, &signed_bytes(&self.content), &signature)
+            .map_err(|_| NodeError::Other("Invalid recovery message signature".to_string()))?;
+        let content: SignedContent = serde_json::from_str(&self.content)
+            .map_err(|e| NodeError::Parse(format!("Invalid recovery message: {}", e)))?;
+        Ok((credential, content.kind, content.digest))
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_json(self, "recovery message")?);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<RecoveryMessage, NodeError> {
+        if !RecoveryMessage::is_recovery(bytes) {
+            return Err(NodeError::Parse("Not a recovery message".to_string()));
+        }
+        serde_json::from_slice(&bytes[1..])
+            .map_err(|e| NodeError::Parse(format!("Invalid recovery message: {}", e)))
+    }
+}
+
+fn signed_bytes(content: &str) -> Vec<u8> {
+    let mut bytes = SIGNATURE_LABEL.to_vec();
+    bytes.extend_from_slice(content.as_bytes());
+    bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub head: (u64, Vec<u8>),
+    pub members: Vec<String>,
+    pub has_leader: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryAction {
+    /// Everyone who answered is on our branch.
+    None,
+    /// We are canonical; the listed members should rejoin.
+    AwaitRejoin(Vec<String>),
+    /// We are off the canonical branch and should ask to be re-admitted.
+    Rejoin,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryReport {
+    /// Canonical branch first.
+    pub branches: Vec<Branch>,
+    /// Last epoch we share with the canonical branch, if any.
+    pub fork_epoch: Option<u64>,
+    pub action: RecoveryAction,
+}
+
+/// Groups `own` and the `peers` that answered into branches by their latest
+/// epoch digest and decides what we should do.
+pub fn analyze(own: &StateDigest, peers: &[StateDigest]) -> Result<RecoveryReport, NodeError> {
+    let own_head = own
+        .head()
+        .ok_or_else(|| NodeError::Other("No epoch history to compare".to_string()))?;
+    let mut branches: HashMap<&(u64, Vec<u8>), Branch> = HashMap::new();
+    for digest in std::iter::once(own).chain(peers) {
+        let head = match digest.head() {
+            Some(head) if digest.group_id == own.group_id => head,
+            _ => continue,
+        };
+        let branch = branches.entry(head).or_insert_with(|| Branch {
+            head: head.clone(),
+            members: Vec::new(),
+            has_leader: false,
+        });
+        branch.members.push(digest.identity.clone());
+        branch.has_leader |= digest.is_leader;
+    }
+    let mut branches: Vec<Branch> = branches.into_values().collect();
+    // Leader first, then the most members, then the furthest epoch.
+    branches.sort_by(|a, b| {
+        (b.has_leader, b.members.len(), b.head.0).cmp(&(a.has_leader, a.members.len(), a.head.0))
+    });
+
+    let canonical = &branches[0];
+    let canonical_history = std::iter::once(own)
+        .chain(peers)
+        .find(|digest| digest.head() == Some(&canonical.head))
+        .map(|digest| &digest.history)
+        .expect("canonical branch has a member");
+    let fork_epoch = own
+        .history
+        .iter()
+        .rev()
+        .find(|entry| canonical_history.contains(entry))
+        .map(|(epoch, _)| *epoch);
+
+    let action = if &canonical.head != own_head {
+        RecoveryAction::Rejoin
+    } else if branches.len() == 1 {
+        RecoveryAction::None
+    } else {
+        RecoveryAction::AwaitRejoin(
+            branches[1..]
+                .iter()
+                .flat_map(|branch| branch.members.clone())
+                .collect(),
+        )
+    };
+    Ok(RecoveryReport {
+        branches,
+        fork_epoch,
+        action,
+    })
+}
+
+impl Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, branch) in self.branches.iter().enumerate() {
+            writeln!(
+                f,
+                "{} epoch {} ({}){}: {}",
+                if i == 0 { "canonical" } else { "fork" },
+                branch.head.0,
+                &hex_encode(&branch.head.1)[..8],
+                if branch.has_leader { ", leader" } else { "" },
+                branch.members.join(", ")
+            )?;
+        }
+        match self.fork_epoch {
+            Some(epoch) => writeln!(f, "branches diverged after epoch {}", epoch)?,
+            None => writeln!(f, "no shared epoch in the remembered history")?,
+        }
+        match &self.action {
+            RecoveryAction::None => write!(f, "no divergence among the members that answered"),
+            RecoveryAction::AwaitRejoin(members) => write!(
+                f,
+                "we are canonical; waiting for {} to rejoin",
+                members.join(", ")
+            ),
+            RecoveryAction::Rejoin => write!(
+                f,
+                "we are off the canonical branch; run `node recover --rejoin` to be re-admitted"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(identity: &str, is_leader: bool, history: &[(u64, u8)]) -> StateDigest {
+        StateDigest {
+            identity: identity.to_string(),
+            group_id: b"group".to_vec(),
+            is_leader,
+            history: history.iter().map(|(e, d)| (*e, vec![*d; 4])).collect(),
+        }
+    }
+
+    #[test]
+    fn minority_branch_rejoins() {
+        let leader = digest("alice", true, &[(1, 1), (2, 2), (3, 3)]);
+        let bob = digest("bob", false, &[(1, 1), (2, 2), (3, 3)]);
+        let carol = digest("carol", false, &[(1, 1), (2, 2), (3, 9)]);
+        let dave = digest("dave", false, &[(1, 1), (2, 2), (3, 9), (4, 10)]);
+
+        let report = analyze(&carol, &[leader.clone(), bob.clone(), dave.clone()]).unwrap();
+        assert_eq!(report.branches[0].members, vec!["alice", "bob"]);
+        assert_eq!(report.fork_epoch, Some(2));
+        assert_eq!(report.action, RecoveryAction::Rejoin);
+
+        let report = analyze(&leader, &[bob.clone(), carol.clone(), dave]).unwrap();
+        assert_eq!(report.branches.len(), 3);
+        assert!(matches!(report.action, RecoveryAction::AwaitRejoin(ref m) if m.len() == 2));
+
+        let report = analyze(&bob, &[leader]).unwrap();
+        assert_eq!(report.action, RecoveryAction::None);
+    }
+}
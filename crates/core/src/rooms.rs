@@ -0,0 +1,324 @@
+//! Announcements of groups that are open to join, for `node rooms`.
+//!
+//! A leader started with `--announce` periodically publishes a signed
+//! [`RoomAnnouncement`] on the rendezvous topic: its identity and
+//! fingerprint, the group's id and name, its join policy, how many
+//! members it has and when it was signed. Frames
+//! are `0xFA | JSON`. Receivers drop announcements signed more than
+//! [`MAX_ANNOUNCEMENT_AGE`] from their own clock, so old ones cannot be
+//! replayed to list a room that is gone or changed, and keep the latest
+//! announcement per leader in a [`RoomDirectory`], together with how they
+//! found the announcing peer, and forget rooms that stop announcing.
+
+use std::fmt::Display;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use openmls::prelude::{
+    Credential, CredentialBundle, OpenMlsCryptoProvider, Signature, TlsDeserializeTrait,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admission::AdmissionConfig,
+    codec,
+    crypto::{credential_identity, fingerprint::Fingerprint, hex_encode},
+    error::NodeError,
+    peers::DiscoverySource,
+};
+
+const MARKER: u8 = 0xFA;
+const SIGNATURE_LABEL: &[u8] = b"p2p-mls room";
+/// How often leaders announce their room.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+// Rooms are listed until they miss a few announcements.
+const ROOM_TTL: Duration = Duration::from_secs(3 * 10);
+/// How far an announcement's timestamp may be from our clock, either way.
+pub const MAX_ANNOUNCEMENT_AGE: Duration = Duration::from_secs(60);
+
+/// What a join request has to carry for the leader to consider it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JoinPolicy {
+    pub proof_of_work: Option<u8>,
+    pub psk: bool,
+}
+
+impl JoinPolicy {
+    pub fn of(config: &AdmissionConfig) -> JoinPolicy {
+        JoinPolicy {
+            proof_of_work: config.proof_of_work,
+            psk: config.psk.is_some(),
+        }
+    }
+}
+
+impl Display for JoinPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut badges = Vec::new();
+        if self.psk {
+            badges.push("invite only".to_string());
+        }
+        if let Some(bits) = self.proof_of_work {
+            badges.push(format!("pow {}", bits));
+        }
+        if badges.is_empty() {
+            badges.push("open".to_string());
+        }
+        write!(f, "[{}]", badges.join("] ["))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomAnnouncement {
+    pub leader: String,
+    pub fingerprint: String,
+    /// Hex; empty, like the name, in announcements from older releases.
+    #[serde(default)]
+    pub group_id: String,
+    #[serde(default)]
+    pub name: String,
+    pub members: usize,
+    pub policy: JoinPolicy,
+    /// Unix seconds when the leader signed it; 0 from older releases,
+    /// whose announcements are therefore refused as stale.
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedAnnouncement {
+    content: String,
+    credential: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl SignedAnnouncement {
+    pub fn is_announcement(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn sign(
+        group_id: &[u8],
+        name: &str,
+        members: usize,
+        policy: JoinPolicy,
+        credential_bundle: CredentialBundle,
+        backend: &impl OpenMlsCryptoProvider,
+        now: SystemTime,
+    ) -> Result<SignedAnnouncement, NodeError> {
+        let (credential, private_key) = credential_bundle.into_parts();
+        let announcement = RoomAnnouncement {
+            leader: credential_identity(&credential),
+            fingerprint: Fingerprint::of_credential(&credential, backend).to_string(),
+            group_id: hex_encode(group_id),
+            name: name.to_string(),
+            members,
+            policy,
+            timestamp: unix_seconds(now),
+        };
+        let content = codec::to_json_string(&announcement, "room announcement")?;
+        let signature = private_key
+            .sign(backend, &signed_bytes(&content))
+            .map_err(|e| NodeError::Other(format!("Could not sign room announcement: {:?}", e)))?;
+        Ok(SignedAnnouncement {
+            content,
+            credential: codec::to_tls(&credential, "credential")?,
+            signature: codec::to_tls(&signature, "signature")?,
+        })
+    }
+
+    /// Checks the signature, that the announced leader and fingerprint
+    /// are those of the signing credential, and that it was signed within
+    /// [`MAX_ANNOUNCEMENT_AGE`] of `now`.
+    pub fn verify(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+        now: SystemTime,
+    ) -> Result<RoomAnnouncement, NodeError> {
+        let credential = Credential::tls_deserialize(&mut self.credential.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed room credential".to_string()))?;
+        let signature = Signature::tls_deserialize(&mut self.signature.as_slice())
+            .map_err(|_| NodeError::Parse("Malformed room signature".to_string()))?;
+        credential
+            .verify(backend, &signed_bytes(&self.content), &signature)
+            .map_err(|_| NodeError::Other("Invalid room announcement signature".to_string()))?;
+        let announcement: RoomAnnouncement = serde_json::from_str(&self.content)
+            .map_err(|e| NodeError::Parse(format!("Invalid room announcement: {}", e)))?;
+        if announcement.leader != credential_identity(&credential)
+            || announcement.fingerprint
+                != Fingerprint::of_credential(&credential, backend).to_string()
+        {
+            return Err(NodeError::Other(
+                "Room announcement does not match its signer".to_string(),
+            ));
+        }
+        if announcement.timestamp.abs_diff(unix_seconds(now)) > MAX_ANNOUNCEMENT_AGE.as_secs() {
+            return Err(NodeError::Other(format!(
+                "Stale room announcement from {}",
+                announcement.leader
+            )));
+        }
+        Ok(announcement)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, NodeError> {
+        let mut bytes = vec![MARKER];
+        bytes.extend(codec::to_json(self, "room announcement")?);
+        Ok(bytes)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SignedAnnouncement, NodeError> {
+        if !SignedAnnouncement::is_announcement(bytes) {
+            return Err(NodeError::Parse("Not a room announcement".to_string()));
+        }
+        serde_json::from_slice(&bytes[1..])
+            .map_err(|e| NodeError::Parse(format!("Invalid room announcement: {}", e)))
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs()
+}
+
+fn signed_bytes(content: &str) -> Vec<u8> {
+    let mut bytes = SIGNATURE_LABEL.to_vec();
+    bytes.extend_from_slice(content.as_bytes());
+    bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Room {
+    pub announcement: RoomAnnouncement,
+    pub source: DiscoverySource,
+    seen: Instant,
+}
+
+/// Rooms in the order they were first seen, so listing numbers stay put
+/// while the listing is refreshed.
+#[derive(Debug, Default)]
+pub struct RoomDirectory {
+    rooms: Vec<Room>,
+}
+
+impl RoomDirectory {
+    pub fn record(
+        &mut self,
+        announcement: RoomAnnouncement,
+        source: DiscoverySource,
+        now: Instant,
+    ) {
+        match self
+            .rooms
+            .iter_mut()
+            .find(|room| room.announcement.leader == announcement.leader)
+        {
+            // An older announcement replayed within the window.
+            Some(room) if room.announcement.timestamp > announcement.timestamp => {}
+            Some(room) => {
+                room.announcement = announcement;
+                room.source = source;
+                room.seen = now;
+            }
+            None => self.rooms.push(Room {
+                announcement,
+                source,
+                seen: now,
+            }),
+        }
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        self.rooms
+            .retain(|room| now.duration_since(room.seen) < ROOM_TTL);
+    }
+
+    /// The room listed as `#index`, counting from 1.
+    pub fn get(&self, index: usize) -> Option<&Room> {
+        index.checked_sub(1).and_then(|i| self.rooms.get(i))
+    }
+
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+}
+
+impl Display for RoomDirectory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.rooms.is_empty() {
+            return write!(f, "no rooms announced nearby");
+        }
+        let lines: Vec<String> = self
+            .rooms
+            .iter()
+            .enumerate()
+            .map(|(i, room)| {
+                let announcement = &room.announcement;
+                format!(
+                    "#{} {}{} ~{} members, leader {} ({}) via {}",
+                    i + 1,
+                    match announcement.name.as_str() {
+                        "" => String::new(),
+                        name => format!("{} ", name),
+                    },
+                    announcement.policy,
+                    announcement.members,
+                    announcement.leader,
+                    announcement.fingerprint,
+                    room.source
+                )
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+    use openmls_rust_crypto::OpenMlsRustCrypto;
+
+    #[test]
+    fn rooms_are_listed_from_verified_announcements() {
+        let backend = OpenMlsRustCrypto::default();
+        let mut leader = Node::default();
+        leader.join_new_group();
+        leader.set_admission_config(AdmissionConfig {
+            proof_of_work: Some(4),
+            ..AdmissionConfig::default()
+        });
+        let frame = leader.create_room_announcement().unwrap().encode().unwrap();
+
+        let signed = SignedAnnouncement::decode(&frame).unwrap();
+        let announcement = signed.verify(&backend, SystemTime::now()).unwrap();
+        assert_eq!(announcement.members, 1);
+        assert_eq!(announcement.name, "Test Group");
+        assert_eq!(
+            announcement.group_id,
+            hex_encode(&leader.group_info().unwrap().group_id)
+        );
+        assert_eq!(announcement.fingerprint, leader.fingerprint().to_string());
+        assert_eq!(announcement.policy.to_string(), "[pow 4]");
+
+        let mut forged = SignedAnnouncement::decode(&frame).unwrap();
+        forged.content = forged.content.replace("\"members\":1", "\"members\":9");
+        assert!(forged.verify(&backend, SystemTime::now()).is_err());
+        let later = SystemTime::now() + MAX_ANNOUNCEMENT_AGE + Duration::from_secs(1);
+        assert!(signed.verify(&backend, later).is_err());
+
+        let now = Instant::now();
+        let mut directory = RoomDirectory::default();
+        let mut newer = announcement.clone();
+        newer.timestamp += 10;
+        newer.members = 2;
+        directory.record(newer, DiscoverySource::Mdns, now);
+        directory.record(announcement, DiscoverySource::Mdns, now);
+        assert_eq!(directory.rooms().len(), 1);
+        assert_eq!(directory.get(1).unwrap().announcement.members, 2);
+        assert!(directory.get(1).is_some());
+        assert!(directory.get(0).is_none());
+        directory.expire(now + ROOM_TTL);
+        assert!(directory.rooms().is_empty());
+    }
+}
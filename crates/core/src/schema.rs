@@ -0,0 +1,444 @@
+//! Versioned formats for what the node persists, and migrations between them.
+//!
+//! Identities, group state, message history, archives and key stores are
+//! stored as `{"schema": N, "kind": ..., "data": ...}`. Anything written
+//! before formats were versioned is schema 0. Reading runs every migration from the stored
+//! schema up to [`CURRENT_SCHEMA`], so old files keep loading, and
+//! `node migrate` rewrites files in the current format, keeping the original
+//! next to it as `<file>.bak`. Files from a newer release are refused rather
+//! than misread.
+//!
+//! History migrates entry by entry. It is stored as a JSON array in backups
+//! and, in an `--audit-log` file, as JSON lines after a header line
+//! `{"schema": N, "kind": "history"}`.
+
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{codec, error::NodeError};
+
+pub const CURRENT_SCHEMA: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Artifact {
+    Identity,
+    Group,
+    History,
+    Archive,
+    KeyStore,
+    Journal,
+}
+
+impl Display for Artifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Artifact::Identity => "identity",
+            Artifact::Group => "group state",
+            Artifact::History => "history",
+            Artifact::Archive => "archive",
+            Artifact::KeyStore => "key store",
+            Artifact::Journal => "group journal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Upgrades `artifact` data from schema `from` to `from + 1`. Artifacts
+/// without a migration for a version carry over unchanged.
+struct Migration {
+    artifact: Artifact,
+    from: u32,
+    apply: fn(&mut Value) -> Result<(), NodeError>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        artifact: Artifact::Group,
+        from: 0,
+        apply: add_group_topics,
+    },
+    Migration {
+        artifact: Artifact::Group,
+        from: 1,
+        apply: add_history_secret,
+    },
+    Migration {
+        artifact: Artifact::Group,
+        from: 2,
+        apply: add_group_name,
+    },
+    Migration {
+        artifact: Artifact::Group,
+        from: 3,
+        apply: add_admins,
+    },
+];
+
+// Schema 1 records the topics group traffic is published on. Schema 0 groups
+// get none, and pick a fresh topic when loaded.
+fn add_group_topics(data: &mut Value) -> Result<(), NodeError> {
+    let group = data
+        .as_object_mut()
+        .ok_or_else(|| NodeError::Parse("Group state is not an object".to_string()))?;
+    group
+        .entry("topics")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    Ok(())
+}
+
+// Schema 2 keeps the secret the group's history is sealed under, see
+// `audit`. Older groups get none, and export one when loaded.
+fn add_history_secret(data: &mut Value) -> Result<(), NodeError> {
+    let group = data
+        .as_object_mut()
+        .ok_or_else(|| NodeError::Parse("Group state is not an object".to_string()))?;
+    group
+        .entry("history_secret")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    Ok(())
+}
+
+// Schema 3 keeps the name of the group, now that ids are random. Older
+// groups get none, and go by their id, which was their name.
+fn add_group_name(data: &mut Value) -> Result<(), NodeError> {
+    let group = data
+        .as_object_mut()
+        .ok_or_else(|| NodeError::Parse("Group state is not an object".to_string()))?;
+    group
+        .entry("name")
+        .or_insert_with(|| Value::String(String::new()));
+    Ok(())
+}
+
+// Schema 4 keeps the admin roster of the group, see `admins`. Older groups
+// have no admins.
+fn add_admins(data: &mut Value) -> Result<(), NodeError> {
+    let group = data
+        .as_object_mut()
+        .ok_or_else(|| NodeError::Parse("Group state is not an object".to_string()))?;
+    group
+        .entry("admins")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    schema: u32,
+    kind: Artifact,
+    #[serde(default)]
+    data: Value,
+}
+
+impl Envelope {
+    // Files without an envelope are schema 0 of whatever `legacy` is.
+    fn parse(
+        bytes: &[u8],
+        legacy: impl FnOnce(&Value) -> Option<Artifact>,
+    ) -> Result<Envelope, NodeError> {
+        let value: Value = serde_json::from_slice(bytes).map_err(invalid)?;
+        if value.get("schema").is_some() && value.get("kind").is_some() {
+            return serde_json::from_value(value).map_err(invalid);
+        }
+        let kind = legacy(&value)
+            .ok_or_else(|| NodeError::Parse("Unrecognised file format".to_string()))?;
+        Ok(Envelope {
+            schema: 0,
+            kind,
+            data: value,
+        })
+    }
+
+    fn upgrade(&mut self) -> Result<(), NodeError> {
+        upgrade(self.kind, self.schema, &mut self.data)?;
+        self.schema = CURRENT_SCHEMA;
+        Ok(())
+    }
+}
+
+fn invalid(e: serde_json::Error) -> NodeError {
+    NodeError::Parse(format!("Invalid stored data: {}", e))
+}
+
+fn upgrade(artifact: Artifact, schema: u32, data: &mut Value) -> Result<(), NodeError> {
+    if schema > CURRENT_SCHEMA {
+        return Err(NodeError::Parse(format!(
+            "Stored {} has schema {}, newer than this release supports ({})",
+            artifact, schema, CURRENT_SCHEMA
+        )));
+    }
+    for version in schema..CURRENT_SCHEMA {
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|m| m.artifact == artifact && m.from == version)
+        {
+            match (artifact, &mut *data) {
+                (Artifact::History, Value::Array(entries)) => {
+                    entries.iter_mut().try_for_each(migration.apply)?
+                }
+                (_, data) => (migration.apply)(data)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serializes `value` as the current schema of `artifact`.
+pub fn encode<T: Serialize + ?Sized>(artifact: Artifact, value: &T) -> Result<Vec<u8>, NodeError> {
+    codec::to_json(
+        &Envelope {
+            schema: CURRENT_SCHEMA,
+            kind: artifact,
+            data: serde_json::to_value(value)
+                .map_err(|e| NodeError::serialize(&artifact.to_string(), e))?,
+        },
+        &artifact.to_string(),
+    )
+}
+
+/// Reads `artifact` written with any schema up to the current one.
+pub fn decode<T: DeserializeOwned>(artifact: Artifact, bytes: &[u8]) -> Result<T, NodeError> {
+    let mut envelope = Envelope::parse(bytes, |_| Some(artifact))?;
+    if envelope.kind != artifact {
+        return Err(NodeError::Parse(format!(
+            "Expected {}, found {}",
+            artifact, envelope.kind
+        )));
+    }
+    envelope.upgrade()?;
+    serde_json::from_value(envelope.data).map_err(invalid)
+}
+
+/// The first line of a history file.
+pub fn history_header() -> Vec<u8> {
+    let mut line = serde_json::to_vec(&serde_json::json!({
+        "schema": CURRENT_SCHEMA,
+        "kind": Artifact::History,
+    }))
+    .expect("header should serialize");
+    line.push(b'\n');
+    line
+}
+
+/// Reads a history file, with or without its header line.
+pub fn decode_history<T: DeserializeOwned>(bytes: &[u8]) -> Result<Vec<T>, NodeError> {
+    let (schema, entries) = history_lines(bytes)?;
+    let mut entries = Value::Array(entries);
+    upgrade(Artifact::History, schema, &mut entries)?;
+    serde_json::from_value(entries).map_err(invalid)
+}
+
+fn history_lines(bytes: &[u8]) -> Result<(u32, Vec<Value>), NodeError> {
+    let mut lines = bytes
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice::<Value>(line).map_err(invalid))
+        .peekable();
+    let schema = match lines.peek() {
+        Some(Ok(header)) if header.get("kind") == Some(&Value::from("history")) => {
+            let schema = header.get("schema").and_then(Value::as_u64).unwrap_or(0);
+            lines.next();
+            schema as u32
+        }
+        _ => 0,
+    };
+    Ok((schema, lines.collect::<Result<_, _>>()?))
+}
+
+/// What `migrate_file` did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Migrated {
+    Current(Artifact),
+    Upgraded {
+        artifact: Artifact,
+        from: u32,
+        original: PathBuf,
+    },
+}
+
+impl Display for Migrated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Migrated::Current(artifact) => {
+                write!(f, "{} already at schema {}", artifact, CURRENT_SCHEMA)
+            }
+            Migrated::Upgraded {
+                artifact,
+                from,
+                original,
+            } => write!(
+                f,
+                "{} migrated from schema {} to {}, original kept at {}",
+                artifact,
+                from,
+                CURRENT_SCHEMA,
+                original.display()
+            ),
+        }
+    }
+}
+
+/// Rewrites the file at `path` in the current schema. Archives are sealed,
+/// so they are upgraded in memory whenever they are opened instead.
+pub fn migrate_file(path: &Path) -> Result<Migrated, NodeError> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(crate::archive::MAGIC) {
+        return Err(NodeError::Parse(
+            "Archives are sealed and upgrade when opened".to_string(),
+        ));
+    }
+    if bytes.starts_with(crate::provision::MAGIC) {
+        return Err(NodeError::Parse(
+            "Sealed identities upgrade when loaded".to_string(),
+        ));
+    }
+    if bytes.starts_with(crate::crypto::key_store::MAGIC) {
+        return Err(NodeError::Parse(
+            "Sealed key stores upgrade when loaded".to_string(),
+        ));
+    }
+    let (artifact, from, migrated) = if is_history_file(&bytes) {
+        let (from, entries) = history_lines(&bytes)?;
+        let mut entries = Value::Array(entries);
+        upgrade(Artifact::History, from, &mut entries)?;
+        let mut migrated = history_header();
+        for entry in entries.as_array().expect("history entries") {
+            migrated.extend(codec::to_json(entry, "history entry")?);
+            migrated.push(b'\n');
+        }
+        (Artifact::History, from, migrated)
+    } else {
+        let mut envelope = Envelope::parse(&bytes, legacy_kind)?;
+        let from = envelope.schema;
+        envelope.upgrade()?;
+        let migrated = codec::to_json(&envelope, "stored state")?;
+        (envelope.kind, from, migrated)
+    };
+    if from == CURRENT_SCHEMA {
+        return Ok(Migrated::Current(artifact));
+    }
+    let mut original = path.as_os_str().to_owned();
+    original.push(".bak");
+    let original = PathBuf::from(original);
+    write_private(&original, &bytes)?;
+    write_private(path, &migrated)?;
+    Ok(Migrated::Upgraded {
+        artifact,
+        from,
+        original,
+    })
+}
+
+/// Replaces the file at `path` with `bytes`, readable by us alone. They go
+/// to `<path>.partial` first and are renamed into place once synced, so a
+/// crash leaves either the old file or the new one.
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> Result<(), NodeError> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&partial)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+// JSON lines starting with a history header or an entry.
+fn is_history_file(bytes: &[u8]) -> bool {
+    let first = bytes.split(|b| *b == b'\n').find(|line| !line.is_empty());
+    match first.and_then(|line| serde_json::from_slice::<Value>(line).ok()) {
+        Some(value) => {
+            (value.get("kind") == Some(&Value::from("history")) && value.get("data").is_none())
+                || value.get("SignedMessage").is_some()
+        }
+        None => false,
+    }
+}
+
+// Tells unversioned files apart by their fields.
+fn legacy_kind(value: &Value) -> Option<Artifact> {
+    if value.get("network_key").is_some() {
+        Some(Artifact::Identity)
+    } else if value.get("state").is_some() && value.get("is_group_leader").is_some() {
+        Some(Artifact::Group)
+    } else if value.is_array() {
+        Some(Artifact::History)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEntry;
+
+    #[test]
+    fn old_schemas_migrate_to_current() {
+        #[derive(Debug, Deserialize)]
+        struct Group {
+            topics: Vec<String>,
+            history_secret: Vec<u8>,
+            name: String,
+            admins: Vec<u8>,
+        }
+        let legacy = br#"{"state":[],"is_group_leader":true,"policy":[]}"#;
+        let group: Group = decode(Artifact::Group, legacy).unwrap();
+        assert!(group.topics.is_empty());
+        assert!(group.history_secret.is_empty());
+        assert!(group.name.is_empty());
+        assert!(group.admins.is_empty());
+        assert!(
+            decode::<Group>(Artifact::Identity, &encode(Artifact::Group, &()).unwrap()).is_err()
+        );
+        let future = br#"{"schema":99,"kind":"group","data":{}}"#;
+        assert!(decode::<Group>(Artifact::Group, future).is_err());
+
+        let entry = AuditEntry::SignedMessage {
+            group_id: b"group".to_vec(),
+            epoch: 1,
+            signer: "alice".to_string(),
+            message: b"hi".to_vec(),
+            signature: Vec::new(),
+        };
+        let dir = std::env::temp_dir().join(format!("p2p-mls-schema-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let mut lines = serde_json::to_vec(&entry).unwrap();
+        lines.push(b'\n');
+        fs::write(&path, &lines).unwrap();
+
+        let migrated = migrate_file(&path).unwrap();
+        assert!(matches!(
+            migrated,
+            Migrated::Upgraded {
+                artifact: Artifact::History,
+                from: 0,
+                ..
+            }
+        ));
+        assert_eq!(fs::read(dir.join("audit.jsonl.bak")).unwrap(), lines);
+        #[cfg(unix)]
+        for file in [&path, &dir.join("audit.jsonl.bak")] {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let entries: Vec<AuditEntry> = decode_history(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(entries, vec![entry]);
+        assert_eq!(
+            migrate_file(&path).unwrap(),
+            Migrated::Current(Artifact::History)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
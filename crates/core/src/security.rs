@@ -0,0 +1,26 @@
+//! The active group's security at a glance, for a client to show where the
+//! user is typing, as the `mls` prompt does.
+
+use crate::policy::GroupPolicy;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityState {
+    /// `None` until the node is in a group.
+    pub epoch: Option<u64>,
+    pub members: usize,
+    pub verified_members: usize,
+    pub policy: GroupPolicy,
+    /// Several recent messages could not be processed.
+    pub degraded: bool,
+    /// The last failing message was for a different epoch than ours.
+    pub desynced: bool,
+}
+
+impl SecurityState {
+    pub fn verified_percent(&self) -> u32 {
+        if self.members == 0 {
+            return 0;
+        }
+        (self.verified_members * 100 / self.members) as u32
+    }
+}
@@ -0,0 +1,203 @@
+//! Compact binary application messages for sensors.
+//!
+//! A frame is `0xFF | schema: u16 | version: u8 | payload`, all big endian.
+//! The leading `0xFF` can never start valid UTF-8, so frames cannot be
+//! confused with chat text. Payload layouts are fixed per (schema, version)
+//! and decoded on the receiving side through a [`DecoderRegistry`].
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::NodeError;
+
+const MARKER: u8 = 0xFF;
+const HEADER_LEN: usize = 4;
+
+/// Schema 1: a single scalar reading, `sensor: u16 | timestamp: u32 | value: f32`.
+pub const SCALAR_READING: (u16, u8) = (1, 1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryFrame {
+    pub schema: u16,
+    pub version: u8,
+    pub payload: Vec<u8>,
+}
+
+impl TelemetryFrame {
+    pub fn is_telemetry(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        bytes.push(MARKER);
+        bytes.extend_from_slice(&self.schema.to_be_bytes());
+        bytes.push(self.version);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<TelemetryFrame, NodeError> {
+        if bytes.len() < HEADER_LEN || !TelemetryFrame::is_telemetry(bytes) {
+            return Err(NodeError::Parse("Not a telemetry frame".to_string()));
+        }
+        Ok(TelemetryFrame {
+            schema: u16::from_be_bytes([bytes[1], bytes[2]]),
+            version: bytes[3],
+            payload: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// Rounds the timestamp of known schemas down to `granularity` seconds.
+    pub fn round_timestamp(&mut self, granularity: u32) {
+        if (self.schema, self.version) != SCALAR_READING || self.payload.len() != 10 {
+            return;
+        }
+        let timestamp = u32::from_be_bytes(self.payload[2..6].try_into().expect("4 bytes"));
+        let rounded = timestamp - timestamp % granularity;
+        self.payload[2..6].copy_from_slice(&rounded.to_be_bytes());
+    }
+
+    /// Builds a [`SCALAR_READING`] frame stamped with the current time.
+    pub fn scalar_reading(sensor: u16, value: f32) -> TelemetryFrame {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+            .as_secs() as u32;
+        let mut payload = Vec::with_capacity(10);
+        payload.extend_from_slice(&sensor.to_be_bytes());
+        payload.extend_from_slice(&timestamp.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+        TelemetryFrame {
+            schema: SCALAR_READING.0,
+            version: SCALAR_READING.1,
+            payload,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Unsigned(v) => write!(f, "{}", v),
+            FieldValue::Signed(v) => write!(f, "{}", v),
+            FieldValue::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A decoded frame: named fields in schema order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Telemetry {
+    pub schema: u16,
+    pub version: u8,
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+impl Display for Telemetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[telemetry {}v{}]", self.schema, self.version)?;
+        for (name, value) in &self.fields {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+pub type Decoder = fn(&[u8]) -> Option<Vec<(String, FieldValue)>>;
+
+/// Maps (schema, version) to the decoder for its payload layout.
+#[derive(Debug)]
+pub struct DecoderRegistry {
+    decoders: HashMap<(u16, u8), Decoder>,
+}
+
+impl Default for DecoderRegistry {
+    fn default() -> DecoderRegistry {
+        let mut registry = DecoderRegistry {
+            decoders: HashMap::new(),
+        };
+        registry.register(SCALAR_READING.0, SCALAR_READING.1, decode_scalar_reading);
+        registry
+    }
+}
+
+impl DecoderRegistry {
+    pub fn register(&mut self, schema: u16, version: u8, decoder: Decoder) {
+        self.decoders.insert((schema, version), decoder);
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Telemetry, NodeError> {
+        let frame = TelemetryFrame::decode(bytes)?;
+        let decoder = self
+            .decoders
+            .get(&(frame.schema, frame.version))
+            .ok_or_else(|| {
+                NodeError::Other(format!(
+                    "No decoder for telemetry schema {} version {}",
+                    frame.schema, frame.version
+                ))
+            })?;
+        let fields = decoder(&frame.payload).ok_or_else(|| {
+            NodeError::Parse(format!(
+                "Malformed telemetry payload for schema {} version {}",
+                frame.schema, frame.version
+            ))
+        })?;
+        Ok(Telemetry {
+            schema: frame.schema,
+            version: frame.version,
+            fields,
+        })
+    }
+}
+
+fn decode_scalar_reading(payload: &[u8]) -> Option<Vec<(String, FieldValue)>> {
+    if payload.len() != 10 {
+        return None;
+    }
+    let sensor = u16::from_be_bytes(payload[0..2].try_into().ok()?);
+    let timestamp = u32::from_be_bytes(payload[2..6].try_into().ok()?);
+    let value = f32::from_be_bytes(payload[6..10].try_into().ok()?);
+    Some(vec![
+        ("sensor".to_string(), FieldValue::Unsigned(sensor.into())),
+        (
+            "timestamp".to_string(),
+            FieldValue::Unsigned(timestamp.into()),
+        ),
+        ("value".to_string(), FieldValue::Float(value.into())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scalar_reading_round_trip() {
+        let encoded = TelemetryFrame::scalar_reading(7, 21.5).encode();
+        assert_eq!(encoded.len(), 14);
+        assert!(std::str::from_utf8(&encoded).is_err());
+
+        let telemetry = DecoderRegistry::default().decode(&encoded).unwrap();
+        assert_eq!(telemetry.fields[0].1, FieldValue::Unsigned(7));
+        assert_eq!(telemetry.fields[2].1, FieldValue::Float(21.5));
+
+        let unknown = TelemetryFrame {
+            schema: 9,
+            version: 1,
+            payload: vec![],
+        };
+        assert!(DecoderRegistry::default()
+            .decode(&unknown.encode())
+            .is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! Files sent to the group in sealed chunks.
+//!
+//! `node sendfile <path>` first sends an [`Offer`] as an application
+//! message, so members learn who sends the file, its name, size and hash
+//! from a message the group authenticates:
+//!
+//! `0xF4 | file id: 8 | chunks: u32 | size: u64 | sha256: 32 | name length: u8 | name`
+//!
+//! The contents follow in chunks of [`CHUNK_LEN`] bytes, each a control
+//! frame of its own, see `protocol`:
+//!
+//! `group id length: u8 | group id | file id: 8 | index: u32 | ciphertext`
+//!
+//! sealed with a key the sender and every member export for the file id
+//! from the epoch of the offer, and the chunk's index as nonce. Chunks are
+//! not MLS messages: a file would otherwise use up the sender's ratchet
+//! generations, and chat sent while it goes out would overtake its chunks
+//! by more than the ratchet tolerates, see `crypto::sender_ratchet_window`.
+//! They go out as bulk traffic, see `qos`, and may arrive in any order.
+//! Once all have arrived and the contents match the offer's hash,
+//! [`Transfers`] writes the file to the download directory. Chunks are not
+//! acknowledged or resent: a transfer missing some stays incomplete.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use openmls::prelude::{MlsGroup, OpenMlsCryptoProvider};
+use openmls_traits::{
+    crypto::OpenMlsCrypto,
+    random::OpenMlsRand,
+    types::{AeadType, HashType},
+};
+
+use crate::{crypto::hex_encode, error::NodeError};
+
+const MARKER: u8 = 0xF4;
+const FILE_LABEL: &str = "p2p-mls file";
+const ID_LEN: usize = 8;
+const HASH_LEN: usize = 32;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const OFFER_LEN: usize = 1 + ID_LEN + 4 + 8 + HASH_LEN + 1;
+
+/// Leaves room for the header and floodsub's framing under the 2 KiB it
+/// accepts in one message.
+pub const CHUNK_LEN: usize = 1024;
+pub const MAX_FILE_LEN: u64 = 1024 * 1024;
+/// Incomplete transfers followed at once; more are refused.
+const MAX_INCOMING: usize = 8;
+/// Finished transfers remembered, so late copies of their chunks do not
+/// count again.
+const FINISHED_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId([u8; ID_LEN]);
+
+impl Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex_encode(&self.0))
+    }
+}
+
+/// A file a member is about to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    pub id: FileId,
+    pub count: u32,
+    pub size: u64,
+    pub hash: Vec<u8>,
+    pub name: String,
+}
+
+impl Offer {
+    /// Offers `contents` of `path` under a fresh id.
+    pub fn new(
+        backend: &impl OpenMlsCryptoProvider,
+        path: &Path,
+        contents: &[u8],
+    ) -> Result<Offer, NodeError> {
+        let size = contents.len() as u64;
+        if size > MAX_FILE_LEN {
+            return Err(NodeError::Other(format!(
+                "{} is larger than {} bytes",
+                path.display(),
+                MAX_FILE_LEN
+            )));
+        }
+        let id = backend
+            .rand()
+            .random_array()
+            .map_err(|e| NodeError::Crypto(format!("Could not generate file id: {:?}", e)))?;
+        Ok(Offer {
+            id: FileId(id),
+            count: chunk_count(size) as u32,
+            size,
+            hash: sha256(backend, contents)?,
+            name: file_name(path)?,
+        })
+    }
+
+    pub fn is_offer(bytes: &[u8]) -> bool {
+        bytes.first() == Some(&MARKER)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![MARKER];
+        bytes.extend_from_slice(&self.id.0);
+        bytes.extend_from_slice(&self.count.to_be_bytes());
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.hash);
+        bytes.push(self.name.len() as u8);
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Offer, NodeError> {
+        let malformed = || NodeError::Parse("Malformed file offer".to_string());
+        if bytes.len() < OFFER_LEN || !Offer::is_offer(bytes) {
+            return Err(malformed());
+        }
+        let field = |at: usize, len: usize| &bytes[at..at + len];
+        let name = &bytes[OFFER_LEN..];
+        if name.len() != bytes[OFFER_LEN - 1] as usize {
+            return Err(malformed());
+        }
+        Ok(Offer {
+            id: FileId(field(1, ID_LEN).try_into().expect("eight bytes")),
+            count: u32::from_be_bytes(field(9, 4).try_into().expect("four bytes")),
+            size: u64::from_be_bytes(field(13, 8).try_into().expect("eight bytes")),
+            hash: field(21, HASH_LEN).to_vec(),
+            name: String::from_utf8(name.to_vec()).map_err(|_| malformed())?,
+        })
+    }
+
+    /// The key the file's chunks are sealed with, exported from the epoch
+    /// `mls_group` is in.
+    pub fn key(
+        &self,
+        mls_group: &MlsGroup,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<Vec<u8>, NodeError> {
+        mls_group
+            .export_secret(backend, FILE_LABEL, &self.id.0, KEY_LEN)
+            .map_err(|e| NodeError::Crypto(format!("Could not derive file key: {:?}", e)))
+    }
+
+    /// Splits `contents` into the chunks to send for `group_id`, sealed with
+    /// `key`.
+    pub fn seal(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+        key: &[u8],
+        group_id: &[u8],
+        contents: &[u8],
+    ) -> Result<Vec<SealedChunk>, NodeError> {
+        (0..self.count)
+            .map(|index| {
+                let start = (index as usize * CHUNK_LEN).min(contents.len());
+                let end = (start + CHUNK_LEN).min(contents.len());
+                let mut chunk = SealedChunk {
+                    group_id: group_id.to_vec(),
+                    id: self.id,
+                    index,
+                    ciphertext: Vec::new(),
+                };
+                chunk.ciphertext = backend
+                    .crypto()
+                    .aead_encrypt(
+                        AeadType::ChaCha20Poly1305,
+                        key,
+                        &contents[start..end],
+                        &nonce(index),
+                        &chunk.header(),
+                    )
+                    .map_err(|e| NodeError::Crypto(format!("Could not seal chunk: {:?}", e)))?;
+                Ok(chunk)
+            })
+            .collect()
+    }
+}
+
+/// One chunk of a file, as it travels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SealedChunk {
+    pub group_id: Vec<u8>,
+    pub id: FileId,
+    pub index: u32,
+    ciphertext: Vec<u8>,
+}
+
+impl SealedChunk {
+    // Authenticated along with the ciphertext.
+    fn header(&self) -> Vec<u8> {
+        let mut bytes = vec![self.group_id.len() as u8];
+        bytes.extend_from_slice(&self.group_id);
+        bytes.extend_from_slice(&self.id.0);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<SealedChunk, NodeError> {
+        let malformed = || NodeError::Parse("Malformed file chunk".to_string());
+        let (&len, rest) = bytes.split_first().ok_or_else(malformed)?;
+        let len = len as usize;
+        if rest.len() < len + ID_LEN + 4 {
+            return Err(malformed());
+        }
+        let (group_id, rest) = rest.split_at(len);
+        let (id, rest) = rest.split_at(ID_LEN);
+        let (index, ciphertext) = rest.split_at(4);
+        Ok(SealedChunk {
+            group_id: group_id.to_vec(),
+            id: FileId(id.try_into().expect("eight bytes")),
+            index: u32::from_be_bytes(index.try_into().expect("four bytes")),
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+
+    fn open(&self, backend: &impl OpenMlsCryptoProvider, key: &[u8]) -> Result<Vec<u8>, NodeError> {
+        backend
+            .crypto()
+            .aead_decrypt(
+                AeadType::ChaCha20Poly1305,
+                key,
+                &self.ciphertext,
+                &nonce(self.index),
+                &self.header(),
+            )
+            .map_err(|_| NodeError::Crypto(format!("Chunk {} does not open", self.index)))
+    }
+}
+
+// Each file has a key of its own, so the index alone keeps nonces unique.
+fn nonce(index: u32) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[NONCE_LEN - 4..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+fn sha256(backend: &impl OpenMlsCryptoProvider, data: &[u8]) -> Result<Vec<u8>, NodeError> {
+    backend
+        .crypto()
+        .hash(HashType::Sha2_256, data)
+        .map_err(|e| NodeError::Crypto(format!("Could not hash file: {:?}", e)))
+}
+
+/// What `path` is sent as: its file name, without directories.
+fn file_name(path: &Path) -> Result<String, NodeError> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| name.len() <= u8::MAX as usize)
+        .map(str::to_string)
+        .ok_or_else(|| NodeError::Other(format!("Cannot send {} by that name", path.display())))
+}
+
+// Empty files are sent as one empty chunk.
+fn chunk_count(size: u64) -> u64 {
+    size.div_ceil(CHUNK_LEN as u64).max(1)
+}
+
+/// How a transfer to us is going, reported on its offer, every quarter of
+/// the way and once it is saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub id: FileId,
+    pub name: String,
+    /// The sender's identity, see `crypto::credential_identity`.
+    pub sender: String,
+    pub received: u32,
+    pub count: u32,
+    pub size: u64,
+    pub saved: Option<PathBuf>,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.saved {
+            Some(path) => write!(
+                f,
+                "sent {} ({} bytes), saved to {}",
+                self.name,
+                self.size,
+                path.display()
+            ),
+            None => write!(
+                f,
+                "sending {} ({} bytes): {} of {} chunks",
+                self.name, self.size, self.received, self.count
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Incoming {
+    offer: Offer,
+    group_id: Vec<u8>,
+    key: Vec<u8>,
+    sender: String,
+    chunks: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Incoming {
+    fn progress(&self, saved: Option<PathBuf>) -> Progress {
+        Progress {
+            id: self.offer.id,
+            name: self.offer.name.clone(),
+            sender: self.sender.clone(),
+            received: self.chunks.len() as u32,
+            count: self.offer.count,
+            size: self.offer.size,
+            saved,
+        }
+    }
+}
+
+/// Transfers to us, put together in the download directory.
+#[derive(Debug, Default)]
+pub struct Transfers {
+    dir: Option<PathBuf>,
+    incoming: HashMap<FileId, Incoming>,
+    finished: VecDeque<FileId>,
+}
+
+impl Transfers {
+    /// Where received files go; without one files are refused.
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        self.dir = Some(dir);
+    }
+
+    /// Follows the file `sender` offered in `group_id`, whose chunks open
+    /// with `key`.
+    pub fn offered(
+        &mut self,
+        group_id: &[u8],
+        sender: String,
+        offer: Offer,
+        key: Vec<u8>,
+    ) -> Result<Progress, NodeError> {
+        if self.dir.is_none() {
+            return Err(NodeError::Other(format!(
+                "Not accepting files, refused {}",
+                offer.name
+            )));
+        }
+        if offer.size > MAX_FILE_LEN
+            || offer.count as u64 != chunk_count(offer.size)
+            || file_name(Path::new(&offer.name)).ok().as_ref() != Some(&offer.name)
+            || self.finished.contains(&offer.id)
+        {
+            return Err(NodeError::Other(format!("Refused file {}", offer.name)));
+        }
+        if self.incoming.len() >= MAX_INCOMING {
+            return Err(NodeError::Other(format!(
+                "Refused {}, {} transfers are incomplete",
+                offer.name, MAX_INCOMING
+            )));
+        }
+        let incoming = Incoming {
+            offer,
+            group_id: group_id.to_vec(),
+            key,
+            sender,
+            chunks: BTreeMap::new(),
+        };
+        let progress = incoming.progress(None);
+        self.incoming.insert(progress.id, incoming);
+        Ok(progress)
+    }
+
+    /// Takes `chunk`, writing its file once it is complete. Chunks of files
+    /// we were not offered, or already saved, are ignored.
+    pub fn receive(
+        &mut self,
+        backend: &impl OpenMlsCryptoProvider,
+        chunk: SealedChunk,
+    ) -> Result<Option<Progress>, NodeError> {
+        let incoming = match self.incoming.get_mut(&chunk.id) {
+            Some(incoming) if incoming.group_id == chunk.group_id => incoming,
+            _ => return Ok(None),
+        };
+        if chunk.index >= incoming.offer.count || incoming.chunks.contains_key(&chunk.index) {
+            return Ok(None);
+        }
+        let data = chunk.open(backend, &incoming.key)?;
+        let before = incoming.chunks.len() as u32;
+        incoming.chunks.insert(chunk.index, data);
+        let (received, count) = (before + 1, incoming.offer.count);
+        if received < count {
+            let quarter = |chunks: u32| chunks * 4 / count;
+            return Ok((quarter(received) > quarter(before)).then(|| incoming.progress(None)));
+        }
+        let incoming = self.incoming.remove(&chunk.id).expect("followed above");
+        if self.finished.len() >= FINISHED_LEN {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(chunk.id);
+        let contents: Vec<u8> = incoming.chunks.values().flatten().copied().collect();
+        if contents.len() as u64 != incoming.offer.size
+            || sha256(backend, &contents)? != incoming.offer.hash
+        {
+            return Err(NodeError::Other(format!(
+                "{} does not match its hash",
+                incoming.offer.name
+            )));
+        }
+        let dir = self.dir.as_deref().expect("checked on offer");
+        let path = save(dir, &incoming.offer, &contents)?;
+        Ok(Some(incoming.progress(Some(path))))
+    }
+}
+
+// Files already there are kept; the new one is prefixed with its id.
+fn save(dir: &Path, offer: &Offer, contents: &[u8]) -> Result<PathBuf, NodeError> {
+    std::fs::create_dir_all(dir)?;
+    let mut path = dir.join(&offer.name);
+    if path.exists() {
+        path = dir.join(format!("{}-{}", offer.id, offer.name));
+    }
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{ApplicationPayload, Node};
+
+    #[test]
+    fn files_arrive_in_any_order_and_are_saved_once_complete() {
+        let dir = std::env::temp_dir().join(format!("p2p-mls-transfer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let source = dir.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        let path = source.join("notes.bin");
+        let contents: Vec<u8> = (0..CHUNK_LEN * 5 / 2).map(|i| i as u8).collect();
+        std::fs::write(&path, &contents).unwrap();
+
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        bob.set_download_dir(dir.join("downloads"));
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+
+        let (offer, count) = alice.send_file(&path).unwrap();
+        assert_eq!(count, 3);
+        let mut chunks = alice.take_bulk();
+        // Chunks of a file not offered yet are ignored.
+        assert_eq!(bob.receive_chunk(chunks[0].clone()).unwrap(), None);
+        let mut progress = match bob.parse_application_message(offer).unwrap() {
+            Some(ApplicationPayload::File(update)) => vec![update],
+            payload => panic!("{:?}", payload),
+        };
+        let mut tampered = chunks[1].clone();
+        *tampered.ciphertext.last_mut().unwrap() ^= 1;
+        assert!(bob.receive_chunk(tampered).is_err());
+        chunks.swap(0, 2);
+        for chunk in chunks.iter().chain(&chunks[..1]).cloned() {
+            progress.extend(bob.receive_chunk(chunk).unwrap());
+        }
+        let received: Vec<u32> = progress.iter().map(|update| update.received).collect();
+        assert_eq!(received, vec![0, 1, 2, 3]);
+        let saved = progress[3].saved.clone().unwrap();
+        assert_eq!(saved, dir.join("downloads").join("notes.bin"));
+        assert_eq!(std::fs::read(&saved).unwrap(), contents);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,176 @@
+//! Admission checks against an external source of truth for member keys.
+//!
+//! Before the leader adds a key package, its credential is passed to the
+//! configured [`KeyTransparency`] implementation, which can consult a key
+//! transparency log or a static trust bundle. [`SignedSnapshot`] is the
+//! reference implementation: a JSON snapshot of `identity -> signature key`
+//! entries, signed by an operator key the node trusts.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+
+use openmls::prelude::Credential;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec,
+    crypto::{credential_identity, fingerprint::ct_eq, hex_decode, hex_encode},
+    error::NodeError,
+    identity::{Keypair, PublicKey},
+};
+
+pub trait KeyTransparency: Debug + Send {
+    /// Returns an error if `credential` must not be admitted to the group.
+    fn check(&self, credential: &Credential) -> Result<(), NodeError>;
+}
+
+/// Admits every credential; the default when no log is configured.
+#[derive(Debug, Default)]
+pub struct AllowAll;
+
+impl KeyTransparency for AllowAll {
+    fn check(&self, _credential: &Credential) -> Result<(), NodeError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub identity: String,
+    /// Hex-encoded credential signature key.
+    pub signature_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotContents {
+    version: u32,
+    entries: Vec<SnapshotEntry>,
+}
+
+// The snapshot is kept as the exact string that was signed, avoiding any
+// need for canonical JSON.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    snapshot: String,
+    signature: String,
+}
+
+#[derive(Debug)]
+pub struct SignedSnapshot {
+    version: u32,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl SignedSnapshot {
+    /// Produces the file contents for a snapshot signed by `signer`.
+    pub fn sign(
+        version: u32,
+        entries: Vec<SnapshotEntry>,
+        signer: &Keypair,
+    ) -> Result<String, NodeError> {
+        let snapshot = codec::to_json_string(&SnapshotContents { version, entries }, "snapshot")?;
+        let signature = signer.sign(snapshot.as_bytes());
+        codec::to_json_pretty(
+            &SnapshotFile {
+                snapshot,
+                signature: hex_encode(&signature),
+            },
+            "snapshot",
+        )
+    }
+
+    pub fn verify(contents: &str, signer: &PublicKey) -> Result<SignedSnapshot, NodeError> {
+        let file: SnapshotFile = serde_json::from_str(contents)
+            .map_err(|e| NodeError::Parse(format!("Invalid key transparency snapshot: {}", e)))?;
+        let signature = hex_decode(&file.signature)
+            .ok_or_else(|| NodeError::Other("Invalid snapshot signature encoding".to_string()))?;
+        if !signer.verify(file.snapshot.as_bytes(), &signature) {
+            return Err(NodeError::Other(
+                "Key transparency snapshot signature does not verify".to_string(),
+            ));
+        }
+        let snapshot: SnapshotContents = serde_json::from_str(&file.snapshot)
+            .map_err(|e| NodeError::Parse(format!("Invalid key transparency snapshot: {}", e)))?;
+        let keys = snapshot
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let key = hex_decode(&entry.signature_key).ok_or_else(|| {
+                    NodeError::Other(format!("Invalid signature key for {}", entry.identity))
+                })?;
+                Ok((entry.identity, key))
+            })
+            .collect::<Result<_, NodeError>>()?;
+        Ok(SignedSnapshot {
+            version: snapshot.version,
+            keys,
+        })
+    }
+
+    pub fn load(path: &Path, signer: &PublicKey) -> Result<SignedSnapshot, NodeError> {
+        SignedSnapshot::verify(&fs::read_to_string(path)?, signer)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+}
+
+impl KeyTransparency for SignedSnapshot {
+    fn check(&self, credential: &Credential) -> Result<(), NodeError> {
+        let identity = credential_identity(credential);
+        match self.keys.get(&identity) {
+            Some(key) if ct_eq(key, credential.signature_key().as_slice()) => Ok(()),
+            Some(_) => Err(NodeError::Other(format!(
+                "Signature key of {} does not match the key transparency snapshot",
+                identity
+            ))),
+            None => Err(NodeError::Other(format!(
+                "{} is not in the key transparency snapshot",
+                identity
+            ))),
+        }
+    }
+}
+
+/// Entry describing `credential`, for building snapshots.
+pub fn snapshot_entry(credential: &Credential) -> SnapshotEntry {
+    SnapshotEntry {
+        identity: credential_identity(credential),
+        signature_key: hex_encode(credential.signature_key().as_slice()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Node;
+
+    #[test]
+    fn snapshot_gates_admission() {
+        let operator = Keypair::generate_ed25519();
+        let bob = Node::default();
+        let carol = Node::default();
+        let contents = SignedSnapshot::sign(
+            1,
+            vec![snapshot_entry(bob.get_key_package().credential())],
+            &operator,
+        )
+        .unwrap();
+
+        let impostor = Keypair::generate_ed25519();
+        assert!(SignedSnapshot::verify(&contents, &impostor.public()).is_err());
+        let tampered = contents.replace("\\\"version\\\":1", "\\\"version\\\":2");
+        assert_ne!(tampered, contents);
+        assert!(SignedSnapshot::verify(&tampered, &operator.public()).is_err());
+
+        let snapshot = SignedSnapshot::verify(&contents, &operator.public()).unwrap();
+        let mut alice = Node::default();
+        alice.join_new_group();
+        alice.set_key_transparency(Box::new(snapshot));
+        assert!(alice.add_member_to_group(carol.get_key_package()).is_err());
+        assert!(alice.add_member_to_group(bob.get_key_package()).is_ok());
+    }
+}
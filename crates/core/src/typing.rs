@@ -0,0 +1,93 @@
+//! Typing indicators.
+//!
+//! While we type, the client asks the node to tell the group, see
+//! `Node::create_typing_message`; the node sends an application message of
+//! just `0xEE` at most once per [`TYPING_INTERVAL`] per group, and the
+//! client publishes it as realtime traffic, see `qos`, so one from an epoch
+//! a member has not reached is dropped rather than held. Receivers report
+//! it as `NodeEvent::Typing` and keep nothing: it is neither acknowledged,
+//! nor kept in any history, and UIs show it until [`TYPING_TIMEOUT`] passes
+//! without another. Groups with the presence feature off send and show none,
+//! see `features`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const MARKER: u8 = 0xEE;
+
+/// We tell a group we are typing at most this often.
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+/// How long a member is shown as typing after its last indicator.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
+pub fn typing() -> Vec<u8> {
+    vec![MARKER]
+}
+
+pub fn is_typing(bytes: &[u8]) -> bool {
+    bytes == [MARKER]
+}
+
+/// When we last told each group, by group id.
+#[derive(Debug, Default)]
+pub struct TypingLimiter {
+    sent: HashMap<Vec<u8>, Instant>,
+}
+
+impl TypingLimiter {
+    /// Whether to tell `group_id` at `now`, counting it if so.
+    pub fn allow(&mut self, group_id: &[u8], now: Instant) -> bool {
+        match self.sent.get(group_id) {
+            Some(sent) if now.saturating_duration_since(*sent) < TYPING_INTERVAL => false,
+            _ => {
+                self.sent.insert(group_id.to_vec(), now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::credential_identity;
+    use crate::events::NodeEvent;
+    use crate::features::Feature;
+    use crate::node::Node;
+    use crate::protocol::WireMessage;
+    use crate::qos::Qos;
+
+    #[test]
+    fn typing_is_rate_limited_reported_and_forgotten() {
+        let (mut alice, mut bob) = (Node::default(), Node::default());
+        alice.join_new_group();
+        let (_, welcome) = alice.add_member_to_group(bob.get_key_package()).unwrap();
+        bob.join_existing_group(welcome).unwrap();
+        let alice_peer = alice.get_network_keypair().public().to_peer_id();
+
+        let now = Instant::now();
+        let msg_out = alice.create_typing_message(now).unwrap().unwrap();
+        assert!(alice
+            .create_typing_message(now + Duration::from_secs(1))
+            .unwrap()
+            .is_none());
+        let frame = WireMessage::from(msg_out).encode_as(Qos::Realtime).unwrap();
+        match &bob.handle_incoming(&alice_peer, &frame)[..] {
+            [NodeEvent::Typing { identity, .. }] => assert_eq!(
+                identity,
+                &credential_identity(alice.get_key_package().credential())
+            ),
+            events => panic!("{:?}", events),
+        }
+        // Neither acknowledged nor kept.
+        assert!(bob.take_outgoing().is_empty());
+        assert!(alice.deliveries().iter().next().is_none());
+
+        let flags = alice.set_feature(Feature::Presence, false).unwrap();
+        bob.parse_application_message(flags).unwrap();
+        assert!(alice
+            .create_typing_message(now + TYPING_INTERVAL)
+            .unwrap()
+            .is_none());
+    }
+}
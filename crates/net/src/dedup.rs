@@ -0,0 +1,100 @@
+//! Dropping frames we already handed to the application.
+//!
+//! Floodsub only forgets a message id after a while, and the same frame can
+//! reach us over several connections, on several of a group's topics, or
+//! both over floodsub and from the mailbox. MLS refuses a message it has
+//! processed, but only after decrypting it, and frames outside MLS, such as
+//! join requests, would be handled twice. [`SeenFrames`] remembers hashes
+//! of the frames seen most recently, so the event loop and the mailbox
+//! poller drop repeats before they reach the node. Hashes are keyed with a
+//! random key per process, so peers cannot craft frames that collide with
+//! others'.
+//!
+//! Some frames are sent again unchanged on purpose, like room
+//! announcements, so a frame is only a repeat within [`REPEAT_WINDOW`] of
+//! its last sighting.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+/// How many frames [`SeenFrames`] remembers by default.
+pub const SEEN_FRAMES: usize = 4096;
+/// How long after its last sighting the same frame is a repeat; shorter
+/// than `rooms::ANNOUNCE_INTERVAL`.
+pub const REPEAT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Hashes of the frames seen most recently, forgetting the least recently
+/// seen first.
+#[derive(Debug)]
+pub struct SeenFrames {
+    capacity: usize,
+    hasher: RandomState,
+    // When each hash was last seen, as a count of sightings and in time.
+    last_seen: HashMap<u64, (u64, Instant)>,
+    // Every sighting, oldest first; those of hashes seen again since are
+    // skipped when evicting.
+    sightings: VecDeque<(u64, u64)>,
+    count: u64,
+}
+
+impl Default for SeenFrames {
+    fn default() -> SeenFrames {
+        SeenFrames::new(SEEN_FRAMES)
+    }
+}
+
+impl SeenFrames {
+    pub fn new(capacity: usize) -> SeenFrames {
+        SeenFrames {
+            capacity: capacity.max(1),
+            hasher: RandomState::new(),
+            last_seen: HashMap::new(),
+            sightings: VecDeque::new(),
+            count: 0,
+        }
+    }
+
+    /// Whether `frame` is not a repeat at `now`; remembers it either way.
+    pub fn first_sighting(&mut self, frame: &[u8], now: Instant) -> bool {
+        let hash = self.hasher.hash_one(frame);
+        self.count += 1;
+        let repeat = match self.last_seen.insert(hash, (self.count, now)) {
+            Some((_, seen)) => now.saturating_duration_since(seen) < REPEAT_WINDOW,
+            None => false,
+        };
+        self.sightings.push_back((self.count, hash));
+        while self.last_seen.len() > self.capacity || self.sightings.len() > 2 * self.capacity {
+            let (count, hash) = self.sightings.pop_front().expect("sightings");
+            if matches!(self.last_seen.get(&hash), Some((last, _)) if *last == count) {
+                self.last_seen.remove(&hash);
+            }
+        }
+        !repeat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_are_dropped_until_forgotten() {
+        let now = Instant::now();
+        let mut seen = SeenFrames::new(2);
+        assert!(seen.first_sighting(b"one", now));
+        assert!(!seen.first_sighting(b"one", now));
+        assert!(seen.first_sighting(b"two", now));
+        // Seeing "one" again makes "two" the least recently seen.
+        assert!(!seen.first_sighting(b"one", now));
+        assert!(seen.first_sighting(b"three", now));
+        assert!(seen.first_sighting(b"two", now));
+        assert!(!seen.first_sighting(b"three", now));
+
+        // Frames sent again on purpose, later, get through.
+        let later = now + REPEAT_WINDOW;
+        assert!(seen.first_sighting(b"three", later));
+        assert!(!seen.first_sighting(b"three", later));
+    }
+}
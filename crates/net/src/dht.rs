@@ -0,0 +1,94 @@
+//! Peer discovery beyond the local network through a Kademlia DHT.
+//!
+//! mDNS only reaches the LAN. With `--bootstrap`, or `--dht` on a node that
+//! others bootstrap from, the swarm also runs Kademlia under its own
+//! protocol name, so it never mixes with the public IPFS DHT. Peers tell
+//! each other their listen addresses with identify, which is what gets
+//! them into each other's routing tables, and every peer entering ours is
+//! offered to floodsub like one found on mDNS.
+
+use std::time::Duration;
+
+use libp2p::{
+    identify::{Identify, IdentifyConfig},
+    identity::PublicKey,
+    kad::{store::MemoryStore, Kademlia, KademliaConfig},
+    multiaddr::Protocol,
+    Multiaddr, PeerId,
+};
+
+use crate::node_peer;
+use p2p_mls_core::{error::NodeError, identity};
+
+pub const DHT_PROTOCOL: &[u8] = b"/p2p-mls/kad/1.0.0";
+const IDENTIFY_PROTOCOL: &str = "/p2p-mls/id/1.0.0";
+
+/// How often we walk the DHT again to find peers that joined since.
+pub const DHT_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Splits a bootstrap multiaddr such as `/dns4/host/tcp/4001/p2p/<peer id>`
+/// into the peer and the address to reach it at. Kademlia needs the peer
+/// id up front, so addresses without one are refused.
+pub fn parse_bootstrap(address: &str) -> Result<(identity::PeerId, Multiaddr), NodeError> {
+    let mut address: Multiaddr = address
+        .parse()
+        .map_err(|e| NodeError::Network(format!("Invalid bootstrap address {}: {}", address, e)))?;
+    match address.pop() {
+        Some(Protocol::P2p(hash)) => {
+            let peer = PeerId::from_multihash(hash)
+                .map_err(|_| NodeError::Network(format!("Invalid peer id in {}", address)))?;
+            Ok((node_peer(&peer), address))
+        }
+        _ => Err(NodeError::Network(format!(
+            "Bootstrap address {} must end in /p2p/<peer id>",
+            address
+        ))),
+    }
+}
+
+/// A Kademlia behaviour that starts out knowing the `bootstrap` peers.
+pub fn kademlia(peer_id: PeerId, bootstrap: &[(PeerId, Multiaddr)]) -> Kademlia<MemoryStore> {
+    let mut config = KademliaConfig::default();
+    config.set_protocol_name(DHT_PROTOCOL);
+    let mut kademlia = Kademlia::with_config(peer_id, MemoryStore::new(peer_id), config);
+    for (peer, address) in bootstrap {
+        kademlia.add_address(peer, address.clone());
+    }
+    kademlia
+}
+
+pub fn identify(public_key: PublicKey) -> Identify {
+    Identify::new(IdentifyConfig::new(
+        IDENTIFY_PROTOCOL.to_string(),
+        public_key,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bootstrap_peers_seed_the_routing_table() {
+        let peer = PeerId::random();
+        let (parsed, address) =
+            parse_bootstrap(&format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", peer)).unwrap();
+        assert_eq!(parsed, node_peer(&peer));
+        assert_eq!(address, "/ip4/203.0.113.7/tcp/4001".parse().unwrap());
+        assert!(parse_bootstrap("/ip4/203.0.113.7/tcp/4001").is_err());
+        assert!(parse_bootstrap("not an address").is_err());
+
+        let mut kademlia = kademlia(PeerId::random(), &[(peer, address)]);
+        let known: Vec<PeerId> = kademlia
+            .kbuckets()
+            .flat_map(|bucket| {
+                bucket
+                    .iter()
+                    .map(|entry| *entry.node.key.preimage())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(known, vec![peer]);
+        assert!(kademlia.bootstrap().is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+//! Frames sent to one peer instead of the whole topic.
+//!
+//! A join request on the rendezvous topic reaches every peer, and any of
+//! them can add the joiner. `node join --from=<peer>` instead sends it to
+//! that peer alone over the `/p2p-mls/direct/1.0.0` request-response
+//! protocol, and the peer sends the Welcome back the same way. Each request
+//! carries one length-prefixed frame and is answered with an empty
+//! acknowledgement as soon as it arrives, so a Welcome the leader only
+//! sends after `node accept` travels as a request of its own.
+
+use std::io;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName},
+    request_response::{
+        ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+    },
+};
+
+/// Larger than any frame the size limits let through by default.
+const MAX_FRAME: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct DirectProtocol;
+
+impl ProtocolName for DirectProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/p2p-mls/direct/1.0.0"
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FrameCodec;
+
+#[async_trait]
+impl RequestResponseCodec for FrameCodec {
+    type Protocol = DirectProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, MAX_FRAME).await
+    }
+
+    async fn read_response<T>(&mut self, _: &DirectProtocol, io: &mut T) -> io::Result<Vec<u8>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, MAX_FRAME).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DirectProtocol,
+        io: &mut T,
+        frame: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, frame).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DirectProtocol,
+        io: &mut T,
+        ack: Vec<u8>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, ack).await?;
+        io.close().await
+    }
+}
+
+pub fn direct() -> RequestResponse<FrameCodec> {
+    RequestResponse::new(
+        FrameCodec,
+        std::iter::once((DirectProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
@@ -0,0 +1,263 @@
+//! A behaviour that decides how long idle connections stay open.
+//!
+//! Floodsub lets a connection go after a few seconds without traffic and
+//! then redials, so a quiet chat keeps reconnecting. [`MemberKeepAlive`]
+//! holds connections to group members open for as long as configured, and
+//! gives everyone else a short grace period, enough to send a join request,
+//! before the connection is allowed to close.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use libp2p::{
+    core::{
+        connection::ConnectionId,
+        upgrade::{DeniedUpgrade, InboundUpgrade, OutboundUpgrade},
+        ConnectedPoint,
+    },
+    swarm::{
+        ConnectionHandler, ConnectionHandlerEvent, ConnectionHandlerUpgrErr, KeepAlive,
+        NegotiatedSubstream, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler,
+        PollParameters, SubstreamProtocol,
+    },
+    Multiaddr, PeerId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeepAliveConfig {
+    /// How long idle connections to members stay open; `None` for as long
+    /// as both sides run.
+    pub member: Option<Duration>,
+    /// How long connections to anyone else stay open, idle or not, unless
+    /// another protocol is still using them.
+    pub non_member: Duration,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> KeepAliveConfig {
+        KeepAliveConfig {
+            member: None,
+            non_member: Duration::from_secs(15),
+        }
+    }
+}
+
+impl KeepAliveConfig {
+    fn keep_alive(&self, is_member: bool) -> KeepAlive {
+        match (is_member, self.member) {
+            (true, None) => KeepAlive::Yes,
+            (true, Some(timeout)) => KeepAlive::Until(Instant::now() + timeout),
+            (false, _) => KeepAlive::Until(Instant::now() + self.non_member),
+        }
+    }
+}
+
+pub struct MemberKeepAlive {
+    config: KeepAliveConfig,
+    members: HashSet<PeerId>,
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    pending: VecDeque<(PeerId, ConnectionId, bool)>,
+    waker: Option<Waker>,
+}
+
+impl MemberKeepAlive {
+    pub fn new(config: KeepAliveConfig) -> MemberKeepAlive {
+        MemberKeepAlive {
+            config,
+            members: HashSet::new(),
+            connections: HashMap::new(),
+            pending: VecDeque::new(),
+            waker: None,
+        }
+    }
+
+    /// Records whether `peer` is in our group, updating its open connections.
+    pub fn set_member(&mut self, peer: PeerId, is_member: bool) {
+        let changed = if is_member {
+            self.members.insert(peer)
+        } else {
+            self.members.remove(&peer)
+        };
+        if !changed {
+            return;
+        }
+        for connection in self.connections.get(&peer).into_iter().flatten() {
+            self.pending.push_back((peer, *connection, is_member));
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains(peer)
+    }
+}
+
+impl NetworkBehaviour for MemberKeepAlive {
+    type ConnectionHandler = KeepAliveHandler;
+    type OutEvent = Infallible;
+
+    fn new_handler(&mut self) -> KeepAliveHandler {
+        KeepAliveHandler {
+            config: self.config,
+            keep_alive: self.config.keep_alive(false),
+        }
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+        _failed_addresses: Option<&Vec<Multiaddr>>,
+        _other_established: usize,
+    ) {
+        self.connections
+            .entry(*peer_id)
+            .or_default()
+            .push(*connection_id);
+        if self.members.contains(peer_id) {
+            self.pending.push_back((*peer_id, *connection_id, true));
+        }
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+        _handler: KeepAliveHandler,
+        remaining_established: usize,
+    ) {
+        if remaining_established == 0 {
+            self.connections.remove(peer_id);
+        } else if let Some(connections) = self.connections.get_mut(peer_id) {
+            connections.retain(|c| c != connection_id);
+        }
+    }
+
+    fn inject_event(&mut self, _peer_id: PeerId, _connection: ConnectionId, event: Infallible) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        _params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Infallible, KeepAliveHandler>> {
+        match self.pending.pop_front() {
+            Some((peer_id, connection, is_member)) => {
+                Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id,
+                    handler: NotifyHandler::One(connection),
+                    event: is_member,
+                })
+            }
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Holds one connection open according to whether its peer is a member,
+/// which the behaviour tells it with a `bool`.
+pub struct KeepAliveHandler {
+    config: KeepAliveConfig,
+    keep_alive: KeepAlive,
+}
+
+impl ConnectionHandler for KeepAliveHandler {
+    type InEvent = bool;
+    type OutEvent = Infallible;
+    type Error = Infallible;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = DeniedUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = Infallible;
+
+    fn listen_protocol(&self) -> SubstreamProtocol<DeniedUpgrade, ()> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn inject_fully_negotiated_inbound(
+        &mut self,
+        _: <DeniedUpgrade as InboundUpgrade<NegotiatedSubstream>>::Output,
+        _: (),
+    ) {
+        unreachable!("DeniedUpgrade never succeeds");
+    }
+
+    fn inject_fully_negotiated_outbound(
+        &mut self,
+        _: <DeniedUpgrade as OutboundUpgrade<NegotiatedSubstream>>::Output,
+        info: Infallible,
+    ) {
+        match info {}
+    }
+
+    fn inject_event(&mut self, is_member: bool) {
+        self.keep_alive = self.config.keep_alive(is_member);
+    }
+
+    fn inject_address_change(&mut self, _: &Multiaddr) {}
+
+    fn inject_dial_upgrade_error(
+        &mut self,
+        info: Infallible,
+        _: ConnectionHandlerUpgrErr<<DeniedUpgrade as OutboundUpgrade<NegotiatedSubstream>>::Error>,
+    ) {
+        match info {}
+    }
+
+    fn inject_listen_upgrade_error(
+        &mut self,
+        _: (),
+        _: ConnectionHandlerUpgrErr<<DeniedUpgrade as InboundUpgrade<NegotiatedSubstream>>::Error>,
+    ) {
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        self.keep_alive
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<DeniedUpgrade, Infallible, Infallible, Infallible>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_are_kept_alive() {
+        let config = KeepAliveConfig::default();
+        let mut handler = MemberKeepAlive::new(config).new_handler();
+        assert!(matches!(
+            handler.connection_keep_alive(),
+            KeepAlive::Until(_)
+        ));
+        handler.inject_event(true);
+        assert_eq!(handler.connection_keep_alive(), KeepAlive::Yes);
+        handler.inject_event(false);
+        assert!(matches!(
+            handler.connection_keep_alive(),
+            KeepAlive::Until(_)
+        ));
+
+        let mut behaviour = MemberKeepAlive::new(config);
+        let peer = PeerId::random();
+        behaviour.set_member(peer, true);
+        assert!(behaviour.is_member(&peer));
+        // No connection yet, so nothing to notify.
+        assert!(behaviour.pending.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+//! Media packets sent straight to each member, see the crate's `media`.
+//!
+//! With `--media` the swarm speaks `/p2p-mls/media/1.0.0`, a
+//! request-response protocol like `direct` whose requests carry one sealed
+//! packet each. Packets are not buffered, retried or published anywhere
+//! else: a late packet of a stream is as good as a lost one.
+
+use std::io;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::upgrade::{read_length_prefixed, write_length_prefixed, ProtocolName},
+    request_response::{
+        ProtocolSupport, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+    },
+};
+
+/// Room for a frame of audio or video, not a file.
+const MAX_PACKET: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct MediaProtocol;
+
+impl ProtocolName for MediaProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/p2p-mls/media/1.0.0"
+    }
+}
+
+/// One sealed packet, told apart from direct frames by type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaPacket(pub Vec<u8>);
+
+#[derive(Debug, Clone, Default)]
+pub struct MediaCodec;
+
+#[async_trait]
+impl RequestResponseCodec for MediaCodec {
+    type Protocol = MediaProtocol;
+    type Request = MediaPacket;
+    type Response = ();
+
+    async fn read_request<T>(&mut self, _: &MediaProtocol, io: &mut T) -> io::Result<MediaPacket>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_length_prefixed(io, MAX_PACKET).await.map(MediaPacket)
+    }
+
+    async fn read_response<T>(&mut self, _: &MediaProtocol, _: &mut T) -> io::Result<()>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Ok(())
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &MediaProtocol,
+        io: &mut T,
+        MediaPacket(packet): MediaPacket,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, packet).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &MediaProtocol, io: &mut T, _: ()) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.close().await
+    }
+}
+
+pub fn media() -> RequestResponse<MediaCodec> {
+    RequestResponse::new(
+        MediaCodec,
+        std::iter::once((MediaProtocol, ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    )
+}
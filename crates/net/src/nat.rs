@@ -0,0 +1,90 @@
+//! Reaching peers behind NATs through circuit relays.
+//!
+//! A node given `--relay` reserves a slot on each relay and listens on
+//! `<relay address>/p2p-circuit`, so peers that cannot dial it directly
+//! connect through the relay instead. `--relay-server` makes a publicly
+//! reachable node serve as such a relay. Nodes taking part in either, or in
+//! the DHT, also run AutoNAT: connected peers dial us back to tell whether
+//! we are reachable from outside, which is reported as a `NatStatus`.
+//!
+//! Not done: upgrading relayed connections to direct ones by hole punching
+//! (DCUtR), split off from this module's work and still open. It needs
+//! libp2p's `dcutr` feature, whose crate this build cannot fetch, so
+//! relayed connections stay relayed for as long as they last.
+
+use libp2p::{
+    autonat,
+    multiaddr::Protocol,
+    relay::v2::relay::{self, Relay},
+    Multiaddr, PeerId,
+};
+
+pub use libp2p::autonat::NatStatus;
+
+use crate::node_peer;
+use p2p_mls_core::{error::NodeError, identity};
+
+/// Splits a relay multiaddr such as `/ip4/203.0.113.7/tcp/4001/p2p/<peer id>`
+/// into the relay's peer id and the full address. The relay must be named
+/// to reserve a slot on it, so addresses without a peer id are refused.
+pub fn parse_relay(address: &str) -> Result<(identity::PeerId, Multiaddr), NodeError> {
+    let relay: Multiaddr = address
+        .parse()
+        .map_err(|e| NodeError::Network(format!("Invalid relay address {}: {}", address, e)))?;
+    match relay.iter().last() {
+        Some(Protocol::P2p(hash)) => {
+            let peer = PeerId::from_multihash(hash)
+                .map_err(|_| NodeError::Network(format!("Invalid peer id in {}", relay)))?;
+            Ok((node_peer(&peer), relay))
+        }
+        _ => Err(NodeError::Network(format!(
+            "Relay address {} must end in /p2p/<peer id>",
+            relay
+        ))),
+    }
+}
+
+/// The address to listen on to be reachable through `relay`.
+pub fn relay_listen_address(relay: &Multiaddr) -> Multiaddr {
+    relay.clone().with(Protocol::P2pCircuit)
+}
+
+pub fn autonat(peer_id: PeerId) -> autonat::Behaviour {
+    autonat::Behaviour::new(peer_id, autonat::Config::default())
+}
+
+pub fn relay_server(peer_id: PeerId) -> Relay {
+    Relay::new(peer_id, relay::Config::default())
+}
+
+/// How a `NatStatus` reads in the log.
+pub fn describe(status: &NatStatus) -> String {
+    match status {
+        NatStatus::Public(address) => format!("publicly reachable at {}", address),
+        NatStatus::Private => "behind a NAT, reachable only through relays".to_string(),
+        NatStatus::Unknown => "reachability unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_are_named_and_listened_through() {
+        let peer = PeerId::random();
+        let (parsed, relay) =
+            parse_relay(&format!("/ip4/203.0.113.7/tcp/4001/p2p/{}", peer)).unwrap();
+        assert_eq!(parsed, node_peer(&peer));
+        assert_eq!(
+            relay_listen_address(&relay).to_string(),
+            format!("/ip4/203.0.113.7/tcp/4001/p2p/{}/p2p-circuit", peer)
+        );
+        assert!(parse_relay("/ip4/203.0.113.7/tcp/4001").is_err());
+        assert!(parse_relay("not an address").is_err());
+        assert_eq!(
+            describe(&NatStatus::Private),
+            "behind a NAT, reachable only through relays"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Dropping floods before they reach the node.
+//!
+//! Every frame costs the node up to three deserializations, so frames over
+//! a peer's token bucket, see [`PeerRateLimits`], are dropped in the swarm.
+//! The bucket is that of the peer the connection is with, not the frame's
+//! `source`: floodsub does not authenticate sources, so a peer could claim a
+//! fresh one for every frame, or drain a member's bucket with frames under
+//! that member's id. Frames relayed by an honest peer count against it like
+//! its own. Dropped frames are not relayed to others either.
+
+use std::ops::{Deref, DerefMut};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use libp2p::{
+    core::{connection::ConnectionId, ConnectedPoint},
+    floodsub::Floodsub,
+    swarm::{ConnectionHandler, NetworkBehaviour, NetworkBehaviourAction, PollParameters},
+    Multiaddr, PeerId,
+};
+
+use super::node_peer;
+use p2p_mls_core::limits::PeerRateLimits;
+
+// What floodsub's handler reports, named for its variants.
+type FloodsubHandlerEvent =
+    <<Floodsub as NetworkBehaviour>::ConnectionHandler as ConnectionHandler>::OutEvent;
+
+/// Floodsub, with the messages of each RPC counted against the peer that
+/// sent it; subscriptions always go through.
+pub struct LimitedFloodsub {
+    floodsub: Floodsub,
+    limits: PeerRateLimits,
+}
+
+impl LimitedFloodsub {
+    pub fn new(floodsub: Floodsub, limits: PeerRateLimits) -> LimitedFloodsub {
+        LimitedFloodsub { floodsub, limits }
+    }
+
+    /// Counts a frame `peer` sent over a connection of ours, logging when it
+    /// starts being over the limit.
+    pub fn admit(&mut self, peer: &PeerId) -> bool {
+        match self.limits.check(&node_peer(peer), Instant::now()) {
+            Ok(()) => true,
+            Err(1) => {
+                log::warn!("Dropping frames from {}: over --peer-rate", peer);
+                false
+            }
+            Err(dropped) => {
+                log::debug!("Dropped {} frames in a row from {}", dropped, peer);
+                false
+            }
+        }
+    }
+}
+
+impl Deref for LimitedFloodsub {
+    type Target = Floodsub;
+
+    fn deref(&self) -> &Floodsub {
+        &self.floodsub
+    }
+}
+
+impl DerefMut for LimitedFloodsub {
+    fn deref_mut(&mut self) -> &mut Floodsub {
+        &mut self.floodsub
+    }
+}
+
+impl NetworkBehaviour for LimitedFloodsub {
+    type ConnectionHandler = <Floodsub as NetworkBehaviour>::ConnectionHandler;
+    type OutEvent = <Floodsub as NetworkBehaviour>::OutEvent;
+
+    fn new_handler(&mut self) -> Self::ConnectionHandler {
+        self.floodsub.new_handler()
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        failed_addresses: Option<&Vec<Multiaddr>>,
+        other_established: usize,
+    ) {
+        self.floodsub.inject_connection_established(
+            peer_id,
+            connection_id,
+            endpoint,
+            failed_addresses,
+            other_established,
+        )
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        handler: Self::ConnectionHandler,
+        remaining_established: usize,
+    ) {
+        self.floodsub.inject_connection_closed(
+            peer_id,
+            connection_id,
+            endpoint,
+            handler,
+            remaining_established,
+        )
+    }
+
+    fn inject_event(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        event: FloodsubHandlerEvent,
+    ) {
+        let event = match event {
+            FloodsubHandlerEvent::Rx(mut rpc) => {
+                rpc.messages.retain(|_| self.admit(&peer_id));
+                FloodsubHandlerEvent::Rx(rpc)
+            }
+            event => event,
+        };
+        self.floodsub.inject_event(peer_id, connection, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ConnectionHandler>> {
+        self.floodsub.poll(cx, params)
+    }
+}
@@ -0,0 +1,1153 @@
+//! The libp2p swarm and its event loop, for binaries embedding the node.
+//!
+//! [`NetworkService::spawn`] builds the swarm from a [`NetworkConfig`],
+//! starts listening and dialing, and runs the event loop as a supervised
+//! task. Frames handed to [`NetworkService::send`] are published on the
+//! topics of their group, or left in the delivery service mailbox while no
+//! peers are connected; frames from peers, including those found in the
+//! mailbox, and connection changes come back as [`NetworkEvent`]s, each
+//! frame once, see `dedup`.
+//! [`NetworkService::send_to`] sends a frame to one peer instead, see
+//! `direct`, and [`NetworkService::send_media`] a stream packet, see
+//! `media`. The loop keeps the node's peer table and the keep-alive
+//! membership up to date itself, and reserves slots on the relays we are
+//! reachable through, see `nat`. Peers the node blocked are banned from the
+//! swarm and left out of the floodsub view, and frames they originate are
+//! dropped wherever they come from, see [`NetworkService::sync_blocked`].
+//! Frames over the rate of the peer sending them, published or direct, are
+//! dropped too, see `rate_limit`.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_std::channel;
+use futures::{lock::Mutex, StreamExt};
+use libp2p::{
+    autonat,
+    floodsub::{self, Floodsub, FloodsubEvent},
+    identify::{Identify, IdentifyEvent},
+    kad::{store::MemoryStore, Kademlia, KademliaEvent},
+    mdns::{Mdns, MdnsEvent},
+    relay::v2::{
+        client::{self as relay_client, Client},
+        relay::{self, Relay},
+    },
+    request_response::{RequestId, RequestResponse, RequestResponseEvent, RequestResponseMessage},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        AddressScore, SwarmBuilder, SwarmEvent,
+    },
+    websocket::tls,
+    Multiaddr, NetworkBehaviour, PeerId, Swarm,
+};
+
+use super::dedup::SeenFrames;
+use super::direct::{direct, FrameCodec};
+use super::media::{media, MediaCodec, MediaPacket};
+use super::rate_limit::LimitedFloodsub;
+use super::{
+    autonat, identify, kademlia, node_peer, publish_topics, relay_listen_address, relay_server,
+    swarm_peer, transport, welcome_topic, Discovery, DiscoverySource, KeepAliveConfig,
+    MemberKeepAlive, NatStatus, Transport, TransportPolicies, DHT_REFRESH_INTERVAL,
+    RENDEZVOUS_TOPIC,
+};
+use crate::supervisor::{Restart, Supervisor};
+use p2p_mls_core::{
+    ds::DsClient,
+    error::NodeError,
+    identity,
+    limits::{FrameKind, PeerRateLimits},
+    node::Node,
+    outbox::{DeliveryState, OutboxEntry},
+    protocol::WireMessage,
+    qos::{Budget, OutboundQueue},
+};
+
+const MAILBOX: &str = "chat";
+const MAILBOX_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MEMBERSHIP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Frames of each plane, and bulk frames, go out at most this many per
+/// tick, see `qos`.
+const PACE_BUDGET: Budget = Budget {
+    control: 16,
+    data: 64,
+    bulk: 8,
+};
+const PACE_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct NetworkConfig {
+    pub discovery: Discovery,
+    /// Secure WebSocket settings, see `tls_config`.
+    pub tls: Option<tls::Config>,
+    pub listen: Vec<Multiaddr>,
+    /// Addresses to dial at startup; their peers stay in the floodsub view
+    /// even when they are not members.
+    pub dial: Vec<Multiaddr>,
+    /// Kademlia bootstrap peers, see `parse_bootstrap`.
+    pub bootstrap: Vec<(identity::PeerId, Multiaddr)>,
+    /// Run the DHT even without bootstrap peers, for others to bootstrap from.
+    pub dht: bool,
+    /// Relays to be reachable through, see `parse_relay`.
+    pub relays: Vec<(identity::PeerId, Multiaddr)>,
+    /// Relay circuits for other peers.
+    pub relay_server: bool,
+    pub keep_alive: KeepAliveConfig,
+    pub policies: TransportPolicies,
+    /// Mailbox for frames published while no peers are connected.
+    pub ds: Option<DsClient>,
+    /// Speak the experimental media protocol.
+    pub media: bool,
+    /// Frames taken from each connected peer, see `rate_limit`.
+    pub peer_limits: PeerRateLimits,
+}
+
+#[derive(Debug)]
+pub enum NetworkEvent {
+    /// A frame `peer` published, or left in the mailbox.
+    Frame {
+        peer: identity::PeerId,
+        frame: Vec<u8>,
+    },
+    /// A frame `peer` sent to us alone.
+    Direct {
+        peer: identity::PeerId,
+        frame: Vec<u8>,
+    },
+    /// A media packet `peer` streamed to us.
+    Media {
+        peer: identity::PeerId,
+        packet: Vec<u8>,
+    },
+    Listening(Multiaddr),
+    Connected {
+        peer: identity::PeerId,
+        address: Multiaddr,
+    },
+    /// The transport policies do not admit `peer` over `transport`, so the
+    /// connection was closed.
+    Refused {
+        peer: identity::PeerId,
+        transport: Transport,
+    },
+    DialFailed {
+        peer: Option<identity::PeerId>,
+        error: String,
+    },
+    /// A frame for [`NetworkService::send_to`] did not reach `peer`.
+    DirectFailed {
+        peer: identity::PeerId,
+        error: String,
+    },
+    Disconnected(identity::PeerId),
+    /// AutoNAT changed its mind about whether peers can dial us.
+    NatStatus(NatStatus),
+    /// `relay` refused or failed to keep our reservation.
+    RelayFailed {
+        relay: identity::PeerId,
+        error: String,
+    },
+    /// One of our own messages went out, see `Node::record_published`.
+    Published(OutboxEntry),
+    /// Depositing in or fetching from the mailbox failed.
+    Mailbox(NodeError),
+}
+
+enum Command {
+    Dial(PeerId, Vec<Multiaddr>),
+    SendTo(PeerId, Vec<u8>),
+    SendMedia(PeerId, Vec<u8>),
+    Bootstrap(Vec<(PeerId, Multiaddr)>),
+    SyncTopics,
+    SyncBlocked,
+}
+
+/// Sends frames and requests to the event loop; clones share the loop.
+#[derive(Clone)]
+pub struct NetworkService {
+    peer_id: identity::PeerId,
+    frames: channel::Sender<Vec<u8>>,
+    commands: channel::Sender<Command>,
+}
+
+impl NetworkService {
+    /// Builds the swarm for `node` and spawns its event loop, and the mailbox
+    /// poller when there is a mailbox, on `supervisor`. The event loop owns
+    /// the swarm, so it cannot be restarted.
+    pub async fn spawn(
+        config: NetworkConfig,
+        node: Arc<Mutex<Node>>,
+        supervisor: &Supervisor,
+    ) -> Result<(NetworkService, channel::Receiver<NetworkEvent>), NodeError> {
+        let id_keys = config
+            .discovery
+            .network_keypair(&node.lock().await.get_network_keypair());
+        let peer_id = PeerId::from(id_keys.public());
+        let mdns = match config.discovery.mdns_config() {
+            Some(mdns) => Some(Mdns::new(mdns).await?),
+            None => None,
+        };
+        let dht = config.dht || !config.bootstrap.is_empty();
+        // Identify tells peers the addresses they reach us at, which AutoNAT
+        // needs to ask them to dial back.
+        let nat = dht || config.relay_server || !config.relays.is_empty();
+        let bootstrap: Vec<_> = config
+            .bootstrap
+            .iter()
+            .map(|(peer, address)| (swarm_peer(peer), address.clone()))
+            .collect();
+        let (relay_transport, relay_client) = Client::new_transport_and_behaviour(peer_id);
+        let mut swarm = SwarmBuilder::new(
+            transport(&id_keys, config.tls, relay_transport).await?,
+            Behaviour {
+                floodsub: LimitedFloodsub::new(Floodsub::new(peer_id), config.peer_limits),
+                mdns: Toggle::from(mdns),
+                kademlia: Toggle::from(dht.then(|| kademlia(peer_id, &bootstrap))),
+                identify: Toggle::from(nat.then(|| identify(id_keys.public()))),
+                autonat: Toggle::from(nat.then(|| autonat(peer_id))),
+                relay_client,
+                relay: Toggle::from(config.relay_server.then(|| relay_server(peer_id))),
+                keep_alive: MemberKeepAlive::new(config.keep_alive),
+                direct: direct(),
+                media: Toggle::from(config.media.then(media)),
+            },
+            peer_id,
+        )
+        .build();
+        for address in config.listen {
+            swarm
+                .listen_on(address.clone())
+                .map_err(|e| NodeError::Network(format!("Cannot listen on {}: {}", address, e)))?;
+        }
+        if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+            // Fails only without bootstrap peers, when others find us instead.
+            let _ = kademlia.bootstrap();
+        }
+        let mut pinned = HashSet::new();
+        for (relay, address) in &config.relays {
+            swarm
+                .listen_on(relay_listen_address(address))
+                .map_err(|e| {
+                    NodeError::Network(format!("Cannot listen through {}: {}", address, e))
+                })?;
+            pinned.insert(swarm_peer(relay));
+        }
+        let mut dialed = HashSet::new();
+        for address in config.dial {
+            swarm
+                .dial(address.clone())
+                .map_err(|e| NodeError::Network(format!("Could not dial {}: {}", address, e)))?;
+            dialed.insert(address);
+        }
+
+        let (frames, frame_receiver) = channel::unbounded();
+        let (commands, command_receiver) = channel::unbounded();
+        let (events, event_receiver) = channel::unbounded();
+        let seen = Arc::new(Mutex::new(SeenFrames::default()));
+        if let Some(ds) = &config.ds {
+            let (ds, events, seen) = (ds.clone(), events.clone(), Arc::clone(&seen));
+            let node = Arc::clone(&node);
+            supervisor.spawn(
+                "mailbox poller",
+                Restart::OnFailure { max_restarts: 5 },
+                move || {
+                    let (ds, node, seen) = (ds.clone(), Arc::clone(&node), Arc::clone(&seen));
+                    poll_mailbox(ds, peer_id, node, events.clone(), seen)
+                },
+            );
+        }
+        let event_loop = EventLoop {
+            swarm,
+            node,
+            events,
+            seen,
+            ds: config.ds,
+            policies: config.policies,
+            dialed,
+            pinned,
+            relay_server: config.relay_server,
+            group_topics: Vec::new(),
+            welcomes: HashMap::new(),
+            blocked: HashSet::new(),
+        };
+        supervisor.spawn_once(
+            "network event loop",
+            event_loop.run(frame_receiver, command_receiver),
+        );
+        let service = NetworkService {
+            peer_id: node_peer(&peer_id),
+            frames,
+            commands,
+        };
+        Ok((service, event_receiver))
+    }
+
+    /// The id the swarm runs under, a throwaway one with private discovery.
+    pub fn peer_id(&self) -> identity::PeerId {
+        self.peer_id
+    }
+
+    pub async fn send(&self, frame: Vec<u8>) -> Result<(), NodeError> {
+        self.frames
+            .send(frame)
+            .await
+            .map_err(|_| NodeError::ChannelClosed)
+    }
+
+    /// Sends `frame` to `peer` alone, dialing it if need be. Welcomes that
+    /// cannot be sent this way are published on its `welcome_topic`.
+    pub async fn send_to(&self, peer: identity::PeerId, frame: Vec<u8>) -> Result<(), NodeError> {
+        self.command(Command::SendTo(swarm_peer(&peer), frame))
+            .await
+    }
+
+    /// Streams a media packet to `peer`, which only arrives if we are
+    /// connected and both speak the media protocol.
+    pub async fn send_media(
+        &self,
+        peer: identity::PeerId,
+        packet: Vec<u8>,
+    ) -> Result<(), NodeError> {
+        self.command(Command::SendMedia(swarm_peer(&peer), packet))
+            .await
+    }
+
+    /// Adds DHT bootstrap peers, as `--bootstrap` does at startup, and
+    /// bootstraps again. Without the DHT they are only dialed.
+    pub async fn bootstrap(
+        &self,
+        peers: Vec<(identity::PeerId, Multiaddr)>,
+    ) -> Result<(), NodeError> {
+        let peers = peers
+            .into_iter()
+            .map(|(peer, address)| (swarm_peer(&peer), address))
+            .collect();
+        self.command(Command::Bootstrap(peers)).await
+    }
+
+    /// Dials `peer` at `addresses` unless we are connected already.
+    pub async fn dial(
+        &self,
+        peer: identity::PeerId,
+        addresses: Vec<Multiaddr>,
+    ) -> Result<(), NodeError> {
+        self.command(Command::Dial(swarm_peer(&peer), addresses))
+            .await
+    }
+
+    /// Follows the node onto its current group topics, after it joined,
+    /// left or changed membership in a group.
+    pub async fn sync_topics(&self) -> Result<(), NodeError> {
+        self.command(Command::SyncTopics).await
+    }
+
+    /// Follows the node's block list, after peers were blocked or
+    /// unblocked in its peer table.
+    pub async fn sync_blocked(&self) -> Result<(), NodeError> {
+        self.command(Command::SyncBlocked).await
+    }
+
+    async fn command(&self, command: Command) -> Result<(), NodeError> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| NodeError::ChannelClosed)
+    }
+}
+
+// Feeds frames other peers left in the delivery service mailbox to the
+// application, as if they had arrived over floodsub.
+async fn poll_mailbox(
+    ds: DsClient,
+    own_peer_id: PeerId,
+    node: Arc<Mutex<Node>>,
+    events: channel::Sender<NetworkEvent>,
+    seen: Arc<Mutex<SeenFrames>>,
+) -> Result<(), NodeError> {
+    let own_peer_id = own_peer_id.to_string();
+    let mut after = 0;
+    loop {
+        let client = ds.clone();
+        match async_std::task::spawn_blocking(move || client.fetch(MAILBOX, after)).await {
+            Ok(frames) => {
+                for frame in frames {
+                    after = frame.seq;
+                    if frame.sender == own_peer_id
+                        || !seen
+                            .lock()
+                            .await
+                            .first_sighting(&frame.frame, Instant::now())
+                    {
+                        continue;
+                    }
+                    let event = match frame.sender.parse() {
+                        Ok(peer) if node.lock().await.peers().is_blocked(&peer) => continue,
+                        Ok(peer) => NetworkEvent::Frame {
+                            peer,
+                            frame: frame.frame,
+                        },
+                        Err(_) => NetworkEvent::Mailbox(NodeError::Network(format!(
+                            "Mailbox frame from invalid peer {}",
+                            frame.sender
+                        ))),
+                    };
+                    emit(&events, event).await?;
+                }
+            }
+            Err(e) => {
+                let error = NodeError::Network(format!("Could not fetch mailbox: {}", e));
+                emit(&events, NetworkEvent::Mailbox(error)).await?;
+            }
+        }
+        async_std::task::sleep(MAILBOX_POLL_INTERVAL).await;
+    }
+}
+
+// Unbounded, so this only fails once the application stopped listening.
+async fn emit(
+    events: &channel::Sender<NetworkEvent>,
+    event: NetworkEvent,
+) -> Result<(), NodeError> {
+    events
+        .send(event)
+        .await
+        .map_err(|_| NodeError::ChannelClosed)
+}
+
+// Until we are in a group anyone might be the leader we want to join.
+fn keeps_connection(node: &Node, peer: &identity::PeerId) -> bool {
+    !node.in_group() || node.is_member_peer(peer)
+}
+
+struct EventLoop {
+    swarm: Swarm<Behaviour>,
+    node: Arc<Mutex<Node>>,
+    events: channel::Sender<NetworkEvent>,
+    /// Frames handed to the application lately, shared with the mailbox
+    /// poller.
+    seen: Arc<Mutex<SeenFrames>>,
+    ds: Option<DsClient>,
+    policies: TransportPolicies,
+    /// Addresses we dialed at startup.
+    dialed: HashSet<Multiaddr>,
+    /// Peers we dialed on request and our relays, which stay in the floodsub
+    /// view regardless.
+    pinned: HashSet<PeerId>,
+    /// Whether we relay for others, who need to be told where to reach us.
+    relay_server: bool,
+    group_topics: Vec<String>,
+    /// Welcomes sent directly and not acknowledged yet, published on the
+    /// joiner's `welcome_topic` if the stream fails.
+    welcomes: HashMap<RequestId, Vec<u8>>,
+    /// The node's blocked peers, as banned from the swarm.
+    blocked: HashSet<PeerId>,
+}
+
+impl EventLoop {
+    /// Handles some network events itself like mDNS and talks to the rest of
+    /// the application through channels.
+    /// Conceptually, this is an actor-ish design.
+    async fn run(
+        mut self,
+        frames: channel::Receiver<Vec<u8>>,
+        commands: channel::Receiver<Command>,
+    ) -> Result<(), NodeError> {
+        let mut membership_check = async_std::stream::interval(MEMBERSHIP_CHECK_INTERVAL).fuse();
+        let mut dht_refresh = async_std::stream::interval(DHT_REFRESH_INTERVAL).fuse();
+        let mut pace = async_std::stream::interval(PACE_INTERVAL).fuse();
+        let mut outbound = OutboundQueue::default();
+        let mut budget = PACE_BUDGET;
+        self.swarm
+            .behaviour_mut()
+            .floodsub
+            .subscribe(floodsub::Topic::new(RENDEZVOUS_TOPIC));
+        let own_peer_id = *self.swarm.local_peer_id();
+        self.swarm
+            .behaviour_mut()
+            .floodsub
+            .subscribe(floodsub::Topic::new(welcome_topic(&node_peer(
+                &own_peer_id,
+            ))));
+        let mut frames = frames.fuse();
+        let mut commands = commands.fuse();
+        loop {
+            futures::select! {
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event).await?,
+                _ = membership_check.select_next_some() => {
+                    let node = &*self.node.lock().await;
+                    for peer in node.peers().peer_ids() {
+                        self.swarm.behaviour_mut().keep_alive.set_member(swarm_peer(peer), keeps_connection(node, peer));
+                    }
+                }
+                _ = dht_refresh.select_next_some() => {
+                    if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                        let _ = kademlia.bootstrap();
+                    }
+                }
+                command = commands.select_next_some() => self.handle_command(command).await?,
+                frame = frames.select_next_some() => {
+                    // Frames already waiting go out by plane and class,
+                    // control and realtime first, while this tick's budget
+                    // lasts; the rest wait for the next tick.
+                    {
+                        let node = &*self.node.lock().await;
+                        outbound.push_on(node.frame_plane(&frame), frame);
+                        while let Ok(frame) = frames.get_ref().try_recv() {
+                            outbound.push_on(node.frame_plane(&frame), frame);
+                        }
+                    }
+                    self.publish_outbound(&mut outbound, &mut budget).await;
+                }
+                _ = pace.select_next_some() => {
+                    budget = PACE_BUDGET;
+                    if !outbound.is_empty() {
+                        self.publish_outbound(&mut outbound, &mut budget).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_swarm_event<E>(
+        &mut self,
+        event: SwarmEvent<BehaviourEvent, E>,
+    ) -> Result<(), NodeError> {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                // Relays hand their external addresses to the peers reserving
+                // with them; a relay is expected to listen on public ones.
+                if self.relay_server {
+                    self.swarm
+                        .add_external_address(address.clone(), AddressScore::Finite(1));
+                }
+                self.node
+                    .lock()
+                    .await
+                    .peers_mut()
+                    .listening(address.clone());
+                emit(&self.events, NetworkEvent::Listening(address)).await?;
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                self.node
+                    .lock()
+                    .await
+                    .peers_mut()
+                    .stopped_listening(&address);
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id, endpoint, ..
+            } => {
+                let address = endpoint.get_remote_address().clone();
+                let transport = Transport::of(&address);
+                let peer = node_peer(&peer_id);
+                let node = Arc::clone(&self.node);
+                let node = &mut *node.lock().await;
+                if !self.policies.admits(transport, node.is_member_peer(&peer)) {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    return emit(&self.events, NetworkEvent::Refused { peer, transport }).await;
+                }
+                emit(
+                    &self.events,
+                    NetworkEvent::Connected {
+                        peer,
+                        address: address.clone(),
+                    },
+                )
+                .await?;
+                if endpoint.is_dialer() && self.dialed.contains(&address) {
+                    self.pinned.insert(peer_id);
+                    node.peers_mut().discovered(peer, DiscoverySource::Dialed);
+                }
+                self.swarm
+                    .behaviour_mut()
+                    .keep_alive
+                    .set_member(peer_id, keeps_connection(node, &peer));
+                if endpoint.is_dialer() {
+                    node.peers_mut().dialed(peer, address.clone());
+                }
+                node.peers_mut().connected(peer, address);
+                self.swarm
+                    .behaviour_mut()
+                    .floodsub
+                    .add_node_to_partial_view(peer_id);
+                // Messages held while no member was connected.
+                for msg_out in node.release_held() {
+                    match WireMessage::from(msg_out).encode() {
+                        Ok(frame) => self.publish(node, frame),
+                        Err(e) => log::warn!("Could not send held message: {}", e),
+                    }
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+                let failed = NetworkEvent::DialFailed {
+                    peer: peer_id.as_ref().map(node_peer),
+                    error: error.to_string(),
+                };
+                emit(&self.events, failed).await?;
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.node
+                    .lock()
+                    .await
+                    .peers_mut()
+                    .disconnected(&node_peer(&peer_id), endpoint.get_remote_address());
+                emit(
+                    &self.events,
+                    NetworkEvent::Disconnected(node_peer(&peer_id)),
+                )
+                .await?;
+                // Otherwise floodsub dials straight back.
+                if num_established == 0
+                    && !self.pinned.contains(&peer_id)
+                    && !self.swarm.behaviour().keep_alive.is_member(&peer_id)
+                {
+                    self.swarm
+                        .behaviour_mut()
+                        .floodsub
+                        .remove_node_from_partial_view(&peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(MdnsEvent::Discovered(list))) => {
+                let node = &mut *self.node.lock().await;
+                for (peer, _) in list {
+                    node.peers_mut()
+                        .discovered(node_peer(&peer), DiscoverySource::Mdns);
+                    if self.blocked.contains(&peer) {
+                        continue;
+                    }
+                    self.swarm
+                        .behaviour_mut()
+                        .floodsub
+                        .add_node_to_partial_view(peer);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(MdnsEvent::Expired(list))) => {
+                for (peer, _) in list {
+                    let still_there = self
+                        .swarm
+                        .behaviour()
+                        .mdns
+                        .as_ref()
+                        .is_some_and(|mdns| mdns.has_node(&peer));
+                    if !still_there {
+                        self.swarm
+                            .behaviour_mut()
+                            .floodsub
+                            .remove_node_from_partial_view(&peer);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(KademliaEvent::RoutingUpdated {
+                peer,
+                is_new_peer: true,
+                ..
+            })) => {
+                self.node
+                    .lock()
+                    .await
+                    .peers_mut()
+                    .discovered(node_peer(&peer), DiscoverySource::Dht);
+                if !self.blocked.contains(&peer) {
+                    self.swarm
+                        .behaviour_mut()
+                        .floodsub
+                        .add_node_to_partial_view(peer);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(IdentifyEvent::Received {
+                peer_id,
+                info,
+            })) => {
+                if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                    for address in info.listen_addrs {
+                        kademlia.add_address(&peer_id, address);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged {
+                new,
+                ..
+            })) => {
+                if let NatStatus::Public(address) = &new {
+                    self.swarm
+                        .add_external_address(address.clone(), AddressScore::Infinite);
+                }
+                emit(&self.events, NetworkEvent::NatStatus(new)).await?;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay_client::Event::ReservationReqFailed {
+                    relay_peer_id,
+                    error,
+                    ..
+                },
+            )) => {
+                let failed = NetworkEvent::RelayFailed {
+                    relay: node_peer(&relay_peer_id),
+                    error: format!("{:?}", error),
+                };
+                emit(&self.events, failed).await?;
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(event)) => {
+                log::debug!("Relay client: {:?}", event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Relay(event)) => {
+                log::debug!("Relay: {:?}", event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Direct(event)) => {
+                self.handle_direct(event).await?
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Media(event)) => self.handle_media(event).await?,
+            // Members relay what blocked peers publish, so their frames can
+            // still reach us from others.
+            SwarmEvent::Behaviour(BehaviourEvent::Floodsub(FloodsubEvent::Message(message)))
+                if self.blocked.contains(&message.source) =>
+            {
+                log::trace!("Dropped a frame from blocked {}", message.source);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Floodsub(FloodsubEvent::Message(message))) => {
+                if !self
+                    .seen
+                    .lock()
+                    .await
+                    .first_sighting(&message.data, Instant::now())
+                {
+                    log::trace!("Dropped a repeated frame from {}", message.source);
+                    return Ok(());
+                }
+                let frame = NetworkEvent::Frame {
+                    peer: node_peer(&message.source),
+                    frame: message.data,
+                };
+                emit(&self.events, frame).await?;
+            }
+            _ => {} // ignore all other events
+        }
+        Ok(())
+    }
+
+    async fn handle_direct(
+        &mut self,
+        event: RequestResponseEvent<Vec<u8>, Vec<u8>>,
+    ) -> Result<(), NodeError> {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Acknowledged on arrival; answers travel as requests of their own.
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .direct
+                    .send_response(channel, Vec::new());
+                if !self.swarm.behaviour_mut().floodsub.admit(&peer) {
+                    return Ok(());
+                }
+                let frame = NetworkEvent::Direct {
+                    peer: node_peer(&peer),
+                    frame: request,
+                };
+                emit(&self.events, frame).await?;
+            }
+            RequestResponseEvent::Message {
+                message: RequestResponseMessage::Response { request_id, .. },
+                ..
+            } => {
+                self.welcomes.remove(&request_id);
+            }
+            // Peers that only speak floodsub still hear their Welcome.
+            RequestResponseEvent::OutboundFailure {
+                peer, request_id, ..
+            } if self.welcomes.contains_key(&request_id) => {
+                let frame = self.welcomes.remove(&request_id).expect("checked above");
+                self.swarm.behaviour_mut().floodsub.publish(
+                    floodsub::Topic::new(welcome_topic(&node_peer(&peer))),
+                    frame,
+                );
+            }
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                let failed = NetworkEvent::DirectFailed {
+                    peer: node_peer(&peer),
+                    error: format!("{:?}", error),
+                };
+                emit(&self.events, failed).await?;
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                log::debug!("Direct frame from {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_media(
+        &mut self,
+        event: RequestResponseEvent<MediaPacket, ()>,
+    ) -> Result<(), NodeError> {
+        match event {
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request: MediaPacket(packet),
+                        channel,
+                        ..
+                    },
+            } => {
+                if let Some(media) = self.swarm.behaviour_mut().media.as_mut() {
+                    let _ = media.send_response(channel, ());
+                }
+                emit(
+                    &self.events,
+                    NetworkEvent::Media {
+                        peer: node_peer(&peer),
+                        packet,
+                    },
+                )
+                .await?;
+            }
+            // Lost packets stay lost, see `media`.
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                log::debug!("Media packet to {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                log::debug!("Media packet from {} failed: {:?}", peer, error);
+            }
+            RequestResponseEvent::Message { .. } | RequestResponseEvent::ResponseSent { .. } => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, command: Command) -> Result<(), NodeError> {
+        match command {
+            Command::SendTo(peer, frame) => {
+                let welcome = FrameKind::classify(&frame) == FrameKind::Welcome;
+                let request = self
+                    .swarm
+                    .behaviour_mut()
+                    .direct
+                    .send_request(&peer, frame.clone());
+                if welcome {
+                    self.welcomes.insert(request, frame);
+                }
+            }
+            Command::SendMedia(peer, packet) => {
+                if let Some(media) = self.swarm.behaviour_mut().media.as_mut() {
+                    media.send_request(&peer, MediaPacket(packet));
+                }
+            }
+            Command::Dial(peer, addresses) => {
+                self.node
+                    .lock()
+                    .await
+                    .peers_mut()
+                    .discovered(node_peer(&peer), DiscoverySource::Introduced);
+                self.dial_peer(peer, addresses).await?;
+            }
+            Command::Bootstrap(peers) => {
+                for (peer, address) in peers {
+                    if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                        kademlia.add_address(&peer, address.clone());
+                    }
+                    self.dial_peer(peer, vec![address]).await?;
+                }
+                if let Some(kademlia) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                    let _ = kademlia.bootstrap();
+                }
+            }
+            Command::SyncTopics => {
+                let wanted = self.node.lock().await.all_group_topics();
+                self.sync_group_topics(&wanted);
+            }
+            Command::SyncBlocked => {
+                let wanted: HashSet<_> = self
+                    .node
+                    .lock()
+                    .await
+                    .peers()
+                    .blocked()
+                    .map(swarm_peer)
+                    .collect();
+                self.sync_blocked(wanted);
+            }
+        }
+        Ok(())
+    }
+
+    // Bans the peers the node blocked, closing their connections, and lifts
+    // the bans it dropped.
+    fn sync_blocked(&mut self, wanted: HashSet<PeerId>) {
+        for peer in self.blocked.difference(&wanted) {
+            self.swarm.unban_peer_id(*peer);
+        }
+        for peer in wanted.difference(&self.blocked) {
+            self.swarm.ban_peer_id(*peer);
+            self.pinned.remove(peer);
+            let behaviour = self.swarm.behaviour_mut();
+            behaviour.keep_alive.set_member(*peer, false);
+            behaviour.floodsub.remove_node_from_partial_view(peer);
+        }
+        self.blocked = wanted;
+    }
+
+    async fn dial_peer(
+        &mut self,
+        peer: PeerId,
+        addresses: Vec<Multiaddr>,
+    ) -> Result<(), NodeError> {
+        let opts = DialOpts::peer_id(peer)
+            .condition(PeerCondition::Disconnected)
+            .addresses(addresses)
+            .build();
+        if let Err(e) = self.swarm.dial(opts) {
+            let failed = NetworkEvent::DialFailed {
+                peer: Some(node_peer(&peer)),
+                error: e.to_string(),
+            };
+            emit(&self.events, failed).await?;
+        }
+        Ok(())
+    }
+
+    // Follows the node onto its current group topics, leaving those it dropped.
+    fn sync_group_topics(&mut self, wanted: &[String]) {
+        if self.group_topics.as_slice() == wanted {
+            return;
+        }
+        let floodsub = &mut self.swarm.behaviour_mut().floodsub;
+        for topic in self
+            .group_topics
+            .iter()
+            .filter(|topic| !wanted.contains(topic))
+        {
+            floodsub.unsubscribe(floodsub::Topic::new(topic.clone()));
+        }
+        for topic in wanted
+            .iter()
+            .filter(|topic| !self.group_topics.contains(topic))
+        {
+            floodsub.subscribe(floodsub::Topic::new(topic.clone()));
+        }
+        self.group_topics = wanted.to_vec();
+    }
+
+    // Publishes a frame on its topics, or leaves it in the mailbox while no
+    // peers are connected.
+    async fn publish_outbound(&mut self, outbound: &mut OutboundQueue, budget: &mut Budget) {
+        let node = Arc::clone(&self.node);
+        let node = &mut *node.lock().await;
+        while let Some(frame) = outbound.pop_within(budget) {
+            self.publish(node, frame);
+        }
+    }
+
+    fn publish(&mut self, node: &mut Node, frame: Vec<u8>) {
+        let peers = node.peers().peer_ids().count();
+        let state = match (&self.ds, peers) {
+            (Some(_), 0) => DeliveryState::Mailbox,
+            (None, 0) => DeliveryState::Undelivered,
+            _ => DeliveryState::Sent { peers },
+        };
+        if let Some(sent) = node.record_published(&frame, state) {
+            // Unbounded, so this only fails once nobody is listening.
+            let _ = self.events.try_send(NetworkEvent::Published(sent));
+        }
+        match &self.ds {
+            Some(ds) if peers == 0 => {
+                let (ds, sender) = (ds.clone(), self.swarm.local_peer_id().to_string());
+                let events = self.events.clone();
+                async_std::task::spawn_blocking(move || {
+                    if let Err(e) = ds.deposit(MAILBOX, &sender, &frame) {
+                        let error =
+                            NodeError::Network(format!("Could not deposit in mailbox: {}", e));
+                        let _ = events.try_send(NetworkEvent::Mailbox(error));
+                    }
+                });
+            }
+            _ => {
+                self.sync_group_topics(&node.all_group_topics());
+                let topics = publish_topics(&frame, &node.frame_topics(&frame));
+                self.swarm
+                    .behaviour_mut()
+                    .floodsub
+                    .publish_many(topics.into_iter().map(floodsub::Topic::new), frame);
+            }
+        }
+    }
+}
+
+#[derive(NetworkBehaviour)]
+#[behaviour(event_process = false, out_event = "BehaviourEvent")]
+struct Behaviour {
+    floodsub: LimitedFloodsub,
+    mdns: Toggle<Mdns>,
+    kademlia: Toggle<Kademlia<MemoryStore>>,
+    identify: Toggle<Identify>,
+    autonat: Toggle<autonat::Behaviour>,
+    relay_client: Client,
+    relay: Toggle<Relay>,
+    keep_alive: MemberKeepAlive,
+    direct: RequestResponse<FrameCodec>,
+    media: Toggle<RequestResponse<MediaCodec>>,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum BehaviourEvent {
+    Floodsub(FloodsubEvent),
+    Direct(RequestResponseEvent<Vec<u8>, Vec<u8>>),
+    Media(RequestResponseEvent<MediaPacket, ()>),
+    Mdns(MdnsEvent),
+    Kademlia(KademliaEvent),
+    Identify(IdentifyEvent),
+    Autonat(autonat::Event),
+    RelayClient(relay_client::Event),
+    Relay(relay::Event),
+}
+
+impl From<FloodsubEvent> for BehaviourEvent {
+    fn from(event: FloodsubEvent) -> BehaviourEvent {
+        BehaviourEvent::Floodsub(event)
+    }
+}
+
+impl From<RequestResponseEvent<Vec<u8>, Vec<u8>>> for BehaviourEvent {
+    fn from(event: RequestResponseEvent<Vec<u8>, Vec<u8>>) -> BehaviourEvent {
+        BehaviourEvent::Direct(event)
+    }
+}
+
+impl From<RequestResponseEvent<MediaPacket, ()>> for BehaviourEvent {
+    fn from(event: RequestResponseEvent<MediaPacket, ()>) -> BehaviourEvent {
+        BehaviourEvent::Media(event)
+    }
+}
+
+impl From<Infallible> for BehaviourEvent {
+    fn from(event: Infallible) -> BehaviourEvent {
+        match event {}
+    }
+}
+
+impl From<MdnsEvent> for BehaviourEvent {
+    fn from(event: MdnsEvent) -> BehaviourEvent {
+        BehaviourEvent::Mdns(event)
+    }
+}
+
+impl From<KademliaEvent> for BehaviourEvent {
+    fn from(event: KademliaEvent) -> BehaviourEvent {
+        BehaviourEvent::Kademlia(event)
+    }
+}
+
+impl From<IdentifyEvent> for BehaviourEvent {
+    fn from(event: IdentifyEvent) -> BehaviourEvent {
+        BehaviourEvent::Identify(event)
+    }
+}
+
+impl From<autonat::Event> for BehaviourEvent {
+    fn from(event: autonat::Event) -> BehaviourEvent {
+        BehaviourEvent::Autonat(event)
+    }
+}
+
+impl From<relay_client::Event> for BehaviourEvent {
+    fn from(event: relay_client::Event) -> BehaviourEvent {
+        BehaviourEvent::RelayClient(event)
+    }
+}
+
+impl From<relay::Event> for BehaviourEvent {
+    fn from(event: relay::Event) -> BehaviourEvent {
+        BehaviourEvent::Relay(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(listen: Vec<Multiaddr>, dial: Vec<Multiaddr>) -> NetworkConfig {
+        NetworkConfig {
+            discovery: Discovery::Off,
+            tls: None,
+            listen,
+            dial,
+            bootstrap: Vec::new(),
+            dht: false,
+            relays: Vec::new(),
+            relay_server: false,
+            keep_alive: KeepAliveConfig::default(),
+            policies: TransportPolicies::default(),
+            ds: None,
+            media: false,
+            peer_limits: PeerRateLimits::default(),
+        }
+    }
+
+    #[test]
+    fn services_publish_and_send_frames_between_nodes() {
+        async_std::task::block_on(async {
+            let (supervisor, _tasks) = Supervisor::new();
+            let node = || Arc::new(Mutex::new(Node::default()));
+            let listen = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+            let (alice, alice_events) =
+                NetworkService::spawn(config(vec![listen], vec![]), node(), &supervisor)
+                    .await
+                    .unwrap();
+            let address = loop {
+                if let NetworkEvent::Listening(address) = alice_events.recv().await.unwrap() {
+                    break address;
+                }
+            };
+            let (bob, bob_events) =
+                NetworkService::spawn(config(vec![], vec![address]), node(), &supervisor)
+                    .await
+                    .unwrap();
+
+            // Floodsub drops frames until it has heard Bob subscribe, so keep sending.
+            let alice_peer = alice.peer_id();
+            let sender = alice.clone();
+            async_std::task::spawn(async move {
+                while sender.send(b"hello".to_vec()).await.is_ok() {
+                    async_std::task::sleep(Duration::from_millis(100)).await;
+                }
+            });
+            let received = async_std::future::timeout(Duration::from_secs(10), async {
+                loop {
+                    if let NetworkEvent::Frame { peer, frame } = bob_events.recv().await.unwrap() {
+                        break (peer, frame);
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(received, (alice_peer, b"hello".to_vec()));
+
+            bob.send_to(alice_peer, b"just you".to_vec()).await.unwrap();
+            let received = async_std::future::timeout(Duration::from_secs(10), async {
+                loop {
+                    if let NetworkEvent::Direct { peer, frame } = alice_events.recv().await.unwrap()
+                    {
+                        break (peer, frame);
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(received, (bob.peer_id(), b"just you".to_vec()));
+        });
+    }
+}
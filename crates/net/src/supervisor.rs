@@ -0,0 +1,188 @@
+//! Supervision for the node's long-running tasks.
+//!
+//! Each task is spawned through a [`Supervisor`] with a [`Restart`] policy.
+//! A task that returns an error or panics is restarted with a growing
+//! backoff while its policy allows, and otherwise reported as
+//! [`TaskEvent::Fatal`], which `main` turns into a controlled shutdown
+//! instead of carrying on without the task.
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use async_std::{channel, task::JoinHandle};
+use futures::FutureExt;
+
+use p2p_mls_core::error::NodeError;
+
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// The task owns state that cannot be rebuilt, so failing is fatal.
+    Never,
+    /// Start the task again after failures, up to `max_restarts` times.
+    OnFailure { max_restarts: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskEvent {
+    Restarting {
+        task: &'static str,
+        cause: String,
+        attempt: u32,
+    },
+    Fatal {
+        task: &'static str,
+        cause: String,
+    },
+}
+
+impl std::fmt::Display for TaskEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskEvent::Restarting {
+                task,
+                cause,
+                attempt,
+            } => write!(f, "{} failed ({}), restart {}", task, cause, attempt),
+            TaskEvent::Fatal { task, cause } => write!(f, "{} failed: {}", task, cause),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    events: channel::Sender<TaskEvent>,
+    backoff: Duration,
+}
+
+impl Supervisor {
+    pub fn new() -> (Supervisor, channel::Receiver<TaskEvent>) {
+        Supervisor::with_backoff(DEFAULT_BACKOFF)
+    }
+
+    /// Waits `backoff` times the attempt number before each restart.
+    pub fn with_backoff(backoff: Duration) -> (Supervisor, channel::Receiver<TaskEvent>) {
+        let (events, receiver) = channel::unbounded();
+        (Supervisor { events, backoff }, receiver)
+    }
+
+    /// Runs the future `start` builds, building a fresh one for each restart.
+    /// A task that returns `Ok` has finished and is not restarted.
+    pub fn spawn<F, Fut>(
+        &self,
+        task: &'static str,
+        restart: Restart,
+        mut start: F,
+    ) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), NodeError>> + Send + 'static,
+    {
+        let events = self.events.clone();
+        let backoff = self.backoff;
+        async_std::task::spawn(async move {
+            let mut attempt = 0;
+            loop {
+                let cause = match AssertUnwindSafe(start()).catch_unwind().await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => e.to_string(),
+                    Err(panic) => panic_message(panic),
+                };
+                match restart {
+                    Restart::OnFailure { max_restarts } if attempt < max_restarts => {
+                        attempt += 1;
+                        let event = TaskEvent::Restarting {
+                            task,
+                            cause,
+                            attempt,
+                        };
+                        // Nobody listening just means we are shutting down.
+                        let _ = events.send(event).await;
+                        async_std::task::sleep(backoff * attempt).await;
+                    }
+                    _ => {
+                        let _ = events.send(TaskEvent::Fatal { task, cause }).await;
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Runs `future` once; failing is fatal.
+    pub fn spawn_once<Fut>(&self, task: &'static str, future: Fut) -> JoinHandle<()>
+    where
+        Fut: Future<Output = Result<(), NodeError>> + Send + 'static,
+    {
+        let mut future = Some(future);
+        self.spawn(task, Restart::Never, move || {
+            future.take().expect("Restart::Never starts the task once")
+        })
+    }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+    format!("panicked: {}", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn failed_tasks_restart_until_their_policy_gives_up() {
+        async_std::task::block_on(async {
+            let (supervisor, events) = Supervisor::with_backoff(Duration::from_millis(1));
+            let runs = Arc::new(AtomicU32::new(0));
+            let counted = Arc::clone(&runs);
+            supervisor
+                .spawn("flaky", Restart::OnFailure { max_restarts: 2 }, move || {
+                    let run = counted.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        match run {
+                            0 => panic!("first run"),
+                            1 => Err(NodeError::Other("second run".to_string())),
+                            _ => Ok(()),
+                        }
+                    }
+                })
+                .await;
+            assert_eq!(runs.load(Ordering::SeqCst), 3);
+            assert_eq!(
+                events.recv().await.unwrap(),
+                TaskEvent::Restarting {
+                    task: "flaky",
+                    cause: "panicked: first run".to_string(),
+                    attempt: 1
+                }
+            );
+            assert!(matches!(
+                events.recv().await.unwrap(),
+                TaskEvent::Restarting { attempt: 2, .. }
+            ));
+
+            supervisor
+                .spawn("owner", Restart::Never, || async {
+                    Err(NodeError::Other("gone".to_string()))
+                })
+                .await;
+            assert_eq!(
+                events.recv().await.unwrap(),
+                TaskEvent::Fatal {
+                    task: "owner",
+                    cause: "gone".to_string()
+                }
+            );
+        });
+    }
+}
@@ -1,8 +0,0 @@
-#[macro_use]
-extern crate lazy_static;
-
-pub mod cli;
-pub mod crypto;
-pub mod error;
-pub mod network;
-pub mod node;